@@ -0,0 +1,36 @@
+-- Tombstones for files that disappeared from disk
+
+-- Content hash used to recognise a file that comes back under another path
+ALTER TABLE files ADD COLUMN content_hash TEXT;
+CREATE INDEX idx_files_content_hash ON files(content_hash);
+
+-- Removed files that still carried tags or custom metadata
+CREATE TABLE file_tombstones (
+    id TEXT PRIMARY KEY,
+    original_file_id TEXT NOT NULL,
+    path TEXT NOT NULL,
+    name TEXT NOT NULL,
+    directory_id TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    content_hash TEXT,
+    inode INTEGER,
+    device_id INTEGER,
+    birth_time TIMESTAMP,
+    is_directory BOOLEAN NOT NULL DEFAULT 0,
+    custom_metadata TEXT NOT NULL DEFAULT '{}',
+    deleted_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (directory_id) REFERENCES directories (id) ON DELETE CASCADE
+);
+
+CREATE TABLE file_tombstone_tags (
+    tombstone_id TEXT NOT NULL,
+    tag_id TEXT NOT NULL,
+    PRIMARY KEY (tombstone_id, tag_id),
+    FOREIGN KEY (tombstone_id) REFERENCES file_tombstones (id) ON DELETE CASCADE,
+    FOREIGN KEY (tag_id) REFERENCES tags (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_file_tombstones_content_hash ON file_tombstones(content_hash);
+CREATE INDEX idx_file_tombstones_inode ON file_tombstones(inode, device_id);
+CREATE INDEX idx_file_tombstones_deleted_at ON file_tombstones(deleted_at);
+CREATE INDEX idx_file_tombstone_tags_tag_id ON file_tombstone_tags(tag_id);
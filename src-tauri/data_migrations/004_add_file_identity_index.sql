@@ -0,0 +1,4 @@
+-- File identity index
+-- inode is reused after deletes, so a file is identified by (device_id, inode, birth_time)
+
+CREATE INDEX idx_files_identity ON files(device_id, inode, birth_time);
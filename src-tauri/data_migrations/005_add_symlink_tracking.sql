@@ -0,0 +1,9 @@
+-- Symlink tracking
+
+-- Symlinks are indexed as links (with their target) instead of as the target itself
+ALTER TABLE files ADD COLUMN is_symlink BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE files ADD COLUMN symlink_target TEXT;
+ALTER TABLE files ADD COLUMN is_broken_link BOOLEAN NOT NULL DEFAULT 0;
+
+-- Whether scanning descends into symlinked directories (per registered directory)
+ALTER TABLE directories ADD COLUMN follow_symlinks BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Hierarchical tags
+-- tags.name keeps the full path (e.g. "client/acme/invoices"), so existing flat tags stay valid top-level tags.
+-- Deleting a parent tag deletes its whole subtree.
+
+ALTER TABLE tags ADD COLUMN parent_id TEXT REFERENCES tags (id) ON DELETE CASCADE;
+
+CREATE INDEX idx_tags_parent_id ON tags (parent_id);
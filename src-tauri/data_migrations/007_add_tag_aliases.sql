@@ -0,0 +1,12 @@
+-- Tag aliases
+-- Searching or tagging with an alias resolves to the canonical tag.
+-- Merging a tag into another keeps the merged tag's name as an alias.
+
+CREATE TABLE tag_aliases (
+    alias TEXT PRIMARY KEY COLLATE NOCASE,
+    tag_id TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (tag_id) REFERENCES tags (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_tag_aliases_tag_id ON tag_aliases (tag_id);
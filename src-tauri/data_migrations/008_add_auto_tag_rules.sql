@@ -0,0 +1,15 @@
+-- Auto-tagging rules
+-- Conditions and actions are stored as JSON and evaluated when files are indexed.
+-- Rules with a higher priority win when they set the same metadata key.
+
+CREATE TABLE auto_tag_rules (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    priority INTEGER NOT NULL DEFAULT 0,
+    match_mode TEXT NOT NULL DEFAULT 'all',
+    conditions TEXT NOT NULL,
+    actions TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
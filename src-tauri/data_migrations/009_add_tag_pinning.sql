@@ -0,0 +1,5 @@
+-- Pinned tags
+-- Tags created or curated deliberately by the user are pinned and survive
+-- automatic cleanup of unused tags. Existing tags keep the previous behaviour.
+
+ALTER TABLE tags ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- Per-shelf settings
+-- Settings that depend on the shelf (e.g. the xattr tag sync direction) live
+-- in the shelf's own database instead of the global settings table.
+
+CREATE TABLE shelf_settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
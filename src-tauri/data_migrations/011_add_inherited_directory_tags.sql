@@ -0,0 +1,19 @@
+-- Inherited directory tags
+-- A tag on a directory entry applies to everything below it without being copied.
+-- include_subfolders = 0 limits the tag to the files directly inside the directory.
+-- effective_file_tags lists direct tags (inherited_from NULL) and inherited ones
+-- (inherited_from = id of the tagged directory entry); a file may appear more than once per tag.
+
+ALTER TABLE file_tags ADD COLUMN include_subfolders BOOLEAN NOT NULL DEFAULT 1;
+
+CREATE VIEW effective_file_tags AS
+    SELECT file_id, tag_id, NULL AS inherited_from
+    FROM file_tags
+    UNION ALL
+    SELECT f.id AS file_id, ft.tag_id, d.id AS inherited_from
+    FROM file_tags ft
+    INNER JOIN files d ON d.id = ft.file_id AND d.is_directory = 1
+    -- '0' is the character right after '/', so this range is every path under d.path
+    INNER JOIN files f ON f.path > d.path || '/' AND f.path < d.path || '0'
+    WHERE ft.include_subfolders = 1
+       OR instr(substr(f.path, length(d.path) + 2), '/') = 0;
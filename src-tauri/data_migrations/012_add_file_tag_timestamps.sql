@@ -0,0 +1,15 @@
+-- Tagging timestamps
+-- Records when a tag was applied to a file so usage can be reported over time.
+-- Rows that existed before this migration keep tagged_at NULL (unknown).
+
+ALTER TABLE file_tags ADD COLUMN tagged_at TIMESTAMP;
+
+CREATE TRIGGER file_tags_set_tagged_at
+AFTER INSERT ON file_tags
+FOR EACH ROW WHEN NEW.tagged_at IS NULL
+BEGIN
+    UPDATE file_tags SET tagged_at = CURRENT_TIMESTAMP
+    WHERE file_id = NEW.file_id AND tag_id = NEW.tag_id;
+END;
+
+CREATE INDEX idx_file_tags_tagged_at ON file_tags (tagged_at);
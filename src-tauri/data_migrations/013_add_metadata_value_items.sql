@@ -0,0 +1,14 @@
+-- Multi-value custom metadata
+-- multi_select values keep their canonical JSON array in custom_metadata_values.value
+-- and are also expanded here, one row per item, so search can filter on single items.
+
+CREATE TABLE custom_metadata_value_items (
+    value_id TEXT NOT NULL,
+    key_id TEXT NOT NULL, -- References custom_metadata_keys.id in settings database
+    item TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (value_id, item),
+    FOREIGN KEY (value_id) REFERENCES custom_metadata_values (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_metadata_value_items_key_item ON custom_metadata_value_items (key_id, item);
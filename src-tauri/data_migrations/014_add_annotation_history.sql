@@ -0,0 +1,72 @@
+-- Annotation history
+-- Every change to a file's custom metadata values or tags is recorded by triggers,
+-- so bulk operations, imports and restores are covered without each caller logging.
+-- changed_by is taken from the 'annotation_actor' shelf setting, which the app sets on startup.
+-- History is removed together with the file it describes.
+
+CREATE TABLE annotation_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    file_id TEXT NOT NULL,
+    action TEXT NOT NULL, -- 'set', 'delete', 'tag_added', 'tag_removed'
+    key_id TEXT, -- References custom_metadata_keys.id in settings database
+    tag_id TEXT,
+    old_value TEXT, -- For tag changes, the tag name
+    new_value TEXT,
+    changed_by TEXT,
+    changed_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX idx_annotation_history_file ON annotation_history (file_id, id);
+
+CREATE TRIGGER custom_metadata_values_history_insert
+AFTER INSERT ON custom_metadata_values
+FOR EACH ROW
+BEGIN
+    INSERT INTO annotation_history (file_id, action, key_id, new_value, changed_by)
+    VALUES (NEW.file_id, 'set', NEW.key_id, NEW.value,
+            (SELECT value FROM shelf_settings WHERE key = 'annotation_actor'));
+END;
+
+CREATE TRIGGER custom_metadata_values_history_update
+AFTER UPDATE OF value ON custom_metadata_values
+FOR EACH ROW WHEN OLD.value IS NOT NEW.value
+BEGIN
+    INSERT INTO annotation_history (file_id, action, key_id, old_value, new_value, changed_by)
+    VALUES (NEW.file_id, 'set', NEW.key_id, OLD.value, NEW.value,
+            (SELECT value FROM shelf_settings WHERE key = 'annotation_actor'));
+END;
+
+-- Values removed because the file itself was deleted are not recorded
+CREATE TRIGGER custom_metadata_values_history_delete
+AFTER DELETE ON custom_metadata_values
+FOR EACH ROW WHEN EXISTS (SELECT 1 FROM files WHERE id = OLD.file_id)
+BEGIN
+    INSERT INTO annotation_history (file_id, action, key_id, old_value, changed_by)
+    VALUES (OLD.file_id, 'delete', OLD.key_id, OLD.value,
+            (SELECT value FROM shelf_settings WHERE key = 'annotation_actor'));
+END;
+
+CREATE TRIGGER file_tags_history_insert
+AFTER INSERT ON file_tags
+FOR EACH ROW
+BEGIN
+    INSERT INTO annotation_history (file_id, action, tag_id, new_value, changed_by)
+    VALUES (NEW.file_id, 'tag_added', NEW.tag_id, (SELECT name FROM tags WHERE id = NEW.tag_id),
+            (SELECT value FROM shelf_settings WHERE key = 'annotation_actor'));
+END;
+
+CREATE TRIGGER file_tags_history_delete
+AFTER DELETE ON file_tags
+FOR EACH ROW WHEN EXISTS (SELECT 1 FROM files WHERE id = OLD.file_id)
+BEGIN
+    INSERT INTO annotation_history (file_id, action, tag_id, old_value, changed_by)
+    VALUES (OLD.file_id, 'tag_removed', OLD.tag_id, (SELECT name FROM tags WHERE id = OLD.tag_id),
+            (SELECT value FROM shelf_settings WHERE key = 'annotation_actor'));
+END;
+
+CREATE TRIGGER files_history_delete
+AFTER DELETE ON files
+FOR EACH ROW
+BEGIN
+    DELETE FROM annotation_history WHERE file_id = OLD.id;
+END;
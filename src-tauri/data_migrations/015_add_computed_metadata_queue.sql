@@ -0,0 +1,77 @@
+-- Computed custom metadata queue
+-- Files whose computed custom metadata values may be out of date. Triggers add a file
+-- whenever one of the inputs of a computed key changes (the file row, its tags, a tag
+-- name, or its other custom metadata values); the app recomputes the queued files
+-- before reading values and removes them from the queue.
+-- The triggers use ON CONFLICT DO NOTHING rather than INSERT OR IGNORE, because the
+-- conflict policy of an outer upsert on files would override OR IGNORE.
+
+CREATE TABLE computed_metadata_stale (
+    file_id TEXT PRIMARY KEY REFERENCES files(id) ON DELETE CASCADE
+);
+
+CREATE TRIGGER files_computed_stale_insert
+AFTER INSERT ON files
+FOR EACH ROW
+BEGIN
+    INSERT INTO computed_metadata_stale (file_id) VALUES (NEW.id)
+    ON CONFLICT(file_id) DO NOTHING;
+END;
+
+CREATE TRIGGER files_computed_stale_update
+AFTER UPDATE ON files
+FOR EACH ROW
+BEGIN
+    INSERT INTO computed_metadata_stale (file_id) VALUES (NEW.id)
+    ON CONFLICT(file_id) DO NOTHING;
+END;
+
+CREATE TRIGGER file_tags_computed_stale_insert
+AFTER INSERT ON file_tags
+FOR EACH ROW
+BEGIN
+    INSERT INTO computed_metadata_stale (file_id) SELECT id FROM files WHERE id = NEW.file_id
+    ON CONFLICT(file_id) DO NOTHING;
+END;
+
+CREATE TRIGGER file_tags_computed_stale_delete
+AFTER DELETE ON file_tags
+FOR EACH ROW
+BEGIN
+    INSERT INTO computed_metadata_stale (file_id) SELECT id FROM files WHERE id = OLD.file_id
+    ON CONFLICT(file_id) DO NOTHING;
+END;
+
+CREATE TRIGGER tags_computed_stale_rename
+AFTER UPDATE OF name ON tags
+FOR EACH ROW WHEN OLD.name IS NOT NEW.name
+BEGIN
+    INSERT INTO computed_metadata_stale (file_id)
+    SELECT file_id FROM file_tags WHERE tag_id = NEW.id
+    ON CONFLICT(file_id) DO NOTHING;
+END;
+
+CREATE TRIGGER custom_metadata_values_computed_stale_insert
+AFTER INSERT ON custom_metadata_values
+FOR EACH ROW
+BEGIN
+    INSERT INTO computed_metadata_stale (file_id) VALUES (NEW.file_id)
+    ON CONFLICT(file_id) DO NOTHING;
+END;
+
+CREATE TRIGGER custom_metadata_values_computed_stale_update
+AFTER UPDATE OF value ON custom_metadata_values
+FOR EACH ROW WHEN OLD.value IS NOT NEW.value
+BEGIN
+    INSERT INTO computed_metadata_stale (file_id) VALUES (NEW.file_id)
+    ON CONFLICT(file_id) DO NOTHING;
+END;
+
+-- Values removed because the file itself was deleted need no recomputation
+CREATE TRIGGER custom_metadata_values_computed_stale_delete
+AFTER DELETE ON custom_metadata_values
+FOR EACH ROW WHEN EXISTS (SELECT 1 FROM files WHERE id = OLD.file_id)
+BEGIN
+    INSERT INTO computed_metadata_stale (file_id) VALUES (OLD.file_id)
+    ON CONFLICT(file_id) DO NOTHING;
+END;
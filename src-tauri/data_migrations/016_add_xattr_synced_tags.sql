@@ -0,0 +1,10 @@
+-- Tag names last agreed between Clerica and a file's extended attributes.
+-- Sync compares both sides against this set, so a tag removed on one side
+-- is removed on the other instead of being copied back.
+
+CREATE TABLE xattr_synced_tags (
+    file_id TEXT NOT NULL,
+    tag_name TEXT NOT NULL,
+    PRIMARY KEY (file_id, tag_name),
+    FOREIGN KEY (file_id) REFERENCES files (id) ON DELETE CASCADE
+);
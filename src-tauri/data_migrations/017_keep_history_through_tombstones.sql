@@ -0,0 +1,55 @@
+-- Keep annotation history while a file is tombstoned
+-- Deleting a files row used to wipe its history, so a file reattached from a tombstone
+-- came back without it. History now stays keyed by the original file ID while a tombstone
+-- for it exists; restoring re-keys it to the new file, and it is purged when the tombstone
+-- expires or is discarded.
+
+DROP TRIGGER files_history_delete;
+
+CREATE TRIGGER files_history_delete
+AFTER DELETE ON files
+FOR EACH ROW WHEN NOT EXISTS (SELECT 1 FROM file_tombstones WHERE original_file_id = OLD.id)
+BEGIN
+    DELETE FROM annotation_history WHERE file_id = OLD.id;
+END;
+
+CREATE TRIGGER file_tombstones_history_delete
+AFTER DELETE ON file_tombstones
+FOR EACH ROW WHEN NOT EXISTS (SELECT 1 FROM files WHERE id = OLD.original_file_id)
+BEGIN
+    DELETE FROM annotation_history WHERE file_id = OLD.original_file_id;
+END;
+
+CREATE INDEX idx_file_tombstones_original_file ON file_tombstones (original_file_id);
+
+-- The tag name is kept on each file_tags row, so a removal caused by deleting the tag
+-- (which cascades after the tags row is gone) is still recorded with its name.
+ALTER TABLE file_tags ADD COLUMN tag_name TEXT;
+
+UPDATE file_tags SET tag_name = (SELECT name FROM tags WHERE id = file_tags.tag_id);
+
+CREATE TRIGGER file_tags_set_tag_name
+AFTER INSERT ON file_tags
+FOR EACH ROW
+BEGIN
+    UPDATE file_tags SET tag_name = (SELECT name FROM tags WHERE id = NEW.tag_id)
+    WHERE file_id = NEW.file_id AND tag_id = NEW.tag_id;
+END;
+
+CREATE TRIGGER tags_rename_file_tags
+AFTER UPDATE OF name ON tags
+FOR EACH ROW WHEN OLD.name IS NOT NEW.name
+BEGIN
+    UPDATE file_tags SET tag_name = NEW.name WHERE tag_id = NEW.id;
+END;
+
+DROP TRIGGER file_tags_history_delete;
+
+CREATE TRIGGER file_tags_history_delete
+AFTER DELETE ON file_tags
+FOR EACH ROW WHEN EXISTS (SELECT 1 FROM files WHERE id = OLD.file_id)
+BEGIN
+    INSERT INTO annotation_history (file_id, action, tag_id, old_value, changed_by)
+    VALUES (OLD.file_id, 'tag_removed', OLD.tag_id, OLD.tag_name,
+            (SELECT value FROM shelf_settings WHERE key = 'annotation_actor'));
+END;
@@ -0,0 +1,81 @@
+-- Computed custom metadata refinements
+-- Values written by recomputing a computed key are flagged, and the history triggers
+-- skip them, so recomputation does not fill the annotation history.
+-- The files trigger only queues a file when a column that templates can read changes;
+-- access time and bookkeeping columns change without the file itself changing.
+-- Renaming or moving a registered directory queues its files, since templates read
+-- root_name and root_path.
+
+ALTER TABLE custom_metadata_values ADD COLUMN is_computed BOOLEAN NOT NULL DEFAULT 0;
+
+DROP TRIGGER custom_metadata_values_history_insert;
+DROP TRIGGER custom_metadata_values_history_update;
+DROP TRIGGER custom_metadata_values_history_delete;
+
+CREATE TRIGGER custom_metadata_values_history_insert
+AFTER INSERT ON custom_metadata_values
+FOR EACH ROW WHEN NOT NEW.is_computed
+BEGIN
+    INSERT INTO annotation_history (file_id, action, key_id, new_value, changed_by)
+    VALUES (NEW.file_id, 'set', NEW.key_id, NEW.value,
+            (SELECT value FROM shelf_settings WHERE key = 'annotation_actor'));
+END;
+
+CREATE TRIGGER custom_metadata_values_history_update
+AFTER UPDATE OF value ON custom_metadata_values
+FOR EACH ROW WHEN OLD.value IS NOT NEW.value AND NOT NEW.is_computed
+BEGIN
+    INSERT INTO annotation_history (file_id, action, key_id, old_value, new_value, changed_by)
+    VALUES (NEW.file_id, 'set', NEW.key_id, OLD.value, NEW.value,
+            (SELECT value FROM shelf_settings WHERE key = 'annotation_actor'));
+END;
+
+-- Values removed because the file itself was deleted are not recorded
+CREATE TRIGGER custom_metadata_values_history_delete
+AFTER DELETE ON custom_metadata_values
+FOR EACH ROW WHEN NOT OLD.is_computed AND EXISTS (SELECT 1 FROM files WHERE id = OLD.file_id)
+BEGIN
+    INSERT INTO annotation_history (file_id, action, key_id, old_value, changed_by)
+    VALUES (OLD.file_id, 'delete', OLD.key_id, OLD.value,
+            (SELECT value FROM shelf_settings WHERE key = 'annotation_actor'));
+END;
+
+DROP TRIGGER files_computed_stale_update;
+
+CREATE TRIGGER files_computed_stale_update
+AFTER UPDATE ON files
+FOR EACH ROW WHEN OLD.path IS NOT NEW.path
+    OR OLD.name IS NOT NEW.name
+    OR OLD.directory_id IS NOT NEW.directory_id
+    OR OLD.size IS NOT NEW.size
+    OR OLD.file_type IS NOT NEW.file_type
+    OR OLD.created_at IS NOT NEW.created_at
+    OR OLD.modified_at IS NOT NEW.modified_at
+    OR OLD.birth_time IS NOT NEW.birth_time
+    OR OLD.inode IS NOT NEW.inode
+    OR OLD.is_directory IS NOT NEW.is_directory
+    OR OLD.file_size IS NOT NEW.file_size
+    OR OLD.mime_type IS NOT NEW.mime_type
+    OR OLD.permissions IS NOT NEW.permissions
+    OR OLD.owner_uid IS NOT NEW.owner_uid
+    OR OLD.group_gid IS NOT NEW.group_gid
+    OR OLD.hard_links IS NOT NEW.hard_links
+    OR OLD.device_id IS NOT NEW.device_id
+    OR OLD.metadata IS NOT NEW.metadata
+    OR OLD.content_hash IS NOT NEW.content_hash
+    OR OLD.is_symlink IS NOT NEW.is_symlink
+    OR OLD.symlink_target IS NOT NEW.symlink_target
+    OR OLD.is_broken_link IS NOT NEW.is_broken_link
+BEGIN
+    INSERT INTO computed_metadata_stale (file_id) VALUES (NEW.id)
+    ON CONFLICT(file_id) DO NOTHING;
+END;
+
+CREATE TRIGGER directories_computed_stale_rename
+AFTER UPDATE OF name, path ON directories
+FOR EACH ROW WHEN OLD.name IS NOT NEW.name OR OLD.path IS NOT NEW.path
+BEGIN
+    INSERT INTO computed_metadata_stale (file_id)
+    SELECT id FROM files WHERE directory_id = NEW.id
+    ON CONFLICT(file_id) DO NOTHING;
+END;
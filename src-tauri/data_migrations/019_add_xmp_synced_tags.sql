@@ -0,0 +1,10 @@
+-- Tag names last agreed between Clerica and a file's XMP sidecar keywords.
+-- A keyword removed from the sidecar removes the tag it imported, while a
+-- tag removed in Clerica is not copied back from an unchanged sidecar.
+
+CREATE TABLE xmp_synced_tags (
+    file_id TEXT NOT NULL,
+    tag_name TEXT NOT NULL,
+    PRIMARY KEY (file_id, tag_name),
+    FOREIGN KEY (file_id) REFERENCES files (id) ON DELETE CASCADE
+);
@@ -0,0 +1,35 @@
+-- Share tags across hard links in the database
+-- When the shelf setting 'share_tags_across_hard_links' is 'true', adding or
+-- removing a tag on a file does the same on every other regular file with the
+-- same device_id and inode, whichever code path wrote the row. The setting is
+-- copied from the global settings into each shelf so the triggers can read it.
+-- Recursive triggers are off, so the rows written here do not cascade again.
+
+CREATE TRIGGER file_tags_share_hard_links_insert
+AFTER INSERT ON file_tags
+WHEN (SELECT value FROM shelf_settings WHERE key = 'share_tags_across_hard_links') = 'true'
+BEGIN
+    INSERT OR IGNORE INTO file_tags (file_id, tag_id)
+    SELECT sibling.id, NEW.tag_id
+    FROM files target
+    INNER JOIN files sibling ON sibling.device_id IS target.device_id AND sibling.inode = target.inode
+    WHERE target.id = NEW.file_id AND sibling.id != target.id
+      AND target.is_directory = 0 AND target.is_symlink = 0
+      AND sibling.is_directory = 0 AND sibling.is_symlink = 0;
+END;
+
+CREATE TRIGGER file_tags_share_hard_links_delete
+AFTER DELETE ON file_tags
+WHEN (SELECT value FROM shelf_settings WHERE key = 'share_tags_across_hard_links') = 'true'
+BEGIN
+    DELETE FROM file_tags
+    WHERE tag_id = OLD.tag_id
+      AND file_id IN (
+          SELECT sibling.id
+          FROM files target
+          INNER JOIN files sibling ON sibling.device_id IS target.device_id AND sibling.inode = target.inode
+          WHERE target.id = OLD.file_id AND sibling.id != target.id
+            AND target.is_directory = 0 AND target.is_symlink = 0
+            AND sibling.is_directory = 0 AND sibling.is_symlink = 0
+      );
+END;
@@ -0,0 +1,3 @@
+-- Options for select / multi_select custom metadata keys (JSON array of strings)
+
+ALTER TABLE custom_metadata_keys ADD COLUMN options TEXT;
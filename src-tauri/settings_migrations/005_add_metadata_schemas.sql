@@ -0,0 +1,25 @@
+-- Custom metadata schemas
+-- A schema groups custom metadata keys and lists the files it applies to
+-- (by category, extension, tag or directory; stored as JSON, any condition matches).
+-- Keys that belong to no schema apply to every file; keys in schemas apply only to
+-- files matched by one of their schemas, and is_required is enforced only there.
+
+CREATE TABLE custom_metadata_schemas (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT,
+    conditions TEXT NOT NULL DEFAULT '[]',
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE custom_metadata_schema_keys (
+    schema_id TEXT NOT NULL,
+    key_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (schema_id, key_id),
+    FOREIGN KEY (schema_id) REFERENCES custom_metadata_schemas (id) ON DELETE CASCADE,
+    FOREIGN KEY (key_id) REFERENCES custom_metadata_keys (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_custom_metadata_schema_keys_key ON custom_metadata_schema_keys (key_id);
@@ -0,0 +1,6 @@
+-- Computed custom metadata keys
+-- A key with an expression (a Tera template) is computed from the file, its metadata JSON,
+-- tags and other custom metadata values. The results are cached in each shelf's
+-- custom_metadata_values so they can be searched and sorted like stored values.
+
+ALTER TABLE custom_metadata_keys ADD COLUMN expression TEXT;
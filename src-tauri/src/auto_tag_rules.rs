@@ -0,0 +1,724 @@
+use chrono::{DateTime, Utc};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use tauri::State;
+use uuid::Uuid;
+
+use crate::database::{file_from_row, Database, DatabaseTrait, File};
+use crate::file_manager::tags::normalize_tag_path;
+use crate::search::{find_matching_file_ids, SearchFilter};
+use crate::shelf_manager::ShelfManager;
+
+/// ルールで作成するタグの色
+const DEFAULT_RULE_TAG_COLOR: &str = "#3B82F6";
+
+/// ルールの条件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleCondition {
+    /// パスのglob（`**/invoices/*.pdf` など）
+    PathGlob { pattern: String },
+    /// ファイル名の正規表現
+    NameRegex { pattern: String },
+    /// 拡張子（ドットなし、大文字小文字は区別しない）
+    Extension { extensions: Vec<String> },
+    /// サイズの範囲（バイト、両端を含む）
+    SizeRange { min: Option<i64>, max: Option<i64> },
+    /// EXIF・音声メタデータの値（`exif.Make`、`audio.tags.artist` など）
+    MetadataField { field: String, operator: String, value: String },
+    /// カスタムメタデータの値
+    CustomMetadata { key_id: String, operator: String, value: String },
+}
+
+/// 条件の組み合わせ方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleMatchMode {
+    #[default]
+    All,
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleMetadataAction {
+    pub key_id: String,
+    pub value: String,
+}
+
+/// 条件に一致したときの動作
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuleActions {
+    /// 付けるタグ（`a/b/c` のような階層パスも可、なければ作成する）
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 設定するカスタムメタデータ（既に値がある場合は上書きしない）
+    #[serde(default)]
+    pub metadata: Vec<RuleMetadataAction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoTagRule {
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+    pub priority: i64,
+    pub match_mode: RuleMatchMode,
+    pub conditions: Vec<RuleCondition>,
+    pub actions: RuleActions,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AutoTagRuleRequest {
+    pub name: String,
+    pub enabled: Option<bool>,
+    pub priority: Option<i64>,
+    pub match_mode: Option<RuleMatchMode>,
+    pub conditions: Vec<RuleCondition>,
+    pub actions: RuleActions,
+}
+
+/// 1ファイルに対するルールの適用結果（ドライランでは適用予定の内容）
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleApplication {
+    pub file_id: String,
+    pub path: String,
+    pub rule_ids: Vec<String>,
+    /// 新たに付くタグ
+    pub tags: Vec<String>,
+    /// 新たに設定されるカスタムメタデータ
+    pub metadata: Vec<RuleMetadataAction>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AutoTagRunResult {
+    pub dry_run: bool,
+    pub files_checked: usize,
+    pub applications: Vec<RuleApplication>,
+}
+
+enum ConditionMatcher {
+    Path(GlobMatcher),
+    Name(Regex),
+    Extension(Vec<String>),
+    Size { min: Option<i64>, max: Option<i64> },
+    Field { path: Vec<String>, operator: String, value: String },
+    Custom { key_id: String, operator: String, value: String },
+}
+
+impl ConditionMatcher {
+    fn compile(condition: &RuleCondition) -> Result<Self, String> {
+        Ok(match condition {
+            RuleCondition::PathGlob { pattern } => {
+                let glob = GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| format!("無効なglobパターンです: {e}"))?;
+                ConditionMatcher::Path(glob.compile_matcher())
+            }
+            RuleCondition::NameRegex { pattern } => {
+                ConditionMatcher::Name(Regex::new(pattern).map_err(|e| format!("無効な正規表現です: {e}"))?)
+            }
+            RuleCondition::Extension { extensions } => ConditionMatcher::Extension(
+                extensions
+                    .iter()
+                    .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                    .collect(),
+            ),
+            RuleCondition::SizeRange { min, max } => ConditionMatcher::Size { min: *min, max: *max },
+            RuleCondition::MetadataField { field, operator, value } => ConditionMatcher::Field {
+                path: field.split('.').map(str::to_string).collect(),
+                operator: operator.clone(),
+                value: value.clone(),
+            },
+            RuleCondition::CustomMetadata { key_id, operator, value } => ConditionMatcher::Custom {
+                key_id: key_id.clone(),
+                operator: operator.clone(),
+                value: value.clone(),
+            },
+        })
+    }
+
+    fn is_match(&self, file: &File, metadata: Option<&serde_json::Value>, custom_values: &HashMap<String, String>) -> bool {
+        match self {
+            ConditionMatcher::Path(glob) => glob.is_match(&file.path),
+            ConditionMatcher::Name(regex) => regex.is_match(&file.name),
+            ConditionMatcher::Extension(extensions) => std::path::Path::new(&file.name)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| extensions.contains(&ext.to_lowercase())),
+            ConditionMatcher::Size { min, max } => {
+                min.map_or(true, |min| file.size >= min) && max.map_or(true, |max| file.size <= max)
+            }
+            ConditionMatcher::Field { path, operator, value } => {
+                let actual = metadata
+                    .and_then(|metadata| path.iter().try_fold(metadata, |current, key| current.get(key)))
+                    .and_then(json_value_to_string);
+                actual.is_some_and(|actual| compare_value(&actual, operator, value))
+            }
+            ConditionMatcher::Custom { key_id, operator, value } => custom_values
+                .get(key_id)
+                .is_some_and(|actual| compare_value(actual, operator, value)),
+        }
+    }
+}
+
+/// メタデータの値を比較用の文字列にする（配列は先頭の要素を使う）
+fn json_value_to_string(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Null => None,
+        serde_json::Value::Array(values) => values.first().and_then(json_value_to_string),
+        other => Some(other.to_string()),
+    }
+}
+
+/// 検索のカスタムメタデータフィルタと同じ演算子で比較する
+fn compare_value(actual: &str, operator: &str, expected: &str) -> bool {
+    match operator {
+        "contains" => actual.to_lowercase().contains(&expected.to_lowercase()),
+        "not_equals" => actual != expected,
+        "greater_than" | "less_than" => match (actual.trim().parse::<f64>(), expected.trim().parse::<f64>()) {
+            (Ok(actual), Ok(expected)) if operator == "greater_than" => actual > expected,
+            (Ok(actual), Ok(expected)) => actual < expected,
+            _ => false,
+        },
+        _ => actual == expected,
+    }
+}
+
+struct CompiledRule {
+    rule: AutoTagRule,
+    matchers: Vec<ConditionMatcher>,
+}
+
+impl CompiledRule {
+    fn compile(rule: AutoTagRule) -> Result<Self, String> {
+        let matchers = rule
+            .conditions
+            .iter()
+            .map(ConditionMatcher::compile)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { rule, matchers })
+    }
+
+    fn is_match(&self, file: &File, metadata: Option<&serde_json::Value>, custom_values: &HashMap<String, String>) -> bool {
+        let mut results = self.matchers.iter().map(|m| m.is_match(file, metadata, custom_values));
+        match self.rule.match_mode {
+            RuleMatchMode::All => results.all(|matched| matched),
+            RuleMatchMode::Any => results.any(|matched| matched),
+        }
+    }
+}
+
+/// 有効なルールをまとめて評価する
+pub struct AutoTagRuleEngine {
+    rules: Vec<CompiledRule>,
+}
+
+/// シェルフごとのコンパイル済みルール。ルールを変更すると世代が進み、古い読み込み結果は捨てる
+#[derive(Default)]
+struct EngineCache {
+    generation: u64,
+    engines: HashMap<String, Arc<AutoTagRuleEngine>>,
+}
+
+static ENGINE_CACHE: OnceLock<Mutex<EngineCache>> = OnceLock::new();
+
+fn engine_cache() -> &'static Mutex<EngineCache> {
+    ENGINE_CACHE.get_or_init(Default::default)
+}
+
+/// ルールを変更したシェルフのコンパイル済みルールを破棄する
+fn invalidate_cached_rules(shelf_id: &str) {
+    let mut cache = engine_cache().lock().unwrap();
+    cache.generation += 1;
+    cache.engines.remove(shelf_id);
+}
+
+impl AutoTagRuleEngine {
+    /// アクティブなシェルフの有効なルールを返す（監視イベントごとに読み込み直さないようキャッシュする）
+    pub async fn cached(pools: &ShelfManager) -> Result<Arc<Self>, String> {
+        let shelf_id = pools.get_active_shelf_id_sync();
+        let generation = {
+            let cache = engine_cache().lock().unwrap();
+            if let Some(engine) = cache.engines.get(&shelf_id) {
+                return Ok(engine.clone());
+            }
+            cache.generation
+        };
+
+        let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+        let engine = Arc::new(Self::load(&data_pool).await?);
+        let mut cache = engine_cache().lock().unwrap();
+        // 読み込み中にルールが変更された場合はキャッシュしない
+        if cache.generation == generation {
+            cache.engines.insert(shelf_id, engine.clone());
+        }
+        Ok(engine)
+    }
+
+    /// 有効なルールを優先度順に読み込む（不正なルールはスキップする）
+    async fn load(pool: &SqlitePool) -> Result<Self, String> {
+        let rules = get_rules_from_db(pool)
+            .await?
+            .into_iter()
+            .filter(|rule| rule.enabled)
+            .filter_map(|rule| match CompiledRule::compile(rule) {
+                Ok(compiled) => Some(compiled),
+                Err(e) => {
+                    eprintln!("自動タグルールの読み込みエラー: {e}");
+                    None
+                }
+            })
+            .collect();
+        Ok(Self { rules })
+    }
+
+    fn from_rules(rules: Vec<AutoTagRule>) -> Result<Self, String> {
+        let rules = rules
+            .into_iter()
+            .map(CompiledRule::compile)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    fn matching_rules(&self, file: &File, custom_values: &HashMap<String, String>) -> Vec<&AutoTagRule> {
+        let metadata: Option<serde_json::Value> = file
+            .metadata
+            .as_deref()
+            .and_then(|m| serde_json::from_str(m).ok());
+        self.rules
+            .iter()
+            .filter(|rule| rule.is_match(file, metadata.as_ref(), custom_values))
+            .map(|rule| &rule.rule)
+            .collect()
+    }
+
+    /// ファイルにルールを適用する。一致するルールがなければ None
+    /// 既に付いているタグや、既に値があるカスタムメタデータは変更しない
+    pub async fn apply(&self, pools: &ShelfManager, file: &File, dry_run: bool) -> Result<Option<RuleApplication>, String> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let db = Database;
+        let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+
+        let custom_values: HashMap<String, String> = db
+            .get_custom_metadata_values_by_file(&data_pool, &file.id)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .filter_map(|v| v.value.map(|value| (v.key_id, value)))
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+
+        let rules = self.matching_rules(file, &custom_values);
+        if rules.is_empty() {
+            return Ok(None);
+        }
+
+        let current_tags: Vec<String> = db.get_file_tags(&data_pool, &file.id)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|tag| tag.name)
+            .collect();
+
+        let mut application = RuleApplication {
+            file_id: file.id.clone(),
+            path: file.path.clone(),
+            rule_ids: rules.iter().map(|rule| rule.id.clone()).collect(),
+            tags: Vec::new(),
+            metadata: Vec::new(),
+        };
+        for rule in &rules {
+            for tag in &rule.actions.tags {
+                if !current_tags.contains(tag) && !application.tags.contains(tag) {
+                    application.tags.push(tag.clone());
+                }
+            }
+            for action in &rule.actions.metadata {
+                // 優先度の高いルールの値を使う
+                let already_set = custom_values.contains_key(&action.key_id)
+                    || application.metadata.iter().any(|m| m.key_id == action.key_id);
+                if !already_set {
+                    application.metadata.push(action.clone());
+                }
+            }
+        }
+
+        if !dry_run {
+            for tag_path in &application.tags {
+                let tag = db.ensure_tag_path(&data_pool, tag_path, DEFAULT_RULE_TAG_COLOR)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                db.add_file_tag(&data_pool, &file.id, &tag.id)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            for action in &application.metadata {
+                db.set_custom_metadata_value(&data_pool, pools.get_settings_pool(), &file.id, &action.key_id, Some(action.value.clone()))
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        Ok(Some(application))
+    }
+}
+
+/// データベースからルールを取得
+async fn get_rules_from_db(pool: &SqlitePool) -> Result<Vec<AutoTagRule>, String> {
+    let rows = sqlx::query("SELECT * FROM auto_tag_rules ORDER BY priority DESC, created_at")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("自動タグルールの取得に失敗しました: {e}"))?;
+
+    rows.iter().map(rule_from_row).collect()
+}
+
+fn rule_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<AutoTagRule, String> {
+    let match_mode: String = row.get("match_mode");
+    let conditions: String = row.get("conditions");
+    let actions: String = row.get("actions");
+
+    Ok(AutoTagRule {
+        id: row.get("id"),
+        name: row.get("name"),
+        enabled: row.get("enabled"),
+        priority: row.get("priority"),
+        match_mode: if match_mode == "any" { RuleMatchMode::Any } else { RuleMatchMode::All },
+        conditions: serde_json::from_str(&conditions).map_err(|e| e.to_string())?,
+        actions: serde_json::from_str(&actions).map_err(|e| e.to_string())?,
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    })
+}
+
+/// ルールの内容が有効かチェックし、タグパスを正規化する
+fn validate_rule(request: &mut AutoTagRuleRequest) -> Result<(), String> {
+    if request.name.trim().is_empty() {
+        return Err("ルール名が空です".to_string());
+    }
+    if request.conditions.is_empty() {
+        return Err("条件を1つ以上指定してください".to_string());
+    }
+    if request.actions.tags.is_empty() && request.actions.metadata.is_empty() {
+        return Err("タグかカスタムメタデータを1つ以上指定してください".to_string());
+    }
+    for condition in &request.conditions {
+        ConditionMatcher::compile(condition)?;
+    }
+    for tag in &mut request.actions.tags {
+        *tag = normalize_tag_path(tag)?;
+    }
+    Ok(())
+}
+
+/// 設定するカスタムメタデータの値をキーの型に合わせて検証・正規化する
+async fn validate_rule_metadata(settings_pool: &SqlitePool, request: &mut AutoTagRuleRequest) -> Result<(), String> {
+    let keys = Database.get_all_custom_metadata_keys(settings_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    for action in &mut request.actions.metadata {
+        let key = keys.iter()
+            .find(|k| k.id == action.key_id)
+            .ok_or_else(|| format!("カスタムメタデータキーが見つかりません: {}", action.key_id))?;
+        if key.expression.is_some() {
+            return Err(format!("'{}' は計算フィールドのため値を設定できません", key.display_name));
+        }
+        action.value = key.normalize_value(Some(&action.value))?
+            .ok_or_else(|| format!("'{}' に設定する値が空です", key.display_name))?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_auto_tag_rules(
+    shelf_manager: State<'_, ShelfManager>,
+) -> Result<Vec<AutoTagRule>, String> {
+    let pool = shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?;
+    get_rules_from_db(&pool).await
+}
+
+#[tauri::command]
+pub async fn create_auto_tag_rule(
+    shelf_manager: State<'_, ShelfManager>,
+    mut request: AutoTagRuleRequest,
+) -> Result<AutoTagRule, String> {
+    validate_rule(&mut request)?;
+    validate_rule_metadata(shelf_manager.get_settings_pool(), &mut request).await?;
+
+    let now = Utc::now();
+    let rule = AutoTagRule {
+        id: Uuid::new_v4().to_string(),
+        name: request.name.trim().to_string(),
+        enabled: request.enabled.unwrap_or(true),
+        priority: request.priority.unwrap_or(0),
+        match_mode: request.match_mode.unwrap_or_default(),
+        conditions: request.conditions,
+        actions: request.actions,
+        created_at: now,
+        updated_at: now,
+    };
+
+    let pool = shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?;
+    sqlx::query(
+        "INSERT INTO auto_tag_rules (id, name, enabled, priority, match_mode, conditions, actions, created_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&rule.id)
+    .bind(&rule.name)
+    .bind(rule.enabled)
+    .bind(rule.priority)
+    .bind(if rule.match_mode == RuleMatchMode::Any { "any" } else { "all" })
+    .bind(serde_json::to_string(&rule.conditions).map_err(|e| e.to_string())?)
+    .bind(serde_json::to_string(&rule.actions).map_err(|e| e.to_string())?)
+    .bind(rule.created_at)
+    .bind(rule.updated_at)
+    .execute(&pool)
+    .await
+    .map_err(|e| format!("自動タグルールの追加に失敗しました: {e}"))?;
+    invalidate_cached_rules(&shelf_manager.get_active_shelf_id_sync());
+
+    Ok(rule)
+}
+
+#[tauri::command]
+pub async fn update_auto_tag_rule(
+    shelf_manager: State<'_, ShelfManager>,
+    id: String,
+    mut request: AutoTagRuleRequest,
+) -> Result<(), String> {
+    validate_rule(&mut request)?;
+    validate_rule_metadata(shelf_manager.get_settings_pool(), &mut request).await?;
+
+    let pool = shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?;
+    let result = sqlx::query(
+        "UPDATE auto_tag_rules
+         SET name = ?, enabled = ?, priority = ?, match_mode = ?, conditions = ?, actions = ?, updated_at = ?
+         WHERE id = ?",
+    )
+    .bind(request.name.trim())
+    .bind(request.enabled.unwrap_or(true))
+    .bind(request.priority.unwrap_or(0))
+    .bind(if request.match_mode == Some(RuleMatchMode::Any) { "any" } else { "all" })
+    .bind(serde_json::to_string(&request.conditions).map_err(|e| e.to_string())?)
+    .bind(serde_json::to_string(&request.actions).map_err(|e| e.to_string())?)
+    .bind(Utc::now())
+    .bind(&id)
+    .execute(&pool)
+    .await
+    .map_err(|e| format!("自動タグルールの更新に失敗しました: {e}"))?;
+    invalidate_cached_rules(&shelf_manager.get_active_shelf_id_sync());
+
+    if result.rows_affected() == 0 {
+        return Err("指定されたルールが見つかりません".to_string());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_auto_tag_rule(
+    shelf_manager: State<'_, ShelfManager>,
+    id: String,
+) -> Result<(), String> {
+    let pool = shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?;
+    let result = sqlx::query("DELETE FROM auto_tag_rules WHERE id = ?")
+        .bind(&id)
+        .execute(&pool)
+        .await
+        .map_err(|e| format!("自動タグルールの削除に失敗しました: {e}"))?;
+    invalidate_cached_rules(&shelf_manager.get_active_shelf_id_sync());
+
+    if result.rows_affected() == 0 {
+        return Err("指定されたルールが見つかりません".to_string());
+    }
+    Ok(())
+}
+
+/// 既存のファイルにルールを適用する
+/// 対象を指定しなければ全ファイル、`rule_ids` を指定しなければ有効なすべてのルールを使う。
+/// `dry_run` の場合は変更せずに適用予定の内容だけを返す
+#[tauri::command]
+pub async fn run_auto_tag_rules(
+    shelf_manager: State<'_, ShelfManager>,
+    rule_ids: Option<Vec<String>>,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+    dry_run: bool,
+) -> Result<AutoTagRunResult, String> {
+    let pool = shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?;
+
+    let engine = match rule_ids {
+        Some(rule_ids) => {
+            let rules = get_rules_from_db(&pool)
+                .await?
+                .into_iter()
+                .filter(|rule| rule_ids.contains(&rule.id))
+                .collect();
+            Arc::new(AutoTagRuleEngine::from_rules(rules)?)
+        }
+        None => AutoTagRuleEngine::cached(&shelf_manager).await?,
+    };
+
+    let file_ids = match (file_ids, filter) {
+        (Some(file_ids), _) => file_ids,
+        (None, Some(filter)) => find_matching_file_ids(&shelf_manager, filter).await?,
+        (None, None) => sqlx::query_scalar("SELECT id FROM files ORDER BY path")
+            .fetch_all(&pool)
+            .await
+            .map_err(|e| e.to_string())?,
+    };
+
+    let mut applications = Vec::new();
+    for file_id in &file_ids {
+        let row = sqlx::query("SELECT * FROM files WHERE id = ?")
+            .bind(file_id)
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        let Some(row) = row else {
+            continue;
+        };
+        if let Some(application) = engine.apply(&shelf_manager, &file_from_row(&row), dry_run).await? {
+            if !application.tags.is_empty() || !application.metadata.is_empty() {
+                applications.push(application);
+            }
+        }
+    }
+
+    Ok(AutoTagRunResult {
+        dry_run,
+        files_checked: file_ids.len(),
+        applications,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_file;
+
+    fn test_rule(id: &str, match_mode: RuleMatchMode, conditions: Vec<RuleCondition>) -> AutoTagRule {
+        AutoTagRule {
+            id: id.to_string(),
+            name: id.to_string(),
+            enabled: true,
+            priority: 0,
+            match_mode,
+            conditions,
+            actions: RuleActions {
+                tags: vec!["auto".to_string()],
+                metadata: Vec::new(),
+            },
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn matched_ids(engine: &AutoTagRuleEngine, file: &File, custom_values: &HashMap<String, String>) -> Vec<String> {
+        engine
+            .matching_rules(file, custom_values)
+            .into_iter()
+            .map(|rule| rule.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_path_name_extension_and_size_conditions() {
+        let engine = AutoTagRuleEngine::from_rules(vec![
+            test_rule("glob", RuleMatchMode::All, vec![RuleCondition::PathGlob {
+                pattern: "/docs/**/invoices/*.pdf".to_string(),
+            }]),
+            test_rule("shallow", RuleMatchMode::All, vec![RuleCondition::PathGlob {
+                pattern: "/docs/*.pdf".to_string(),
+            }]),
+            test_rule("name", RuleMatchMode::All, vec![RuleCondition::NameRegex {
+                pattern: r"^INV-\d+".to_string(),
+            }]),
+            test_rule("ext_and_size", RuleMatchMode::All, vec![
+                RuleCondition::Extension { extensions: vec![".PDF".to_string()] },
+                RuleCondition::SizeRange { min: Some(1024), max: Some(4096) },
+            ]),
+            test_rule("too_large", RuleMatchMode::All, vec![
+                RuleCondition::Extension { extensions: vec!["pdf".to_string()] },
+                RuleCondition::SizeRange { min: Some(4097), max: None },
+            ]),
+            test_rule("any", RuleMatchMode::Any, vec![
+                RuleCondition::Extension { extensions: vec!["jpg".to_string()] },
+                RuleCondition::SizeRange { min: None, max: Some(2048) },
+            ]),
+        ])
+        .unwrap();
+
+        let file = test_file("f1", "/docs/2024/invoices/INV-001.pdf", "d1");
+        assert_eq!(
+            matched_ids(&engine, &file, &HashMap::new()),
+            vec!["glob", "name", "ext_and_size", "any"]
+        );
+    }
+
+    #[test]
+    fn test_metadata_conditions() {
+        let engine = AutoTagRuleEngine::from_rules(vec![
+            test_rule("camera", RuleMatchMode::All, vec![RuleCondition::MetadataField {
+                field: "exif.Make".to_string(),
+                operator: "contains".to_string(),
+                value: "canon".to_string(),
+            }]),
+            test_rule("artist", RuleMatchMode::All, vec![RuleCondition::MetadataField {
+                field: "audio.tags.artist".to_string(),
+                operator: "equals".to_string(),
+                value: "Someone".to_string(),
+            }]),
+            test_rule("rating", RuleMatchMode::All, vec![RuleCondition::CustomMetadata {
+                key_id: "rating".to_string(),
+                operator: "greater_than".to_string(),
+                value: "3".to_string(),
+            }]),
+        ])
+        .unwrap();
+
+        let mut file = test_file("f1", "/photos/a.jpg", "d1");
+        file.metadata = Some(r#"{"exif":{"Make":"Canon Inc."}}"#.to_string());
+        let custom_values = HashMap::from([("rating".to_string(), "4".to_string())]);
+        assert_eq!(matched_ids(&engine, &file, &custom_values), vec!["camera", "rating"]);
+
+        // メタデータがなければ一致しない
+        file.metadata = None;
+        assert!(matched_ids(&engine, &file, &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_rule_is_rejected() {
+        let mut request = AutoTagRuleRequest {
+            name: "broken".to_string(),
+            enabled: None,
+            priority: None,
+            match_mode: None,
+            conditions: vec![RuleCondition::NameRegex { pattern: "(".to_string() }],
+            actions: RuleActions {
+                tags: vec!["a".to_string()],
+                metadata: Vec::new(),
+            },
+        };
+        assert!(validate_rule(&mut request).is_err());
+
+        request.conditions = vec![RuleCondition::PathGlob { pattern: "**/*.txt".to_string() }];
+        request.actions.tags = vec![" work / / report ".to_string()];
+        validate_rule(&mut request).unwrap();
+        assert_eq!(request.actions.tags, vec!["work/report"]);
+    }
+}
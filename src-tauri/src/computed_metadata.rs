@@ -0,0 +1,352 @@
+use chrono::Local;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use tera::Tera;
+
+use crate::database::{CustomMetadataKey, Database, DatabaseTrait, Directory, File};
+use crate::file_manager::files::create_template_context;
+use crate::file_manager::template_filters::template_engine;
+use crate::settings;
+use crate::ShelfManager;
+
+/// 最後にすべてのファイルを計算し直した日（today や days_until を使う式は日付が変わると結果が変わる）
+const COMPUTED_REFRESHED_ON_KEY: &str = "computed_metadata_refreshed_on";
+
+/// 式をTeraテンプレートにする（`{{` や `{%` を含まない式は `{{ }}` で囲む）
+pub fn expression_template(expression: &str) -> String {
+    let expression = expression.trim();
+    if expression.contains("{{") || expression.contains("{%") {
+        expression.to_string()
+    } else {
+        format!("{{{{ {expression} }}}}")
+    }
+}
+
+/// Teraのエラーは原因をたどらないと内容がわからないため、まとめて1つのメッセージにする
+fn tera_error_message(error: &tera::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// 計算フィールドの式をまとめてコンパイルしたもの
+pub struct ComputedFieldEngine {
+    tera: Tera,
+    /// 式のあるキー（名前順）
+    keys: Vec<CustomMetadataKey>,
+}
+
+impl ComputedFieldEngine {
+    pub fn from_keys(keys: &[CustomMetadataKey]) -> Result<Self, String> {
+        let mut tera = template_engine();
+
+        let mut computed_keys: Vec<CustomMetadataKey> =
+            keys.iter().filter(|key| key.expression.is_some()).cloned().collect();
+        computed_keys.sort_by(|a, b| a.name.cmp(&b.name));
+        for key in &computed_keys {
+            let template = expression_template(key.expression.as_deref().unwrap_or_default());
+            tera.add_raw_template(&key.id, &template)
+                .map_err(|e| format!("'{}' の式が不正です: {}", key.display_name, tera_error_message(&e)))?;
+        }
+        Ok(Self { tera, keys: computed_keys })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// ファイルの計算フィールドを名前順に計算し、(キーID, 正規化した値) を返す
+    /// custom_metadata には結果を書き足すので、後のフィールドの式から前のフィールドの値を参照できる
+    pub fn compute(
+        &self,
+        file: &File,
+        root: Option<&Directory>,
+        tags: &[String],
+        custom_metadata: &mut serde_json::Map<String, serde_json::Value>,
+    ) -> Vec<(String, Result<Option<String>, String>)> {
+        let metadata: serde_json::Value = file
+            .metadata
+            .as_deref()
+            .and_then(|metadata| serde_json::from_str(metadata).ok())
+            .unwrap_or(serde_json::json!({}));
+
+        let mut results = Vec::new();
+        for key in &self.keys {
+            let custom_value = serde_json::Value::Object(custom_metadata.clone());
+            let mut context = create_template_context(file, root, tags, &metadata, &custom_value);
+            context.insert("today", &Local::now().date_naive().format("%Y-%m-%d").to_string());
+
+            let result = self
+                .tera
+                .render(&key.id, &context)
+                .map_err(|e| format!("'{}' の計算に失敗しました: {}", key.display_name, tera_error_message(&e)))
+                .and_then(|output| key.normalize_value(Some(output.trim())));
+            match &result {
+                Ok(Some(value)) => {
+                    custom_metadata.insert(key.name.clone(), key.typed_value(value));
+                }
+                _ => {
+                    custom_metadata.remove(&key.name);
+                }
+            }
+            results.push((key.id.clone(), result));
+        }
+        results
+    }
+}
+
+/// 計算できなかった計算フィールドの値
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ComputedFieldError {
+    pub file_id: String,
+    pub path: String,
+    pub key_id: String,
+    pub message: String,
+}
+
+/// 計算フィールドを計算し直した結果
+#[derive(Debug, Default, Serialize)]
+pub struct ComputedRefreshResult {
+    /// 変わった値の数
+    pub changed: u64,
+    pub errors: Vec<ComputedFieldError>,
+}
+
+/// 入力が変わったファイルの計算フィールドを計算し直す
+/// 計算できなかった値は消して結果の errors で返す（式の誤りで他のファイルの計算を止めないため）
+pub async fn refresh_computed_metadata(
+    data_pool: &SqlitePool,
+    settings_pool: &SqlitePool,
+) -> Result<ComputedRefreshResult, String> {
+    let db = Database;
+    let keys = db.get_all_custom_metadata_keys(settings_pool)
+        .await
+        .map_err(|e| format!("カスタムメタデータキーの取得に失敗しました: {e}"))?;
+    let engine = ComputedFieldEngine::from_keys(&keys)?;
+
+    if !engine.is_empty() {
+        let today = Local::now().date_naive().format("%Y-%m-%d").to_string();
+        if settings::get_shelf_setting(data_pool, COMPUTED_REFRESHED_ON_KEY).await?.as_deref() != Some(today.as_str()) {
+            db.mark_computed_metadata_stale(data_pool, None)
+                .await
+                .map_err(|e| format!("計算フィールドの更新に失敗しました: {e}"))?;
+            settings::set_shelf_setting(data_pool, COMPUTED_REFRESHED_ON_KEY, &today).await?;
+        }
+    }
+
+    let stale_ids = db.get_stale_computed_file_ids(data_pool)
+        .await
+        .map_err(|e| format!("計算フィールドの更新に失敗しました: {e}"))?;
+    let keys_by_id: HashMap<&str, &CustomMetadataKey> = keys.iter().map(|key| (key.id.as_str(), key)).collect();
+
+    let roots: HashMap<String, Directory> = db.get_directories(data_pool)
+        .await
+        .map_err(|e| format!("ディレクトリの取得に失敗しました: {e}"))?
+        .into_iter()
+        .map(|directory| (directory.id.clone(), directory))
+        .collect();
+
+    let mut result = ComputedRefreshResult::default();
+    for chunk in stale_ids.chunks(500) {
+        let mut values = Vec::new();
+        if !engine.is_empty() {
+            let files = db.get_files_by_ids(data_pool, chunk)
+                .await
+                .map_err(|e| format!("ファイルの取得に失敗しました: {e}"))?;
+            let mut file_tags: HashMap<String, Vec<String>> = HashMap::new();
+            for (file_id, tag) in db.get_file_tags_by_files(data_pool, chunk)
+                .await
+                .map_err(|e| format!("タグの取得に失敗しました: {e}"))?
+            {
+                file_tags.entry(file_id).or_default().push(tag.name);
+            }
+            let mut custom_values: HashMap<String, serde_json::Map<String, serde_json::Value>> = HashMap::new();
+            for value in db.get_custom_metadata_values_by_files(data_pool, chunk)
+                .await
+                .map_err(|e| format!("カスタムメタデータ値の取得に失敗しました: {e}"))?
+            {
+                if let (Some(key), Some(v)) = (keys_by_id.get(value.key_id.as_str()), value.value) {
+                    custom_values.entry(value.file_id).or_default().insert(key.name.clone(), key.typed_value(&v));
+                }
+            }
+
+            for file in &files {
+                let tags = file_tags.remove(&file.id).unwrap_or_default();
+                let mut custom_metadata = custom_values.remove(&file.id).unwrap_or_default();
+                for (key_id, computed) in engine.compute(file, roots.get(&file.directory_id), &tags, &mut custom_metadata) {
+                    let value = computed.unwrap_or_else(|message| {
+                        result.errors.push(ComputedFieldError {
+                            file_id: file.id.clone(),
+                            path: file.path.clone(),
+                            key_id: key_id.clone(),
+                            message,
+                        });
+                        None
+                    });
+                    values.push((file.id.clone(), key_id, value));
+                }
+            }
+        }
+        result.changed += db.store_computed_metadata_values(data_pool, &keys, chunk, &values)
+            .await
+            .map_err(|e| format!("計算フィールドの更新に失敗しました: {e}"))?;
+    }
+    Ok(result)
+}
+
+/// アクティブなシェルフの計算フィールドを計算し直す（値を読む前に呼ぶ）
+pub async fn refresh_active_shelf(shelf_manager: &ShelfManager) -> Result<(), String> {
+    let data_pool = shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?;
+    refresh_computed_metadata(&data_pool, shelf_manager.get_settings_pool()).await?;
+    Ok(())
+}
+
+/// すべてのシェルフで計算フィールドを計算し直す対象にする（式を作成・変更したとき）
+pub async fn invalidate_all_shelves(shelf_manager: &ShelfManager) -> Result<(), String> {
+    let db = Database;
+    for (shelf, data_pool) in shelf_manager.get_all_data_pools().await.map_err(|e| e.to_string())? {
+        db.mark_computed_metadata_stale(&data_pool, None)
+            .await
+            .map_err(|e| format!("計算フィールドの更新に失敗しました ({}): {e}", shelf.name))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::setup_test_db;
+    use crate::test_support::{computed_metadata_key, test_file};
+    use crate::database::MetadataError;
+    use chrono::Utc;
+
+    #[test]
+    fn test_expression_template() {
+        assert_eq!(expression_template(" file.size / 1024 "), "{{ file.size / 1024 }}");
+        assert_eq!(expression_template("{{ filename }}.bak"), "{{ filename }}.bak");
+        assert_eq!(expression_template("{% if tags %}yes{% endif %}"), "{% if tags %}yes{% endif %}");
+    }
+
+    #[test]
+    fn test_compute_fields() {
+        let due = (Local::now().date_naive() + chrono::Duration::days(10)).format("%Y-%m-%d").to_string();
+        let engine = ComputedFieldEngine::from_keys(&[
+            computed_metadata_key("shot_year", "number", "metadata.exif.DateTimeOriginal | year"),
+            computed_metadata_key("days_left", "number", "due | days_until"),
+            // 名前順で後のキーは前のキーの結果を使える
+            computed_metadata_key("summary", "text", "{{ shot_year }}-{{ tags | join(sep='+') }}"),
+            computed_metadata_key("broken", "number", "filename"),
+        ])
+        .unwrap();
+
+        let mut file = test_file("f1", "/photos/a.jpg", "d1");
+        file.metadata = Some(r#"{"exif":{"DateTimeOriginal":"2021:07:04 10:00:00"}}"#.to_string());
+        let mut custom_metadata = serde_json::Map::from_iter([("due".to_string(), serde_json::Value::String(due))]);
+        let results: HashMap<String, Result<Option<String>, String>> = engine
+            .compute(&file, None, &["a".to_string(), "b".to_string()], &mut custom_metadata)
+            .into_iter()
+            .collect();
+
+        assert_eq!(results["shot_year"], Ok(Some("2021".to_string())));
+        assert_eq!(results["days_left"], Ok(Some("10".to_string())));
+        assert_eq!(results["summary"], Ok(Some("2021-a+b".to_string())));
+        // 型に合わない結果はエラーになり、値は残らない
+        assert!(results["broken"].is_err());
+        assert!(!custom_metadata.contains_key("broken"));
+    }
+
+    #[test]
+    fn test_invalid_expression_is_rejected() {
+        assert!(ComputedFieldEngine::from_keys(&[computed_metadata_key("bad", "text", "{{ file.name ")]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_computed_metadata() {
+        let pool = setup_test_db().await;
+        let settings_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./settings_migrations").run(&settings_pool).await.unwrap();
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/docs", "docs").await.unwrap();
+        db.add_file(&pool, &test_file("a", "/docs/a.txt", &dir.id)).await.unwrap();
+        let rating = db
+            .create_custom_metadata_key(&settings_pool, &CustomMetadataKey { expression: None, ..computed_metadata_key("rating", "number", "") })
+            .await
+            .unwrap();
+        let stars = db
+            .create_custom_metadata_key(
+                &settings_pool,
+                &computed_metadata_key("stars", "text", "{% if rating %}{% for i in range(end=rating | int) %}*{% endfor %}{% endif %}"),
+            )
+            .await
+            .unwrap();
+        let label = db
+            .create_custom_metadata_key(&settings_pool, &computed_metadata_key("label", "text", "{{ filename }}:{{ tags | join(sep=',') }}"))
+            .await
+            .unwrap();
+        let value = |key_id: String| {
+            let pool = pool.clone();
+            async move {
+                Database.get_custom_metadata_value(&pool, "a", &key_id).await.unwrap().and_then(|v| v.value)
+            }
+        };
+
+        db.set_custom_metadata_value(&pool, &settings_pool, "a", &rating.id, Some("3".to_string())).await.unwrap();
+        refresh_computed_metadata(&pool, &settings_pool).await.unwrap();
+        assert_eq!(value(stars.id.clone()).await.as_deref(), Some("***"));
+        assert_eq!(value(label.id.clone()).await.as_deref(), Some("a.txt:"));
+        // 入力が変わらなければ計算し直さない
+        assert_eq!(refresh_computed_metadata(&pool, &settings_pool).await.unwrap().changed, 0);
+        // 計算し直した値は変更履歴に残さない
+        assert!(db.get_annotation_history(&pool, "a", Some(label.id.clone()), None).await.unwrap().is_empty());
+        // アクセス日時だけの変更では計算し直さない
+        sqlx::query("UPDATE files SET last_accessed = ?, updated_at_db = ? WHERE id = 'a'")
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(db.get_stale_computed_file_ids(&pool).await.unwrap().is_empty());
+
+        // タグや他のキーの値が変わると計算し直す
+        let tag = db.create_tag(&pool, "draft", "#ff0000").await.unwrap();
+        db.add_file_tag(&pool, "a", &tag.id).await.unwrap();
+        db.delete_custom_metadata_value(&pool, "a", &rating.id).await.unwrap();
+        assert_eq!(refresh_computed_metadata(&pool, &settings_pool).await.unwrap().changed, 2);
+        assert_eq!(value(label.id.clone()).await.as_deref(), Some("a.txt:draft"));
+        assert_eq!(value(stars.id.clone()).await, None);
+
+        // ルートの名前が変わると計算し直す
+        let root = db
+            .create_custom_metadata_key(&settings_pool, &computed_metadata_key("root", "text", "{{ root_name }}"))
+            .await
+            .unwrap();
+        db.mark_computed_metadata_stale(&pool, None).await.unwrap();
+        refresh_computed_metadata(&pool, &settings_pool).await.unwrap();
+        assert_eq!(value(root.id.clone()).await.as_deref(), Some("docs"));
+        db.relocate_directory(&pool, &dir.id, "/docs", "papers").await.unwrap();
+        refresh_computed_metadata(&pool, &settings_pool).await.unwrap();
+        assert_eq!(value(root.id.clone()).await.as_deref(), Some("papers"));
+
+        // 計算できなかった値は消して呼び出し元に返す
+        let broken = db
+            .create_custom_metadata_key(&settings_pool, &computed_metadata_key("broken", "number", "{{ filename }}"))
+            .await
+            .unwrap();
+        db.mark_computed_metadata_stale(&pool, None).await.unwrap();
+        let refreshed = refresh_computed_metadata(&pool, &settings_pool).await.unwrap();
+        assert_eq!(refreshed.errors.len(), 1);
+        assert_eq!((refreshed.errors[0].file_id.as_str(), refreshed.errors[0].key_id.as_str()), ("a", broken.id.as_str()));
+        assert_eq!(value(broken.id.clone()).await, None);
+
+        // 計算フィールドには値を設定できない
+        let err = db.set_custom_metadata_value(&pool, &settings_pool, "a", &label.id, Some("x".to_string())).await;
+        assert!(matches!(err, Err(MetadataError::Invalid(_))));
+    }
+}
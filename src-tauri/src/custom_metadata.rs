@@ -2,7 +2,13 @@ use tauri::State;
 use uuid::Uuid;
 use chrono::Utc;
 
-use crate::database::{Database, DatabaseTrait, CustomMetadataKey, CustomMetadataValue};
+use crate::computed_metadata::{self, ComputedFieldEngine};
+use crate::database::{
+    AnnotationHistoryEntry, BulkMetadataResult, CustomMetadataKey, CustomMetadataSchema, CustomMetadataValue,
+    CustomMetadataViolation, Database, DatabaseTrait, MetadataError, OrphanedMetadataValues, SchemaCondition, CUSTOM_METADATA_DATA_TYPES,
+};
+use crate::file_manager::xmp_sidecar;
+use crate::search::{resolve_bulk_targets, SearchFilter};
 use crate::ShelfManager;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -14,6 +20,11 @@ pub struct CreateCustomMetadataKeyRequest {
     pub is_required: bool,
     pub default_value: Option<String>,
     pub validation_pattern: Option<String>,
+    #[serde(default)]
+    pub options: Option<Vec<String>>,
+    /// 計算フィールドの式（Teraの式またはテンプレート）
+    #[serde(default)]
+    pub expression: Option<String>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -25,6 +36,20 @@ pub struct UpdateCustomMetadataKeyRequest {
     pub is_required: bool,
     pub default_value: Option<String>,
     pub validation_pattern: Option<String>,
+    #[serde(default)]
+    pub options: Option<Vec<String>>,
+    /// 計算フィールドの式（Teraの式またはテンプレート）
+    #[serde(default)]
+    pub expression: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct CustomMetadataSchemaRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub key_ids: Vec<String>,
+    #[serde(default)]
+    pub conditions: Vec<SchemaCondition>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -41,11 +66,10 @@ pub async fn create_custom_metadata_key(
     request: CreateCustomMetadataKeyRequest,
 ) -> Result<CustomMetadataKey, String> {
     // データ型の妥当性チェック
-    let valid_data_types = ["text", "number", "date", "boolean", "json"];
-    if !valid_data_types.contains(&request.data_type.as_str()) {
+    if !CUSTOM_METADATA_DATA_TYPES.contains(&request.data_type.as_str()) {
         return Err(format!(
             "Invalid data type: {}. Valid types are: {:?}",
-            request.data_type, valid_data_types
+            request.data_type, CUSTOM_METADATA_DATA_TYPES
         ));
     }
 
@@ -55,7 +79,7 @@ pub async fn create_custom_metadata_key(
         return Err(format!("カスタムメタデータキー '{}' は既に存在します", request.name));
     }
 
-    let key = CustomMetadataKey {
+    let mut key = CustomMetadataKey {
         id: Uuid::new_v4().to_string(),
         name: request.name,
         display_name: request.display_name,
@@ -64,14 +88,20 @@ pub async fn create_custom_metadata_key(
         is_required: request.is_required,
         default_value: request.default_value,
         validation_pattern: request.validation_pattern,
+        options: request.options,
+        expression: request.expression,
         created_at: Utc::now(),
         updated_at: Utc::now(),
     };
+    validate_key_definition(&mut key)?;
 
-    match db.create_custom_metadata_key(shelf_manager.get_settings_pool(), &key).await {
-        Ok(created_key) => Ok(created_key),
-        Err(e) => Err(format!("カスタムメタデータキーの作成に失敗しました: {e}")),
+    let created_key = db.create_custom_metadata_key(shelf_manager.get_settings_pool(), &key)
+        .await
+        .map_err(|e| format!("カスタムメタデータキーの作成に失敗しました: {e}"))?;
+    if created_key.expression.is_some() {
+        computed_metadata::invalidate_all_shelves(&shelf_manager).await?;
     }
+    Ok(created_key)
 }
 
 /// 全てのカスタムメタデータキーを取得
@@ -86,18 +116,35 @@ pub async fn get_custom_metadata_keys(
     }
 }
 
-/// カスタムメタデータキーを更新
-#[tauri::command]
-pub async fn update_custom_metadata_key(
-    shelf_manager: State<'_, ShelfManager>,
+/// シェルフごとの、キー定義の変更で変換できない値
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ShelfMetadataConversion {
+    pub shelf_id: String,
+    pub shelf_name: String,
+    pub failures: Vec<CustomMetadataViolation>,
+}
+
+/// シェルフごとの、カスタムメタデータの参照切れ
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ShelfMetadataConsistency {
+    pub shelf_id: String,
+    pub shelf_name: String,
+    /// 削除されたキーの値
+    pub orphaned_values: Vec<OrphanedMetadataValues>,
+    /// 削除されたキーを指しているXMPフィールドの対応（フィールド名）
+    pub orphaned_xmp_mappings: Vec<String>,
+}
+
+/// 更新リクエストから新しいキー定義を作る。戻り値は変更前と変更後のキー
+async fn updated_key_from_request(
+    shelf_manager: &ShelfManager,
     request: UpdateCustomMetadataKeyRequest,
-) -> Result<CustomMetadataKey, String> {
+) -> Result<(CustomMetadataKey, CustomMetadataKey), String> {
     // データ型の妥当性チェック
-    let valid_data_types = ["text", "number", "date", "boolean", "json"];
-    if !valid_data_types.contains(&request.data_type.as_str()) {
+    if !CUSTOM_METADATA_DATA_TYPES.contains(&request.data_type.as_str()) {
         return Err(format!(
             "Invalid data type: {}. Valid types are: {:?}",
-            request.data_type, valid_data_types
+            request.data_type, CUSTOM_METADATA_DATA_TYPES
         ));
     }
 
@@ -109,13 +156,13 @@ pub async fn update_custom_metadata_key(
         Err(e) => return Err(format!("カスタムメタデータキーの取得に失敗しました: {e}")),
     };
 
-    let existing_key = keys.iter().find(|k| k.id == request.id);
+    let existing_key = keys.into_iter().find(|k| k.id == request.id);
     let existing_key = match existing_key {
         Some(key) => key,
         None => return Err("指定されたカスタムメタデータキーが見つかりません".to_string()),
     };
 
-    let updated_key = CustomMetadataKey {
+    let mut updated_key = CustomMetadataKey {
         id: request.id,
         name: existing_key.name.clone(), // nameは変更不可
         display_name: request.display_name,
@@ -124,14 +171,123 @@ pub async fn update_custom_metadata_key(
         is_required: request.is_required,
         default_value: request.default_value,
         validation_pattern: request.validation_pattern,
+        options: request.options,
+        expression: request.expression,
         created_at: existing_key.created_at,
         updated_at: Utc::now(),
     };
+    validate_key_definition(&mut updated_key)?;
+    Ok((existing_key, updated_key))
+}
 
-    match db.update_custom_metadata_key(shelf_manager.get_settings_pool(), &updated_key).await {
-        Ok(key) => Ok(key),
-        Err(e) => Err(format!("カスタムメタデータキーの更新に失敗しました: {e}")),
+/// すべてのシェルフで、既存の値のうち新しいキー定義に変換できないものを調べる（書き込みはしない）
+async fn find_conversion_failures(
+    shelf_manager: &ShelfManager,
+    previous: &CustomMetadataKey,
+    key: &CustomMetadataKey,
+) -> Result<Vec<ShelfMetadataConversion>, String> {
+    let db = Database;
+    let mut conversions = Vec::new();
+    for (shelf, data_pool) in shelf_manager.get_all_data_pools().await.map_err(|e| e.to_string())? {
+        let failures = db.convert_custom_metadata_values(&data_pool, previous, key, false, false)
+            .await
+            .map_err(|e| format!("カスタムメタデータ値の変換に失敗しました ({}): {e}", shelf.name))?;
+        conversions.push(ShelfMetadataConversion {
+            shelf_id: shelf.id,
+            shelf_name: shelf.name,
+            failures,
+        });
+    }
+    Ok(conversions)
+}
+
+/// キー定義を変更した場合に、各シェルフで変換できない値を調べる（書き込みはしない）
+#[tauri::command]
+pub async fn preview_custom_metadata_key_update(
+    shelf_manager: State<'_, ShelfManager>,
+    request: UpdateCustomMetadataKeyRequest,
+) -> Result<Vec<ShelfMetadataConversion>, String> {
+    let (previous, key) = updated_key_from_request(&shelf_manager, request).await?;
+    find_conversion_failures(&shelf_manager, &previous, &key).await
+}
+
+/// カスタムメタデータキーを更新
+/// 型などを変えた場合はすべてのシェルフの値を変換する。変換できない値があると更新しないが、
+/// drop_invalid_values を指定するとそれらの値を削除して更新する
+/// 途中で失敗した場合は、どのシェルフの値もキー定義も変更しない
+#[tauri::command]
+pub async fn update_custom_metadata_key(
+    shelf_manager: State<'_, ShelfManager>,
+    request: UpdateCustomMetadataKeyRequest,
+    drop_invalid_values: Option<bool>,
+) -> Result<CustomMetadataKey, String> {
+    let db = Database;
+    let (previous, updated_key) = updated_key_from_request(&shelf_manager, request).await?;
+
+    let data_pools: Vec<_> = shelf_manager.get_all_data_pools()
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|(_, pool)| pool)
+        .collect();
+    let key = db
+        .update_custom_metadata_key_in_shelves(
+            shelf_manager.get_settings_pool(),
+            &data_pools,
+            &previous,
+            &updated_key,
+            drop_invalid_values.unwrap_or(false),
+        )
+        .await
+        .map_err(|e| match e {
+            MetadataError::Invalid(message) => message,
+            MetadataError::Database(e) => format!("カスタムメタデータキーの更新に失敗しました: {e}"),
+        })?;
+    if key.expression.is_some() {
+        computed_metadata::invalidate_all_shelves(&shelf_manager).await?;
+    }
+    Ok(key)
+}
+
+/// 検証パターンと既定値がキーの型に合っているかを確かめ、既定値を正規形にする
+fn validate_key_definition(key: &mut CustomMetadataKey) -> Result<(), String> {
+    key.expression = key.expression.take().filter(|e| !e.trim().is_empty());
+    if key.expression.is_some() {
+        if key.data_type == "file_ref" {
+            return Err("ファイル参照のキーは計算フィールドにできません".to_string());
+        }
+        if key.default_value.as_deref().is_some_and(|v| !v.trim().is_empty()) {
+            return Err("計算フィールドには既定値を指定できません".to_string());
+        }
+        ComputedFieldEngine::from_keys(std::slice::from_ref(key))?;
+    }
+    if matches!(key.data_type.as_str(), "select" | "multi_select") {
+        // 選択肢は前後の空白を除き、重複を取り除く
+        let mut options: Vec<String> = Vec::new();
+        for option in key.options.take().unwrap_or_default() {
+            let option = option.trim().to_string();
+            if !option.is_empty() && !options.contains(&option) {
+                options.push(option);
+            }
+        }
+        if options.is_empty() {
+            return Err("選択肢を1つ以上指定してください".to_string());
+        }
+        if key.data_type == "multi_select" && options.iter().any(|o| o.contains(',')) {
+            return Err("複数選択の選択肢にはカンマを含められません".to_string());
+        }
+        key.options = Some(options);
+    } else {
+        key.options = None;
+    }
+    if let Some(pattern) = key.validation_pattern.as_deref().filter(|p| !p.is_empty()) {
+        regex::Regex::new(pattern).map_err(|e| format!("検証パターンが不正です: {e}"))?;
     }
+    if let Some(default_value) = key.default_value.clone().filter(|v| !v.trim().is_empty()) {
+        key.default_value = key.normalize_value(Some(&default_value))
+            .map_err(|e| format!("既定値が不正です: {e}"))?;
+    }
+    Ok(())
 }
 
 /// カスタムメタデータキーを削除
@@ -141,10 +297,26 @@ pub async fn delete_custom_metadata_key(
     key_id: String,
 ) -> Result<(), String> {
     let db = Database;
-    match db.delete_custom_metadata_key(shelf_manager.get_settings_pool(), &shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?, &key_id).await {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("カスタムメタデータキーの削除に失敗しました: {e}")),
+    let data_pools: Vec<_> = shelf_manager.get_all_data_pools()
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|(_, pool)| pool)
+        .collect();
+    if let Err(e) = db.delete_custom_metadata_key(shelf_manager.get_settings_pool(), &data_pools, &key_id).await {
+        return Err(format!("カスタムメタデータキーの削除に失敗しました: {e}"));
     }
+
+    // 各シェルフのXMPフィールドの対応からも外す
+    for data_pool in &data_pools {
+        let mut mappings = xmp_sidecar::get_field_mappings(data_pool).await?;
+        let count = mappings.len();
+        mappings.retain(|mapping| mapping.key_id != key_id);
+        if mappings.len() != count {
+            xmp_sidecar::set_field_mappings(data_pool, &mappings).await?;
+        }
+    }
+    Ok(())
 }
 
 /// 名前でカスタムメタデータキーを取得
@@ -173,6 +345,79 @@ pub async fn set_custom_metadata_value(
     }
 }
 
+/// 複数のファイル（または検索結果）に同じ値を設定する
+#[tauri::command]
+pub async fn bulk_set_custom_metadata_value(
+    shelf_manager: State<'_, ShelfManager>,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+    key_id: String,
+    value: Option<String>,
+) -> Result<BulkMetadataResult, String> {
+    let db = Database;
+    let file_ids = resolve_bulk_targets(&shelf_manager, file_ids, filter).await?;
+    match db.bulk_set_custom_metadata_value(&shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?, shelf_manager.get_settings_pool(), &file_ids, &key_id, value).await {
+        Ok(result) => Ok(result),
+        Err(e) => Err(format!("カスタムメタデータ値の一括設定に失敗しました: {e}")),
+    }
+}
+
+/// 複数のファイル（または検索結果）からキーの値を消す
+#[tauri::command]
+pub async fn bulk_clear_custom_metadata_value(
+    shelf_manager: State<'_, ShelfManager>,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+    key_id: String,
+) -> Result<BulkMetadataResult, String> {
+    let db = Database;
+    let file_ids = resolve_bulk_targets(&shelf_manager, file_ids, filter).await?;
+    match db.bulk_clear_custom_metadata_value(&shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?, &file_ids, &key_id).await {
+        Ok(result) => Ok(result),
+        Err(e) => Err(format!("カスタムメタデータ値の一括削除に失敗しました: {e}")),
+    }
+}
+
+/// あるファイルのカスタムメタデータを他のファイルにコピーする
+/// key_ids を指定するとそのキーだけ、overwrite を指定しないと値のないキーだけをコピーする
+#[tauri::command]
+pub async fn copy_custom_metadata(
+    shelf_manager: State<'_, ShelfManager>,
+    source_file_id: String,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+    key_ids: Option<Vec<String>>,
+    overwrite: Option<bool>,
+) -> Result<BulkMetadataResult, String> {
+    let db = Database;
+    let file_ids = resolve_bulk_targets(&shelf_manager, file_ids, filter).await?;
+    match db.copy_custom_metadata(
+        &shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?,
+        shelf_manager.get_settings_pool(),
+        &source_file_id,
+        &file_ids,
+        key_ids,
+        overwrite.unwrap_or(false),
+    ).await {
+        Ok(result) => Ok(result),
+        Err(e) => Err(format!("カスタムメタデータのコピーに失敗しました: {e}")),
+    }
+}
+
+/// 複数のファイルのカスタムメタデータ値をまとめて取得
+#[tauri::command]
+pub async fn get_custom_metadata_values_by_files(
+    shelf_manager: State<'_, ShelfManager>,
+    file_ids: Vec<String>,
+) -> Result<Vec<CustomMetadataValue>, String> {
+    let db = Database;
+    computed_metadata::refresh_active_shelf(&shelf_manager).await?;
+    match db.get_custom_metadata_values_by_files(&shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?, &file_ids).await {
+        Ok(values) => Ok(values),
+        Err(e) => Err(format!("カスタムメタデータ値の取得に失敗しました: {e}")),
+    }
+}
+
 /// ファイルのカスタムメタデータ値を全て取得
 #[tauri::command]
 pub async fn get_custom_metadata_values_by_file(
@@ -180,6 +425,7 @@ pub async fn get_custom_metadata_values_by_file(
     file_id: String,
 ) -> Result<Vec<CustomMetadataValue>, String> {
     let db = Database;
+    computed_metadata::refresh_active_shelf(&shelf_manager).await?;
     match db.get_custom_metadata_values_by_file(&shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?, &file_id).await {
         Ok(values) => Ok(values),
         Err(e) => Err(format!("カスタムメタデータ値の取得に失敗しました: {e}")),
@@ -194,6 +440,7 @@ pub async fn get_custom_metadata_value(
     key_id: String,
 ) -> Result<Option<CustomMetadataValue>, String> {
     let db = Database;
+    computed_metadata::refresh_active_shelf(&shelf_manager).await?;
     match db.get_custom_metadata_value(&shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?, &file_id, &key_id).await {
         Ok(value) => Ok(value),
         Err(e) => Err(format!("カスタムメタデータ値の取得に失敗しました: {e}")),
@@ -212,4 +459,250 @@ pub async fn delete_custom_metadata_value(
         Ok(_) => Ok(()),
         Err(e) => Err(format!("カスタムメタデータ値の削除に失敗しました: {e}")),
     }
-}
\ No newline at end of file
+}
+
+/// 必須キーが未設定のファイルと、型・検証パターンに合わない値を一覧する
+/// apply_defaults を指定すると、既定値のある必須キーが未設定のファイルに既定値を設定してから一覧する
+#[tauri::command]
+pub async fn audit_custom_metadata(
+    shelf_manager: State<'_, ShelfManager>,
+    apply_defaults: Option<bool>,
+) -> Result<Vec<CustomMetadataViolation>, String> {
+    let db = Database;
+    let data_pool = shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?;
+    let settings_pool = shelf_manager.get_settings_pool();
+    let refreshed = computed_metadata::refresh_computed_metadata(&data_pool, settings_pool).await?;
+    let keys = db.get_all_custom_metadata_keys(settings_pool)
+        .await
+        .map_err(|e| format!("カスタムメタデータキーの取得に失敗しました: {e}"))?;
+    // 計算できなかった計算フィールドも問題として含める
+    let computed_errors: Vec<CustomMetadataViolation> = refreshed.errors
+        .into_iter()
+        .map(|error| CustomMetadataViolation {
+            key_name: keys.iter()
+                .find(|k| k.id == error.key_id)
+                .map(|k| k.display_name.clone())
+                .unwrap_or_default(),
+            file_id: error.file_id,
+            path: error.path,
+            key_id: error.key_id,
+            value: None,
+            message: error.message,
+        })
+        .collect();
+
+    let mut violations = db.audit_custom_metadata(&data_pool, settings_pool)
+        .await
+        .map_err(|e| format!("カスタムメタデータの監査に失敗しました: {e}"))?;
+    if !apply_defaults.unwrap_or(false) {
+        violations.extend(computed_errors);
+        return Ok(violations);
+    }
+
+    let mut applied = false;
+    for violation in violations.iter().filter(|v| v.value.is_none()) {
+        let has_default = keys.iter()
+            .any(|k| {
+                k.id == violation.key_id
+                    && k.expression.is_none()
+                    && k.default_value.as_deref().is_some_and(|v| !v.trim().is_empty())
+            });
+        if has_default {
+            db.set_custom_metadata_value(&data_pool, settings_pool, &violation.file_id, &violation.key_id, None)
+                .await
+                .map_err(|e| format!("既定値の設定に失敗しました ({}): {e}", violation.path))?;
+            applied = true;
+        }
+    }
+    if applied {
+        violations = db.audit_custom_metadata(&data_pool, settings_pool)
+            .await
+            .map_err(|e| format!("カスタムメタデータの監査に失敗しました: {e}"))?;
+    }
+    violations.extend(computed_errors);
+    Ok(violations)
+}
+
+/// すべてのシェルフで、削除されたキーを参照している値とXMPフィールドの対応を探す
+/// fix を指定するとそれらを削除する
+#[tauri::command]
+pub async fn check_custom_metadata_consistency(
+    shelf_manager: State<'_, ShelfManager>,
+    fix: Option<bool>,
+) -> Result<Vec<ShelfMetadataConsistency>, String> {
+    let db = Database;
+    let fix = fix.unwrap_or(false);
+    let key_ids: Vec<String> = db.get_all_custom_metadata_keys(shelf_manager.get_settings_pool())
+        .await
+        .map_err(|e| format!("カスタムメタデータキーの取得に失敗しました: {e}"))?
+        .into_iter()
+        .map(|key| key.id)
+        .collect();
+
+    let mut reports = Vec::new();
+    for (shelf, data_pool) in shelf_manager.get_all_data_pools().await.map_err(|e| e.to_string())? {
+        let orphaned_values = db.find_orphaned_custom_metadata_values(&data_pool, &key_ids, fix)
+            .await
+            .map_err(|e| format!("カスタムメタデータの整合性チェックに失敗しました ({}): {e}", shelf.name))?;
+
+        let (mappings, orphaned_mappings): (Vec<_>, Vec<_>) = xmp_sidecar::get_field_mappings(&data_pool)
+            .await?
+            .into_iter()
+            .partition(|mapping| key_ids.contains(&mapping.key_id));
+        if fix && !orphaned_mappings.is_empty() {
+            xmp_sidecar::set_field_mappings(&data_pool, &mappings).await?;
+        }
+
+        reports.push(ShelfMetadataConsistency {
+            shelf_id: shelf.id,
+            shelf_name: shelf.name,
+            orphaned_values,
+            orphaned_xmp_mappings: orphaned_mappings.into_iter().map(|mapping| mapping.field).collect(),
+        });
+    }
+    Ok(reports)
+}
+
+/// ファイルのタグ・カスタムメタデータの変更履歴を新しい順に取得（key_id を指定するとそのキーだけ）
+#[tauri::command]
+pub async fn get_annotation_history(
+    shelf_manager: State<'_, ShelfManager>,
+    file_id: String,
+    key_id: Option<String>,
+    limit: Option<u32>,
+) -> Result<Vec<AnnotationHistoryEntry>, String> {
+    let db = Database;
+    match db.get_annotation_history(&shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?, &file_id, key_id, limit).await {
+        Ok(history) => Ok(history),
+        Err(e) => Err(format!("変更履歴の取得に失敗しました: {e}")),
+    }
+}
+
+/// 履歴の変更を取り消し、カスタムメタデータ値をその変更の前の値に戻す
+#[tauri::command]
+pub async fn revert_custom_metadata_change(
+    shelf_manager: State<'_, ShelfManager>,
+    history_id: i64,
+) -> Result<Option<CustomMetadataValue>, String> {
+    let db = Database;
+    match db.revert_custom_metadata_change(&shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?, shelf_manager.get_settings_pool(), history_id).await {
+        Ok(value) => Ok(value),
+        Err(e) => Err(format!("カスタムメタデータ値を元に戻せませんでした: {e}")),
+    }
+}
+
+/// スキーマのキーと条件を確かめる
+async fn validate_schema_request(
+    shelf_manager: &ShelfManager,
+    request: &CustomMetadataSchemaRequest,
+) -> Result<(), String> {
+    if request.name.trim().is_empty() {
+        return Err("スキーマ名が空です".to_string());
+    }
+    let keys = Database.get_all_custom_metadata_keys(shelf_manager.get_settings_pool())
+        .await
+        .map_err(|e| format!("カスタムメタデータキーの取得に失敗しました: {e}"))?;
+    for key_id in &request.key_ids {
+        if !keys.iter().any(|key| &key.id == key_id) {
+            return Err(format!("カスタムメタデータキーが見つかりません: {key_id}"));
+        }
+    }
+    for condition in &request.conditions {
+        let empty = match condition {
+            SchemaCondition::Category { category } => category.trim().is_empty(),
+            SchemaCondition::Extension { extensions } => extensions.iter().all(|e| e.trim().is_empty()),
+            SchemaCondition::Tag { tag_id } => tag_id.is_empty(),
+            SchemaCondition::Directory { path } => path.trim().is_empty(),
+        };
+        if empty {
+            return Err("スキーマの条件に値がありません".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// カスタムメタデータのスキーマを作成
+#[tauri::command]
+pub async fn create_custom_metadata_schema(
+    shelf_manager: State<'_, ShelfManager>,
+    request: CustomMetadataSchemaRequest,
+) -> Result<CustomMetadataSchema, String> {
+    validate_schema_request(&shelf_manager, &request).await?;
+    let schema = CustomMetadataSchema {
+        id: String::new(),
+        name: request.name.trim().to_string(),
+        description: request.description,
+        key_ids: request.key_ids,
+        conditions: request.conditions,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+    match Database.create_custom_metadata_schema(shelf_manager.get_settings_pool(), &schema).await {
+        Ok(schema) => Ok(schema),
+        Err(e) => Err(format!("スキーマの作成に失敗しました: {e}")),
+    }
+}
+
+/// すべてのカスタムメタデータのスキーマを取得
+#[tauri::command]
+pub async fn get_custom_metadata_schemas(
+    shelf_manager: State<'_, ShelfManager>,
+) -> Result<Vec<CustomMetadataSchema>, String> {
+    match Database.get_all_custom_metadata_schemas(shelf_manager.get_settings_pool()).await {
+        Ok(schemas) => Ok(schemas),
+        Err(e) => Err(format!("スキーマの取得に失敗しました: {e}")),
+    }
+}
+
+/// カスタムメタデータのスキーマを更新
+#[tauri::command]
+pub async fn update_custom_metadata_schema(
+    shelf_manager: State<'_, ShelfManager>,
+    schema_id: String,
+    request: CustomMetadataSchemaRequest,
+) -> Result<CustomMetadataSchema, String> {
+    validate_schema_request(&shelf_manager, &request).await?;
+    let db = Database;
+    let existing = db.get_all_custom_metadata_schemas(shelf_manager.get_settings_pool())
+        .await
+        .map_err(|e| format!("スキーマの取得に失敗しました: {e}"))?
+        .into_iter()
+        .find(|schema| schema.id == schema_id)
+        .ok_or_else(|| "指定されたスキーマが見つかりません".to_string())?;
+    let schema = CustomMetadataSchema {
+        name: request.name.trim().to_string(),
+        description: request.description,
+        key_ids: request.key_ids,
+        conditions: request.conditions,
+        ..existing
+    };
+    match db.update_custom_metadata_schema(shelf_manager.get_settings_pool(), &schema).await {
+        Ok(schema) => Ok(schema),
+        Err(e) => Err(format!("スキーマの更新に失敗しました: {e}")),
+    }
+}
+
+/// カスタムメタデータのスキーマを削除（キーと値は残る）
+#[tauri::command]
+pub async fn delete_custom_metadata_schema(
+    shelf_manager: State<'_, ShelfManager>,
+    schema_id: String,
+) -> Result<(), String> {
+    match Database.delete_custom_metadata_schema(shelf_manager.get_settings_pool(), &schema_id).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("スキーマの削除に失敗しました: {e}")),
+    }
+}
+
+/// ファイルに適用されるカスタムメタデータキーを取得
+#[tauri::command]
+pub async fn get_applicable_custom_metadata_keys(
+    shelf_manager: State<'_, ShelfManager>,
+    file_id: String,
+) -> Result<Vec<CustomMetadataKey>, String> {
+    let db = Database;
+    match db.get_applicable_custom_metadata_keys(&shelf_manager.get_active_data_pool().map_err(|e| e.to_string())?, shelf_manager.get_settings_pool(), &file_id).await {
+        Ok(keys) => Ok(keys),
+        Err(e) => Err(format!("カスタムメタデータキーの取得に失敗しました: {e}")),
+    }
+}
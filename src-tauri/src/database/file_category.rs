@@ -0,0 +1,50 @@
+/// カテゴリごとの拡張子（検索の絞り込み条件にも使う）
+pub const CATEGORY_EXTENSIONS: &[(&str, &[&str])] = &[
+    ("image", &["jpg", "jpeg", "png", "gif", "bmp", "webp", "svg", "ico", "tiff", "raw"]),
+    ("audio", &["mp3", "wav", "ogg", "flac", "aac", "m4a", "wma", "opus"]),
+    ("video", &["mp4", "avi", "mov", "wmv", "flv", "webm", "mkv", "m4v", "3gp"]),
+    (
+        "document",
+        &["pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "txt", "md", "html", "htm", "css", "js", "json", "xml", "csv", "rtf"],
+    ),
+    ("archive", &["zip", "rar", "7z", "tar", "gz", "bz2", "xz", "lzma"]),
+];
+
+/// ファイルのカテゴリを分類する（検索の絞り込み、スキーマの条件、タグの集計で共通）
+/// MIMEタイプでの判定を優先し、判定できなければ拡張子で判定する
+pub fn classify_file_category(file_name: &str, mime_type: Option<&str>) -> &'static str {
+    if let Some(mime) = mime_type {
+        if mime.starts_with("image/") {
+            return "image";
+        } else if mime.starts_with("audio/") {
+            return "audio";
+        } else if mime.starts_with("video/") {
+            return "video";
+        } else if mime.starts_with("application/pdf")
+            || mime.starts_with("application/msword")
+            || mime.starts_with("application/vnd.")
+            || mime.starts_with("text/")
+        {
+            return "document";
+        } else if mime.starts_with("application/zip")
+            || mime.starts_with("application/x-rar")
+            || mime.starts_with("application/x-7z")
+            || mime.starts_with("application/x-tar")
+            || mime.starts_with("application/gzip")
+        {
+            return "archive";
+        }
+    }
+
+    let extension = file_name.rsplit_once('.').map(|(_, extension)| extension).unwrap_or_default();
+    category_from_extension(extension)
+}
+
+/// 拡張子からカテゴリを判定する（大文字小文字は区別しない）
+pub fn category_from_extension(extension: &str) -> &'static str {
+    CATEGORY_EXTENSIONS
+        .iter()
+        .find(|(_, extensions)| extensions.iter().any(|e| e.eq_ignore_ascii_case(extension)))
+        .map(|(category, _)| *category)
+        .unwrap_or("other")
+}
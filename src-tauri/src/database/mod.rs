@@ -1,6 +1,14 @@
-use chrono::{DateTime, Utc};
-use sqlx::{Row, SqlitePool};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+use regex::Regex;
+use sqlx::{Acquire, Row, SqliteConnection, SqlitePool};
 use uuid::Uuid;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+mod file_category;
+
+pub use file_category::{category_from_extension, classify_file_category, CATEGORY_EXTENSIONS};
 
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
@@ -10,6 +18,7 @@ pub struct Directory {
     pub name: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub follow_symlinks: bool,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
@@ -36,14 +45,94 @@ pub struct File {
     pub device_id: Option<i64>,
     pub last_accessed: Option<DateTime<Utc>>,
     pub metadata: Option<String>,
+    pub content_hash: Option<String>,
+    pub is_symlink: bool,
+    pub symlink_target: Option<String>,
+    pub is_broken_link: bool,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
 pub struct Tag {
     pub id: String,
+    /// 階層タグの場合は `client/acme/invoices` のような完全パス
     pub name: String,
     pub color: String,
     pub created_at: DateTime<Utc>,
+    pub parent_id: Option<String>,
+    /// ユーザーが意図して作成・整理したタグ（未使用になっても自動削除しない）
+    pub pinned: bool,
+}
+
+impl Tag {
+    /// 階層の末尾の名前
+    pub fn label(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or(&self.name)
+    }
+}
+
+/// タグ候補を挙げた根拠
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TagSuggestionReason {
+    /// 同じディレクトリのファイルに付いている
+    SameDirectory,
+    /// 名前の似たファイルに付いている
+    SimilarName,
+    /// 同じ拡張子のファイルに付いている
+    SameExtension,
+    /// 同じカスタムメタデータの値を持つファイルに付いている
+    SharedMetadata,
+    /// 既に付いているタグと一緒に使われることが多い
+    CoOccurrence,
+}
+
+/// ファイルに効いているタグと、その付き方
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct FileTagAssignment {
+    pub tag: Tag,
+    /// 継承元のディレクトリ（直接付いている場合はNone）
+    pub inherited_from: Option<InheritedTagSource>,
+    /// ディレクトリに付けたタグをサブフォルダにも継承するか
+    pub include_subfolders: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct InheritedTagSource {
+    pub file_id: String,
+    pub path: String,
+}
+
+/// タグごとの利用状況
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct TagUsage {
+    pub tag: Tag,
+    pub file_count: i64,
+    /// タグが付いたファイルの合計サイズ（ディレクトリは含めない）
+    pub total_bytes: i64,
+}
+
+/// タグの付いたファイル数を、ディレクトリ・拡張子・期間などの単位で数えたもの
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct TagGroupCount {
+    pub tag_id: String,
+    pub group: Option<String>,
+    pub file_count: i64,
+    pub total_bytes: i64,
+}
+
+/// 同じファイルに付いているタグの組
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct TagPairCount {
+    pub tag_id: String,
+    pub other_tag_id: String,
+    pub file_count: i64,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct TagSuggestion {
+    pub tag: Tag,
+    pub score: f64,
+    pub reasons: Vec<TagSuggestionReason>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
@@ -56,10 +145,465 @@ pub struct CustomMetadataKey {
     pub is_required: bool,
     pub default_value: Option<String>,
     pub validation_pattern: Option<String>,
+    /// select / multi_select の選択肢
+    #[serde(default)]
+    pub options: Option<Vec<String>>,
+    /// 計算フィールドの式（Teraテンプレート）。ある場合は値を手で設定せず、式から計算する
+    #[serde(default)]
+    pub expression: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// カスタムメタデータの値を書き込むときのエラー（値の検証エラーとデータベースのエラーを区別する）
+#[derive(Debug, thiserror::Error)]
+pub enum MetadataError {
+    /// 値がキーの型・検証パターン・必須設定に合わない、参照先がないなど
+    #[error("{0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+/// カスタムメタデータキーに指定できるデータ型
+pub const CUSTOM_METADATA_DATA_TYPES: [&str; 11] = [
+    "text", "number", "date", "boolean", "json", "select", "multi_select", "rating", "url", "duration", "file_ref",
+];
+
+/// rating 型の最大値（星の数）
+pub const MAX_RATING: i64 = 5;
+
+impl CustomMetadataKey {
+    /// 値の解釈が変わる変更か（型・選択肢・検証パターン）
+    pub fn changes_value_format(&self, previous: &CustomMetadataKey) -> bool {
+        previous.data_type != self.data_type
+            || previous.options != self.options
+            || previous.validation_pattern != self.validation_pattern
+    }
+
+    /// 値をデータ型と検証パターンで検査し、保存用の正規形に変換する
+    /// 空の値には既定値を使い、必須キーで既定値もなければエラーにする
+    /// file_ref 型は参照先の存在確認をしない（データベースへの書き込み時に確認する）
+    pub fn normalize_value(&self, value: Option<&str>) -> Result<Option<String>, String> {
+        let is_blank = |v: &&str| {
+            v.trim().is_empty() || (self.data_type == "multi_select" && parse_multi_select_input(v).is_empty())
+        };
+        let value = match value.filter(|v| !is_blank(v)) {
+            Some(value) => value,
+            None => match self.default_value.as_deref().filter(|v| !is_blank(v)) {
+                Some(default) => default,
+                None if self.is_required => return Err(format!("'{}' は必須です", self.display_name)),
+                None => return Ok(None),
+            },
+        };
+
+        let normalized = match self.data_type.as_str() {
+            "number" => normalize_number(value.trim())
+                .ok_or_else(|| format!("'{}' には数値を指定してください: {value}", self.display_name))?,
+            "date" => normalize_date(value.trim())
+                .ok_or_else(|| format!("'{}' には日付 (YYYY-MM-DD) を指定してください: {value}", self.display_name))?,
+            "boolean" => normalize_boolean(value.trim())
+                .ok_or_else(|| format!("'{}' には true / false を指定してください: {value}", self.display_name))?,
+            "json" => serde_json::from_str::<serde_json::Value>(value)
+                .map(|json| json.to_string())
+                .map_err(|e| format!("'{}' のJSONが不正です: {e}", self.display_name))?,
+            "select" => self.match_option(value.trim())?,
+            // 複数選択は選択肢の表記に揃え、重複を除いたJSON配列で保存する
+            "multi_select" => {
+                let mut items: Vec<String> = Vec::new();
+                for item in parse_multi_select_input(value) {
+                    let item = self.match_option(&item)?;
+                    if !items.contains(&item) {
+                        items.push(item);
+                    }
+                }
+                serde_json::to_string(&items).map_err(|e| e.to_string())?
+            }
+            "rating" => normalize_rating(value.trim()).ok_or_else(|| {
+                format!("'{}' には0〜{MAX_RATING}の整数を指定してください: {value}", self.display_name)
+            })?,
+            "url" => normalize_url(value.trim())
+                .ok_or_else(|| format!("'{}' にはURLを指定してください: {value}", self.display_name))?,
+            // 長さは秒数で保存する
+            "duration" => parse_duration(value.trim())
+                .and_then(|seconds| normalize_number(&seconds.to_string()))
+                .ok_or_else(|| {
+                    format!("'{}' には長さ (1:30:00, 90m, 秒数など) を指定してください: {value}", self.display_name)
+                })?,
+            "file_ref" => value.trim().to_string(),
+            _ => value.to_string(),
+        };
+
+        if let Some(pattern) = self.validation_pattern.as_deref().filter(|p| !p.is_empty()) {
+            // パターンは値全体（複数選択では各項目）に一致する必要がある
+            let regex = validation_regex(pattern)
+                .map_err(|e| format!("'{}' の検証パターンが不正です: {e}", self.display_name))?;
+            let targets = if self.data_type == "multi_select" {
+                multi_select_items(&normalized)
+            } else {
+                vec![normalized.clone()]
+            };
+            if let Some(target) = targets.iter().find(|t| !regex.is_match(t)) {
+                return Err(format!(
+                    "'{}' の値 '{target}' が検証パターン {pattern} に一致しません",
+                    self.display_name
+                ));
+            }
+        }
+
+        Ok(Some(normalized))
+    }
+
+    /// 保存された値を型に合わせたJSON値にする（テンプレートで数値・真偽値・配列として扱うため）
+    pub fn typed_value(&self, value: &str) -> serde_json::Value {
+        let typed = match self.data_type.as_str() {
+            "number" | "rating" | "duration" => serde_json::from_str::<serde_json::Number>(value)
+                .ok()
+                .map(serde_json::Value::Number),
+            "boolean" => value.parse::<bool>().ok().map(serde_json::Value::Bool),
+            "multi_select" => Some(multi_select_items(value).into()),
+            "json" => serde_json::from_str(value).ok(),
+            _ => None,
+        };
+        typed.unwrap_or_else(|| serde_json::Value::String(value.to_string()))
+    }
+
+    /// 選択肢から値を探す（大文字・小文字の違いは選択肢の表記に揃える）
+    fn match_option(&self, value: &str) -> Result<String, String> {
+        let options = self.options.as_deref().unwrap_or_default();
+        options
+            .iter()
+            .find(|o| o.as_str() == value)
+            .or_else(|| options.iter().find(|o| o.eq_ignore_ascii_case(value)))
+            .cloned()
+            .ok_or_else(|| format!("'{}' の値 '{value}' は選択肢にありません", self.display_name))
+    }
+}
+
+/// 検証パターンを値全体に一致する正規表現にする（値ごとにコンパイルし直さないようパターンごとに保持する）
+fn validation_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+    let mut cache = CACHE.get_or_init(Default::default).lock().unwrap();
+    if let Some(regex) = cache.get(pattern) {
+        return Ok(regex.clone());
+    }
+    let regex = Regex::new(&format!("^(?:{pattern})$"))?;
+    cache.insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
+
+/// 保存された multi_select の値を項目の一覧にする
+pub fn multi_select_items(value: &str) -> Vec<String> {
+    serde_json::from_str(value).unwrap_or_default()
+}
+
+/// 入力された multi_select の値（JSON配列またはカンマ区切り）を項目に分ける
+fn parse_multi_select_input(value: &str) -> Vec<String> {
+    let items: Vec<String> = if value.trim_start().starts_with('[') {
+        serde_json::from_str(value).unwrap_or_else(|_| vec![value.to_string()])
+    } else {
+        value.split(',').map(str::to_string).collect()
+    };
+    items.into_iter().map(|i| i.trim().to_string()).filter(|i| !i.is_empty()).collect()
+}
+
+fn normalize_rating(value: &str) -> Option<String> {
+    // ★の数でも指定できる
+    let rating = if !value.is_empty() && value.chars().all(|c| c == '★' || c == '☆') {
+        value.chars().filter(|c| *c == '★').count() as i64
+    } else {
+        let number: f64 = value.parse().ok()?;
+        if number.fract() != 0.0 {
+            return None;
+        }
+        number as i64
+    };
+    (0..=MAX_RATING).contains(&rating).then(|| rating.to_string())
+}
+
+fn normalize_url(value: &str) -> Option<String> {
+    let url = url::Url::parse(value).ok()?;
+    (url.has_host() || matches!(url.scheme(), "mailto" | "file")).then(|| url.to_string())
+}
+
+/// 長さを秒数に変換する（`1:30:00`・`90:00`・`1h30m`・`45s`・秒数）
+pub fn parse_duration(value: &str) -> Option<f64> {
+    let seconds = if let Ok(seconds) = value.parse::<f64>() {
+        seconds
+    } else if value.contains(':') {
+        let parts: Vec<f64> = value.split(':').map(|p| p.trim().parse::<f64>().ok()).collect::<Option<_>>()?;
+        if !(2..=3).contains(&parts.len()) || parts[1..].iter().any(|p| *p >= 60.0) {
+            return None;
+        }
+        parts.iter().fold(0.0, |total, part| total * 60.0 + part)
+    } else {
+        static UNITS: OnceLock<Regex> = OnceLock::new();
+        let units = UNITS.get_or_init(|| {
+            Regex::new(r"(?i)^(?:(\d+(?:\.\d+)?)\s*h)?\s*(?:(\d+(?:\.\d+)?)\s*m)?\s*(?:(\d+(?:\.\d+)?)\s*s)?$").unwrap()
+        });
+        let captures = units.captures(value)?;
+        if captures.iter().skip(1).all(|c| c.is_none()) {
+            return None;
+        }
+        [3600.0, 60.0, 1.0]
+            .iter()
+            .enumerate()
+            .map(|(i, unit)| captures.get(i + 1).map_or(0.0, |m| m.as_str().parse::<f64>().unwrap_or(0.0)) * unit)
+            .sum()
+    };
+    (seconds.is_finite() && seconds >= 0.0).then_some(seconds)
+}
+
+fn normalize_number(value: &str) -> Option<String> {
+    let number: f64 = value.parse().ok().filter(|n: &f64| n.is_finite())?;
+    // 整数で表せる値は小数点なしで保存する
+    if number.fract() == 0.0 && number.abs() < 1e15 {
+        Some((number as i64).to_string())
+    } else {
+        Some(number.to_string())
+    }
+}
+
+/// 日付は YYYY-MM-DD、時刻付きは ISO 8601（タイムゾーン付きはUTCに変換）で保存する
+pub fn normalize_date(value: &str) -> Option<String> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Some(datetime.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::AutoSi, true));
+    }
+    // EXIF の日時（2024:05:01 10:00:00）も受け付ける
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M", "%Y:%m:%d %H:%M:%S"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(datetime.format("%Y-%m-%dT%H:%M:%S").to_string());
+        }
+    }
+    for format in ["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d"] {
+        if let Ok(date) = NaiveDate::parse_from_str(value, format) {
+            return Some(date.format("%Y-%m-%d").to_string());
+        }
+    }
+    None
+}
+
+fn normalize_boolean(value: &str) -> Option<String> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some("true".to_string()),
+        "false" | "0" | "no" | "off" => Some("false".to_string()),
+        _ => None,
+    }
+}
+
+/// カスタムメタデータの監査で見つかった問題
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct CustomMetadataViolation {
+    pub file_id: String,
+    pub path: String,
+    pub key_id: String,
+    pub key_name: String,
+    /// 必須キーに値がない場合は None
+    pub value: Option<String>,
+    pub message: String,
+}
+
+/// カスタムメタデータのスキーマを適用するファイルの条件
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SchemaCondition {
+    /// 検索と同じファイルカテゴリ（image、document など、大文字小文字は区別しない）
+    Category { category: String },
+    /// 拡張子（ドットなし、大文字小文字は区別しない）
+    Extension { extensions: Vec<String> },
+    /// タグ（下位のタグやディレクトリから継承したタグが付いたファイルも含む）
+    Tag { tag_id: String },
+    /// ディレクトリのパス（配下のすべてのファイル）
+    Directory { path: String },
+}
+
+/// カスタムメタデータキーのまとまりと、それを使うファイルの条件
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct CustomMetadataSchema {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// 表示順に並べたキー
+    pub key_ids: Vec<String>,
+    /// いずれかに一致するファイルに適用する（空の場合はすべてのファイル）
+    pub conditions: Vec<SchemaCondition>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// ファイルにどのキーを使うかをスキーマから判定する
+struct SchemaMatcher {
+    schemas: Vec<CustomMetadataSchema>,
+    /// タグID -> 親タグID
+    tag_parents: HashMap<String, String>,
+}
+
+impl SchemaMatcher {
+    async fn load(conn: &mut SqliteConnection, schemas: Vec<CustomMetadataSchema>) -> Result<Self, sqlx::Error> {
+        let tag_parents = sqlx::query_as::<_, (String, String)>("SELECT id, parent_id FROM tags WHERE parent_id IS NOT NULL")
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .collect();
+        Ok(Self { schemas, tag_parents })
+    }
+
+    /// タグに上位のタグを加える（上位のタグの条件は下位のタグにも一致させるため）
+    fn with_ancestors(&self, tag_ids: impl IntoIterator<Item = String>) -> HashSet<String> {
+        let mut result = HashSet::new();
+        for tag_id in tag_ids {
+            let mut current = Some(tag_id);
+            while let Some(id) = current {
+                current = self.tag_parents.get(&id).cloned();
+                if !result.insert(id) {
+                    break;
+                }
+            }
+        }
+        result
+    }
+
+    fn schema_matches(schema: &CustomMetadataSchema, file: &SchemaFileContext) -> bool {
+        let path = file.path.as_str();
+        let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or_default();
+        schema.conditions.is_empty()
+            || schema.conditions.iter().any(|condition| match condition {
+                SchemaCondition::Category { category } => {
+                    classify_file_category(path, file.mime_type.as_deref()).eq_ignore_ascii_case(category)
+                }
+                SchemaCondition::Extension { extensions } => extensions
+                    .iter()
+                    .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension)),
+                SchemaCondition::Tag { tag_id } => file.tag_ids.contains(tag_id),
+                SchemaCondition::Directory { path: directory } => {
+                    let directory = directory.trim_end_matches('/');
+                    path.strip_prefix(directory).is_some_and(|rest| rest.starts_with('/'))
+                }
+            })
+    }
+
+    /// キーがファイルに適用されるか（どのスキーマにも属さないキーはすべてのファイルに適用する）
+    fn key_applies(&self, key_id: &str, file: &SchemaFileContext) -> bool {
+        let mut schemas = self.schemas.iter().filter(|s| s.key_ids.iter().any(|id| id == key_id)).peekable();
+        schemas.peek().is_none() || schemas.any(|s| Self::schema_matches(s, file))
+    }
+
+    /// ファイルのパス・MIMEタイプと、継承したものと上位のものを含むタグ
+    async fn file_context(
+        &self,
+        conn: &mut SqliteConnection,
+        file_id: &str,
+    ) -> Result<Option<SchemaFileContext>, sqlx::Error> {
+        let Some((path, mime_type)) =
+            sqlx::query_as::<_, (String, Option<String>)>("SELECT path, mime_type FROM files WHERE id = ?")
+                .bind(file_id)
+                .fetch_optional(&mut *conn)
+                .await?
+        else {
+            return Ok(None);
+        };
+        let tag_ids: Vec<String> = sqlx::query_scalar("SELECT tag_id FROM effective_file_tags WHERE file_id = ?")
+            .bind(file_id)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(Some(SchemaFileContext { path, mime_type, tag_ids: self.with_ancestors(tag_ids) }))
+    }
+}
+
+/// スキーマの条件の判定に使うファイルの情報
+struct SchemaFileContext {
+    path: String,
+    mime_type: Option<String>,
+    /// 継承したものと上位のものを含むタグ
+    tag_ids: HashSet<String>,
+}
+
+/// ファイルのタグ・カスタムメタデータの変更履歴
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct AnnotationHistoryEntry {
+    pub id: i64,
+    pub file_id: String,
+    /// 'set', 'delete', 'tag_added', 'tag_removed'
+    pub action: String,
+    pub key_id: Option<String>,
+    pub tag_id: Option<String>,
+    /// タグの変更ではタグ名
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_by: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// 定義されていないキーを参照しているカスタムメタデータ値
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct OrphanedMetadataValues {
+    pub key_id: String,
+    pub value_count: i64,
+}
+
+/// カスタムメタデータの一括操作の結果
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct BulkMetadataResult {
+    /// 対象になったファイル数
+    pub file_count: usize,
+    /// 値を書き込んだ・削除したファイル数
+    pub updated: u64,
+    /// 処理できなかったファイルと理由（他のファイルの処理は続ける）
+    pub failures: Vec<BulkMetadataFailure>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct BulkMetadataFailure {
+    pub file_id: String,
+    pub message: String,
+}
+
+impl BulkMetadataResult {
+    fn new(file_count: usize) -> Self {
+        Self { file_count, updated: 0, failures: Vec::new() }
+    }
+
+    fn fail(&mut self, file_id: &str, message: &str) {
+        self.failures.push(BulkMetadataFailure {
+            file_id: file_id.to_string(),
+            message: message.to_string(),
+        });
+    }
+}
+
+/// 取り込む行とファイルを照合する項目
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileMatchField {
+    Path,
+    /// 登録ディレクトリからの相対パス
+    RelativePath,
+    ContentHash,
+    FileId,
+}
+
+/// 取り込む1行分（照合済みのファイルと書き込むタグ・値）
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataImportRow {
+    /// 取り込み元での行番号
+    pub row: usize,
+    pub file_id: String,
+    /// 追加するタグのパス
+    pub tags: Vec<String>,
+    /// キーIDと値
+    pub values: Vec<(String, String)>,
+}
+
+/// 1行分の取り込み結果（error のある行は何も書き込まない）
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct MetadataImportRowResult {
+    pub row: usize,
+    pub file_id: String,
+    pub tags_added: u64,
+    pub values_set: u64,
+    pub error: Option<String>,
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
 pub struct CustomMetadataValue {
     pub id: String,
@@ -70,6 +614,26 @@ pub struct CustomMetadataValue {
     pub updated_at: DateTime<Utc>,
 }
 
+/// ディスクから消えたファイルのタグ・カスタムメタデータを保持する墓標
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct FileTombstone {
+    pub id: String,
+    pub original_file_id: String,
+    pub path: String,
+    pub name: String,
+    pub directory_id: String,
+    pub size: i64,
+    pub content_hash: Option<String>,
+    pub inode: Option<i64>,
+    pub device_id: Option<i64>,
+    pub birth_time: Option<DateTime<Utc>>,
+    pub is_directory: bool,
+    pub tags: Vec<Tag>,
+    /// key_id -> value
+    pub custom_metadata: serde_json::Value,
+    pub deleted_at: DateTime<Utc>,
+}
+
 #[cfg(test)]
 use mockall::predicate::*;
 
@@ -83,7 +647,39 @@ pub trait DatabaseTrait {
         name: &str,
     ) -> Result<Directory, sqlx::Error>;
     async fn get_directories(&self, pool: &SqlitePool) -> Result<Vec<Directory>, sqlx::Error>;
-    async fn add_file(&self, pool: &SqlitePool, file: &File) -> Result<(), sqlx::Error>;
+    // 登録ディレクトリの移動・統合
+    async fn relocate_directory(
+        &self,
+        pool: &SqlitePool,
+        directory_id: &str,
+        new_path: &str,
+        new_name: &str,
+    ) -> Result<(), sqlx::Error>;
+    async fn assign_files_to_directory(
+        &self,
+        pool: &SqlitePool,
+        root_path: &str,
+        from_directory_id: &str,
+        to_directory_id: &str,
+    ) -> Result<u64, sqlx::Error>;
+    // 外側の登録から配下のファイルを引き継ぎ、内側の登録を吸収して追加する（1トランザクションで実行）
+    async fn add_directory_with_overlap(
+        &self,
+        pool: &SqlitePool,
+        path: &str,
+        name: &str,
+        parent_id: Option<String>,
+        absorbed_ids: &[String],
+    ) -> Result<Directory, sqlx::Error>;
+    // 登録を削除し、ファイルと墓標は外側の登録に引き継ぐ。削除した孤児タグのIDを返す
+    async fn remove_directory(
+        &self,
+        pool: &SqlitePool,
+        directory_id: &str,
+        parent_id: Option<String>,
+    ) -> Result<Vec<String>, sqlx::Error>;
+    // 同じパスが登録済みの場合はIDを保ったまま更新し、保存されたIDを返す
+    async fn add_file(&self, pool: &SqlitePool, file: &File) -> Result<String, sqlx::Error>;
     async fn get_files_by_directory_sorted(
         &self,
         pool: &SqlitePool,
@@ -176,11 +772,86 @@ pub trait DatabaseTrait {
         tag_id: &str,
     ) -> Result<(), sqlx::Error>;
     async fn delete_orphaned_tags(&self, pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error>;
+    async fn set_tag_pinned(&self, pool: &SqlitePool, tag_id: &str, pinned: bool) -> Result<Tag, sqlx::Error>;
+    async fn delete_unused_tags(
+        &self,
+        pool: &SqlitePool,
+        include_pinned: bool,
+        dry_run: bool,
+    ) -> Result<Vec<Tag>, sqlx::Error>;
+    // 複数ファイルへの一括タグ操作（1トランザクションで実行し、変更された行数を返す）
+    async fn bulk_add_file_tags(
+        &self,
+        pool: &SqlitePool,
+        file_ids: &[String],
+        tag_ids: &[String],
+    ) -> Result<u64, sqlx::Error>;
+    async fn bulk_remove_file_tags(
+        &self,
+        pool: &SqlitePool,
+        file_ids: &[String],
+        tag_ids: &[String],
+    ) -> Result<u64, sqlx::Error>;
+    async fn bulk_toggle_file_tag(
+        &self,
+        pool: &SqlitePool,
+        file_ids: &[String],
+        tag_id: &str,
+    ) -> Result<(u64, u64), sqlx::Error>;
+    // 階層タグ
+    async fn ensure_tag_path(&self, pool: &SqlitePool, path: &str, color: &str) -> Result<Tag, sqlx::Error>;
+    async fn move_tag(
+        &self,
+        pool: &SqlitePool,
+        tag_id: &str,
+        new_parent_id: Option<String>,
+    ) -> Result<Tag, sqlx::Error>;
+    async fn get_tag_subtree_ids(&self, pool: &SqlitePool, tag_id: &str) -> Result<Vec<String>, sqlx::Error>;
+    // タグの編集・統合・別名
+    async fn rename_tag(&self, pool: &SqlitePool, tag_id: &str, new_label: &str) -> Result<Tag, sqlx::Error>;
+    async fn update_tag_color(&self, pool: &SqlitePool, tag_id: &str, color: &str) -> Result<Tag, sqlx::Error>;
+    async fn merge_tags(&self, pool: &SqlitePool, source_id: &str, target_id: &str) -> Result<Tag, sqlx::Error>;
+    async fn add_tag_alias(&self, pool: &SqlitePool, tag_id: &str, alias: &str) -> Result<(), sqlx::Error>;
+    async fn remove_tag_alias(&self, pool: &SqlitePool, alias: &str) -> Result<(), sqlx::Error>;
+    async fn get_tag_aliases(&self, pool: &SqlitePool, tag_id: &str) -> Result<Vec<String>, sqlx::Error>;
+    async fn resolve_tag_alias(&self, pool: &SqlitePool, alias: &str) -> Result<Option<Tag>, sqlx::Error>;
+    async fn get_tag_file_counts(&self, pool: &SqlitePool) -> Result<HashMap<String, (i64, i64)>, sqlx::Error>;
+    // タグの利用統計
+    async fn get_tag_usage(&self, pool: &SqlitePool) -> Result<Vec<TagUsage>, sqlx::Error>;
+    async fn get_tag_counts_by_directory(&self, pool: &SqlitePool) -> Result<Vec<TagGroupCount>, sqlx::Error>;
+    async fn get_tag_counts_by_extension(&self, pool: &SqlitePool) -> Result<Vec<TagGroupCount>, sqlx::Error>;
+    /// `period_format` は strftime の書式（例: `%Y-%m`）
+    async fn get_tag_counts_by_period(
+        &self,
+        pool: &SqlitePool,
+        period_format: &str,
+    ) -> Result<Vec<TagGroupCount>, sqlx::Error>;
+    async fn get_tag_pair_counts(&self, pool: &SqlitePool, limit: u32) -> Result<Vec<TagPairCount>, sqlx::Error>;
+    // タグの候補（複数ファイルの場合は平均したスコアで並べる）
+    async fn suggest_tags(
+        &self,
+        pool: &SqlitePool,
+        file_ids: &[String],
+        limit: usize,
+    ) -> Result<Vec<TagSuggestion>, sqlx::Error>;
     async fn get_file_tags(
         &self,
         pool: &SqlitePool,
         file_id: &str,
     ) -> Result<Vec<Tag>, sqlx::Error>;
+    // 直接のタグとディレクトリから継承したタグ
+    async fn get_file_tag_assignments(
+        &self,
+        pool: &SqlitePool,
+        file_id: &str,
+    ) -> Result<Vec<FileTagAssignment>, sqlx::Error>;
+    async fn set_tag_include_subfolders(
+        &self,
+        pool: &SqlitePool,
+        file_id: &str,
+        tag_id: &str,
+        include_subfolders: bool,
+    ) -> Result<(), sqlx::Error>;
     // カスタムメタデータキー管理（設定用データベース）
     async fn create_custom_metadata_key(
         &self,
@@ -196,17 +867,69 @@ pub trait DatabaseTrait {
         settings_pool: &SqlitePool,
         key: &CustomMetadataKey,
     ) -> Result<CustomMetadataKey, sqlx::Error>;
+    /// 型・選択肢・検証パターンが変わる場合はすべてのシェルフの値も新しい定義に変換する
+    /// 各シェルフの変換はキー定義の書き込みが成功してからコミットし、変換できない値があれば
+    /// drop_invalid でない限りどのシェルフにも書き込まない（drop_invalid の場合はそれらの値を削除する）
+    async fn update_custom_metadata_key_in_shelves(
+        &self,
+        settings_pool: &SqlitePool,
+        data_pools: &[SqlitePool],
+        previous: &CustomMetadataKey,
+        key: &CustomMetadataKey,
+        drop_invalid: bool,
+    ) -> Result<CustomMetadataKey, MetadataError>;
+    /// キーの値はすべてのシェルフのデータベースから削除する（キー定義の削除が成功してからコミットする）
     async fn delete_custom_metadata_key(
         &self,
         settings_pool: &SqlitePool,
-        data_pool: &SqlitePool,
+        data_pools: &[SqlitePool],
         key_id: &str,
     ) -> Result<(), sqlx::Error>;
+    /// キー定義の変更に合わせて既存の値を変換し、変換できなかった値を返す
+    /// apply が false の場合は書き込まない。drop_invalid の場合は変換できなかった値を削除する
+    async fn convert_custom_metadata_values(
+        &self,
+        data_pool: &SqlitePool,
+        previous: &CustomMetadataKey,
+        key: &CustomMetadataKey,
+        apply: bool,
+        drop_invalid: bool,
+    ) -> Result<Vec<CustomMetadataViolation>, sqlx::Error>;
+    /// key_ids にないキーの値を数える。delete の場合はそれらの値を削除する
+    async fn find_orphaned_custom_metadata_values(
+        &self,
+        data_pool: &SqlitePool,
+        key_ids: &[String],
+        delete: bool,
+    ) -> Result<Vec<OrphanedMetadataValues>, sqlx::Error>;
     async fn get_custom_metadata_key_by_name(
         &self,
         settings_pool: &SqlitePool,
         name: &str,
     ) -> Result<Option<CustomMetadataKey>, sqlx::Error>;
+    // カスタムメタデータのスキーマ
+    async fn create_custom_metadata_schema(
+        &self,
+        settings_pool: &SqlitePool,
+        schema: &CustomMetadataSchema,
+    ) -> Result<CustomMetadataSchema, sqlx::Error>;
+    async fn get_all_custom_metadata_schemas(
+        &self,
+        settings_pool: &SqlitePool,
+    ) -> Result<Vec<CustomMetadataSchema>, sqlx::Error>;
+    async fn update_custom_metadata_schema(
+        &self,
+        settings_pool: &SqlitePool,
+        schema: &CustomMetadataSchema,
+    ) -> Result<CustomMetadataSchema, sqlx::Error>;
+    async fn delete_custom_metadata_schema(&self, settings_pool: &SqlitePool, schema_id: &str) -> Result<(), sqlx::Error>;
+    /// ファイルに適用されるキー（どのスキーマにも属さないキーと、ファイルが条件に一致するスキーマのキー）
+    async fn get_applicable_custom_metadata_keys(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+        file_id: &str,
+    ) -> Result<Vec<CustomMetadataKey>, sqlx::Error>;
     // カスタムメタデータ値管理（データ用データベース、設定用データベースの参照が必要）
     async fn set_custom_metadata_value(
         &self,
@@ -215,24 +938,115 @@ pub trait DatabaseTrait {
         file_id: &str,
         key_id: &str,
         value: Option<String>,
-    ) -> Result<CustomMetadataValue, sqlx::Error>;
+    ) -> Result<CustomMetadataValue, MetadataError>;
+    // 一括操作（1つのトランザクションで実行し、処理できなかったファイルは結果に含める）
+    async fn bulk_set_custom_metadata_value(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+        file_ids: &[String],
+        key_id: &str,
+        value: Option<String>,
+    ) -> Result<BulkMetadataResult, sqlx::Error>;
+    async fn bulk_clear_custom_metadata_value(
+        &self,
+        data_pool: &SqlitePool,
+        file_ids: &[String],
+        key_id: &str,
+    ) -> Result<BulkMetadataResult, sqlx::Error>;
+    async fn copy_custom_metadata(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+        source_file_id: &str,
+        target_file_ids: &[String],
+        key_ids: Option<Vec<String>>,
+        overwrite: bool,
+    ) -> Result<BulkMetadataResult, sqlx::Error>;
     async fn get_custom_metadata_values_by_file(
         &self,
         data_pool: &SqlitePool,
         file_id: &str,
     ) -> Result<Vec<CustomMetadataValue>, sqlx::Error>;
-    async fn get_custom_metadata_value(
+    async fn get_custom_metadata_values_by_files(
         &self,
         data_pool: &SqlitePool,
+        file_ids: &[String],
+    ) -> Result<Vec<CustomMetadataValue>, sqlx::Error>;
+    // 変更履歴（新しい順）
+    async fn get_annotation_history(
+        &self,
+        pool: &SqlitePool,
         file_id: &str,
-        key_id: &str,
-    ) -> Result<Option<CustomMetadataValue>, sqlx::Error>;
-    async fn delete_custom_metadata_value(
+        key_id: Option<String>,
+        limit: Option<u32>,
+    ) -> Result<Vec<AnnotationHistoryEntry>, sqlx::Error>;
+    /// 履歴の変更を取り消し、値を変更前に戻す。戻した値（削除した場合は None）を返す
+    async fn revert_custom_metadata_change(
         &self,
         data_pool: &SqlitePool,
-        file_id: &str,
-        key_id: &str,
-    ) -> Result<(), sqlx::Error>;
+        settings_pool: &SqlitePool,
+        history_id: i64,
+    ) -> Result<Option<CustomMetadataValue>, MetadataError>;
+    // 計算フィールド（入力が変わったファイルを待ち行列に入れ、値を読む前に計算し直す）
+    /// file_ids を指定しなければすべてのファイルを計算し直す対象にする
+    async fn mark_computed_metadata_stale(
+        &self,
+        pool: &SqlitePool,
+        file_ids: Option<Vec<String>>,
+    ) -> Result<u64, sqlx::Error>;
+    async fn get_stale_computed_file_ids(&self, pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error>;
+    /// 計算した値（file_id, key_id, 値）を書き込み、file_ids を待ち行列から外す。値が None なら消す
+    /// 戻り値は変わった値の数
+    async fn store_computed_metadata_values(
+        &self,
+        pool: &SqlitePool,
+        keys: &[CustomMetadataKey],
+        file_ids: &[String],
+        values: &[(String, String, Option<String>)],
+    ) -> Result<u64, sqlx::Error>;
+    // 取り込み・書き出し
+    async fn get_files_by_ids(&self, pool: &SqlitePool, file_ids: &[String]) -> Result<Vec<File>, sqlx::Error>;
+    async fn get_file_tags_by_files(
+        &self,
+        pool: &SqlitePool,
+        file_ids: &[String],
+    ) -> Result<Vec<(String, Tag)>, sqlx::Error>;
+    async fn match_file_ids(
+        &self,
+        pool: &SqlitePool,
+        field: FileMatchField,
+        value: &str,
+    ) -> Result<Vec<String>, sqlx::Error>;
+    /// apply が false の場合は書き込みを取り消し、結果だけを返す
+    /// skip_invalid が false の場合は、不正な行が1行でもあればすべての行を取り消す
+    async fn import_file_metadata(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+        rows: &[MetadataImportRow],
+        tag_color: &str,
+        apply: bool,
+        skip_invalid: bool,
+    ) -> Result<Vec<MetadataImportRowResult>, sqlx::Error>;
+    // 必須キーの欠落と、型・検証パターンに合わない値を列挙する（ディレクトリは対象外）
+    async fn audit_custom_metadata(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+    ) -> Result<Vec<CustomMetadataViolation>, sqlx::Error>;
+    async fn get_custom_metadata_value(
+        &self,
+        data_pool: &SqlitePool,
+        file_id: &str,
+        key_id: &str,
+    ) -> Result<Option<CustomMetadataValue>, sqlx::Error>;
+    async fn delete_custom_metadata_value(
+        &self,
+        data_pool: &SqlitePool,
+        file_id: &str,
+        key_id: &str,
+    ) -> Result<(), sqlx::Error>;
     // ファイル監視に必要な関数
     async fn remove_file_by_path(&self, pool: &SqlitePool, path: &str) -> Result<(), sqlx::Error>;
     async fn update_file_metadata(
@@ -241,6 +1055,12 @@ pub trait DatabaseTrait {
         path: &str,
         metadata: &std::fs::Metadata,
     ) -> Result<(), sqlx::Error>;
+    async fn update_file_content_hash(
+        &self,
+        pool: &SqlitePool,
+        path: &str,
+        content_hash: Option<String>,
+    ) -> Result<(), sqlx::Error>;
     async fn file_exists_by_path(&self, pool: &SqlitePool, path: &str)
         -> Result<bool, sqlx::Error>;
     async fn find_file_by_inode(
@@ -249,6 +1069,13 @@ pub trait DatabaseTrait {
         inode: i64,
         device_id: Option<i64>,
     ) -> Result<Option<File>, sqlx::Error>;
+    async fn find_file_by_identity(
+        &self,
+        pool: &SqlitePool,
+        device_id: Option<i64>,
+        inode: i64,
+        birth_time: Option<DateTime<Utc>>,
+    ) -> Result<Option<File>, sqlx::Error>;
     async fn update_file_path(
         &self,
         pool: &SqlitePool,
@@ -256,6 +1083,59 @@ pub trait DatabaseTrait {
         new_path: &str,
         new_name: &str,
     ) -> Result<(), sqlx::Error>;
+    // ハードリンク（同じ device_id + inode を共有するファイル）
+    async fn get_hard_link_siblings(&self, pool: &SqlitePool, file_id: &str) -> Result<Vec<File>, sqlx::Error>;
+    async fn get_hard_link_groups(&self, pool: &SqlitePool) -> Result<Vec<Vec<File>>, sqlx::Error>;
+    // 墓標（消えたファイルの注釈保持）
+    async fn remove_files_by_directory(&self, pool: &SqlitePool, directory_id: &str) -> Result<(), sqlx::Error>;
+    async fn get_file_tombstones(&self, pool: &SqlitePool) -> Result<Vec<FileTombstone>, sqlx::Error>;
+    async fn get_file_tombstone(
+        &self,
+        pool: &SqlitePool,
+        tombstone_id: &str,
+    ) -> Result<Option<FileTombstone>, sqlx::Error>;
+    // inode・device_id・作成日時が一致する墓標
+    async fn find_tombstone_by_identity(
+        &self,
+        pool: &SqlitePool,
+        file: &File,
+    ) -> Result<Option<FileTombstone>, sqlx::Error>;
+    // 内容ハッシュとサイズが一致する墓標（複数あれば最も新しいもの）
+    async fn find_tombstone_by_content(
+        &self,
+        pool: &SqlitePool,
+        file: &File,
+    ) -> Result<Option<FileTombstone>, sqlx::Error>;
+    // 内容ハッシュとサイズが墓標と一致するファイル（関連付けの候補）
+    async fn find_tombstone_candidates(&self, pool: &SqlitePool, tombstone_id: &str) -> Result<Vec<File>, sqlx::Error>;
+    async fn restore_file_tombstone(
+        &self,
+        pool: &SqlitePool,
+        tombstone_id: &str,
+        file_id: &str,
+    ) -> Result<(), sqlx::Error>;
+    async fn delete_file_tombstone(&self, pool: &SqlitePool, tombstone_id: &str) -> Result<(), sqlx::Error>;
+    async fn purge_expired_tombstones(
+        &self,
+        pool: &SqlitePool,
+        older_than: DateTime<Utc>,
+    ) -> Result<u64, sqlx::Error>;
+    // 拡張属性タグの同期（最後に拡張属性と一致していたタグ名）
+    async fn get_xattr_synced_tags(&self, pool: &SqlitePool, file_id: &str) -> Result<Vec<String>, sqlx::Error>;
+    async fn set_xattr_synced_tags(
+        &self,
+        pool: &SqlitePool,
+        file_id: &str,
+        tag_names: &[String],
+    ) -> Result<(), sqlx::Error>;
+    // XMPサイドカーのキーワードの同期（最後にサイドカーと一致していたタグ名）
+    async fn get_xmp_synced_tags(&self, pool: &SqlitePool, file_id: &str) -> Result<Vec<String>, sqlx::Error>;
+    async fn set_xmp_synced_tags(
+        &self,
+        pool: &SqlitePool,
+        file_id: &str,
+        tag_names: &[String],
+    ) -> Result<(), sqlx::Error>;
 }
 
 pub struct Database;
@@ -292,6 +1172,7 @@ impl DatabaseTrait for Database {
             name: name.to_string(),
             created_at: now,
             updated_at: now,
+            follow_symlinks: false,
         })
     }
 
@@ -308,6 +1189,7 @@ impl DatabaseTrait for Database {
                 name: row.get("name"),
                 created_at: row.get("created_at"),
                 updated_at: row.get("updated_at"),
+                follow_symlinks: row.get("follow_symlinks"),
             });
         }
 
@@ -315,9 +1197,200 @@ impl DatabaseTrait for Database {
     }
 
 
-    async fn add_file(&self, pool: &SqlitePool, file: &File) -> Result<(), sqlx::Error> {
+    async fn relocate_directory(
+        &self,
+        pool: &SqlitePool,
+        directory_id: &str,
+        new_path: &str,
+        new_name: &str,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let now = Utc::now();
+
+        let old_path: String = sqlx::query_scalar("SELECT path FROM directories WHERE id = ?")
+            .bind(directory_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let prefix = format!("{}/", old_path.trim_end_matches('/'));
+        let prefix_len = prefix.chars().count() as i64;
+        // 旧パス部分を置き換える（substr は1始まり）
+        let rest_start = old_path.chars().count() as i64 + 1;
+
+        // 配下に登録されたディレクトリも一緒に移動する
+        sqlx::query(
+            "UPDATE directories SET path = ? || substr(path, ?), updated_at = ?
+             WHERE path = ? OR substr(path, 1, ?) = ?",
+        )
+        .bind(new_path)
+        .bind(rest_start)
+        .bind(now)
+        .bind(&old_path)
+        .bind(prefix_len)
+        .bind(&prefix)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("UPDATE directories SET name = ? WHERE id = ?")
+            .bind(new_name)
+            .bind(directory_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            "UPDATE files SET path = ? || substr(path, ?), updated_at_db = ?
+             WHERE path = ? OR substr(path, 1, ?) = ?",
+        )
+        .bind(new_path)
+        .bind(rest_start)
+        .bind(now)
+        .bind(&old_path)
+        .bind(prefix_len)
+        .bind(&prefix)
+        .execute(&mut *tx)
+        .await?;
+
+        // ルート自体のエントリは名前も変わる
+        let root_name = std::path::Path::new(new_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(new_name);
+        sqlx::query("UPDATE files SET name = ? WHERE path = ?")
+            .bind(root_name)
+            .bind(new_path)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            "UPDATE file_tombstones SET path = ? || substr(path, ?)
+             WHERE path = ? OR substr(path, 1, ?) = ?",
+        )
+        .bind(new_path)
+        .bind(rest_start)
+        .bind(&old_path)
+        .bind(prefix_len)
+        .bind(&prefix)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn assign_files_to_directory(
+        &self,
+        pool: &SqlitePool,
+        root_path: &str,
+        from_directory_id: &str,
+        to_directory_id: &str,
+    ) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let moved = assign_files_in(&mut tx, root_path, from_directory_id, to_directory_id).await?;
+        tx.commit().await?;
+        Ok(moved)
+    }
+
+    async fn add_directory_with_overlap(
+        &self,
+        pool: &SqlitePool,
+        path: &str,
+        name: &str,
+        parent_id: Option<String>,
+        absorbed_ids: &[String],
+    ) -> Result<Directory, sqlx::Error> {
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now();
+        let mut tx = pool.begin().await?;
+
         sqlx::query(
-            "INSERT OR REPLACE INTO files (id, path, name, directory_id, size, file_type, created_at, modified_at, birth_time, inode, is_directory, created_at_db, updated_at_db, file_size, mime_type, permissions, owner_uid, group_gid, hard_links, device_id, last_accessed, metadata) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            "INSERT INTO directories (id, path, name, created_at, updated_at) VALUES (?, ?, ?, ?, ?)"
+        )
+        .bind(&id)
+        .bind(path)
+        .bind(name)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(parent_id) = parent_id {
+            assign_files_in(&mut tx, path, &parent_id, &id).await?;
+        }
+
+        // 吸収する登録のファイルと墓標を移してから削除する（削除で墓標が消えないように）
+        for absorbed_id in absorbed_ids {
+            sqlx::query("UPDATE files SET directory_id = ? WHERE directory_id = ?")
+                .bind(&id)
+                .bind(absorbed_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("UPDATE file_tombstones SET directory_id = ? WHERE directory_id = ?")
+                .bind(&id)
+                .bind(absorbed_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM directories WHERE id = ?")
+                .bind(absorbed_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(Directory {
+            id,
+            path: path.to_string(),
+            name: name.to_string(),
+            created_at: now,
+            updated_at: now,
+            follow_symlinks: false,
+        })
+    }
+
+    async fn remove_directory(
+        &self,
+        pool: &SqlitePool,
+        directory_id: &str,
+        parent_id: Option<String>,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        // 外側の登録があれば、ファイルと墓標をすべて移してから削除する（削除で墓標が消えないように）
+        if let Some(parent_id) = parent_id {
+            sqlx::query("UPDATE files SET directory_id = ? WHERE directory_id = ?")
+                .bind(&parent_id)
+                .bind(directory_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("UPDATE file_tombstones SET directory_id = ? WHERE directory_id = ?")
+                .bind(&parent_id)
+                .bind(directory_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        // 外側の登録がなければ、ファイルと墓標は ON DELETE CASCADE で削除される
+        sqlx::query("DELETE FROM directories WHERE id = ?")
+            .bind(directory_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let deleted_tag_ids = delete_orphaned_tags_in(&mut tx).await?;
+        tx.commit().await?;
+        Ok(deleted_tag_ids)
+    }
+
+    async fn add_file(&self, pool: &SqlitePool, file: &File) -> Result<String, sqlx::Error> {
+        let row = sqlx::query(
+            "INSERT INTO files (id, path, name, directory_id, size, file_type, created_at, modified_at, birth_time, inode, is_directory, created_at_db, updated_at_db, file_size, mime_type, permissions, owner_uid, group_gid, hard_links, device_id, last_accessed, metadata, content_hash, is_symlink, symlink_target, is_broken_link) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT(path) DO UPDATE SET
+                name = excluded.name, directory_id = excluded.directory_id, size = excluded.size,
+                file_type = excluded.file_type, created_at = excluded.created_at, modified_at = excluded.modified_at,
+                birth_time = excluded.birth_time, inode = excluded.inode, is_directory = excluded.is_directory,
+                updated_at_db = excluded.updated_at_db, file_size = excluded.file_size, mime_type = excluded.mime_type,
+                permissions = excluded.permissions, owner_uid = excluded.owner_uid, group_gid = excluded.group_gid,
+                hard_links = excluded.hard_links, device_id = excluded.device_id, last_accessed = excluded.last_accessed,
+                metadata = excluded.metadata, content_hash = excluded.content_hash, is_symlink = excluded.is_symlink,
+                symlink_target = excluded.symlink_target, is_broken_link = excluded.is_broken_link
+             RETURNING id"
         )
         .bind(&file.id)
         .bind(&file.path)
@@ -341,10 +1414,15 @@ impl DatabaseTrait for Database {
         .bind(file.device_id)
         .bind(file.last_accessed)
         .bind(&file.metadata)
-        .execute(pool)
+        .bind(&file.content_hash)
+        .bind(file.is_symlink)
+        .bind(&file.symlink_target)
+        .bind(file.is_broken_link)
+        .fetch_one(pool)
         .await?;
 
-        Ok(())
+        // 既存行を更新した場合は元のIDが返る（タグやメタデータの紐付けを保つ）
+        Ok(row.get("id"))
     }
 
 
@@ -404,6 +1482,10 @@ impl DatabaseTrait for Database {
                 device_id: row.get("device_id"),
                 last_accessed: row.get("last_accessed"),
                 metadata: row.get("metadata"),
+                content_hash: row.get("content_hash"),
+                is_symlink: row.get("is_symlink"),
+                symlink_target: row.get("symlink_target"),
+                is_broken_link: row.get("is_broken_link"),
             });
         }
 
@@ -469,6 +1551,10 @@ impl DatabaseTrait for Database {
                 device_id: row.get("device_id"),
                 last_accessed: row.get("last_accessed"),
                 metadata: row.get("metadata"),
+                content_hash: row.get("content_hash"),
+                is_symlink: row.get("is_symlink"),
+                symlink_target: row.get("symlink_target"),
+                is_broken_link: row.get("is_broken_link"),
             });
         }
 
@@ -554,6 +1640,10 @@ impl DatabaseTrait for Database {
                 device_id: row.get("device_id"),
                 last_accessed: row.get("last_accessed"),
                 metadata: row.get("metadata"),
+                content_hash: row.get("content_hash"),
+                is_symlink: row.get("is_symlink"),
+                symlink_target: row.get("symlink_target"),
+                is_broken_link: row.get("is_broken_link"),
             });
         }
 
@@ -636,6 +1726,10 @@ impl DatabaseTrait for Database {
                 device_id: row.get("device_id"),
                 last_accessed: row.get("last_accessed"),
                 metadata: row.get("metadata"),
+                content_hash: row.get("content_hash"),
+                is_symlink: row.get("is_symlink"),
+                symlink_target: row.get("symlink_target"),
+                is_broken_link: row.get("is_broken_link"),
             });
         }
 
@@ -699,6 +1793,8 @@ impl DatabaseTrait for Database {
                 name: row.get("name"),
                 color: row.get("color"),
                 created_at: row.get("created_at"),
+                parent_id: row.get("parent_id"),
+                pinned: row.get("pinned"),
             });
         }
 
@@ -707,10 +1803,10 @@ impl DatabaseTrait for Database {
 
     async fn get_top_tags(&self, pool: &SqlitePool, limit: u32) -> Result<Vec<Tag>, sqlx::Error> {
         let rows = sqlx::query(
-            "SELECT t.id, t.name, t.color, t.created_at, COUNT(ft.tag_id) as file_count
+            "SELECT t.id, t.name, t.color, t.created_at, t.parent_id, t.pinned, COUNT(ft.tag_id) as file_count
              FROM tags t
              LEFT JOIN file_tags ft ON t.id = ft.tag_id
-             GROUP BY t.id, t.name, t.color, t.created_at
+             GROUP BY t.id, t.name, t.color, t.created_at, t.parent_id, t.pinned
              ORDER BY file_count DESC, t.name ASC
              LIMIT ?",
         )
@@ -725,6 +1821,8 @@ impl DatabaseTrait for Database {
                 name: row.get("name"),
                 color: row.get("color"),
                 created_at: row.get("created_at"),
+                parent_id: row.get("parent_id"),
+                pinned: row.get("pinned"),
             });
         }
 
@@ -737,12 +1835,15 @@ impl DatabaseTrait for Database {
         query: &str,
     ) -> Result<Vec<Tag>, sqlx::Error> {
         let search_pattern = format!("%{query}%");
+        // 別名に一致した場合も正式なタグを返す
         let rows = sqlx::query(
             "SELECT * FROM tags 
              WHERE name LIKE ? 
+                OR id IN (SELECT tag_id FROM tag_aliases WHERE alias LIKE ?)
              ORDER BY name ASC",
         )
         .bind(&search_pattern)
+        .bind(&search_pattern)
         .fetch_all(pool)
         .await?;
 
@@ -753,6 +1854,8 @@ impl DatabaseTrait for Database {
                 name: row.get("name"),
                 color: row.get("color"),
                 created_at: row.get("created_at"),
+                parent_id: row.get("parent_id"),
+                pinned: row.get("pinned"),
             });
         }
 
@@ -760,16 +1863,26 @@ impl DatabaseTrait for Database {
     }
 
     async fn get_tag_by_name(&self, pool: &SqlitePool, name: &str) -> Result<Tag, sqlx::Error> {
-        let row = sqlx::query("SELECT * FROM tags WHERE name = ? LIMIT 1")
-            .bind(name)
-            .fetch_one(pool)
-            .await?;
+        // 一致するタグがなければ別名から正式なタグを引く
+        let row = sqlx::query(
+            "SELECT * FROM (
+                 SELECT t.*, 0 AS priority FROM tags t WHERE t.name = ?
+                 UNION ALL
+                 SELECT t.*, 1 AS priority FROM tags t INNER JOIN tag_aliases ta ON t.id = ta.tag_id WHERE ta.alias = ?
+             ) ORDER BY priority LIMIT 1",
+        )
+        .bind(name)
+        .bind(name)
+        .fetch_one(pool)
+        .await?;
 
         Ok(Tag {
             id: row.get("id"),
             name: row.get("name"),
             color: row.get("color"),
             created_at: row.get("created_at"),
+            parent_id: row.get("parent_id"),
+            pinned: row.get("pinned"),
         })
     }
 
@@ -795,6 +1908,8 @@ impl DatabaseTrait for Database {
             name: name.to_string(),
             color: color.to_string(),
             created_at: now,
+            parent_id: None,
+            pinned: false,
         })
     }
 
@@ -829,623 +1944,2094 @@ impl DatabaseTrait for Database {
     }
 
     async fn delete_orphaned_tags(&self, pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
-        // 参照されていないタグのIDを取得（ファイルタグのみをチェック）
-        let orphaned_tag_ids: Vec<String> = sqlx::query_scalar(
-            "SELECT t.id FROM tags t 
-             LEFT JOIN file_tags ft ON t.id = ft.tag_id 
-             WHERE ft.tag_id IS NULL",
-        )
-        .fetch_all(pool)
-        .await?;
+        let mut conn = pool.acquire().await?;
+        delete_orphaned_tags_in(&mut conn).await
+    }
 
-        // 参照されていないタグを削除
-        if !orphaned_tag_ids.is_empty() {
-            for tag_id in &orphaned_tag_ids {
-                sqlx::query("DELETE FROM tags WHERE id = ?")
+    async fn set_tag_pinned(&self, pool: &SqlitePool, tag_id: &str, pinned: bool) -> Result<Tag, sqlx::Error> {
+        let row = sqlx::query("UPDATE tags SET pinned = ? WHERE id = ? RETURNING *")
+            .bind(pinned)
+            .bind(tag_id)
+            .fetch_one(pool)
+            .await?;
+
+        Ok(tag_from_row(&row))
+    }
+
+    async fn delete_unused_tags(
+        &self,
+        pool: &SqlitePool,
+        include_pinned: bool,
+        dry_run: bool,
+    ) -> Result<Vec<Tag>, sqlx::Error> {
+        let query = if include_pinned { UNUSED_TAGS_QUERY } else { ORPHANED_TAGS_QUERY };
+        let mut tx = pool.begin().await?;
+        let mut deleted_tags = Vec::new();
+
+        // 子タグを削除すると親タグが未使用になるため、プレビューでも実際に削除してからロールバックする
+        loop {
+            let tag_ids: Vec<String> = sqlx::query_scalar(query)
+                .fetch_all(&mut *tx)
+                .await?;
+            if tag_ids.is_empty() {
+                break;
+            }
+
+            for tag_id in &tag_ids {
+                let row = sqlx::query("DELETE FROM tags WHERE id = ? RETURNING *")
                     .bind(tag_id)
-                    .execute(pool)
+                    .fetch_one(&mut *tx)
                     .await?;
+                deleted_tags.push(tag_from_row(&row));
             }
         }
 
-        Ok(orphaned_tag_ids)
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+
+        deleted_tags.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(deleted_tags)
     }
 
-    async fn get_file_tags(
+    async fn bulk_add_file_tags(
         &self,
         pool: &SqlitePool,
-        file_id: &str,
-    ) -> Result<Vec<Tag>, sqlx::Error> {
-        let rows = sqlx::query(
-            "SELECT t.* FROM tags t 
-             INNER JOIN file_tags ft ON t.id = ft.tag_id 
-             WHERE ft.file_id = ? 
-             ORDER BY t.name",
-        )
-        .bind(file_id)
-        .fetch_all(pool)
-        .await?;
-
-        let mut tags = Vec::new();
-        for row in rows {
-            tags.push(Tag {
-                id: row.get("id"),
-                name: row.get("name"),
-                color: row.get("color"),
-                created_at: row.get("created_at"),
-            });
+        file_ids: &[String],
+        tag_ids: &[String],
+    ) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let mut added = 0;
+
+        for file_id in file_ids {
+            for tag_id in tag_ids {
+                added += sqlx::query("INSERT OR IGNORE INTO file_tags (file_id, tag_id) VALUES (?, ?)")
+                    .bind(file_id)
+                    .bind(tag_id)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
         }
 
-        Ok(tags)
+        tx.commit().await?;
+        Ok(added)
     }
 
-    async fn create_custom_metadata_key(
+    async fn bulk_remove_file_tags(
         &self,
-        settings_pool: &SqlitePool,
-        key: &CustomMetadataKey,
-    ) -> Result<CustomMetadataKey, sqlx::Error> {
-        let id = Uuid::new_v4().to_string();
-        let now = Utc::now();
-
-        sqlx::query(
-            "INSERT INTO custom_metadata_keys (id, name, display_name, data_type, description, is_required, default_value, validation_pattern, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
-        )
-        .bind(&id)
-        .bind(&key.name)
-        .bind(&key.display_name)
-        .bind(&key.data_type)
-        .bind(&key.description)
-        .bind(key.is_required)
-        .bind(&key.default_value)
-        .bind(&key.validation_pattern)
-        .bind(now)
-        .bind(now)
-        .execute(settings_pool)
-        .await?;
+        pool: &SqlitePool,
+        file_ids: &[String],
+        tag_ids: &[String],
+    ) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let mut removed = 0;
+
+        for file_id in file_ids {
+            for tag_id in tag_ids {
+                removed += sqlx::query("DELETE FROM file_tags WHERE file_id = ? AND tag_id = ?")
+                    .bind(file_id)
+                    .bind(tag_id)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
+        }
 
-        Ok(CustomMetadataKey {
-            id,
-            name: key.name.clone(),
-            display_name: key.display_name.clone(),
-            data_type: key.data_type.clone(),
-            description: key.description.clone(),
-            is_required: key.is_required,
-            default_value: key.default_value.clone(),
-            validation_pattern: key.validation_pattern.clone(),
-            created_at: now,
-            updated_at: now,
-        })
+        tx.commit().await?;
+        Ok(removed)
     }
 
-    async fn get_all_custom_metadata_keys(
+    async fn bulk_toggle_file_tag(
         &self,
-        settings_pool: &SqlitePool,
-    ) -> Result<Vec<CustomMetadataKey>, sqlx::Error> {
-        let rows = sqlx::query("SELECT * FROM custom_metadata_keys ORDER BY name")
-            .fetch_all(settings_pool)
+        pool: &SqlitePool,
+        file_ids: &[String],
+        tag_id: &str,
+    ) -> Result<(u64, u64), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        // すべてのファイルに付いていれば外し、そうでなければ付いていないファイルに付ける
+        let mut missing = Vec::new();
+        for file_id in file_ids {
+            let has_tag: bool = sqlx::query_scalar(
+                "SELECT EXISTS(SELECT 1 FROM file_tags WHERE file_id = ? AND tag_id = ?)",
+            )
+            .bind(file_id)
+            .bind(tag_id)
+            .fetch_one(&mut *tx)
             .await?;
+            if !has_tag {
+                missing.push(file_id);
+            }
+        }
 
-        let mut keys = Vec::new();
-        for row in rows {
-            keys.push(CustomMetadataKey {
-                id: row.get("id"),
-                name: row.get("name"),
-                display_name: row.get("display_name"),
-                data_type: row.get("data_type"),
-                description: row.get("description"),
-                is_required: row.get("is_required"),
-                default_value: row.get("default_value"),
-                validation_pattern: row.get("validation_pattern"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            });
+        let (mut added, mut removed) = (0, 0);
+        if missing.is_empty() {
+            for file_id in file_ids {
+                removed += sqlx::query("DELETE FROM file_tags WHERE file_id = ? AND tag_id = ?")
+                    .bind(file_id)
+                    .bind(tag_id)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
+        } else {
+            for file_id in missing {
+                added += sqlx::query("INSERT OR IGNORE INTO file_tags (file_id, tag_id) VALUES (?, ?)")
+                    .bind(file_id)
+                    .bind(tag_id)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
         }
 
-        Ok(keys)
+        tx.commit().await?;
+        Ok((added, removed))
     }
 
-    async fn update_custom_metadata_key(
-        &self,
-        settings_pool: &SqlitePool,
-        key: &CustomMetadataKey,
-    ) -> Result<CustomMetadataKey, sqlx::Error> {
-        let now = Utc::now();
+    async fn ensure_tag_path(&self, pool: &SqlitePool, path: &str, color: &str) -> Result<Tag, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let tag = ensure_tag_path_in(&mut tx, path, color).await?;
+        tx.commit().await?;
+        Ok(tag)
+    }
 
-        sqlx::query(
-            "UPDATE custom_metadata_keys SET display_name = ?, data_type = ?, description = ?, is_required = ?, default_value = ?, validation_pattern = ?, updated_at = ? WHERE id = ?"
-        )
-        .bind(&key.display_name)
-        .bind(&key.data_type)
-        .bind(&key.description)
-        .bind(key.is_required)
-        .bind(&key.default_value)
-        .bind(&key.validation_pattern)
-        .bind(now)
-        .bind(&key.id)
-        .execute(settings_pool)
-        .await?;
-
-        Ok(CustomMetadataKey {
-            id: key.id.clone(),
-            name: key.name.clone(),
-            display_name: key.display_name.clone(),
-            data_type: key.data_type.clone(),
-            description: key.description.clone(),
-            is_required: key.is_required,
-            default_value: key.default_value.clone(),
-            validation_pattern: key.validation_pattern.clone(),
-            created_at: key.created_at,
-            updated_at: now,
-        })
-    }
-
-    async fn delete_custom_metadata_key(
+    async fn move_tag(
         &self,
-        settings_pool: &SqlitePool,
-        data_pool: &SqlitePool,
-        key_id: &str,
-    ) -> Result<(), sqlx::Error> {
-        // まず、このキーを参照しているカスタムメタデータ値を削除
-        sqlx::query("DELETE FROM custom_metadata_values WHERE key_id = ?")
-            .bind(key_id)
-            .execute(data_pool)
-            .await?;
+        pool: &SqlitePool,
+        tag_id: &str,
+        new_parent_id: Option<String>,
+    ) -> Result<Tag, sqlx::Error> {
+        let mut tx = pool.begin().await?;
 
-        // その後、キー定義を削除
-        sqlx::query("DELETE FROM custom_metadata_keys WHERE id = ?")
-            .bind(key_id)
-            .execute(settings_pool)
+        let row = sqlx::query("SELECT * FROM tags WHERE id = ?")
+            .bind(tag_id)
+            .fetch_one(&mut *tx)
             .await?;
+        let tag = tag_from_row(&row);
 
-        Ok(())
-    }
+        let new_name = match &new_parent_id {
+            Some(parent_id) => {
+                let parent_name: String = sqlx::query_scalar("SELECT name FROM tags WHERE id = ?")
+                    .bind(parent_id)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                format!("{parent_name}/{}", tag.label())
+            }
+            None => tag.label().to_string(),
+        };
 
-    async fn get_custom_metadata_key_by_name(
-        &self,
-        settings_pool: &SqlitePool,
-        name: &str,
-    ) -> Result<Option<CustomMetadataKey>, sqlx::Error> {
-        let row = sqlx::query("SELECT * FROM custom_metadata_keys WHERE name = ?")
-            .bind(name)
-            .fetch_optional(settings_pool)
+        rename_tag_subtree(&mut tx, &tag, &new_name).await?;
+
+        sqlx::query("UPDATE tags SET parent_id = ? WHERE id = ?")
+            .bind(&new_parent_id)
+            .bind(tag_id)
+            .execute(&mut *tx)
             .await?;
 
-        match row {
-            Some(row) => Ok(Some(CustomMetadataKey {
-                id: row.get("id"),
-                name: row.get("name"),
-                display_name: row.get("display_name"),
-                data_type: row.get("data_type"),
-                description: row.get("description"),
-                is_required: row.get("is_required"),
-                default_value: row.get("default_value"),
-                validation_pattern: row.get("validation_pattern"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            })),
-            None => Ok(None),
-        }
+        tx.commit().await?;
+
+        Ok(Tag {
+            name: new_name,
+            parent_id: new_parent_id,
+            ..tag
+        })
     }
 
-    async fn set_custom_metadata_value(
-        &self,
-        data_pool: &SqlitePool,
-        settings_pool: &SqlitePool,
-        file_id: &str,
-        key_id: &str,
-        value: Option<String>,
-    ) -> Result<CustomMetadataValue, sqlx::Error> {
-        // キーが存在することを確認
-        let key_exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM custom_metadata_keys WHERE id = ?")
-            .bind(key_id)
-            .fetch_one(settings_pool)
+    async fn rename_tag(&self, pool: &SqlitePool, tag_id: &str, new_label: &str) -> Result<Tag, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let row = sqlx::query("SELECT * FROM tags WHERE id = ?")
+            .bind(tag_id)
+            .fetch_one(&mut *tx)
             .await?;
-        
-        if key_exists == 0 {
-            return Err(sqlx::Error::RowNotFound);
-        }
+        let tag = tag_from_row(&row);
 
-        let now = Utc::now();
+        // 親の配下にある場合は親のパスを保つ
+        let new_name = match tag.name.rsplit_once('/') {
+            Some((parent_path, _)) if tag.parent_id.is_some() => format!("{parent_path}/{new_label}"),
+            _ => new_label.to_string(),
+        };
+        rename_tag_subtree(&mut tx, &tag, &new_name).await?;
 
-        // 既存のレコードをチェック
-        if let Ok(existing) = sqlx::query_as::<_, (String, DateTime<Utc>)>(
-            "SELECT id, created_at FROM custom_metadata_values WHERE file_id = ? AND key_id = ?"
-        )
-        .bind(file_id)
-        .bind(key_id)
-        .fetch_one(data_pool)
-        .await
-        {
-            // 既存レコードがある場合は更新
-            sqlx::query(
-                "UPDATE custom_metadata_values SET value = ?, updated_at = ? WHERE file_id = ? AND key_id = ?"
-            )
-            .bind(value.as_deref())
-            .bind(now)
-            .bind(file_id)
-            .bind(key_id)
-            .execute(data_pool)
-            .await?;
+        tx.commit().await?;
+        Ok(Tag {
+            name: new_name,
+            ..tag
+        })
+    }
 
-            Ok(CustomMetadataValue {
-                id: existing.0,
-                file_id: file_id.to_string(),
-                key_id: key_id.to_string(),
-                value,
-                created_at: existing.1,
-                updated_at: now,
-            })
-        } else {
-            // 既存レコードがない場合は新規作成
-            let id = Uuid::new_v4().to_string();
-            
-            sqlx::query(
-                "INSERT INTO custom_metadata_values (id, file_id, key_id, value, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)"
-            )
-            .bind(&id)
-            .bind(file_id)
-            .bind(key_id)
-            .bind(value.as_deref())
-            .bind(now)
-            .bind(now)
-            .execute(data_pool)
+    async fn update_tag_color(&self, pool: &SqlitePool, tag_id: &str, color: &str) -> Result<Tag, sqlx::Error> {
+        // 色を選んだタグは意図して整理されたものとして固定する
+        sqlx::query("UPDATE tags SET color = ?, pinned = 1 WHERE id = ?")
+            .bind(color)
+            .bind(tag_id)
+            .execute(pool)
             .await?;
 
-            Ok(CustomMetadataValue {
-                id,
-                file_id: file_id.to_string(),
-                key_id: key_id.to_string(),
-                value,
-                created_at: now,
-                updated_at: now,
-            })
-        }
+        let row = sqlx::query("SELECT * FROM tags WHERE id = ?")
+            .bind(tag_id)
+            .fetch_one(pool)
+            .await?;
+        Ok(tag_from_row(&row))
     }
 
-    async fn get_custom_metadata_values_by_file(
-        &self,
-        data_pool: &SqlitePool,
-        file_id: &str,
-    ) -> Result<Vec<CustomMetadataValue>, sqlx::Error> {
-        let rows = sqlx::query("SELECT * FROM custom_metadata_values WHERE file_id = ?")
-            .bind(file_id)
-            .fetch_all(data_pool)
-            .await?;
+    async fn merge_tags(&self, pool: &SqlitePool, source_id: &str, target_id: &str) -> Result<Tag, sqlx::Error> {
+        let mut tx = pool.begin().await?;
 
-        let mut values = Vec::new();
-        for row in rows {
-            values.push(CustomMetadataValue {
-                id: row.get("id"),
-                file_id: row.get("file_id"),
-                key_id: row.get("key_id"),
-                value: row.get("value"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            });
+        let source = sqlx::query("SELECT * FROM tags WHERE id = ?")
+            .bind(source_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let target = sqlx::query("SELECT * FROM tags WHERE id = ?")
+            .bind(target_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let target = tag_from_row(&target);
+
+        // 子タグは統合先へ移し、同じ名前の子があればそれも統合する
+        let mut pairs = vec![(tag_from_row(&source), target.clone())];
+        let mut i = 0;
+        while i < pairs.len() {
+            let (from, to) = pairs[i].clone();
+            let children = sqlx::query("SELECT * FROM tags WHERE parent_id = ?")
+                .bind(&from.id)
+                .fetch_all(&mut *tx)
+                .await?;
+            for child in children.iter().map(tag_from_row) {
+                let new_name = format!("{}/{}", to.name, child.label());
+                let existing = sqlx::query("SELECT * FROM tags WHERE name = ?")
+                    .bind(&new_name)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                match existing {
+                    Some(row) => pairs.push((child, tag_from_row(&row))),
+                    None => {
+                        rename_tag_subtree(&mut tx, &child, &new_name).await?;
+                        sqlx::query("UPDATE tags SET parent_id = ? WHERE id = ?")
+                            .bind(&to.id)
+                            .bind(&child.id)
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+                }
+            }
+            i += 1;
         }
 
-        Ok(values)
-    }
+        // 深い階層から順に付け替えて削除する（親を先に消すと子が連鎖削除されるため）
+        for (from, to) in pairs.iter().rev() {
+            // 両方のタグが付いていたファイルは重複させない（統合元の行はタグ削除時に連鎖削除される）
+            sqlx::query("INSERT OR IGNORE INTO file_tags (file_id, tag_id) SELECT file_id, ? FROM file_tags WHERE tag_id = ?")
+                .bind(&to.id)
+                .bind(&from.id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                "INSERT OR IGNORE INTO file_tombstone_tags (tombstone_id, tag_id)
+                 SELECT tombstone_id, ? FROM file_tombstone_tags WHERE tag_id = ?",
+            )
+            .bind(&to.id)
+            .bind(&from.id)
+            .execute(&mut *tx)
+            .await?;
 
-    async fn get_custom_metadata_value(
-        &self,
-        data_pool: &SqlitePool,
-        file_id: &str,
-        key_id: &str,
-    ) -> Result<Option<CustomMetadataValue>, sqlx::Error> {
-        let row =
-            sqlx::query("SELECT * FROM custom_metadata_values WHERE file_id = ? AND key_id = ?")
-                .bind(file_id)
-                .bind(key_id)
-                .fetch_optional(data_pool)
+            sqlx::query("UPDATE tag_aliases SET tag_id = ? WHERE tag_id = ?")
+                .bind(&to.id)
+                .bind(&from.id)
+                .execute(&mut *tx)
+                .await?;
+            // 統合元の名前は別名として残し、以降も同じタグに解決されるようにする
+            sqlx::query("INSERT OR REPLACE INTO tag_aliases (alias, tag_id, created_at) VALUES (?, ?, ?)")
+                .bind(&from.name)
+                .bind(&to.id)
+                .bind(Utc::now())
+                .execute(&mut *tx)
                 .await?;
 
-        match row {
-            Some(row) => Ok(Some(CustomMetadataValue {
-                id: row.get("id"),
-                file_id: row.get("file_id"),
-                key_id: row.get("key_id"),
-                value: row.get("value"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            })),
-            None => Ok(None),
+            sqlx::query("DELETE FROM tags WHERE id = ?")
+                .bind(&from.id)
+                .execute(&mut *tx)
+                .await?;
         }
+
+        tx.commit().await?;
+        Ok(target)
     }
 
-    async fn delete_custom_metadata_value(
-        &self,
-        data_pool: &SqlitePool,
-        file_id: &str,
-        key_id: &str,
-    ) -> Result<(), sqlx::Error> {
-        sqlx::query("DELETE FROM custom_metadata_values WHERE file_id = ? AND key_id = ?")
-            .bind(file_id)
-            .bind(key_id)
-            .execute(data_pool)
+    async fn add_tag_alias(&self, pool: &SqlitePool, tag_id: &str, alias: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT OR REPLACE INTO tag_aliases (alias, tag_id, created_at) VALUES (?, ?, ?)")
+            .bind(alias)
+            .bind(tag_id)
+            .bind(Utc::now())
+            .execute(pool)
             .await?;
 
         Ok(())
     }
 
-    async fn remove_file_by_path(&self, pool: &SqlitePool, path: &str) -> Result<(), sqlx::Error> {
-        sqlx::query("DELETE FROM files WHERE path = ?")
-            .bind(path)
+    async fn remove_tag_alias(&self, pool: &SqlitePool, alias: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM tag_aliases WHERE alias = ?")
+            .bind(alias)
             .execute(pool)
             .await?;
 
         Ok(())
     }
 
-    async fn update_file_metadata(
-        &self,
-        pool: &SqlitePool,
-        path: &str,
-        metadata: &std::fs::Metadata,
-    ) -> Result<(), sqlx::Error> {
-        use chrono::{DateTime, Utc};
-        use std::os::unix::fs::MetadataExt;
+    async fn get_tag_aliases(&self, pool: &SqlitePool, tag_id: &str) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT alias FROM tag_aliases WHERE tag_id = ? ORDER BY alias")
+            .bind(tag_id)
+            .fetch_all(pool)
+            .await
+    }
 
-        let now = Utc::now();
-        let size = metadata.len() as i64;
-        let modified_at = metadata.modified().ok().and_then(|st| {
-            DateTime::from_timestamp(
-                st.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs() as i64,
-                0,
-            )
-        });
-        let created_at = metadata.created().ok().and_then(|st| {
-            DateTime::from_timestamp(
-                st.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs() as i64,
-                0,
-            )
-        });
-        let last_accessed = metadata.accessed().ok().and_then(|st| {
-            DateTime::from_timestamp(
-                st.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs() as i64,
-                0,
-            )
-        });
+    async fn resolve_tag_alias(&self, pool: &SqlitePool, alias: &str) -> Result<Option<Tag>, sqlx::Error> {
+        let row = sqlx::query(
+            "SELECT t.* FROM tags t
+             INNER JOIN tag_aliases ta ON t.id = ta.tag_id
+             WHERE ta.alias = ?",
+        )
+        .bind(alias)
+        .fetch_optional(pool)
+        .await?;
 
-        let inode = metadata.ino() as i64;
-        let owner_uid = metadata.uid() as i64;
-        let group_gid = metadata.gid() as i64;
-        let hard_links = metadata.nlink() as i64;
-        let device_id = metadata.dev() as i64;
-        let permissions = format!("{:o}", metadata.mode() & 0o777);
+        Ok(row.as_ref().map(tag_from_row))
+    }
 
-        sqlx::query(
-            "UPDATE files SET 
-                size = ?, 
-                modified_at = ?, 
-                created_at = ?, 
-                last_accessed = ?, 
-                inode = ?, 
-                owner_uid = ?, 
-                group_gid = ?, 
-                hard_links = ?, 
-                device_id = ?, 
-                permissions = ?, 
-                updated_at_db = ?
-            WHERE path = ?",
+    async fn get_tag_subtree_ids(&self, pool: &SqlitePool, tag_id: &str) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar(TAG_SUBTREE_QUERY)
+            .bind(tag_id)
+            .fetch_all(pool)
+            .await
+    }
+
+    async fn get_tag_file_counts(&self, pool: &SqlitePool) -> Result<HashMap<String, (i64, i64)>, sqlx::Error> {
+        // (直接付いているファイル数, 子孫タグを含めたファイル数)
+        let rows = sqlx::query(
+            "WITH RECURSIVE closure(ancestor_id, tag_id) AS (
+                 SELECT id, id FROM tags
+                 UNION ALL
+                 SELECT c.ancestor_id, t.id FROM closure c INNER JOIN tags t ON t.parent_id = c.tag_id
+             )
+             SELECT c.ancestor_id,
+                    COUNT(DISTINCT CASE WHEN c.tag_id = c.ancestor_id THEN ft.file_id END) AS file_count,
+                    COUNT(DISTINCT ft.file_id) AS total_file_count
+             FROM closure c
+             INNER JOIN file_tags ft ON ft.tag_id = c.tag_id
+             GROUP BY c.ancestor_id",
         )
-        .bind(size)
-        .bind(modified_at)
-        .bind(created_at)
-        .bind(last_accessed)
-        .bind(inode)
-        .bind(owner_uid)
-        .bind(group_gid)
-        .bind(hard_links)
-        .bind(device_id)
-        .bind(permissions)
-        .bind(now)
-        .bind(path)
-        .execute(pool)
+        .fetch_all(pool)
         .await?;
 
-        Ok(())
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get("ancestor_id"),
+                    (row.get("file_count"), row.get("total_file_count")),
+                )
+            })
+            .collect())
     }
 
-    async fn file_exists_by_path(
+    async fn get_tag_usage(&self, pool: &SqlitePool) -> Result<Vec<TagUsage>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT t.*, COUNT(f.id) AS file_count,
+                    COALESCE(SUM(CASE WHEN f.is_directory THEN 0 ELSE f.size END), 0) AS total_bytes
+             FROM tags t
+             LEFT JOIN file_tags ft ON ft.tag_id = t.id
+             LEFT JOIN files f ON f.id = ft.file_id
+             GROUP BY t.id
+             ORDER BY file_count DESC, t.name ASC",
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| TagUsage {
+                tag: tag_from_row(row),
+                file_count: row.get("file_count"),
+                total_bytes: row.get("total_bytes"),
+            })
+            .collect())
+    }
+
+    async fn get_tag_counts_by_directory(&self, pool: &SqlitePool) -> Result<Vec<TagGroupCount>, sqlx::Error> {
+        // 登録したディレクトリではなく、ファイルが実際に置かれている親フォルダのパスでまとめる
+        // （rtrim で最後の / より後ろを落とし、末尾の / も取り除く。ルート直下は /）
+        tag_group_counts(
+            pool,
+            "COALESCE(NULLIF(rtrim(rtrim(f.path, replace(f.path, '/', '')), '/'), ''), '/')",
+            "",
+        )
+        .await
+    }
+
+    async fn get_tag_counts_by_extension(&self, pool: &SqlitePool) -> Result<Vec<TagGroupCount>, sqlx::Error> {
+        tag_group_counts(pool, "lower(f.file_type)", "").await
+    }
+
+    async fn get_tag_counts_by_period(
         &self,
         pool: &SqlitePool,
-        path: &str,
-    ) -> Result<bool, sqlx::Error> {
-        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM files WHERE path = ?")
-            .bind(path)
-            .fetch_one(pool)
-            .await?;
+        period_format: &str,
+    ) -> Result<Vec<TagGroupCount>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT ft.tag_id, strftime(?, ft.tagged_at) AS grp, COUNT(*) AS file_count,
+                    COALESCE(SUM(CASE WHEN f.is_directory THEN 0 ELSE f.size END), 0) AS total_bytes
+             FROM file_tags ft
+             INNER JOIN files f ON f.id = ft.file_id
+             WHERE ft.tagged_at IS NOT NULL
+             GROUP BY ft.tag_id, grp
+             ORDER BY grp, ft.tag_id",
+        )
+        .bind(period_format)
+        .fetch_all(pool)
+        .await?;
 
-        Ok(count > 0)
+        Ok(rows.iter().map(tag_group_count_from_row).collect())
     }
 
-    async fn find_file_by_inode(
+    async fn get_tag_pair_counts(&self, pool: &SqlitePool, limit: u32) -> Result<Vec<TagPairCount>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT a.tag_id, b.tag_id AS other_tag_id, COUNT(*) AS file_count
+             FROM file_tags a
+             INNER JOIN file_tags b ON b.file_id = a.file_id AND b.tag_id > a.tag_id
+             GROUP BY a.tag_id, b.tag_id
+             ORDER BY file_count DESC, a.tag_id, b.tag_id
+             LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| TagPairCount {
+                tag_id: row.get("tag_id"),
+                other_tag_id: row.get("other_tag_id"),
+                file_count: row.get("file_count"),
+            })
+            .collect())
+    }
+
+    async fn suggest_tags(
         &self,
         pool: &SqlitePool,
-        inode: i64,
-        device_id: Option<i64>,
-    ) -> Result<Option<File>, sqlx::Error> {
-        let query = if let Some(_device_id) = device_id {
-            "SELECT * FROM files WHERE inode = ? AND device_id = ?"
-        } else {
-            "SELECT * FROM files WHERE inode = ? AND device_id IS NULL"
-        };
+        file_ids: &[String],
+        limit: usize,
+    ) -> Result<Vec<TagSuggestion>, sqlx::Error> {
+        if file_ids.is_empty() {
+            return Ok(Vec::new());
+        }
 
-        let row = if let Some(device_id) = device_id {
-            sqlx::query(query)
-                .bind(inode)
-                .bind(device_id)
-                .fetch_optional(pool)
-                .await?
-        } else {
-            sqlx::query("SELECT * FROM files WHERE inode = ?")
-                .bind(inode)
-                .fetch_optional(pool)
+        let mut totals: HashMap<String, (f64, Vec<TagSuggestionReason>)> = HashMap::new();
+        // すべてのファイルに既に付いているタグは候補にしない
+        let mut common_tag_ids: Option<HashSet<String>> = None;
+        for file_id in file_ids {
+            let tag_ids: HashSet<String> = sqlx::query_scalar("SELECT tag_id FROM file_tags WHERE file_id = ?")
+                .bind(file_id)
+                .fetch_all(pool)
                 .await?
-        };
+                .into_iter()
+                .collect();
+            for (tag_id, (score, reasons)) in tag_suggestion_scores(pool, file_id, &tag_ids).await? {
+                let total = totals.entry(tag_id).or_insert_with(|| (0.0, Vec::new()));
+                total.0 += score;
+                for reason in reasons {
+                    if !total.1.contains(&reason) {
+                        total.1.push(reason);
+                    }
+                }
+            }
+            common_tag_ids = Some(match common_tag_ids {
+                Some(common) => common.intersection(&tag_ids).cloned().collect(),
+                None => tag_ids,
+            });
+        }
+        let common_tag_ids = common_tag_ids.unwrap_or_default();
 
-        match row {
-            Some(row) => Ok(Some(File {
-                id: row.get("id"),
-                path: row.get("path"),
-                name: row.get("name"),
-                directory_id: row.get("directory_id"),
-                size: row.get("size"),
-                file_type: row.get("file_type"),
-                created_at: row.get("created_at"),
-                modified_at: row.get("modified_at"),
-                birth_time: row.get("birth_time"),
-                inode: row.get("inode"),
-                is_directory: row.get("is_directory"),
-                created_at_db: row.get("created_at_db"),
-                updated_at_db: row.get("updated_at_db"),
-                file_size: row.get("file_size"),
-                mime_type: row.get("mime_type"),
-                permissions: row.get("permissions"),
-                owner_uid: row.get("owner_uid"),
-                group_gid: row.get("group_gid"),
-                hard_links: row.get("hard_links"),
-                device_id: row.get("device_id"),
-                last_accessed: row.get("last_accessed"),
-                metadata: row.get("metadata"),
-            })),
-            None => Ok(None),
+        totals.retain(|tag_id, _| !common_tag_ids.contains(tag_id));
+        if totals.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let placeholders = vec!["?"; totals.len()].join(", ");
+        let sql = format!("SELECT * FROM tags WHERE id IN ({placeholders})");
+        let mut query = sqlx::query(&sql);
+        for tag_id in totals.keys() {
+            query = query.bind(tag_id);
+        }
+        let mut suggestions = Vec::new();
+        for row in query.fetch_all(pool).await? {
+            let tag = tag_from_row(&row);
+            let Some((score, reasons)) = totals.remove(&tag.id) else {
+                continue;
+            };
+            suggestions.push(TagSuggestion {
+                tag,
+                score: score / file_ids.len() as f64,
+                reasons,
+            });
         }
+        suggestions.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.tag.name.cmp(&b.tag.name)));
+        suggestions.truncate(limit);
+        Ok(suggestions)
     }
 
-    async fn update_file_path(
+    async fn get_file_tag_assignments(
         &self,
         pool: &SqlitePool,
         file_id: &str,
-        new_path: &str,
-        new_name: &str,
-    ) -> Result<(), sqlx::Error> {
-        let now = Utc::now();
+    ) -> Result<Vec<FileTagAssignment>, sqlx::Error> {
+        // 同じタグが複数の経路で効いている場合は、直接のタグ、次に最も近いディレクトリを優先する
+        let rows = sqlx::query(
+            "SELECT t.*, eft.inherited_from, d.path AS inherited_path,
+                    COALESCE(ft.include_subfolders, 1) AS include_subfolders
+             FROM effective_file_tags eft
+             INNER JOIN tags t ON t.id = eft.tag_id
+             LEFT JOIN files d ON d.id = eft.inherited_from
+             LEFT JOIN file_tags ft ON ft.file_id = COALESCE(eft.inherited_from, eft.file_id) AND ft.tag_id = eft.tag_id
+             WHERE eft.file_id = ?
+             ORDER BY t.name, eft.inherited_from IS NOT NULL, length(d.path) DESC",
+        )
+        .bind(file_id)
+        .fetch_all(pool)
+        .await?;
 
-        sqlx::query("UPDATE files SET path = ?, name = ?, updated_at_db = ? WHERE id = ?")
-            .bind(new_path)
-            .bind(new_name)
-            .bind(now)
+        let mut assignments: Vec<FileTagAssignment> = Vec::new();
+        for row in rows {
+            let tag = tag_from_row(&row);
+            if assignments.last().is_some_and(|a| a.tag.id == tag.id) {
+                continue;
+            }
+            let inherited_from = row
+                .get::<Option<String>, _>("inherited_from")
+                .map(|file_id| InheritedTagSource {
+                    file_id,
+                    path: row.get("inherited_path"),
+                });
+            assignments.push(FileTagAssignment {
+                tag,
+                inherited_from,
+                include_subfolders: row.get("include_subfolders"),
+            });
+        }
+        Ok(assignments)
+    }
+
+    async fn set_tag_include_subfolders(
+        &self,
+        pool: &SqlitePool,
+        file_id: &str,
+        tag_id: &str,
+        include_subfolders: bool,
+    ) -> Result<(), sqlx::Error> {
+        let result = sqlx::query("UPDATE file_tags SET include_subfolders = ? WHERE file_id = ? AND tag_id = ?")
+            .bind(include_subfolders)
             .bind(file_id)
+            .bind(tag_id)
             .execute(pool)
             .await?;
-
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
         Ok(())
     }
 
-    async fn get_files_paginated_with_category(
+    async fn get_file_tags(
         &self,
         pool: &SqlitePool,
-        category: &str,
-        sort_field: Option<String>,
-        sort_order: Option<String>,
-        limit: u32,
-        offset: u32,
-        show_hidden_files: bool,
-        show_directories: bool,
-    ) -> Result<Vec<File>, sqlx::Error> {
-        let sort_field = sort_field.as_deref().unwrap_or("modified_at");
-        let sort_order = sort_order.as_deref().unwrap_or("desc");
-
-        let sort_column = match sort_field {
-            "name" => "name",
-            "size" => "size",
-            "created_at" => "created_at",
-            "modified_at" => "modified_at",
-            "last_accessed" => "last_accessed",
-            "file_type" => "file_type",
-            _ => "modified_at",
-        };
-
-        let order_direction = match sort_order {
-            "asc" => "ASC",
-            _ => "DESC",
-        };
-
-        let category_where_clause = build_category_where_clause(category);
-        
-        let mut additional_conditions = Vec::new();
-        if !show_hidden_files {
-            additional_conditions.push(" AND name NOT LIKE '.%'");
-        }
-        if !show_directories {
-            additional_conditions.push(" AND is_directory = FALSE");
-        }
-
-        let query = format!(
-            "SELECT * FROM files WHERE 1=1{}{} ORDER BY {} {} NULLS LAST LIMIT ? OFFSET ?",
-            category_where_clause, additional_conditions.join(""), sort_column, order_direction
-        );
-
-        let rows = sqlx::query(&query)
-            .bind(limit)
-            .bind(offset)
-            .fetch_all(pool)
-            .await?;
+        file_id: &str,
+    ) -> Result<Vec<Tag>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT t.* FROM tags t 
+             INNER JOIN file_tags ft ON t.id = ft.tag_id 
+             WHERE ft.file_id = ? 
+             ORDER BY t.name",
+        )
+        .bind(file_id)
+        .fetch_all(pool)
+        .await?;
 
-        let mut files = Vec::new();
+        let mut tags = Vec::new();
         for row in rows {
-            files.push(File {
+            tags.push(Tag {
                 id: row.get("id"),
-                path: row.get("path"),
                 name: row.get("name"),
-                directory_id: row.get("directory_id"),
-                size: row.get("size"),
-                file_type: row.get("file_type"),
+                color: row.get("color"),
                 created_at: row.get("created_at"),
-                modified_at: row.get("modified_at"),
-                birth_time: row.get("birth_time"),
-                inode: row.get("inode"),
-                is_directory: row.get("is_directory"),
-                created_at_db: row.get("created_at_db"),
-                updated_at_db: row.get("updated_at_db"),
-                file_size: row.get("file_size"),
-                mime_type: row.get("mime_type"),
-                permissions: row.get("permissions"),
-                owner_uid: row.get("owner_uid"),
-                group_gid: row.get("group_gid"),
-                hard_links: row.get("hard_links"),
-                device_id: row.get("device_id"),
-                last_accessed: row.get("last_accessed"),
-                metadata: row.get("metadata"),
+                parent_id: row.get("parent_id"),
+                pinned: row.get("pinned"),
             });
         }
 
-        Ok(files)
+        Ok(tags)
     }
 
-    async fn get_files_by_directory_paginated_with_category(
+    async fn create_custom_metadata_key(
         &self,
-        pool: &SqlitePool,
-        directory_id: &str,
-        category: &str,
-        sort_field: Option<String>,
-        sort_order: Option<String>,
-        limit: u32,
-        offset: u32,
-        show_hidden_files: bool,
-        show_directories: bool,
-    ) -> Result<Vec<File>, sqlx::Error> {
-        let sort_field = sort_field.as_deref().unwrap_or("modified_at");
-        let sort_order = sort_order.as_deref().unwrap_or("desc");
-
-        let sort_column = match sort_field {
-            "name" => "name",
-            "size" => "size",
-            "created_at" => "created_at",
-            "modified_at" => "modified_at",
-            "last_accessed" => "last_accessed",
-            "file_type" => "file_type",
-            _ => "modified_at",
-        };
+        settings_pool: &SqlitePool,
+        key: &CustomMetadataKey,
+    ) -> Result<CustomMetadataKey, sqlx::Error> {
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now();
 
-        let order_direction = match sort_order {
-            "asc" => "ASC",
-            _ => "DESC",
-        };
+        sqlx::query(
+            "INSERT INTO custom_metadata_keys (id, name, display_name, data_type, description, is_required, default_value, validation_pattern, options, expression, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(&id)
+        .bind(&key.name)
+        .bind(&key.display_name)
+        .bind(&key.data_type)
+        .bind(&key.description)
+        .bind(key.is_required)
+        .bind(&key.default_value)
+        .bind(&key.validation_pattern)
+        .bind(options_to_json(&key.options))
+        .bind(&key.expression)
+        .bind(now)
+        .bind(now)
+        .execute(settings_pool)
+        .await?;
 
-        let category_where_clause = build_category_where_clause(category);
+        Ok(CustomMetadataKey {
+            id,
+            created_at: now,
+            updated_at: now,
+            ..key.clone()
+        })
+    }
+
+    async fn get_all_custom_metadata_keys(
+        &self,
+        settings_pool: &SqlitePool,
+    ) -> Result<Vec<CustomMetadataKey>, sqlx::Error> {
+        let rows = sqlx::query("SELECT * FROM custom_metadata_keys ORDER BY name")
+            .fetch_all(settings_pool)
+            .await?;
+
+        Ok(rows.iter().map(custom_metadata_key_from_row).collect())
+    }
+
+    async fn update_custom_metadata_key(
+        &self,
+        settings_pool: &SqlitePool,
+        key: &CustomMetadataKey,
+    ) -> Result<CustomMetadataKey, sqlx::Error> {
+        let mut conn = settings_pool.acquire().await?;
+        update_custom_metadata_key_in(&mut conn, key).await
+    }
+
+    async fn update_custom_metadata_key_in_shelves(
+        &self,
+        settings_pool: &SqlitePool,
+        data_pools: &[SqlitePool],
+        previous: &CustomMetadataKey,
+        key: &CustomMetadataKey,
+        drop_invalid: bool,
+    ) -> Result<CustomMetadataKey, MetadataError> {
+        // 計算フィールドの値は変換せず、新しい定義で計算し直す
+        let convert = key.expression.is_none() && key.changes_value_format(previous);
+        let mut transactions = Vec::new();
+        let mut failure_count = 0;
+        for data_pool in data_pools {
+            let mut tx = data_pool.begin().await?;
+            if convert {
+                failure_count += convert_custom_metadata_values_in(&mut tx, previous, key, drop_invalid).await?.len();
+            }
+            transactions.push(tx);
+        }
+        if failure_count > 0 && !drop_invalid {
+            return Err(MetadataError::Invalid(format!("新しい定義に変換できない値が{failure_count}件あります")));
+        }
+
+        let mut conn = settings_pool.acquire().await?;
+        let key = update_custom_metadata_key_in(&mut conn, key).await?;
+        for tx in transactions {
+            tx.commit().await?;
+        }
+        Ok(key)
+    }
+
+    async fn delete_custom_metadata_key(
+        &self,
+        settings_pool: &SqlitePool,
+        data_pools: &[SqlitePool],
+        key_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        // まず、各シェルフでこのキーを参照しているカスタムメタデータ値を削除
+        let mut transactions = Vec::new();
+        for data_pool in data_pools {
+            let mut tx = data_pool.begin().await?;
+            sqlx::query("DELETE FROM custom_metadata_values WHERE key_id = ?")
+                .bind(key_id)
+                .execute(&mut *tx)
+                .await?;
+            transactions.push(tx);
+        }
+
+        // その後、キー定義を削除し、成功してから各シェルフの削除をコミットする
+        sqlx::query("DELETE FROM custom_metadata_keys WHERE id = ?")
+            .bind(key_id)
+            .execute(settings_pool)
+            .await?;
+        for tx in transactions {
+            tx.commit().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn convert_custom_metadata_values(
+        &self,
+        data_pool: &SqlitePool,
+        previous: &CustomMetadataKey,
+        key: &CustomMetadataKey,
+        apply: bool,
+        drop_invalid: bool,
+    ) -> Result<Vec<CustomMetadataViolation>, sqlx::Error> {
+        let mut tx = data_pool.begin().await?;
+        let failures = convert_custom_metadata_values_in(&mut tx, previous, key, drop_invalid).await?;
+        if apply {
+            tx.commit().await?;
+        } else {
+            tx.rollback().await?;
+        }
+        Ok(failures)
+    }
+
+    async fn find_orphaned_custom_metadata_values(
+        &self,
+        data_pool: &SqlitePool,
+        key_ids: &[String],
+        delete: bool,
+    ) -> Result<Vec<OrphanedMetadataValues>, sqlx::Error> {
+        let mut tx = data_pool.begin().await?;
+        let orphans: Vec<OrphanedMetadataValues> = sqlx::query_as::<_, (String, i64)>(
+            "SELECT key_id, COUNT(*) FROM custom_metadata_values GROUP BY key_id ORDER BY key_id",
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .filter(|(key_id, _)| !key_ids.contains(key_id))
+        .map(|(key_id, value_count)| OrphanedMetadataValues { key_id, value_count })
+        .collect();
+
+        if delete {
+            for orphan in &orphans {
+                sqlx::query("DELETE FROM custom_metadata_values WHERE key_id = ?")
+                    .bind(&orphan.key_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(orphans)
+    }
+
+    async fn get_custom_metadata_key_by_name(
+        &self,
+        settings_pool: &SqlitePool,
+        name: &str,
+    ) -> Result<Option<CustomMetadataKey>, sqlx::Error> {
+        let row = sqlx::query("SELECT * FROM custom_metadata_keys WHERE name = ?")
+            .bind(name)
+            .fetch_optional(settings_pool)
+            .await?;
+
+        Ok(row.map(|row| custom_metadata_key_from_row(&row)))
+    }
+
+    async fn create_custom_metadata_schema(
+        &self,
+        settings_pool: &SqlitePool,
+        schema: &CustomMetadataSchema,
+    ) -> Result<CustomMetadataSchema, sqlx::Error> {
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now();
+        let mut tx = settings_pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO custom_metadata_schemas (id, name, description, conditions, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&id)
+        .bind(&schema.name)
+        .bind(&schema.description)
+        .bind(serde_json::to_string(&schema.conditions).unwrap_or_else(|_| "[]".to_string()))
+        .bind(now)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        replace_schema_keys(&mut tx, &id, &schema.key_ids).await?;
+        tx.commit().await?;
+
+        Ok(CustomMetadataSchema {
+            id,
+            created_at: now,
+            updated_at: now,
+            ..schema.clone()
+        })
+    }
+
+    async fn get_all_custom_metadata_schemas(
+        &self,
+        settings_pool: &SqlitePool,
+    ) -> Result<Vec<CustomMetadataSchema>, sqlx::Error> {
+        load_custom_metadata_schemas(settings_pool).await
+    }
+
+    async fn update_custom_metadata_schema(
+        &self,
+        settings_pool: &SqlitePool,
+        schema: &CustomMetadataSchema,
+    ) -> Result<CustomMetadataSchema, sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = settings_pool.begin().await?;
+        let updated = sqlx::query(
+            "UPDATE custom_metadata_schemas SET name = ?, description = ?, conditions = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(&schema.name)
+        .bind(&schema.description)
+        .bind(serde_json::to_string(&schema.conditions).unwrap_or_else(|_| "[]".to_string()))
+        .bind(now)
+        .bind(&schema.id)
+        .execute(&mut *tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        replace_schema_keys(&mut tx, &schema.id, &schema.key_ids).await?;
+        tx.commit().await?;
+
+        Ok(CustomMetadataSchema {
+            updated_at: now,
+            ..schema.clone()
+        })
+    }
+
+    async fn delete_custom_metadata_schema(&self, settings_pool: &SqlitePool, schema_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM custom_metadata_schemas WHERE id = ?")
+            .bind(schema_id)
+            .execute(settings_pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_applicable_custom_metadata_keys(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+        file_id: &str,
+    ) -> Result<Vec<CustomMetadataKey>, sqlx::Error> {
+        let keys = self.get_all_custom_metadata_keys(settings_pool).await?;
+        let mut conn = data_pool.acquire().await?;
+        let matcher = SchemaMatcher::load(&mut conn, load_custom_metadata_schemas(settings_pool).await?).await?;
+        let file = matcher.file_context(&mut conn, file_id).await?.ok_or(sqlx::Error::RowNotFound)?;
+        Ok(keys.into_iter().filter(|key| matcher.key_applies(&key.id, &file)).collect())
+    }
+
+    async fn set_custom_metadata_value(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+        file_id: &str,
+        key_id: &str,
+        value: Option<String>,
+    ) -> Result<CustomMetadataValue, MetadataError> {
+        // キーが存在することを確認する
+        let key = sqlx::query("SELECT * FROM custom_metadata_keys WHERE id = ?")
+            .bind(key_id)
+            .fetch_optional(settings_pool)
+            .await?
+            .map(|row| custom_metadata_key_from_row(&row))
+            .ok_or(sqlx::Error::RowNotFound)?;
+
+        let schemas = load_custom_metadata_schemas(settings_pool).await?;
+        let mut tx = data_pool.begin().await?;
+        let result = write_custom_metadata_value(&mut tx, &schemas, &key, file_id, value).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn bulk_set_custom_metadata_value(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+        file_ids: &[String],
+        key_id: &str,
+        value: Option<String>,
+    ) -> Result<BulkMetadataResult, sqlx::Error> {
+        let key = sqlx::query("SELECT * FROM custom_metadata_keys WHERE id = ?")
+            .bind(key_id)
+            .fetch_optional(settings_pool)
+            .await?
+            .map(|row| custom_metadata_key_from_row(&row))
+            .ok_or(sqlx::Error::RowNotFound)?;
+
+        let schemas = load_custom_metadata_schemas(settings_pool).await?;
+        let mut tx = data_pool.begin().await?;
+        let mut result = BulkMetadataResult::new(file_ids.len());
+        for file_id in file_ids {
+            if !file_exists(&mut tx, file_id).await? {
+                result.fail(file_id, "ファイルが見つかりません");
+                continue;
+            }
+            match write_custom_metadata_value(&mut tx, &schemas, &key, file_id, value.clone()).await {
+                Ok(_) => result.updated += 1,
+                Err(MetadataError::Invalid(message)) => result.fail(file_id, &message),
+                Err(MetadataError::Database(e)) => return Err(e),
+            }
+        }
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn bulk_clear_custom_metadata_value(
+        &self,
+        data_pool: &SqlitePool,
+        file_ids: &[String],
+        key_id: &str,
+    ) -> Result<BulkMetadataResult, sqlx::Error> {
+        let mut tx = data_pool.begin().await?;
+        let mut result = BulkMetadataResult::new(file_ids.len());
+        for file_id in file_ids {
+            if !file_exists(&mut tx, file_id).await? {
+                result.fail(file_id, "ファイルが見つかりません");
+                continue;
+            }
+            let deleted = sqlx::query("DELETE FROM custom_metadata_values WHERE file_id = ? AND key_id = ?")
+                .bind(file_id)
+                .bind(key_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            if deleted > 0 {
+                result.updated += 1;
+            }
+        }
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn copy_custom_metadata(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+        source_file_id: &str,
+        target_file_ids: &[String],
+        key_ids: Option<Vec<String>>,
+        overwrite: bool,
+    ) -> Result<BulkMetadataResult, sqlx::Error> {
+        let keys = self.get_all_custom_metadata_keys(settings_pool).await?;
+        // キー定義が削除された値はコピーしない
+        let source_values: Vec<(CustomMetadataKey, Option<String>)> = self
+            .get_custom_metadata_values_by_file(data_pool, source_file_id)
+            .await?
+            .into_iter()
+            .filter(|v| match &key_ids {
+                Some(ids) => ids.contains(&v.key_id),
+                None => true,
+            })
+            .filter_map(|v| keys.iter().find(|k| k.id == v.key_id).map(|k| (k.clone(), v.value)))
+            // 計算フィールドはコピー先で計算し直される
+            .filter(|(k, _)| k.expression.is_none())
+            .collect();
+
+        let schemas = load_custom_metadata_schemas(settings_pool).await?;
+        let targets: Vec<&String> = target_file_ids.iter().filter(|id| id.as_str() != source_file_id).collect();
+        let mut tx = data_pool.begin().await?;
+        let mut result = BulkMetadataResult::new(targets.len());
+        for file_id in targets {
+            if !file_exists(&mut tx, file_id).await? {
+                result.fail(file_id, "ファイルが見つかりません");
+                continue;
+            }
+            // ファイルごとにセーブポイントを置き、書き込めない値があればそのファイルは変更しない
+            let mut savepoint = Acquire::begin(&mut tx).await?;
+            match copy_custom_metadata_values_to(&mut savepoint, &schemas, &source_values, file_id, overwrite).await {
+                Ok(written) => {
+                    savepoint.commit().await?;
+                    if written {
+                        result.updated += 1;
+                    }
+                }
+                Err(MetadataError::Invalid(message)) => {
+                    savepoint.rollback().await?;
+                    result.fail(file_id, &message);
+                }
+                Err(MetadataError::Database(e)) => return Err(e),
+            }
+        }
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn audit_custom_metadata(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+    ) -> Result<Vec<CustomMetadataViolation>, sqlx::Error> {
+        let keys = self.get_all_custom_metadata_keys(settings_pool).await?;
+        let files: Vec<(String, String, Option<String>)> =
+            sqlx::query_as("SELECT id, path, mime_type FROM files WHERE is_directory = 0 ORDER BY path")
+                .fetch_all(data_pool)
+                .await?;
+        let mut values: HashMap<(String, String), Option<String>> = HashMap::new();
+        for (file_id, key_id, value) in sqlx::query_as::<_, (String, String, Option<String>)>(
+            "SELECT file_id, key_id, value FROM custom_metadata_values",
+        )
+        .fetch_all(data_pool)
+        .await?
+        {
+            values.insert((file_id, key_id), value);
+        }
+
+        let file_ids: HashSet<String> = sqlx::query_scalar("SELECT id FROM files")
+            .fetch_all(data_pool)
+            .await?
+            .into_iter()
+            .collect();
+
+        // 必須のキーは、そのキーを使うファイルだけで確かめる
+        let matcher = {
+            let mut conn = data_pool.acquire().await?;
+            SchemaMatcher::load(&mut conn, load_custom_metadata_schemas(settings_pool).await?).await?
+        };
+        let mut file_tags: HashMap<String, Vec<String>> = HashMap::new();
+        for (file_id, tag_id) in sqlx::query_as::<_, (String, String)>("SELECT file_id, tag_id FROM effective_file_tags")
+            .fetch_all(data_pool)
+            .await?
+        {
+            file_tags.entry(file_id).or_default().push(tag_id);
+        }
+
+        let mut violations = Vec::new();
+        for (file_id, path, mime_type) in files {
+            let file = SchemaFileContext {
+                path,
+                mime_type,
+                tag_ids: matcher.with_ancestors(file_tags.remove(&file_id).unwrap_or_default()),
+            };
+            for key in &keys {
+                let value = values
+                    .get(&(file_id.clone(), key.id.clone()))
+                    .cloned()
+                    .flatten()
+                    .filter(|v| !v.trim().is_empty());
+                let message = match &value {
+                    None if key.is_required && matcher.key_applies(&key.id, &file) => {
+                        Some(format!("'{}' は必須です", key.display_name))
+                    }
+                    None => None,
+                    Some(value) if key.data_type == "file_ref" && !file_ids.contains(value) => {
+                        Some(format!("'{}' の参照先のファイルが見つかりません", key.display_name))
+                    }
+                    // 正規形で保存されていない値も、正規化できれば問題なしとする
+                    Some(value) => key.normalize_value(Some(value)).err(),
+                };
+                if let Some(message) = message {
+                    violations.push(CustomMetadataViolation {
+                        file_id: file_id.clone(),
+                        path: file.path.clone(),
+                        key_id: key.id.clone(),
+                        key_name: key.name.clone(),
+                        value,
+                        message,
+                    });
+                }
+            }
+        }
+        Ok(violations)
+    }
+
+    async fn get_custom_metadata_values_by_file(
+        &self,
+        data_pool: &SqlitePool,
+        file_id: &str,
+    ) -> Result<Vec<CustomMetadataValue>, sqlx::Error> {
+        let rows = sqlx::query("SELECT * FROM custom_metadata_values WHERE file_id = ?")
+            .bind(file_id)
+            .fetch_all(data_pool)
+            .await?;
+
+        let mut values = Vec::new();
+        for row in rows {
+            values.push(CustomMetadataValue {
+                id: row.get("id"),
+                file_id: row.get("file_id"),
+                key_id: row.get("key_id"),
+                value: row.get("value"),
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+            });
+        }
+
+        Ok(values)
+    }
+
+    async fn get_custom_metadata_values_by_files(
+        &self,
+        data_pool: &SqlitePool,
+        file_ids: &[String],
+    ) -> Result<Vec<CustomMetadataValue>, sqlx::Error> {
+        let mut values = Vec::new();
+        // SQLiteの変数の上限を超えないよう分割して取得する
+        for chunk in file_ids.chunks(500) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let sql = format!("SELECT * FROM custom_metadata_values WHERE file_id IN ({placeholders}) ORDER BY file_id");
+            let mut query = sqlx::query(&sql);
+            for file_id in chunk {
+                query = query.bind(file_id);
+            }
+            for row in query.fetch_all(data_pool).await? {
+                values.push(CustomMetadataValue {
+                    id: row.get("id"),
+                    file_id: row.get("file_id"),
+                    key_id: row.get("key_id"),
+                    value: row.get("value"),
+                    created_at: row.get("created_at"),
+                    updated_at: row.get("updated_at"),
+                });
+            }
+        }
+        Ok(values)
+    }
+
+    async fn get_annotation_history(
+        &self,
+        pool: &SqlitePool,
+        file_id: &str,
+        key_id: Option<String>,
+        limit: Option<u32>,
+    ) -> Result<Vec<AnnotationHistoryEntry>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT * FROM annotation_history
+             WHERE file_id = ? AND (? IS NULL OR key_id = ?)
+             ORDER BY id DESC LIMIT ?",
+        )
+        .bind(file_id)
+        .bind(&key_id)
+        .bind(&key_id)
+        .bind(limit.map(i64::from).unwrap_or(-1))
+        .fetch_all(pool)
+        .await?;
+        Ok(rows.iter().map(annotation_history_from_row).collect())
+    }
+
+    async fn revert_custom_metadata_change(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+        history_id: i64,
+    ) -> Result<Option<CustomMetadataValue>, MetadataError> {
+        let entry = sqlx::query("SELECT * FROM annotation_history WHERE id = ?")
+            .bind(history_id)
+            .fetch_optional(data_pool)
+            .await?
+            .map(|row| annotation_history_from_row(&row))
+            .ok_or(sqlx::Error::RowNotFound)?;
+        let Some(key_id) = entry.key_id else {
+            return Err(MetadataError::Invalid("カスタムメタデータの変更ではありません".to_string()));
+        };
+
+        match entry.old_value {
+            Some(value) => self
+                .set_custom_metadata_value(data_pool, settings_pool, &entry.file_id, &key_id, Some(value))
+                .await
+                .map(Some),
+            None => {
+                self.delete_custom_metadata_value(data_pool, &entry.file_id, &key_id).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn mark_computed_metadata_stale(
+        &self,
+        pool: &SqlitePool,
+        file_ids: Option<Vec<String>>,
+    ) -> Result<u64, sqlx::Error> {
+        let Some(file_ids) = file_ids else {
+            return Ok(sqlx::query("INSERT OR IGNORE INTO computed_metadata_stale (file_id) SELECT id FROM files")
+                .execute(pool)
+                .await?
+                .rows_affected());
+        };
+        let mut marked = 0;
+        for file_id in &file_ids {
+            marked += sqlx::query(
+                "INSERT OR IGNORE INTO computed_metadata_stale (file_id) SELECT id FROM files WHERE id = ?",
+            )
+            .bind(file_id)
+            .execute(pool)
+            .await?
+            .rows_affected();
+        }
+        Ok(marked)
+    }
+
+    async fn get_stale_computed_file_ids(&self, pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT file_id FROM computed_metadata_stale ORDER BY file_id")
+            .fetch_all(pool)
+            .await
+    }
+
+    async fn store_computed_metadata_values(
+        &self,
+        pool: &SqlitePool,
+        keys: &[CustomMetadataKey],
+        file_ids: &[String],
+        values: &[(String, String, Option<String>)],
+    ) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let mut changed = 0;
+        for (file_id, key_id, value) in values {
+            let Some(key) = keys.iter().find(|k| &k.id == key_id) else {
+                continue;
+            };
+            // 参照先が見つからないファイル参照は値なしとして扱う
+            let value = match (value, key.data_type.as_str()) {
+                (Some(reference), "file_ref") => resolve_file_ref(&mut tx, reference).await?,
+                _ => value.clone(),
+            };
+            let existing: Option<Option<String>> = sqlx::query_scalar(
+                "SELECT value FROM custom_metadata_values WHERE file_id = ? AND key_id = ?",
+            )
+            .bind(file_id)
+            .bind(key_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            // 変わらない値は書き込まない（更新日時を変えないため）
+            if existing.flatten() == value {
+                continue;
+            }
+            store_computed_value(&mut tx, key, file_id, value.as_deref()).await?;
+            changed += 1;
+        }
+        // 書き込んだ値のトリガーで入った分も含めて待ち行列から外す
+        for file_id in file_ids {
+            sqlx::query("DELETE FROM computed_metadata_stale WHERE file_id = ?")
+                .bind(file_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(changed)
+    }
+
+    async fn get_files_by_ids(&self, pool: &SqlitePool, file_ids: &[String]) -> Result<Vec<File>, sqlx::Error> {
+        let mut files = Vec::new();
+        for chunk in file_ids.chunks(500) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let sql = format!("SELECT * FROM files WHERE id IN ({placeholders})");
+            let mut query = sqlx::query(&sql);
+            for file_id in chunk {
+                query = query.bind(file_id);
+            }
+            files.extend(query.fetch_all(pool).await?.iter().map(file_from_row));
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    async fn get_file_tags_by_files(
+        &self,
+        pool: &SqlitePool,
+        file_ids: &[String],
+    ) -> Result<Vec<(String, Tag)>, sqlx::Error> {
+        let mut tags = Vec::new();
+        for chunk in file_ids.chunks(500) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let sql = format!(
+                "SELECT ft.file_id, t.* FROM file_tags ft INNER JOIN tags t ON t.id = ft.tag_id
+                 WHERE ft.file_id IN ({placeholders}) ORDER BY t.name"
+            );
+            let mut query = sqlx::query(&sql);
+            for file_id in chunk {
+                query = query.bind(file_id);
+            }
+            for row in query.fetch_all(pool).await? {
+                tags.push((row.get("file_id"), tag_from_row(&row)));
+            }
+        }
+        Ok(tags)
+    }
+
+    async fn match_file_ids(
+        &self,
+        pool: &SqlitePool,
+        field: FileMatchField,
+        value: &str,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let sql = match field {
+            FileMatchField::Path => "SELECT id FROM files WHERE path = ?",
+            FileMatchField::RelativePath => {
+                "SELECT f.id FROM files f INNER JOIN directories d ON d.id = f.directory_id
+                 WHERE f.path = d.path || '/' || ?"
+            }
+            FileMatchField::ContentHash => "SELECT id FROM files WHERE content_hash = lower(?)",
+            FileMatchField::FileId => "SELECT id FROM files WHERE id = ?",
+        };
+        sqlx::query_scalar(sql).bind(value).fetch_all(pool).await
+    }
+
+    async fn import_file_metadata(
+        &self,
+        data_pool: &SqlitePool,
+        settings_pool: &SqlitePool,
+        rows: &[MetadataImportRow],
+        tag_color: &str,
+        apply: bool,
+        skip_invalid: bool,
+    ) -> Result<Vec<MetadataImportRowResult>, sqlx::Error> {
+        let keys = self.get_all_custom_metadata_keys(settings_pool).await?;
+        let schemas = load_custom_metadata_schemas(settings_pool).await?;
+        let mut tx = data_pool.begin().await?;
+        let mut results = Vec::new();
+        for row in rows {
+            let mut result = MetadataImportRowResult {
+                row: row.row,
+                file_id: row.file_id.clone(),
+                tags_added: 0,
+                values_set: 0,
+                error: None,
+            };
+            // 行ごとにセーブポイントを置き、値の不正な行だけを取り消す
+            let mut savepoint = Acquire::begin(&mut tx).await?;
+            match import_metadata_row(&mut savepoint, &keys, &schemas, row, tag_color).await {
+                Ok((tags_added, values_set)) => {
+                    savepoint.commit().await?;
+                    result.tags_added = tags_added;
+                    result.values_set = values_set;
+                }
+                Err(MetadataError::Invalid(message)) => {
+                    savepoint.rollback().await?;
+                    result.error = Some(message);
+                }
+                Err(MetadataError::Database(e)) => return Err(e),
+            }
+            results.push(result);
+        }
+
+        let has_invalid = results.iter().any(|result| result.error.is_some());
+        if apply && (skip_invalid || !has_invalid) {
+            tx.commit().await?;
+        } else {
+            tx.rollback().await?;
+        }
+        Ok(results)
+    }
+
+    async fn get_custom_metadata_value(
+        &self,
+        data_pool: &SqlitePool,
+        file_id: &str,
+        key_id: &str,
+    ) -> Result<Option<CustomMetadataValue>, sqlx::Error> {
+        let row =
+            sqlx::query("SELECT * FROM custom_metadata_values WHERE file_id = ? AND key_id = ?")
+                .bind(file_id)
+                .bind(key_id)
+                .fetch_optional(data_pool)
+                .await?;
+
+        match row {
+            Some(row) => Ok(Some(CustomMetadataValue {
+                id: row.get("id"),
+                file_id: row.get("file_id"),
+                key_id: row.get("key_id"),
+                value: row.get("value"),
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete_custom_metadata_value(
+        &self,
+        data_pool: &SqlitePool,
+        file_id: &str,
+        key_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM custom_metadata_values WHERE file_id = ? AND key_id = ?")
+            .bind(file_id)
+            .bind(key_id)
+            .execute(data_pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn remove_file_by_path(&self, pool: &SqlitePool, path: &str) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        // タグやカスタムメタデータが付いていれば墓標として残す
+        let file_id: Option<String> = sqlx::query_scalar("SELECT id FROM files WHERE path = ?")
+            .bind(path)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if let Some(file_id) = file_id {
+            create_tombstone(&mut tx, &file_id).await?;
+        }
+
+        sqlx::query("DELETE FROM files WHERE path = ?")
+            .bind(path)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_file_metadata(
+        &self,
+        pool: &SqlitePool,
+        path: &str,
+        metadata: &std::fs::Metadata,
+    ) -> Result<(), sqlx::Error> {
+        use chrono::{DateTime, Utc};
+        use std::os::unix::fs::MetadataExt;
+
+        let now = Utc::now();
+        let size = metadata.len() as i64;
+        let modified_at = metadata.modified().ok().and_then(|st| {
+            DateTime::from_timestamp(
+                st.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs() as i64,
+                0,
+            )
+        });
+        let created_at = metadata.created().ok().and_then(|st| {
+            DateTime::from_timestamp(
+                st.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs() as i64,
+                0,
+            )
+        });
+        // Linuxではstatx経由で取得され、未対応のファイルシステムではNoneになる
+        let birth_time: Option<DateTime<Utc>> = metadata.created().ok().map(DateTime::from);
+        let last_accessed = metadata.accessed().ok().and_then(|st| {
+            DateTime::from_timestamp(
+                st.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs() as i64,
+                0,
+            )
+        });
+
+        let inode = metadata.ino() as i64;
+        let owner_uid = metadata.uid() as i64;
+        let group_gid = metadata.gid() as i64;
+        let hard_links = metadata.nlink() as i64;
+        let device_id = metadata.dev() as i64;
+        let permissions = format!("{:o}", metadata.mode() & 0o777);
+
+        sqlx::query(
+            "UPDATE files SET 
+                size = ?, 
+                modified_at = ?, 
+                created_at = ?, 
+                birth_time = ?, 
+                last_accessed = ?, 
+                inode = ?, 
+                owner_uid = ?, 
+                group_gid = ?, 
+                hard_links = ?, 
+                device_id = ?, 
+                permissions = ?, 
+                updated_at_db = ?
+            WHERE path = ?",
+        )
+        .bind(size)
+        .bind(modified_at)
+        .bind(created_at)
+        .bind(birth_time)
+        .bind(last_accessed)
+        .bind(inode)
+        .bind(owner_uid)
+        .bind(group_gid)
+        .bind(hard_links)
+        .bind(device_id)
+        .bind(permissions)
+        .bind(now)
+        .bind(path)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn update_file_content_hash(
+        &self,
+        pool: &SqlitePool,
+        path: &str,
+        content_hash: Option<String>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE files SET content_hash = ? WHERE path = ?")
+            .bind(content_hash)
+            .bind(path)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn file_exists_by_path(
+        &self,
+        pool: &SqlitePool,
+        path: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM files WHERE path = ?")
+            .bind(path)
+            .fetch_one(pool)
+            .await?;
+
+        Ok(count > 0)
+    }
+
+    async fn find_file_by_inode(
+        &self,
+        pool: &SqlitePool,
+        inode: i64,
+        device_id: Option<i64>,
+    ) -> Result<Option<File>, sqlx::Error> {
+        let query = if let Some(_device_id) = device_id {
+            "SELECT * FROM files WHERE inode = ? AND device_id = ?"
+        } else {
+            "SELECT * FROM files WHERE inode = ? AND device_id IS NULL"
+        };
+
+        let row = if let Some(device_id) = device_id {
+            sqlx::query(query)
+                .bind(inode)
+                .bind(device_id)
+                .fetch_optional(pool)
+                .await?
+        } else {
+            sqlx::query("SELECT * FROM files WHERE inode = ?")
+                .bind(inode)
+                .fetch_optional(pool)
+                .await?
+        };
+
+        match row {
+            Some(row) => Ok(Some(File {
+                id: row.get("id"),
+                path: row.get("path"),
+                name: row.get("name"),
+                directory_id: row.get("directory_id"),
+                size: row.get("size"),
+                file_type: row.get("file_type"),
+                created_at: row.get("created_at"),
+                modified_at: row.get("modified_at"),
+                birth_time: row.get("birth_time"),
+                inode: row.get("inode"),
+                is_directory: row.get("is_directory"),
+                created_at_db: row.get("created_at_db"),
+                updated_at_db: row.get("updated_at_db"),
+                file_size: row.get("file_size"),
+                mime_type: row.get("mime_type"),
+                permissions: row.get("permissions"),
+                owner_uid: row.get("owner_uid"),
+                group_gid: row.get("group_gid"),
+                hard_links: row.get("hard_links"),
+                device_id: row.get("device_id"),
+                last_accessed: row.get("last_accessed"),
+                metadata: row.get("metadata"),
+                content_hash: row.get("content_hash"),
+                is_symlink: row.get("is_symlink"),
+                symlink_target: row.get("symlink_target"),
+                is_broken_link: row.get("is_broken_link"),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_file_by_identity(
+        &self,
+        pool: &SqlitePool,
+        device_id: Option<i64>,
+        inode: i64,
+        birth_time: Option<DateTime<Utc>>,
+    ) -> Result<Option<File>, sqlx::Error> {
+        // inodeは削除後に再利用されるため、作成日時と組み合わせて同一性を判定する
+        // 作成日時を記録する前にインデックスした行は inode と device_id だけで照合する（作成日時が一致する行を優先）
+        // 作成日時を取得できないファイルシステムでは、作成日時のない行とだけ照合する
+        let row = match birth_time {
+            Some(birth_time) => {
+                sqlx::query(
+                    "SELECT * FROM files WHERE device_id IS ? AND inode = ? AND (birth_time = ? OR birth_time IS NULL)
+                     ORDER BY birth_time IS NULL LIMIT 1",
+                )
+                .bind(device_id)
+                .bind(inode)
+                .bind(birth_time)
+                .fetch_optional(pool)
+                .await?
+            }
+            None => {
+                sqlx::query(
+                    "SELECT * FROM files WHERE device_id IS ? AND inode = ? AND birth_time IS NULL",
+                )
+                .bind(device_id)
+                .bind(inode)
+                .fetch_optional(pool)
+                .await?
+            }
+        };
+
+        Ok(row.as_ref().map(file_from_row))
+    }
+
+    async fn update_file_path(
+        &self,
+        pool: &SqlitePool,
+        file_id: &str,
+        new_path: &str,
+        new_name: &str,
+    ) -> Result<(), sqlx::Error> {
+        let now = Utc::now();
+
+        sqlx::query("UPDATE files SET path = ?, name = ?, updated_at_db = ? WHERE id = ?")
+            .bind(new_path)
+            .bind(new_name)
+            .bind(now)
+            .bind(file_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_hard_link_siblings(&self, pool: &SqlitePool, file_id: &str) -> Result<Vec<File>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT f.* FROM files f
+             INNER JOIN files target ON f.device_id IS target.device_id AND f.inode = target.inode
+             WHERE target.id = ? AND f.id != target.id
+               AND f.is_directory = 0 AND f.is_symlink = 0
+             ORDER BY f.path",
+        )
+        .bind(file_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows.iter().map(file_from_row).collect())
+    }
+
+    async fn get_hard_link_groups(&self, pool: &SqlitePool) -> Result<Vec<Vec<File>>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT f.* FROM files f
+             INNER JOIN (
+                 SELECT device_id, inode FROM files
+                 WHERE inode IS NOT NULL AND is_directory = 0 AND is_symlink = 0
+                 GROUP BY device_id, inode
+                 HAVING COUNT(*) > 1
+             ) g ON f.device_id IS g.device_id AND f.inode = g.inode
+             WHERE f.is_directory = 0 AND f.is_symlink = 0
+             ORDER BY f.device_id, f.inode, f.path",
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut groups: Vec<Vec<File>> = Vec::new();
+        for file in rows.iter().map(file_from_row) {
+            match groups.last_mut() {
+                Some(group) if group[0].device_id == file.device_id && group[0].inode == file.inode => {
+                    group.push(file)
+                }
+                _ => groups.push(vec![file]),
+            }
+        }
+
+        Ok(groups)
+    }
+
+    async fn remove_files_by_directory(&self, pool: &SqlitePool, directory_id: &str) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let file_ids: Vec<String> = sqlx::query_scalar("SELECT id FROM files WHERE directory_id = ?")
+            .bind(directory_id)
+            .fetch_all(&mut *tx)
+            .await?;
+        for file_id in &file_ids {
+            create_tombstone(&mut tx, file_id).await?;
+        }
+
+        sqlx::query("DELETE FROM files WHERE directory_id = ?")
+            .bind(directory_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_file_tombstones(&self, pool: &SqlitePool) -> Result<Vec<FileTombstone>, sqlx::Error> {
+        let rows = sqlx::query("SELECT * FROM file_tombstones ORDER BY deleted_at DESC")
+            .fetch_all(pool)
+            .await?;
+
+        let mut tombstones = Vec::new();
+        for row in rows {
+            tombstones.push(tombstone_from_row(pool, &row).await?);
+        }
+
+        Ok(tombstones)
+    }
+
+    async fn get_file_tombstone(
+        &self,
+        pool: &SqlitePool,
+        tombstone_id: &str,
+    ) -> Result<Option<FileTombstone>, sqlx::Error> {
+        let row = sqlx::query("SELECT * FROM file_tombstones WHERE id = ?")
+            .bind(tombstone_id)
+            .fetch_optional(pool)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(tombstone_from_row(pool, &row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_tombstone_by_identity(
+        &self,
+        pool: &SqlitePool,
+        file: &File,
+    ) -> Result<Option<FileTombstone>, sqlx::Error> {
+        // inode は再利用されるため、作成日時も一致する場合だけ同じファイルとみなす
+        let (Some(inode), Some(birth_time)) = (file.inode, file.birth_time) else {
+            return Ok(None);
+        };
+        let row = sqlx::query(
+            "SELECT * FROM file_tombstones
+             WHERE inode = ? AND device_id IS ? AND birth_time = ?
+             ORDER BY deleted_at DESC LIMIT 1",
+        )
+        .bind(inode)
+        .bind(file.device_id)
+        .bind(birth_time)
+        .fetch_optional(pool)
+        .await?;
+
+        match row {
+            Some(row) => Ok(Some(tombstone_from_row(pool, &row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_tombstone_by_content(
+        &self,
+        pool: &SqlitePool,
+        file: &File,
+    ) -> Result<Option<FileTombstone>, sqlx::Error> {
+        let Some(content_hash) = &file.content_hash else {
+            return Ok(None);
+        };
+        let row = sqlx::query(
+            "SELECT * FROM file_tombstones
+             WHERE content_hash = ? AND size = ? AND is_directory = 0
+             ORDER BY deleted_at DESC LIMIT 1",
+        )
+        .bind(content_hash)
+        .bind(file.size)
+        .fetch_optional(pool)
+        .await?;
+
+        match row {
+            Some(row) => Ok(Some(tombstone_from_row(pool, &row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_tombstone_candidates(&self, pool: &SqlitePool, tombstone_id: &str) -> Result<Vec<File>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT f.* FROM files f
+             INNER JOIN file_tombstones t ON f.content_hash = t.content_hash AND f.size = t.size
+             WHERE t.id = ? AND f.is_directory = 0
+             ORDER BY f.path",
+        )
+        .bind(tombstone_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows.iter().map(file_from_row).collect())
+    }
+
+    async fn restore_file_tombstone(
+        &self,
+        pool: &SqlitePool,
+        tombstone_id: &str,
+        file_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let tombstone: Option<(String, String)> =
+            sqlx::query_as("SELECT original_file_id, custom_metadata FROM file_tombstones WHERE id = ?")
+                .bind(tombstone_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let Some((original_file_id, custom_metadata)) = tombstone else {
+            return Err(sqlx::Error::RowNotFound);
+        };
+
+        // 墓標の間も残していた変更履歴を戻したファイルに付け替える
+        sqlx::query("UPDATE annotation_history SET file_id = ? WHERE file_id = ?")
+            .bind(file_id)
+            .bind(&original_file_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            "INSERT OR IGNORE INTO file_tags (file_id, tag_id)
+             SELECT ?, tag_id FROM file_tombstone_tags WHERE tombstone_id = ?",
+        )
+        .bind(file_id)
+        .bind(tombstone_id)
+        .execute(&mut *tx)
+        .await?;
+
+        // 既に値が設定されているキーは上書きしない
+        let values: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&custom_metadata).unwrap_or_default();
+        let now = Utc::now();
+        for (key_id, value) in values {
+            let items: Vec<String> = match &value {
+                serde_json::Value::Array(items) => items.iter().filter_map(|i| i.as_str().map(str::to_string)).collect(),
+                _ => Vec::new(),
+            };
+            let stored = if value.is_array() {
+                serde_json::to_string(&items).ok()
+            } else {
+                value.as_str().map(str::to_string)
+            };
+            let value_id = Uuid::new_v4().to_string();
+            let inserted = sqlx::query(
+                "INSERT INTO custom_metadata_values (id, file_id, key_id, value, created_at, updated_at)
+                 SELECT ?, ?, ?, ?, ?, ?
+                 WHERE NOT EXISTS (SELECT 1 FROM custom_metadata_values WHERE file_id = ? AND key_id = ?)",
+            )
+            .bind(&value_id)
+            .bind(file_id)
+            .bind(&key_id)
+            .bind(stored)
+            .bind(now)
+            .bind(now)
+            .bind(file_id)
+            .bind(&key_id)
+            .execute(&mut *tx)
+            .await?;
+            if inserted.rows_affected() > 0 {
+                replace_value_items(&mut tx, &value_id, &key_id, &items).await?;
+            }
+        }
+
+        sqlx::query("DELETE FROM file_tombstones WHERE id = ?")
+            .bind(tombstone_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_file_tombstone(&self, pool: &SqlitePool, tombstone_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM file_tombstones WHERE id = ?")
+            .bind(tombstone_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn purge_expired_tombstones(
+        &self,
+        pool: &SqlitePool,
+        older_than: DateTime<Utc>,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM file_tombstones WHERE deleted_at < ?")
+            .bind(older_than)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn get_xattr_synced_tags(&self, pool: &SqlitePool, file_id: &str) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT tag_name FROM xattr_synced_tags WHERE file_id = ? ORDER BY tag_name")
+            .bind(file_id)
+            .fetch_all(pool)
+            .await
+    }
+
+    async fn set_xattr_synced_tags(
+        &self,
+        pool: &SqlitePool,
+        file_id: &str,
+        tag_names: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM xattr_synced_tags WHERE file_id = ?")
+            .bind(file_id)
+            .execute(&mut *tx)
+            .await?;
+        for name in tag_names {
+            sqlx::query("INSERT OR IGNORE INTO xattr_synced_tags (file_id, tag_name) VALUES (?, ?)")
+                .bind(file_id)
+                .bind(name)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn get_xmp_synced_tags(&self, pool: &SqlitePool, file_id: &str) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT tag_name FROM xmp_synced_tags WHERE file_id = ? ORDER BY tag_name")
+            .bind(file_id)
+            .fetch_all(pool)
+            .await
+    }
+
+    async fn set_xmp_synced_tags(
+        &self,
+        pool: &SqlitePool,
+        file_id: &str,
+        tag_names: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM xmp_synced_tags WHERE file_id = ?")
+            .bind(file_id)
+            .execute(&mut *tx)
+            .await?;
+        for name in tag_names {
+            sqlx::query("INSERT OR IGNORE INTO xmp_synced_tags (file_id, tag_name) VALUES (?, ?)")
+                .bind(file_id)
+                .bind(name)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn get_files_paginated_with_category(
+        &self,
+        pool: &SqlitePool,
+        category: &str,
+        sort_field: Option<String>,
+        sort_order: Option<String>,
+        limit: u32,
+        offset: u32,
+        show_hidden_files: bool,
+        show_directories: bool,
+    ) -> Result<Vec<File>, sqlx::Error> {
+        let sort_field = sort_field.as_deref().unwrap_or("modified_at");
+        let sort_order = sort_order.as_deref().unwrap_or("desc");
+
+        let sort_column = match sort_field {
+            "name" => "name",
+            "size" => "size",
+            "created_at" => "created_at",
+            "modified_at" => "modified_at",
+            "last_accessed" => "last_accessed",
+            "file_type" => "file_type",
+            _ => "modified_at",
+        };
+
+        let order_direction = match sort_order {
+            "asc" => "ASC",
+            _ => "DESC",
+        };
+
+        let category_where_clause = build_category_where_clause(category);
+        
+        let mut additional_conditions = Vec::new();
+        if !show_hidden_files {
+            additional_conditions.push(" AND name NOT LIKE '.%'");
+        }
+        if !show_directories {
+            additional_conditions.push(" AND is_directory = FALSE");
+        }
+
+        let query = format!(
+            "SELECT * FROM files WHERE 1=1{}{} ORDER BY {} {} NULLS LAST LIMIT ? OFFSET ?",
+            category_where_clause, additional_conditions.join(""), sort_column, order_direction
+        );
+
+        let rows = sqlx::query(&query)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(pool)
+            .await?;
+
+        let mut files = Vec::new();
+        for row in rows {
+            files.push(File {
+                id: row.get("id"),
+                path: row.get("path"),
+                name: row.get("name"),
+                directory_id: row.get("directory_id"),
+                size: row.get("size"),
+                file_type: row.get("file_type"),
+                created_at: row.get("created_at"),
+                modified_at: row.get("modified_at"),
+                birth_time: row.get("birth_time"),
+                inode: row.get("inode"),
+                is_directory: row.get("is_directory"),
+                created_at_db: row.get("created_at_db"),
+                updated_at_db: row.get("updated_at_db"),
+                file_size: row.get("file_size"),
+                mime_type: row.get("mime_type"),
+                permissions: row.get("permissions"),
+                owner_uid: row.get("owner_uid"),
+                group_gid: row.get("group_gid"),
+                hard_links: row.get("hard_links"),
+                device_id: row.get("device_id"),
+                last_accessed: row.get("last_accessed"),
+                metadata: row.get("metadata"),
+                content_hash: row.get("content_hash"),
+                is_symlink: row.get("is_symlink"),
+                symlink_target: row.get("symlink_target"),
+                is_broken_link: row.get("is_broken_link"),
+            });
+        }
+
+        Ok(files)
+    }
+
+    async fn get_files_by_directory_paginated_with_category(
+        &self,
+        pool: &SqlitePool,
+        directory_id: &str,
+        category: &str,
+        sort_field: Option<String>,
+        sort_order: Option<String>,
+        limit: u32,
+        offset: u32,
+        show_hidden_files: bool,
+        show_directories: bool,
+    ) -> Result<Vec<File>, sqlx::Error> {
+        let sort_field = sort_field.as_deref().unwrap_or("modified_at");
+        let sort_order = sort_order.as_deref().unwrap_or("desc");
+
+        let sort_column = match sort_field {
+            "name" => "name",
+            "size" => "size",
+            "created_at" => "created_at",
+            "modified_at" => "modified_at",
+            "last_accessed" => "last_accessed",
+            "file_type" => "file_type",
+            _ => "modified_at",
+        };
+
+        let order_direction = match sort_order {
+            "asc" => "ASC",
+            _ => "DESC",
+        };
+
+        let category_where_clause = build_category_where_clause(category);
         
         let mut additional_conditions = Vec::new();
         if !show_hidden_files {
@@ -1492,13 +4078,36 @@ impl DatabaseTrait for Database {
                 device_id: row.get("device_id"),
                 last_accessed: row.get("last_accessed"),
                 metadata: row.get("metadata"),
+                content_hash: row.get("content_hash"),
+                is_symlink: row.get("is_symlink"),
+                symlink_target: row.get("symlink_target"),
+                is_broken_link: row.get("is_broken_link"),
             });
         }
 
-        Ok(files)
+        Ok(files)
+    }
+
+    async fn count_files_with_category(&self, pool: &SqlitePool, category: &str, show_hidden_files: bool, show_directories: bool) -> Result<u32, sqlx::Error> {
+        let category_where_clause = build_category_where_clause(category);
+        
+        let mut additional_conditions = Vec::new();
+        if !show_hidden_files {
+            additional_conditions.push(" AND name NOT LIKE '.%'");
+        }
+        if !show_directories {
+            additional_conditions.push(" AND is_directory = FALSE");
+        }
+
+        let query = format!("SELECT COUNT(*) FROM files WHERE 1=1{}{}", category_where_clause, additional_conditions.join(""));
+        
+        let count: i64 = sqlx::query_scalar(&query)
+            .fetch_one(pool)
+            .await?;
+        Ok(count as u32)
     }
 
-    async fn count_files_with_category(&self, pool: &SqlitePool, category: &str, show_hidden_files: bool, show_directories: bool) -> Result<u32, sqlx::Error> {
+    async fn count_files_by_directory_with_category(&self, pool: &SqlitePool, directory_id: &str, category: &str, show_hidden_files: bool, show_directories: bool) -> Result<u32, sqlx::Error> {
         let category_where_clause = build_category_where_clause(category);
         
         let mut additional_conditions = Vec::new();
@@ -1509,62 +4118,1012 @@ impl DatabaseTrait for Database {
             additional_conditions.push(" AND is_directory = FALSE");
         }
 
-        let query = format!("SELECT COUNT(*) FROM files WHERE 1=1{}{}", category_where_clause, additional_conditions.join(""));
+        let query = format!("SELECT COUNT(*) FROM files WHERE directory_id = ?{}{}", category_where_clause, additional_conditions.join(""));
         
         let count: i64 = sqlx::query_scalar(&query)
+            .bind(directory_id)
             .fetch_one(pool)
             .await?;
         Ok(count as u32)
     }
 
-    async fn count_files_by_directory_with_category(&self, pool: &SqlitePool, directory_id: &str, category: &str, show_hidden_files: bool, show_directories: bool) -> Result<u32, sqlx::Error> {
-        let category_where_clause = build_category_where_clause(category);
-        
-        let mut additional_conditions = Vec::new();
-        if !show_hidden_files {
-            additional_conditions.push(" AND name NOT LIKE '.%'");
-        }
-        if !show_directories {
-            additional_conditions.push(" AND is_directory = FALSE");
-        }
+}
+
+fn build_category_where_clause(category: &str) -> String {
+    if category == "all" {
+        return String::new();
+    }
+
+    if category == "other" {
+        let conditions: Vec<String> = CATEGORY_EXTENSIONS
+            .iter()
+            .flat_map(|(_, extensions)| extensions.iter())
+            .map(|ext| format!("path NOT LIKE '%.{}'", ext))
+            .collect();
+        return format!(" AND ({})", conditions.join(" AND "));
+    }
+
+    let Some((_, extensions)) = CATEGORY_EXTENSIONS.iter().find(|(name, _)| *name == category) else {
+        return String::new();
+    };
+
+    let conditions: Vec<String> = extensions.iter()
+        .map(|ext| format!("path LIKE '%.{}' OR path LIKE '%.{}'", ext, ext.to_uppercase()))
+        .collect();
+    
+    format!(" AND ({})", conditions.join(" OR "))
+}
+
+/// filesテーブルの行をFileに変換する
+pub fn file_from_row(row: &sqlx::sqlite::SqliteRow) -> File {
+    File {
+        id: row.get("id"),
+        path: row.get("path"),
+        name: row.get("name"),
+        directory_id: row.get("directory_id"),
+        size: row.get("size"),
+        file_type: row.get("file_type"),
+        created_at: row.get("created_at"),
+        modified_at: row.get("modified_at"),
+        birth_time: row.get("birth_time"),
+        inode: row.get("inode"),
+        is_directory: row.get("is_directory"),
+        created_at_db: row.get("created_at_db"),
+        updated_at_db: row.get("updated_at_db"),
+        file_size: row.get("file_size"),
+        mime_type: row.get("mime_type"),
+        permissions: row.get("permissions"),
+        owner_uid: row.get("owner_uid"),
+        group_gid: row.get("group_gid"),
+        hard_links: row.get("hard_links"),
+        device_id: row.get("device_id"),
+        last_accessed: row.get("last_accessed"),
+        metadata: row.get("metadata"),
+        content_hash: row.get("content_hash"),
+        is_symlink: row.get("is_symlink"),
+        symlink_target: row.get("symlink_target"),
+        is_broken_link: row.get("is_broken_link"),
+    }
+}
+
+/// 自動削除してよいタグ（固定されておらず、ファイルにも墓標にも付いておらず、子タグもないもの）を取得するクエリ
+pub const ORPHANED_TAGS_QUERY: &str = "SELECT t.id FROM tags t
+     LEFT JOIN file_tags ft ON t.id = ft.tag_id
+     WHERE ft.tag_id IS NULL
+       AND t.pinned = 0
+       AND NOT EXISTS (SELECT 1 FROM file_tombstone_tags ftt WHERE ftt.tag_id = t.id)
+       AND NOT EXISTS (SELECT 1 FROM tags child WHERE child.parent_id = t.id)";
+
+/// 固定されたタグも含めて、どこからも参照されていないタグのIDを取得するクエリ
+pub const UNUSED_TAGS_QUERY: &str = "SELECT t.id FROM tags t
+     LEFT JOIN file_tags ft ON t.id = ft.tag_id
+     WHERE ft.tag_id IS NULL
+       AND NOT EXISTS (SELECT 1 FROM file_tombstone_tags ftt WHERE ftt.tag_id = t.id)
+       AND NOT EXISTS (SELECT 1 FROM tags child WHERE child.parent_id = t.id)";
+
+/// 指定したタグ（1番目のパラメータ）と、その子孫タグのIDを取得するクエリ
+pub const TAG_SUBTREE_QUERY: &str = "WITH RECURSIVE subtree(id) AS (
+         SELECT ?
+         UNION
+         SELECT t.id FROM tags t INNER JOIN subtree s ON t.parent_id = s.id
+     )
+     SELECT id FROM subtree";
+
+/// タグ自身と子孫の名前の先頭部分（タグのパス）を置き換える
+async fn rename_tag_subtree(conn: &mut SqliteConnection, tag: &Tag, new_name: &str) -> Result<(), sqlx::Error> {
+    let prefix = format!("{}/", tag.name);
+    // substr は1始まり
+    sqlx::query(&format!(
+        "UPDATE tags SET name = ? || substr(name, ?)
+         WHERE id IN ({TAG_SUBTREE_QUERY}) AND (name = ? OR substr(name, 1, ?) = ?)"
+    ))
+    .bind(new_name)
+    .bind(tag.name.chars().count() as i64 + 1)
+    .bind(&tag.id)
+    .bind(&tag.name)
+    .bind(prefix.chars().count() as i64)
+    .bind(&prefix)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// tagsテーブルの行をTagに変換する
+pub fn tag_from_row(row: &sqlx::sqlite::SqliteRow) -> Tag {
+    Tag {
+        id: row.get("id"),
+        name: row.get("name"),
+        color: row.get("color"),
+        created_at: row.get("created_at"),
+        parent_id: row.get("parent_id"),
+        pinned: row.get("pinned"),
+    }
+}
+
+/// タグごと・グループごとのファイル数と合計サイズを数える
+async fn tag_group_counts(pool: &SqlitePool, group_expr: &str, join: &str) -> Result<Vec<TagGroupCount>, sqlx::Error> {
+    let sql = format!(
+        "SELECT ft.tag_id, {group_expr} AS grp, COUNT(*) AS file_count,
+                COALESCE(SUM(CASE WHEN f.is_directory THEN 0 ELSE f.size END), 0) AS total_bytes
+         FROM file_tags ft
+         INNER JOIN files f ON f.id = ft.file_id
+         {join}
+         GROUP BY ft.tag_id, grp
+         ORDER BY ft.tag_id, file_count DESC"
+    );
+    let rows = sqlx::query(&sql).fetch_all(pool).await?;
+    Ok(rows.iter().map(tag_group_count_from_row).collect())
+}
+
+fn tag_group_count_from_row(row: &sqlx::sqlite::SqliteRow) -> TagGroupCount {
+    TagGroupCount {
+        tag_id: row.get("tag_id"),
+        group: row.get("grp"),
+        file_count: row.get("file_count"),
+        total_bytes: row.get("total_bytes"),
+    }
+}
+
+/// タグ候補の各根拠の重み
+const SUGGESTION_WEIGHTS: [(TagSuggestionReason, f64); 5] = [
+    (TagSuggestionReason::SameDirectory, 3.0),
+    (TagSuggestionReason::SimilarName, 2.0),
+    (TagSuggestionReason::SharedMetadata, 2.0),
+    (TagSuggestionReason::CoOccurrence, 2.0),
+    (TagSuggestionReason::SameExtension, 1.0),
+];
+
+/// 根拠ごとに比較するファイル数の上限
+const SUGGESTION_SAMPLE_LIMIT: i64 = 500;
+
+/// 1ファイルについて、タグIDごとの候補スコアと根拠を求める
+/// 各根拠では「比較対象のファイルのうち、そのタグが付いている割合」に重みを掛けて加算する
+async fn tag_suggestion_scores(
+    pool: &SqlitePool,
+    file_id: &str,
+    current_tag_ids: &HashSet<String>,
+) -> Result<HashMap<String, (f64, Vec<TagSuggestionReason>)>, sqlx::Error> {
+    let Some(row) = sqlx::query("SELECT path, name FROM files WHERE id = ?")
+        .bind(file_id)
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(HashMap::new());
+    };
+    let path: String = row.get("path");
+    let name: String = row.get("name");
+
+    let mut groups: Vec<(TagSuggestionReason, Vec<String>)> = Vec::new();
+
+    // 同じディレクトリ直下のファイル
+    if let Some((parent, _)) = path.rsplit_once('/') {
+        let prefix = format!("{parent}/");
+        let siblings = sqlx::query_scalar(
+            "SELECT id FROM files
+             WHERE substr(path, 1, ?) = ? AND instr(substr(path, ? + 1), '/') = 0 AND id != ?
+             ORDER BY modified_at DESC LIMIT ?",
+        )
+        .bind(prefix.chars().count() as i64)
+        .bind(&prefix)
+        .bind(prefix.chars().count() as i64)
+        .bind(file_id)
+        .bind(SUGGESTION_SAMPLE_LIMIT)
+        .fetch_all(pool)
+        .await?;
+        groups.push((TagSuggestionReason::SameDirectory, siblings));
+    }
+
+    // 名前の主要な単語を含むファイル（数字だけ・短すぎる単語は除く）
+    let stem = name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&name).to_lowercase();
+    let words: Vec<&str> = stem
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() >= 3 && !w.chars().all(|c| c.is_ascii_digit()))
+        .collect();
+    if !words.is_empty() {
+        let conditions = vec!["instr(lower(name), ?) > 0"; words.len()].join(" OR ");
+        let sql = format!(
+            "SELECT id FROM files WHERE id != ? AND ({conditions}) ORDER BY modified_at DESC LIMIT ?"
+        );
+        let mut query = sqlx::query_scalar(&sql).bind(file_id);
+        for word in &words {
+            query = query.bind(*word);
+        }
+        let similar = query.bind(SUGGESTION_SAMPLE_LIMIT).fetch_all(pool).await?;
+        groups.push((TagSuggestionReason::SimilarName, similar));
+    }
+
+    // 同じ拡張子のファイル
+    if let Some((_, extension)) = name.rsplit_once('.').filter(|(stem, _)| !stem.is_empty()) {
+        let same_extension = sqlx::query_scalar(
+            "SELECT id FROM files WHERE id != ? AND lower(name) LIKE ? ESCAPE '\\'
+             ORDER BY modified_at DESC LIMIT ?",
+        )
+        .bind(file_id)
+        .bind(format!("%.{}", escape_like(&extension.to_lowercase())))
+        .bind(SUGGESTION_SAMPLE_LIMIT)
+        .fetch_all(pool)
+        .await?;
+        groups.push((TagSuggestionReason::SameExtension, same_extension));
+    }
+
+    // 同じカスタムメタデータの値を持つファイル
+    let shared_metadata = sqlx::query_scalar(
+        "SELECT DISTINCT other.file_id FROM custom_metadata_values mine
+         INNER JOIN custom_metadata_values other
+             ON other.key_id = mine.key_id AND other.value = mine.value AND other.file_id != mine.file_id
+         WHERE mine.file_id = ? AND mine.value IS NOT NULL AND mine.value != ''
+         LIMIT ?",
+    )
+    .bind(file_id)
+    .bind(SUGGESTION_SAMPLE_LIMIT)
+    .fetch_all(pool)
+    .await?;
+    groups.push((TagSuggestionReason::SharedMetadata, shared_metadata));
+
+    let mut scores: HashMap<String, (f64, Vec<TagSuggestionReason>)> = HashMap::new();
+    let mut add_score = |tag_id: String, reason: TagSuggestionReason, ratio: f64| {
+        if current_tag_ids.contains(&tag_id) {
+            return;
+        }
+        let weight = SUGGESTION_WEIGHTS.iter().find(|(r, _)| *r == reason).map_or(1.0, |(_, w)| *w);
+        let entry = scores.entry(tag_id).or_insert_with(|| (0.0, Vec::new()));
+        entry.0 += weight * ratio;
+        if !entry.1.contains(&reason) {
+            entry.1.push(reason);
+        }
+    };
+
+    for (reason, file_ids) in groups {
+        if file_ids.is_empty() {
+            continue;
+        }
+        let population = file_ids.len() as f64;
+        for (tag_id, count) in count_tags_on_files(pool, &file_ids).await? {
+            add_score(tag_id, reason, count as f64 / population);
+        }
+    }
+
+    // 既に付いているタグと一緒に使われているタグ（付いているタグごとの割合の最大値）
+    let mut co_occurrence: HashMap<String, f64> = HashMap::new();
+    for tag_id in current_tag_ids {
+        let tagged: Vec<String> = sqlx::query_scalar(
+            "SELECT file_id FROM file_tags WHERE tag_id = ? AND file_id != ? LIMIT ?",
+        )
+        .bind(tag_id)
+        .bind(file_id)
+        .bind(SUGGESTION_SAMPLE_LIMIT)
+        .fetch_all(pool)
+        .await?;
+        if tagged.is_empty() {
+            continue;
+        }
+        let population = tagged.len() as f64;
+        for (other_id, count) in count_tags_on_files(pool, &tagged).await? {
+            let ratio = count as f64 / population;
+            let best = co_occurrence.entry(other_id).or_insert(0.0);
+            *best = best.max(ratio);
+        }
+    }
+    for (tag_id, ratio) in co_occurrence {
+        add_score(tag_id, TagSuggestionReason::CoOccurrence, ratio);
+    }
+
+    Ok(scores)
+}
+
+/// 指定したファイル群について、タグごとの付いているファイル数を数える
+async fn count_tags_on_files(pool: &SqlitePool, file_ids: &[String]) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    let placeholders = vec!["?"; file_ids.len()].join(", ");
+    let sql = format!(
+        "SELECT tag_id, COUNT(DISTINCT file_id) FROM file_tags WHERE file_id IN ({placeholders}) GROUP BY tag_id"
+    );
+    let mut query = sqlx::query_as::<_, (String, i64)>(&sql);
+    for file_id in file_ids {
+        query = query.bind(file_id);
+    }
+    query.fetch_all(pool).await
+}
+
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// キー定義の変更に合わせて既存の値を変換し、変換できなかった値を返す（drop_invalid の場合はそれらの値を削除する）
+async fn convert_custom_metadata_values_in(
+    conn: &mut SqliteConnection,
+    previous: &CustomMetadataKey,
+    key: &CustomMetadataKey,
+    drop_invalid: bool,
+) -> Result<Vec<CustomMetadataViolation>, sqlx::Error> {
+    let values: Vec<(String, String, String)> = sqlx::query_as(
+        "SELECT v.file_id, f.path, v.value FROM custom_metadata_values v
+         INNER JOIN files f ON f.id = v.file_id
+         WHERE v.key_id = ? AND v.value IS NOT NULL AND trim(v.value) != ''
+         ORDER BY f.path",
+    )
+    .bind(&key.id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut failures = Vec::new();
+    for (file_id, path, value) in values {
+        // 複数選択から他の型に変える場合は項目を区切って渡す
+        let input = if previous.data_type == "multi_select" && key.data_type != "multi_select" {
+            multi_select_items(&value).join(", ")
+        } else {
+            value.clone()
+        };
+        // 変換しても変わらない値は更新日時を変えないよう書き込まない
+        if key.data_type != "file_ref" && key.normalize_value(Some(&input)).ok().flatten().as_deref() == Some(value.as_str()) {
+            continue;
+        }
+
+        let mut savepoint = Acquire::begin(&mut *conn).await?;
+        // 空でない値だけを変換するため、必須の判定にスキーマは使わない
+        match write_custom_metadata_value(&mut savepoint, &[], key, &file_id, Some(input)).await {
+            Ok(_) => savepoint.commit().await?,
+            Err(MetadataError::Invalid(message)) => {
+                savepoint.rollback().await?;
+                if drop_invalid {
+                    sqlx::query("DELETE FROM custom_metadata_values WHERE file_id = ? AND key_id = ?")
+                        .bind(&file_id)
+                        .bind(&key.id)
+                        .execute(&mut *conn)
+                        .await?;
+                }
+                failures.push(CustomMetadataViolation {
+                    file_id,
+                    path,
+                    key_id: key.id.clone(),
+                    key_name: key.name.clone(),
+                    value: Some(value),
+                    message,
+                });
+            }
+            Err(MetadataError::Database(e)) => return Err(e),
+        }
+    }
+    Ok(failures)
+}
+
+async fn update_custom_metadata_key_in(
+    conn: &mut SqliteConnection,
+    key: &CustomMetadataKey,
+) -> Result<CustomMetadataKey, sqlx::Error> {
+    let now = Utc::now();
+
+    sqlx::query(
+        "UPDATE custom_metadata_keys SET display_name = ?, data_type = ?, description = ?, is_required = ?, default_value = ?, validation_pattern = ?, options = ?, expression = ?, updated_at = ? WHERE id = ?"
+    )
+    .bind(&key.display_name)
+    .bind(&key.data_type)
+    .bind(&key.description)
+    .bind(key.is_required)
+    .bind(&key.default_value)
+    .bind(&key.validation_pattern)
+    .bind(options_to_json(&key.options))
+    .bind(&key.expression)
+    .bind(now)
+    .bind(&key.id)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(CustomMetadataKey {
+        updated_at: now,
+        ..key.clone()
+    })
+}
+
+/// 値をキーの型に合わせて検証・正規化して書き込む（計算フィールドには書き込めない）
+/// スキーマの対象外のファイルでは、必須のキーも空にできる
+async fn write_custom_metadata_value(
+    conn: &mut SqliteConnection,
+    schemas: &[CustomMetadataSchema],
+    key: &CustomMetadataKey,
+    file_id: &str,
+    value: Option<String>,
+) -> Result<CustomMetadataValue, MetadataError> {
+    if key.expression.is_some() {
+        return Err(MetadataError::Invalid(format!(
+            "'{}' は計算フィールドのため値を設定できません",
+            key.display_name
+        )));
+    }
+    if key.is_required && matches!(value.as_deref().map(str::trim), None | Some("")) {
+        let matcher = SchemaMatcher::load(conn, schemas.to_vec()).await?;
+        if let Some(file) = matcher.file_context(conn, file_id).await? {
+            if !matcher.key_applies(&key.id, &file) {
+                let key = CustomMetadataKey { is_required: false, ..key.clone() };
+                return store_custom_metadata_value(conn, &key, file_id, value).await;
+            }
+        }
+    }
+    store_custom_metadata_value(conn, key, file_id, value).await
+}
+
+/// 値をキーの型に合わせて検証・正規化して書き込む（multi_select は項目の行も更新する）
+async fn store_custom_metadata_value(
+    conn: &mut SqliteConnection,
+    key: &CustomMetadataKey,
+    file_id: &str,
+    value: Option<String>,
+) -> Result<CustomMetadataValue, MetadataError> {
+    let key_id = key.id.as_str();
+    let mut value = key.normalize_value(value.as_deref()).map_err(MetadataError::Invalid)?;
+
+    // ファイル参照はIDかパスで指定でき、インデックス済みのファイルのIDとして保存する
+    if key.data_type == "file_ref" {
+        if let Some(reference) = value.take() {
+            value = Some(resolve_file_ref(conn, &reference).await?.ok_or_else(|| {
+                MetadataError::Invalid(format!("参照先のファイルが見つかりません: {reference}"))
+            })?);
+        }
+    }
+
+    let now = Utc::now();
+
+    // 既存のレコードをチェック
+    let result = if let Some(existing) = sqlx::query_as::<_, (String, DateTime<Utc>)>(
+        "SELECT id, created_at FROM custom_metadata_values WHERE file_id = ? AND key_id = ?"
+    )
+    .bind(file_id)
+    .bind(key_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    {
+        // 既存レコードがある場合は更新
+        sqlx::query(
+            "UPDATE custom_metadata_values SET value = ?, updated_at = ?, is_computed = 0 WHERE file_id = ? AND key_id = ?"
+        )
+        .bind(value.as_deref())
+        .bind(now)
+        .bind(file_id)
+        .bind(key_id)
+        .execute(&mut *conn)
+        .await?;
+
+        CustomMetadataValue {
+            id: existing.0,
+            file_id: file_id.to_string(),
+            key_id: key_id.to_string(),
+            value,
+            created_at: existing.1,
+            updated_at: now,
+        }
+    } else {
+        // 既存レコードがない場合は新規作成
+        let id = Uuid::new_v4().to_string();
+        
+        sqlx::query(
+            "INSERT INTO custom_metadata_values (id, file_id, key_id, value, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)"
+        )
+        .bind(&id)
+        .bind(file_id)
+        .bind(key_id)
+        .bind(value.as_deref())
+        .bind(now)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+
+        CustomMetadataValue {
+            id,
+            file_id: file_id.to_string(),
+            key_id: key_id.to_string(),
+            value,
+            created_at: now,
+            updated_at: now,
+        }
+    };
+
+    let items = match (&result.value, key.data_type.as_str()) {
+        (Some(value), "multi_select") => multi_select_items(value),
+        _ => Vec::new(),
+    };
+    replace_value_items(conn, &result.id, key_id, &items).await?;
+
+    Ok(result)
+}
+
+/// ファイル参照（IDかパス）をインデックス済みのファイルのIDにする
+async fn resolve_file_ref(conn: &mut SqliteConnection, reference: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM files WHERE id = ? OR path = ? LIMIT 1")
+        .bind(reference)
+        .bind(reference)
+        .fetch_optional(&mut *conn)
+        .await
+}
+
+/// 計算フィールドの値を書き込む。計算時に正規化済みなので検証はせず、変更履歴にも残さない
+async fn store_computed_value(
+    conn: &mut SqliteConnection,
+    key: &CustomMetadataKey,
+    file_id: &str,
+    value: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let Some(value) = value else {
+        sqlx::query("DELETE FROM custom_metadata_values WHERE file_id = ? AND key_id = ?")
+            .bind(file_id)
+            .bind(&key.id)
+            .execute(&mut *conn)
+            .await?;
+        return Ok(());
+    };
+
+    let now = Utc::now();
+    let existing_id: Option<String> =
+        sqlx::query_scalar("SELECT id FROM custom_metadata_values WHERE file_id = ? AND key_id = ?")
+            .bind(file_id)
+            .bind(&key.id)
+            .fetch_optional(&mut *conn)
+            .await?;
+    let value_id = match existing_id {
+        Some(id) => {
+            sqlx::query("UPDATE custom_metadata_values SET value = ?, updated_at = ?, is_computed = 1 WHERE id = ?")
+                .bind(value)
+                .bind(now)
+                .bind(&id)
+                .execute(&mut *conn)
+                .await?;
+            id
+        }
+        None => {
+            let id = Uuid::new_v4().to_string();
+            sqlx::query(
+                "INSERT INTO custom_metadata_values (id, file_id, key_id, value, created_at, updated_at, is_computed)
+                 VALUES (?, ?, ?, ?, ?, ?, 1)",
+            )
+            .bind(&id)
+            .bind(file_id)
+            .bind(&key.id)
+            .bind(value)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?;
+            id
+        }
+    };
+
+    let items = if key.data_type == "multi_select" { multi_select_items(value) } else { Vec::new() };
+    replace_value_items(conn, &value_id, &key.id, &items).await
+}
+
+/// タグのパスを親から順にたどり、ないものは作る（別名は正式なタグに解決する）
+async fn ensure_tag_path_in(conn: &mut SqliteConnection, path: &str, color: &str) -> Result<Tag, sqlx::Error> {
+    let mut parent: Option<Tag> = None;
+    let mut current_path = String::new();
+
+    for segment in path.split('/') {
+        if !current_path.is_empty() {
+            current_path.push('/');
+        }
+        current_path.push_str(segment);
+        let parent_id = parent.as_ref().map(|t| t.id.clone());
+
+        let existing = sqlx::query("SELECT * FROM tags WHERE name = ?")
+            .bind(&current_path)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let alias = match existing {
+            Some(_) => None,
+            None => sqlx::query(
+                "SELECT t.* FROM tags t INNER JOIN tag_aliases ta ON t.id = ta.tag_id WHERE ta.alias = ?",
+            )
+            .bind(&current_path)
+            .fetch_optional(&mut *conn)
+            .await?,
+        };
+        let tag = match (existing, alias) {
+            // 別名はそのまま正式なタグに解決し、以降はそのタグの配下に作る
+            (None, Some(row)) => {
+                let tag = tag_from_row(&row);
+                current_path = tag.name.clone();
+                tag
+            }
+            (Some(row), _) => {
+                let mut tag = tag_from_row(&row);
+                // 同じパス名の平坦なタグは階層に組み込む
+                if tag.parent_id.is_none() && parent_id.is_some() {
+                    sqlx::query("UPDATE tags SET parent_id = ? WHERE id = ?")
+                        .bind(&parent_id)
+                        .bind(&tag.id)
+                        .execute(&mut *conn)
+                        .await?;
+                    tag.parent_id = parent_id;
+                }
+                tag
+            }
+            (None, None) => {
+                let tag = Tag {
+                    id: Uuid::new_v4().to_string(),
+                    name: current_path.clone(),
+                    color: color.to_string(),
+                    created_at: Utc::now(),
+                    parent_id,
+                    pinned: false,
+                };
+                sqlx::query("INSERT INTO tags (id, name, color, created_at, parent_id) VALUES (?, ?, ?, ?, ?)")
+                    .bind(&tag.id)
+                    .bind(&tag.name)
+                    .bind(&tag.color)
+                    .bind(tag.created_at)
+                    .bind(&tag.parent_id)
+                    .execute(&mut *conn)
+                    .await?;
+                tag
+            }
+        };
+        parent = Some(tag);
+    }
+
+    parent.ok_or(sqlx::Error::RowNotFound)
+}
+
+async fn load_custom_metadata_schemas(settings_pool: &SqlitePool) -> Result<Vec<CustomMetadataSchema>, sqlx::Error> {
+    let mut key_ids: HashMap<String, Vec<String>> = HashMap::new();
+    for (schema_id, key_id) in sqlx::query_as::<_, (String, String)>(
+        "SELECT schema_id, key_id FROM custom_metadata_schema_keys ORDER BY schema_id, position",
+    )
+    .fetch_all(settings_pool)
+    .await?
+    {
+        key_ids.entry(schema_id).or_default().push(key_id);
+    }
+
+    let rows = sqlx::query("SELECT * FROM custom_metadata_schemas ORDER BY name")
+        .fetch_all(settings_pool)
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let id: String = row.get("id");
+            CustomMetadataSchema {
+                key_ids: key_ids.remove(&id).unwrap_or_default(),
+                id,
+                name: row.get("name"),
+                description: row.get("description"),
+                conditions: serde_json::from_str(row.get("conditions")).unwrap_or_default(),
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+            }
+        })
+        .collect())
+}
+
+async fn replace_schema_keys(conn: &mut SqliteConnection, schema_id: &str, key_ids: &[String]) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM custom_metadata_schema_keys WHERE schema_id = ?")
+        .bind(schema_id)
+        .execute(&mut *conn)
+        .await?;
+    for (position, key_id) in key_ids.iter().enumerate() {
+        sqlx::query("INSERT OR IGNORE INTO custom_metadata_schema_keys (schema_id, key_id, position) VALUES (?, ?, ?)")
+            .bind(schema_id)
+            .bind(key_id)
+            .bind(position as i64)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+fn annotation_history_from_row(row: &sqlx::sqlite::SqliteRow) -> AnnotationHistoryEntry {
+    AnnotationHistoryEntry {
+        id: row.get("id"),
+        file_id: row.get("file_id"),
+        action: row.get("action"),
+        key_id: row.get("key_id"),
+        tag_id: row.get("tag_id"),
+        old_value: row.get("old_value"),
+        new_value: row.get("new_value"),
+        changed_by: row.get("changed_by"),
+        changed_at: row.get("changed_at"),
+    }
+}
+
+/// コピー元の値を1ファイルに書き込む（overwrite でなければ値のあるキーは飛ばす）。書き込んだ値があれば true を返す
+async fn copy_custom_metadata_values_to(
+    conn: &mut SqliteConnection,
+    schemas: &[CustomMetadataSchema],
+    values: &[(CustomMetadataKey, Option<String>)],
+    file_id: &str,
+    overwrite: bool,
+) -> Result<bool, MetadataError> {
+    let mut written = false;
+    for (key, value) in values {
+        if !overwrite {
+            let existing: Option<Option<String>> =
+                sqlx::query_scalar("SELECT value FROM custom_metadata_values WHERE file_id = ? AND key_id = ?")
+                    .bind(file_id)
+                    .bind(&key.id)
+                    .fetch_optional(&mut *conn)
+                    .await?;
+            if existing.flatten().is_some_and(|v| !v.trim().is_empty()) {
+                continue;
+            }
+        }
+        write_custom_metadata_value(conn, schemas, key, file_id, value.clone()).await?;
+        written = true;
+    }
+    Ok(written)
+}
+
+/// 取り込む1行分のタグと値を書き込む。戻り値は追加したタグ数と設定した値の数
+async fn import_metadata_row(
+    conn: &mut SqliteConnection,
+    keys: &[CustomMetadataKey],
+    schemas: &[CustomMetadataSchema],
+    row: &MetadataImportRow,
+    tag_color: &str,
+) -> Result<(u64, u64), MetadataError> {
+    if !file_exists(conn, &row.file_id).await? {
+        return Err(MetadataError::Invalid("ファイルが見つかりません".to_string()));
+    }
+
+    let mut values_set = 0;
+    for (key_id, value) in &row.values {
+        let key = keys.iter().find(|k| &k.id == key_id).ok_or_else(|| {
+            MetadataError::Invalid(format!("カスタムメタデータキーが見つかりません: {key_id}"))
+        })?;
+        write_custom_metadata_value(conn, schemas, key, &row.file_id, Some(value.clone())).await?;
+        values_set += 1;
+    }
+
+    let mut tags_added = 0;
+    for tag_path in &row.tags {
+        let tag = ensure_tag_path_in(conn, tag_path, tag_color).await?;
+        tags_added += sqlx::query("INSERT OR IGNORE INTO file_tags (file_id, tag_id) VALUES (?, ?)")
+            .bind(&row.file_id)
+            .bind(&tag.id)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+    }
+    Ok((tags_added, values_set))
+}
+
+async fn file_exists(conn: &mut SqliteConnection, file_id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM files WHERE id = ?)")
+        .bind(file_id)
+        .fetch_one(&mut *conn)
+        .await
+}
+
+/// multi_select の値を項目ごとの行に展開する（検索で項目単位に絞り込むため）
+async fn replace_value_items(
+    conn: &mut SqliteConnection,
+    value_id: &str,
+    key_id: &str,
+    items: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM custom_metadata_value_items WHERE value_id = ?")
+        .bind(value_id)
+        .execute(&mut *conn)
+        .await?;
+    for (position, item) in items.iter().enumerate() {
+        sqlx::query(
+            "INSERT OR IGNORE INTO custom_metadata_value_items (value_id, key_id, item, position) VALUES (?, ?, ?, ?)",
+        )
+        .bind(value_id)
+        .bind(key_id)
+        .bind(item)
+        .bind(position as i64)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+fn custom_metadata_key_from_row(row: &sqlx::sqlite::SqliteRow) -> CustomMetadataKey {
+    CustomMetadataKey {
+        id: row.get("id"),
+        name: row.get("name"),
+        display_name: row.get("display_name"),
+        data_type: row.get("data_type"),
+        description: row.get("description"),
+        is_required: row.get("is_required"),
+        default_value: row.get("default_value"),
+        validation_pattern: row.get("validation_pattern"),
+        options: row
+            .get::<Option<String>, _>("options")
+            .and_then(|options| serde_json::from_str(&options).ok()),
+        expression: row.get("expression"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+fn options_to_json(options: &Option<Vec<String>>) -> Option<String> {
+    options.as_ref().and_then(|options| serde_json::to_string(options).ok())
+}
+
+/// root_path 配下のファイルと墓標を別の登録ディレクトリに付け替える
+async fn assign_files_in(
+    conn: &mut SqliteConnection,
+    root_path: &str,
+    from_directory_id: &str,
+    to_directory_id: &str,
+) -> Result<u64, sqlx::Error> {
+    let root_path = root_path.trim_end_matches('/');
+    let prefix = format!("{root_path}/");
+    let prefix_len = prefix.chars().count() as i64;
+
+    let result = sqlx::query(
+        "UPDATE files SET directory_id = ?
+         WHERE directory_id = ? AND (path = ? OR substr(path, 1, ?) = ?)",
+    )
+    .bind(to_directory_id)
+    .bind(from_directory_id)
+    .bind(root_path)
+    .bind(prefix_len)
+    .bind(&prefix)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "UPDATE file_tombstones SET directory_id = ?
+         WHERE directory_id = ? AND (path = ? OR substr(path, 1, ?) = ?)",
+    )
+    .bind(to_directory_id)
+    .bind(from_directory_id)
+    .bind(root_path)
+    .bind(prefix_len)
+    .bind(&prefix)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// 参照されていないタグを削除し、削除したタグのIDを返す
+async fn delete_orphaned_tags_in(conn: &mut SqliteConnection) -> Result<Vec<String>, sqlx::Error> {
+    let mut deleted_tag_ids = Vec::new();
+
+    // 子タグを削除すると親タグが未参照になることがあるため、なくなるまで繰り返す
+    loop {
+        // 参照されていないタグのIDを取得（墓標が保持しているタグは残す）
+        let orphaned_tag_ids: Vec<String> = sqlx::query_scalar(ORPHANED_TAGS_QUERY)
+            .fetch_all(&mut *conn)
+            .await?;
+        if orphaned_tag_ids.is_empty() {
+            break;
+        }
+
+        // 参照されていないタグを削除
+        for tag_id in &orphaned_tag_ids {
+            sqlx::query("DELETE FROM tags WHERE id = ?")
+                .bind(tag_id)
+                .execute(&mut *conn)
+                .await?;
+        }
+        deleted_tag_ids.extend(orphaned_tag_ids);
+    }
+
+    Ok(deleted_tag_ids)
+}
+
+/// 注釈（タグ・カスタムメタデータ）が付いたファイルを墓標として保存する
+/// 注釈がなければ何もせず false を返す
+async fn create_tombstone(conn: &mut SqliteConnection, file_id: &str) -> Result<bool, sqlx::Error> {
+    let tag_ids: Vec<String> = sqlx::query_scalar("SELECT tag_id FROM file_tags WHERE file_id = ?")
+        .bind(file_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let values = sqlx::query("SELECT id, key_id, value FROM custom_metadata_values WHERE file_id = ?")
+        .bind(file_id)
+        .fetch_all(&mut *conn)
+        .await?;
+
+    if tag_ids.is_empty() && values.is_empty() {
+        return Ok(false);
+    }
+
+    let Some(file) = sqlx::query("SELECT * FROM files WHERE id = ?")
+        .bind(file_id)
+        .fetch_optional(&mut *conn)
+        .await?
+    else {
+        return Ok(false);
+    };
+
+    // 複数選択の値は項目の配列として保存する（復元時に項目の行も作り直す）
+    let mut custom_metadata = serde_json::Map::new();
+    for row in &values {
+        let value: Option<String> = row.get("value");
+        let items: Vec<String> = sqlx::query_scalar(
+            "SELECT item FROM custom_metadata_value_items WHERE value_id = ? ORDER BY position",
+        )
+        .bind(row.get::<String, _>("id"))
+        .fetch_all(&mut *conn)
+        .await?;
+        let value = if items.is_empty() { serde_json::json!(value) } else { serde_json::json!(items) };
+        custom_metadata.insert(row.get("key_id"), value);
+    }
 
-        let query = format!("SELECT COUNT(*) FROM files WHERE directory_id = ?{}{}", category_where_clause, additional_conditions.join(""));
-        
-        let count: i64 = sqlx::query_scalar(&query)
-            .bind(directory_id)
-            .fetch_one(pool)
+    let tombstone_id = Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO file_tombstones (id, original_file_id, path, name, directory_id, size, content_hash, inode, device_id, birth_time, is_directory, custom_metadata, deleted_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(&tombstone_id)
+    .bind(file_id)
+    .bind(file.get::<String, _>("path"))
+    .bind(file.get::<String, _>("name"))
+    .bind(file.get::<String, _>("directory_id"))
+    .bind(file.get::<i64, _>("size"))
+    .bind(file.get::<Option<String>, _>("content_hash"))
+    .bind(file.get::<Option<i64>, _>("inode"))
+    .bind(file.get::<Option<i64>, _>("device_id"))
+    .bind(file.get::<Option<DateTime<Utc>>, _>("birth_time"))
+    .bind(file.get::<bool, _>("is_directory"))
+    .bind(serde_json::Value::Object(custom_metadata).to_string())
+    .bind(Utc::now())
+    .execute(&mut *conn)
+    .await?;
+
+    for tag_id in &tag_ids {
+        sqlx::query("INSERT OR IGNORE INTO file_tombstone_tags (tombstone_id, tag_id) VALUES (?, ?)")
+            .bind(&tombstone_id)
+            .bind(tag_id)
+            .execute(&mut *conn)
             .await?;
-        Ok(count as u32)
     }
 
+    Ok(true)
 }
 
-fn build_category_where_clause(category: &str) -> String {
-    if category == "all" {
-        return String::new();
+async fn tombstone_from_row(
+    pool: &SqlitePool,
+    row: &sqlx::sqlite::SqliteRow,
+) -> Result<FileTombstone, sqlx::Error> {
+    let id: String = row.get("id");
+    let tag_rows = sqlx::query(
+        "SELECT t.* FROM tags t
+         INNER JOIN file_tombstone_tags ftt ON t.id = ftt.tag_id
+         WHERE ftt.tombstone_id = ?
+         ORDER BY t.name",
+    )
+    .bind(&id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut tags = Vec::new();
+    for tag_row in tag_rows {
+        tags.push(Tag {
+            id: tag_row.get("id"),
+            name: tag_row.get("name"),
+            color: tag_row.get("color"),
+            created_at: tag_row.get("created_at"),
+            parent_id: tag_row.get("parent_id"),
+            pinned: tag_row.get("pinned"),
+        });
     }
-    
-    let extensions = match category {
-        "image" => vec!["jpg", "jpeg", "png", "gif", "bmp", "webp", "svg", "ico", "tiff", "raw"],
-        "audio" => vec!["mp3", "wav", "ogg", "flac", "aac", "m4a", "wma", "opus"],
-        "video" => vec!["mp4", "avi", "mov", "wmv", "flv", "webm", "mkv", "m4v", "3gp"],
-        "document" => vec!["pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "txt", "md", "html", "htm", "css", "js", "json", "xml", "csv", "rtf"],
-        "archive" => vec!["zip", "rar", "7z", "tar", "gz", "bz2", "xz", "lzma"],
-        "other" => return " AND (path NOT LIKE '%.jpg' AND path NOT LIKE '%.jpeg' AND path NOT LIKE '%.png' AND path NOT LIKE '%.gif' AND path NOT LIKE '%.bmp' AND path NOT LIKE '%.webp' AND path NOT LIKE '%.svg' AND path NOT LIKE '%.ico' AND path NOT LIKE '%.tiff' AND path NOT LIKE '%.raw' AND path NOT LIKE '%.mp3' AND path NOT LIKE '%.wav' AND path NOT LIKE '%.ogg' AND path NOT LIKE '%.flac' AND path NOT LIKE '%.aac' AND path NOT LIKE '%.m4a' AND path NOT LIKE '%.wma' AND path NOT LIKE '%.opus' AND path NOT LIKE '%.mp4' AND path NOT LIKE '%.avi' AND path NOT LIKE '%.mov' AND path NOT LIKE '%.wmv' AND path NOT LIKE '%.flv' AND path NOT LIKE '%.webm' AND path NOT LIKE '%.mkv' AND path NOT LIKE '%.m4v' AND path NOT LIKE '%.3gp' AND path NOT LIKE '%.pdf' AND path NOT LIKE '%.doc' AND path NOT LIKE '%.docx' AND path NOT LIKE '%.xls' AND path NOT LIKE '%.xlsx' AND path NOT LIKE '%.ppt' AND path NOT LIKE '%.pptx' AND path NOT LIKE '%.txt' AND path NOT LIKE '%.md' AND path NOT LIKE '%.html' AND path NOT LIKE '%.htm' AND path NOT LIKE '%.css' AND path NOT LIKE '%.js' AND path NOT LIKE '%.json' AND path NOT LIKE '%.xml' AND path NOT LIKE '%.csv' AND path NOT LIKE '%.rtf' AND path NOT LIKE '%.zip' AND path NOT LIKE '%.rar' AND path NOT LIKE '%.7z' AND path NOT LIKE '%.tar' AND path NOT LIKE '%.gz' AND path NOT LIKE '%.bz2' AND path NOT LIKE '%.xz' AND path NOT LIKE '%.lzma')".to_string(),
-        _ => return String::new(),
-    };
-    
-    let conditions: Vec<String> = extensions.iter()
-        .map(|ext| format!("path LIKE '%.{}' OR path LIKE '%.{}'", ext, ext.to_uppercase()))
-        .collect();
-    
-    format!(" AND ({})", conditions.join(" OR "))
+
+    let custom_metadata: String = row.get("custom_metadata");
+
+    Ok(FileTombstone {
+        id,
+        original_file_id: row.get("original_file_id"),
+        path: row.get("path"),
+        name: row.get("name"),
+        directory_id: row.get("directory_id"),
+        size: row.get("size"),
+        content_hash: row.get("content_hash"),
+        inode: row.get("inode"),
+        device_id: row.get("device_id"),
+        birth_time: row.get("birth_time"),
+        is_directory: row.get("is_directory"),
+        tags,
+        custom_metadata: serde_json::from_str(&custom_metadata).unwrap_or_else(|_| serde_json::json!({})),
+        deleted_at: row.get("deleted_at"),
+    })
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
     use chrono::Utc;
+    use crate::test_support::{metadata_key, test_file};
     use sqlx::SqlitePool;
     use std::path::PathBuf;
     use std::mem;
@@ -1652,94 +5211,343 @@ pub mod tests {
         }
     }
 
-    impl Drop for TestDatabase {
-        fn drop(&mut self) {
-            if let Some(file_path) = &self.file_path {
-                if file_path.exists() {
-                    let _ = std::fs::remove_file(file_path);
-                }
-            }
-        }
-    }
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            if let Some(file_path) = &self.file_path {
+                if file_path.exists() {
+                    let _ = std::fs::remove_file(file_path);
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn setup_test_db() -> SqlitePool {
+        let test_db = TestDatabase::new_in_memory().await;
+        test_db.into_pool()
+    }
+
+    /// インメモリの設定用・データ用データベースを1つずつ持つシェルフ
+    pub(crate) async fn test_shelf_manager() -> crate::ShelfManager {
+        let settings_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./settings_migrations").run(&settings_pool).await.unwrap();
+        let data_pools = HashMap::from([("test".to_string(), setup_test_db().await)]);
+        crate::ShelfManager {
+            settings_pool,
+            data_pools: std::sync::Arc::new(Mutex::new(data_pools)),
+            active_shelf_id: std::sync::Arc::new(Mutex::new("test".to_string())),
+        }
+    }
+
+
+    #[tokio::test]
+    async fn test_add_directory() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let result = db.add_directory(&pool, "/test/path", "test_dir").await;
+        assert!(result.is_ok());
+
+        let directory = result.unwrap();
+        assert_eq!(directory.path, "/test/path");
+        assert_eq!(directory.name, "test_dir");
+    }
+
+    #[tokio::test]
+    async fn test_add_directory_with_temp_file() {
+        // 一時ファイルDBを使用したテスト（自動削除確認）
+        let test_db = TestDatabase::new_temp_file().await;
+        let db = Database;
+
+        let result = db.add_directory(&test_db.pool, "/test/path", "test_dir").await;
+        assert!(result.is_ok());
+
+        let directory = result.unwrap();
+        assert_eq!(directory.path, "/test/path");
+        assert_eq!(directory.name, "test_dir");
+
+        // test_db がDropされる際に一時ファイルが自動削除される
+    }
+
+    #[tokio::test]
+    async fn test_get_directories() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        db.add_directory(&pool, "/test/path1", "dir1")
+            .await
+            .unwrap();
+        db.add_directory(&pool, "/test/path2", "dir2")
+            .await
+            .unwrap();
+
+        let directories = db.get_directories(&pool).await.unwrap();
+        assert_eq!(directories.len(), 2);
+        assert_eq!(directories[0].name, "dir1"); // ORDER BY name
+        assert_eq!(directories[1].name, "dir2");
+    }
+
+    #[tokio::test]
+    async fn test_create_tag() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let result = db.create_tag(&pool, "important", "#ff0000").await;
+        assert!(result.is_ok());
+
+        let tag = result.unwrap();
+        assert_eq!(tag.name, "important");
+        assert_eq!(tag.color, "#ff0000");
+    }
+
+    #[tokio::test]
+    async fn test_file_tag_operations() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        // ディレクトリとタグを作成
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let tag = db.create_tag(&pool, "test_tag", "#ff0000").await.unwrap();
+
+        // ファイルを作成
+        let file = File {
+            id: "test_file_id".to_string(),
+            path: "/test/file.txt".to_string(),
+            name: "file.txt".to_string(),
+            directory_id: dir.id.clone(),
+            size: 1024,
+            file_type: Some("txt".to_string()),
+            created_at: Some(Utc::now()),
+            modified_at: Some(Utc::now()),
+            birth_time: Some(Utc::now()),
+            inode: Some(12345),
+            is_directory: false,
+            created_at_db: Utc::now(),
+            updated_at_db: Utc::now(),
+            file_size: Some(1024),
+            mime_type: Some("text/plain".to_string()),
+            permissions: Some("644".to_string()),
+            owner_uid: Some(1000),
+            group_gid: Some(1000),
+            hard_links: Some(1),
+            device_id: Some(12345),
+            last_accessed: None,
+            metadata: None,
+            content_hash: None,
+            is_symlink: false,
+            symlink_target: None,
+            is_broken_link: false,
+        };
+
+        db.add_file(&pool, &file).await.unwrap();
+
+        // ファイルにタグを追加
+        db.add_file_tag(&pool, &file.id, &tag.id).await.unwrap();
+
+        // ファイルのタグを取得
+        let file_tags = db.get_file_tags(&pool, &file.id).await.unwrap();
+        assert_eq!(file_tags.len(), 1);
+        assert_eq!(file_tags[0].name, "test_tag");
+
+        // ファイルからタグを削除
+        db.remove_file_tag(&pool, &file.id, &tag.id).await.unwrap();
+
+        let file_tags = db.get_file_tags(&pool, &file.id).await.unwrap();
+        assert_eq!(file_tags.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_all_tags() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        db.create_tag(&pool, "tag1", "#ff0000").await.unwrap();
+        db.create_tag(&pool, "tag2", "#00ff00").await.unwrap();
+
+        let tags = db.get_all_tags(&pool).await.unwrap();
+        assert_eq!(tags.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_init_database() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let result = db.init_database(&pool, &pool).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_add_file_with_various_types() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+
+        // ディレクトリファイル
+        let dir_file = File {
+            id: "dir_file".to_string(),
+            path: "/test/subdir".to_string(),
+            name: "subdir".to_string(),
+            directory_id: dir.id.clone(),
+            size: 0,
+            file_type: None,
+            created_at: None,
+            modified_at: None,
+            birth_time: None,
+            inode: None,
+            is_directory: true,
+            created_at_db: Utc::now(),
+            updated_at_db: Utc::now(),
+            file_size: Some(1024),
+            mime_type: Some("text/plain".to_string()),
+            permissions: Some("644".to_string()),
+            owner_uid: Some(1000),
+            group_gid: Some(1000),
+            hard_links: Some(1),
+            device_id: Some(12345),
+            last_accessed: None,
+            metadata: None,
+            content_hash: None,
+            is_symlink: false,
+            symlink_target: None,
+            is_broken_link: false,
+        };
+
+        let result = db.add_file(&pool, &dir_file).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_add_file_tag_duplicate() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let tag = db.create_tag(&pool, "test_tag", "#ff0000").await.unwrap();
+
+        let file = File {
+            id: "test_file".to_string(),
+            path: "/test/file.txt".to_string(),
+            name: "file.txt".to_string(),
+            directory_id: dir.id.clone(),
+            size: 1024,
+            file_type: Some("txt".to_string()),
+            created_at: Some(Utc::now()),
+            modified_at: Some(Utc::now()),
+            birth_time: Some(Utc::now()),
+            inode: Some(12345),
+            is_directory: false,
+            created_at_db: Utc::now(),
+            updated_at_db: Utc::now(),
+            file_size: Some(1024),
+            mime_type: Some("text/plain".to_string()),
+            permissions: Some("644".to_string()),
+            owner_uid: Some(1000),
+            group_gid: Some(1000),
+            hard_links: Some(1),
+            device_id: Some(12345),
+            last_accessed: None,
+            metadata: None,
+            content_hash: None,
+            is_symlink: false,
+            symlink_target: None,
+            is_broken_link: false,
+        };
+
+        db.add_file(&pool, &file).await.unwrap();
 
-    async fn setup_test_db() -> SqlitePool {
-        let test_db = TestDatabase::new_in_memory().await;
-        test_db.into_pool()
-    }
+        // 同じファイル・タグの組み合わせを複数回追加（IGNORE句でエラーにならない）
+        db.add_file_tag(&pool, &file.id, &tag.id).await.unwrap();
+        db.add_file_tag(&pool, &file.id, &tag.id).await.unwrap();
 
+        let file_tags = db.get_file_tags(&pool, &file.id).await.unwrap();
+        assert_eq!(file_tags.len(), 1);
+    }
 
     #[tokio::test]
-    async fn test_add_directory() {
+    async fn test_remove_nonexistent_file_tag() {
         let pool = setup_test_db().await;
         let db = Database;
 
-        let result = db.add_directory(&pool, "/test/path", "test_dir").await;
+        // 存在しないファイル・タグの組み合わせを削除（エラーにならない）
+        let result = db
+            .remove_file_tag(&pool, "nonexistent_file", "nonexistent_tag")
+            .await;
         assert!(result.is_ok());
-
-        let directory = result.unwrap();
-        assert_eq!(directory.path, "/test/path");
-        assert_eq!(directory.name, "test_dir");
     }
 
     #[tokio::test]
-    async fn test_add_directory_with_temp_file() {
-        // 一時ファイルDBを使用したテスト（自動削除確認）
-        let test_db = TestDatabase::new_temp_file().await;
+    async fn test_get_file_tags_nonexistent_file() {
+        let pool = setup_test_db().await;
         let db = Database;
 
-        let result = db.add_directory(&test_db.pool, "/test/path", "test_dir").await;
-        assert!(result.is_ok());
-
-        let directory = result.unwrap();
-        assert_eq!(directory.path, "/test/path");
-        assert_eq!(directory.name, "test_dir");
-
-        // test_db がDropされる際に一時ファイルが自動削除される
+        let file_tags = db.get_file_tags(&pool, "nonexistent_file").await.unwrap();
+        assert_eq!(file_tags.len(), 0);
     }
 
     #[tokio::test]
-    async fn test_get_directories() {
+    async fn test_file_exists_by_path() {
         let pool = setup_test_db().await;
         let db = Database;
 
-        db.add_directory(&pool, "/test/path1", "dir1")
-            .await
-            .unwrap();
-        db.add_directory(&pool, "/test/path2", "dir2")
-            .await
-            .unwrap();
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
 
-        let directories = db.get_directories(&pool).await.unwrap();
-        assert_eq!(directories.len(), 2);
-        assert_eq!(directories[0].name, "dir1"); // ORDER BY name
-        assert_eq!(directories[1].name, "dir2");
-    }
+        let file = File {
+            id: "test_file".to_string(),
+            path: "/test/file.txt".to_string(),
+            name: "file.txt".to_string(),
+            directory_id: dir.id.clone(),
+            size: 1024,
+            file_type: Some("txt".to_string()),
+            created_at: Some(Utc::now()),
+            modified_at: Some(Utc::now()),
+            birth_time: None,
+            inode: Some(12345),
+            is_directory: false,
+            created_at_db: Utc::now(),
+            updated_at_db: Utc::now(),
+            file_size: Some(1024),
+            mime_type: Some("text/plain".to_string()),
+            permissions: Some("644".to_string()),
+            owner_uid: Some(1000),
+            group_gid: Some(1000),
+            hard_links: Some(1),
+            device_id: Some(12345),
+            last_accessed: None,
+            metadata: None,
+            content_hash: None,
+            is_symlink: false,
+            symlink_target: None,
+            is_broken_link: false,
+        };
 
-    #[tokio::test]
-    async fn test_create_tag() {
-        let pool = setup_test_db().await;
-        let db = Database;
+        // ファイルを追加
+        db.add_file(&pool, &file).await.unwrap();
 
-        let result = db.create_tag(&pool, "important", "#ff0000").await;
-        assert!(result.is_ok());
+        // ファイルが存在することを確認
+        let exists = db
+            .file_exists_by_path(&pool, "/test/file.txt")
+            .await
+            .unwrap();
+        assert!(exists);
 
-        let tag = result.unwrap();
-        assert_eq!(tag.name, "important");
-        assert_eq!(tag.color, "#ff0000");
+        // 存在しないファイルを確認
+        let not_exists = db
+            .file_exists_by_path(&pool, "/test/nonexistent.txt")
+            .await
+            .unwrap();
+        assert!(!not_exists);
     }
 
     #[tokio::test]
-    async fn test_file_tag_operations() {
+    async fn test_find_file_by_inode() {
         let pool = setup_test_db().await;
         let db = Database;
 
-        // ディレクトリとタグを作成
         let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
-        let tag = db.create_tag(&pool, "test_tag", "#ff0000").await.unwrap();
 
-        // ファイルを作成
         let file = File {
-            id: "test_file_id".to_string(),
+            id: "test_file".to_string(),
             path: "/test/file.txt".to_string(),
             name: "file.txt".to_string(),
             directory_id: dir.id.clone(),
@@ -1747,7 +5555,7 @@ pub mod tests {
             file_type: Some("txt".to_string()),
             created_at: Some(Utc::now()),
             modified_at: Some(Utc::now()),
-            birth_time: Some(Utc::now()),
+            birth_time: None,
             inode: Some(12345),
             is_directory: false,
             created_at_db: Utc::now(),
@@ -1758,255 +5566,1276 @@ pub mod tests {
             owner_uid: Some(1000),
             group_gid: Some(1000),
             hard_links: Some(1),
-            device_id: Some(12345),
+            device_id: Some(67890),
             last_accessed: None,
             metadata: None,
+            content_hash: None,
+            is_symlink: false,
+            symlink_target: None,
+            is_broken_link: false,
         };
 
+        // ファイルを追加
         db.add_file(&pool, &file).await.unwrap();
 
-        // ファイルにタグを追加
+        // inode番号とdevice_idでファイルを検索
+        let found_file = db
+            .find_file_by_inode(&pool, 12345, Some(67890))
+            .await
+            .unwrap();
+        assert!(found_file.is_some());
+        assert_eq!(found_file.unwrap().id, "test_file");
+
+        // 存在しないinode番号で検索
+        let not_found = db
+            .find_file_by_inode(&pool, 99999, Some(67890))
+            .await
+            .unwrap();
+        assert!(not_found.is_none());
+
+        // 存在しないdevice_idで検索
+        let not_found = db
+            .find_file_by_inode(&pool, 12345, Some(99999))
+            .await
+            .unwrap();
+        assert!(not_found.is_none());
+    }
+
+    /// 墓標の照合に使う内容ハッシュを持つファイル
+    fn file_with_hash(id: &str, path: &str, directory_id: &str, content_hash: &str) -> File {
+        File { content_hash: Some(content_hash.to_string()), ..test_file(id, path, directory_id) }
+    }
+
+    #[tokio::test]
+    async fn test_remove_file_creates_tombstone_and_restores() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let tag = db.create_tag(&pool, "keep", "#ff0000").await.unwrap();
+
+        let file = file_with_hash("original", "/test/report.txt", &dir.id, "hash-1");
+        db.add_file(&pool, &file).await.unwrap();
         db.add_file_tag(&pool, &file.id, &tag.id).await.unwrap();
+        sqlx::query(
+            "INSERT INTO custom_metadata_values (id, file_id, key_id, value, created_at, updated_at) VALUES ('v1', ?, 'key-1', 'value-1', ?, ?)"
+        )
+        .bind(&file.id)
+        .bind(Utc::now())
+        .bind(Utc::now())
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // 削除すると墓標が残り、タグは孤児扱いされない
+        db.remove_file_by_path(&pool, "/test/report.txt").await.unwrap();
+        assert!(!db.file_exists_by_path(&pool, "/test/report.txt").await.unwrap());
+
+        let tombstones = db.get_file_tombstones(&pool).await.unwrap();
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(tombstones[0].path, "/test/report.txt");
+        assert_eq!(tombstones[0].tags.len(), 1);
+        assert_eq!(tombstones[0].custom_metadata["key-1"], "value-1");
+
+        let orphaned = db.delete_orphaned_tags(&pool).await.unwrap();
+        assert!(orphaned.is_empty());
+
+        // 内容ハッシュとサイズが一致するファイルは同じファイルとして戻す
+        let moved = file_with_hash("moved", "/test/archive/report.txt", &dir.id, "hash-1");
+        db.add_file(&pool, &moved).await.unwrap();
+        let resized = File { size: moved.size + 1, ..moved.clone() };
+        assert!(db.find_tombstone_by_content(&pool, &resized).await.unwrap().is_none());
+        let tombstone = db.find_tombstone_by_content(&pool, &moved).await.unwrap().unwrap();
+        assert_eq!(tombstone.id, tombstones[0].id);
+        let candidates = db.find_tombstone_candidates(&pool, &tombstones[0].id).await.unwrap();
+        assert_eq!(candidates.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["moved"]);
+
+        db.restore_file_tombstone(&pool, &tombstone.id, &moved.id).await.unwrap();
+
+        let tags = db.get_file_tags(&pool, &moved.id).await.unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].name, "keep");
+        let value = db.get_custom_metadata_value(&pool, &moved.id, "key-1").await.unwrap().unwrap();
+        assert_eq!(value.value, Some("value-1".to_string()));
+        assert!(db.get_file_tombstones(&pool).await.unwrap().is_empty());
+    }
 
-        // ファイルのタグを取得
-        let file_tags = db.get_file_tags(&pool, &file.id).await.unwrap();
-        assert_eq!(file_tags.len(), 1);
-        assert_eq!(file_tags[0].name, "test_tag");
+    #[tokio::test]
+    async fn test_find_tombstone_by_identity_requires_birth_time() {
+        let pool = setup_test_db().await;
+        let db = Database;
 
-        // ファイルからタグを削除
-        db.remove_file_tag(&pool, &file.id, &tag.id).await.unwrap();
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let tag = db.create_tag(&pool, "keep", "#ff0000").await.unwrap();
+        let birth_time = Utc::now() - chrono::Duration::days(3);
+
+        let mut file = file_with_hash("original", "/test/photo.jpg", &dir.id, "hash-1");
+        file.inode = Some(4242);
+        file.device_id = Some(7);
+        file.birth_time = Some(birth_time);
+        db.add_file(&pool, &file).await.unwrap();
+        db.add_file_tag(&pool, &file.id, &tag.id).await.unwrap();
+        db.remove_file_by_path(&pool, "/test/photo.jpg").await.unwrap();
+
+        // inodeが同じでも作成日時が違えば再利用された別ファイル
+        let reused = File { id: "reused".to_string(), birth_time: Some(Utc::now()), ..file.clone() };
+        assert!(db.find_tombstone_by_identity(&pool, &reused).await.unwrap().is_none());
+
+        // inode・device_id・作成日時が一致すれば、内容が変わっていても同じファイル
+        let moved = File {
+            id: "moved".to_string(),
+            path: "/test/archive/photo.jpg".to_string(),
+            content_hash: Some("hash-2".to_string()),
+            ..file.clone()
+        };
+        let tombstone = db.find_tombstone_by_identity(&pool, &moved).await.unwrap().unwrap();
+        assert_eq!(tombstone.original_file_id, "original");
+    }
+
+    #[tokio::test]
+    async fn test_remove_file_without_annotations_leaves_no_tombstone() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let file = file_with_hash("plain", "/test/plain.txt", &dir.id, "hash-2");
+        db.add_file(&pool, &file).await.unwrap();
+
+        db.remove_file_by_path(&pool, "/test/plain.txt").await.unwrap();
+        assert!(db.get_file_tombstones(&pool).await.unwrap().is_empty());
+
+        // 内容が異なるファイルは一致しない
+        let other = file_with_hash("other", "/test/other.txt", &dir.id, "hash-3");
+        assert!(db.find_tombstone_by_content(&pool, &other).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired_tombstones() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let tag = db.create_tag(&pool, "old", "#00ff00").await.unwrap();
+        let file = file_with_hash("gone", "/test/gone.txt", &dir.id, "hash-4");
+        db.add_file(&pool, &file).await.unwrap();
+        db.add_file_tag(&pool, &file.id, &tag.id).await.unwrap();
+        db.remove_file_by_path(&pool, "/test/gone.txt").await.unwrap();
+
+        // 保持期限前のものは残る
+        let purged = db
+            .purge_expired_tombstones(&pool, Utc::now() - chrono::Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(purged, 0);
+
+        let purged = db
+            .purge_expired_tombstones(&pool, Utc::now() + chrono::Duration::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(purged, 1);
+
+        // 墓標が消えるとタグは孤児になる
+        let orphaned = db.delete_orphaned_tags(&pool).await.unwrap();
+        assert_eq!(orphaned, vec![tag.id]);
+    }
+
+    #[tokio::test]
+    async fn test_find_file_by_identity_ignores_reused_inode() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let birth_time = Utc::now() - chrono::Duration::days(3);
+
+        let mut file = test_file("identity", "/test/photo.jpg", &dir.id);
+        file.inode = Some(4242);
+        file.device_id = Some(7);
+        file.birth_time = Some(birth_time);
+        db.add_file(&pool, &file).await.unwrap();
+
+        // inode・device_id・作成日時がすべて一致すれば同一ファイル
+        let found = db
+            .find_file_by_identity(&pool, Some(7), 4242, Some(birth_time))
+            .await
+            .unwrap();
+        assert_eq!(found.unwrap().id, "identity");
+
+        // 同じinodeでも作成日時が違えば再利用された別ファイル
+        let reused = db
+            .find_file_by_identity(&pool, Some(7), 4242, Some(Utc::now()))
+            .await
+            .unwrap();
+        assert!(reused.is_none());
+
+        // 作成日時が取得できない場合は作成日時のない行とだけ照合する
+        let unknown = db.find_file_by_identity(&pool, Some(7), 4242, None).await.unwrap();
+        assert!(unknown.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_find_file_by_identity_matches_legacy_rows() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let birth_time = Utc::now() - chrono::Duration::days(3);
+
+        // 作成日時を記録する前にインデックスした行は inode と device_id で照合する
+        let mut legacy = test_file("legacy", "/test/old.txt", &dir.id);
+        legacy.inode = Some(5151);
+        legacy.device_id = Some(7);
+        db.add_file(&pool, &legacy).await.unwrap();
+        let found = db.find_file_by_identity(&pool, Some(7), 5151, Some(birth_time)).await.unwrap();
+        assert_eq!(found.unwrap().id, "legacy");
+        assert!(db.find_file_by_identity(&pool, Some(8), 5151, Some(birth_time)).await.unwrap().is_none());
+
+        // inodeが再利用されて作成日時のある行もある場合は、作成日時が一致する行を優先する
+        let mut current = test_file("current", "/test/new.txt", &dir.id);
+        current.inode = Some(5151);
+        current.device_id = Some(7);
+        current.birth_time = Some(birth_time);
+        db.add_file(&pool, &current).await.unwrap();
+        let found = db.find_file_by_identity(&pool, Some(7), 5151, Some(birth_time)).await.unwrap();
+        assert_eq!(found.unwrap().id, "current");
+    }
+
+    #[tokio::test]
+    async fn test_hard_link_siblings_and_groups() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        for (id, path, inode) in [
+            ("a", "/test/a.txt", 100),
+            ("b", "/test/links/b.txt", 100),
+            ("c", "/test/c.txt", 200),
+        ] {
+            let mut file = test_file(id, path, &dir.id);
+            file.inode = Some(inode);
+            file.device_id = Some(1);
+            db.add_file(&pool, &file).await.unwrap();
+        }
+
+        let siblings = db.get_hard_link_siblings(&pool, "a").await.unwrap();
+        assert_eq!(siblings.len(), 1);
+        assert_eq!(siblings[0].id, "b");
+        assert!(db.get_hard_link_siblings(&pool, "c").await.unwrap().is_empty());
+
+        let groups = db.get_hard_link_groups(&pool).await.unwrap();
+        assert_eq!(groups.len(), 1);
+        let ids: Vec<_> = groups[0].iter().map(|f| f.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+
+        // 設定が無効な間は共有しない
+        let names = |file_id: &'static str| {
+            let pool = pool.clone();
+            async move { Database.get_file_tags(&pool, file_id).await.unwrap().into_iter().map(|t| t.name).collect::<Vec<_>>() }
+        };
+        let draft = db.create_tag(&pool, "draft", "#000000").await.unwrap();
+        db.add_file_tag(&pool, "a", &draft.id).await.unwrap();
+        assert!(names("b").await.is_empty());
+        db.remove_file_tag(&pool, "a", &draft.id).await.unwrap();
+
+        // どの経路で書き込んでもハードリンク先に反映する
+        crate::settings::set_shelf_setting(&pool, "share_tags_across_hard_links", "true").await.unwrap();
+        db.bulk_add_file_tags(&pool, &["a".to_string()], std::slice::from_ref(&draft.id)).await.unwrap();
+        assert_eq!(names("b").await, vec!["draft"]);
+        let final_tag = db.create_tag(&pool, "final", "#000000").await.unwrap();
+        db.merge_tags(&pool, &draft.id, &final_tag.id).await.unwrap();
+        assert_eq!(names("b").await, vec!["final"]);
+        db.remove_file_tag(&pool, "b", &final_tag.id).await.unwrap();
+        assert!(names("a").await.is_empty());
+        let work = db.ensure_tag_path(&pool, "work", "#000000").await.unwrap();
+        db.add_file_tag(&pool, "b", &work.id).await.unwrap();
+        assert_eq!(names("a").await, vec!["work"]);
+        assert!(names("c").await.is_empty());
+
+        // ファイルの削除でハードリンク先のタグは外れない
+        db.remove_file_by_path(&pool, "/test/links/b.txt").await.unwrap();
+        assert_eq!(names("a").await, vec!["work"]);
+    }
+
+    async fn find_test_file(pool: &SqlitePool, id: &str) -> Option<File> {
+        sqlx::query("SELECT * FROM files WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+            .as_ref()
+            .map(file_from_row)
+    }
+
+    #[tokio::test]
+    async fn test_add_file_keeps_id_for_existing_path() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let tag = db.create_tag(&pool, "keep", "#ff0000").await.unwrap();
+        let file = test_file("original", "/test/a.txt", &dir.id);
+        assert_eq!(db.add_file(&pool, &file).await.unwrap(), "original");
+        db.add_file_tag(&pool, "original", &tag.id).await.unwrap();
+
+        // 再スキャンで新しいIDのエントリが来ても既存のIDとタグを保つ
+        let mut rescanned = test_file("rescanned", "/test/a.txt", &dir.id);
+        rescanned.size = 4096;
+        assert_eq!(db.add_file(&pool, &rescanned).await.unwrap(), "original");
+
+        let file = find_test_file(&pool, "original").await.unwrap();
+        assert_eq!(file.size, 4096);
+        assert_eq!(db.get_file_tags(&pool, "original").await.unwrap().len(), 1);
+        assert!(find_test_file(&pool, "rescanned").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_assign_and_relocate_directory() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let outer = db.add_directory(&pool, "/test", "test").await.unwrap();
+        db.add_file(&pool, &test_file("root", "/test", &outer.id)).await.unwrap();
+        db.add_file(&pool, &test_file("a", "/test/a.txt", &outer.id)).await.unwrap();
+        db.add_file(&pool, &test_file("b", "/test/sub/b.txt", &outer.id)).await.unwrap();
+        db.add_file(&pool, &test_file("c", "/test-other/c.txt", &outer.id)).await.unwrap();
+
+        // 入れ子の登録に配下のファイルを引き継ぐ
+        let inner = db.add_directory(&pool, "/test/sub", "sub").await.unwrap();
+        let moved = db.assign_files_to_directory(&pool, "/test/sub", &outer.id, &inner.id).await.unwrap();
+        assert_eq!(moved, 1);
+        let b = find_test_file(&pool, "b").await.unwrap();
+        assert_eq!(b.directory_id, inner.id);
+
+        // ルートの移動では配下の登録とファイルのパスもまとめて書き換える
+        db.relocate_directory(&pool, &outer.id, "/moved", "moved").await.unwrap();
+
+        let directories = db.get_directories(&pool).await.unwrap();
+        let outer = directories.iter().find(|d| d.id == outer.id).unwrap();
+        assert_eq!(outer.path, "/moved");
+        assert_eq!(outer.name, "moved");
+        let inner = directories.iter().find(|d| d.id == inner.id).unwrap();
+        assert_eq!(inner.path, "/moved/sub");
+
+        let root = find_test_file(&pool, "root").await.unwrap();
+        assert_eq!(root.path, "/moved");
+        assert_eq!(root.name, "moved");
+        assert_eq!(find_test_file(&pool, "a").await.unwrap().path, "/moved/a.txt");
+        assert_eq!(find_test_file(&pool, "b").await.unwrap().path, "/moved/sub/b.txt");
+        // 前方一致するだけの別パスは対象外
+        assert_eq!(find_test_file(&pool, "c").await.unwrap().path, "/test-other/c.txt");
+    }
+
+    #[tokio::test]
+    async fn test_merge_and_remove_directory() {
+        let pool = setup_test_db().await;
+        let db = Database;
+        let tag = db.create_tag(&pool, "keep", "#000000").await.unwrap();
 
-        let file_tags = db.get_file_tags(&pool, &file.id).await.unwrap();
-        assert_eq!(file_tags.len(), 0);
+        // 内側の登録を吸収すると、ファイルと墓標は新しい登録に移る
+        let inner = db.add_directory(&pool, "/root/sub", "sub").await.unwrap();
+        db.add_file(&pool, &test_file("a", "/root/sub/a.txt", &inner.id)).await.unwrap();
+        db.add_file(&pool, &test_file("gone", "/root/sub/gone.txt", &inner.id)).await.unwrap();
+        db.add_file_tag(&pool, "gone", &tag.id).await.unwrap();
+        db.remove_file_by_path(&pool, "/root/sub/gone.txt").await.unwrap();
+
+        let merged = db
+            .add_directory_with_overlap(&pool, "/root", "root", None, std::slice::from_ref(&inner.id))
+            .await
+            .unwrap();
+        let directories = db.get_directories(&pool).await.unwrap();
+        assert_eq!(directories.iter().map(|d| d.id.as_str()).collect::<Vec<_>>(), vec![merged.id.as_str()]);
+        assert_eq!(find_test_file(&pool, "a").await.unwrap().directory_id, merged.id);
+        let tombstones = db.get_file_tombstones(&pool).await.unwrap();
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(tombstones[0].directory_id, merged.id);
+
+        // 入れ子の登録を削除すると外側の登録に引き継ぎ、墓標が持つタグは残る
+        let nested = db
+            .add_directory_with_overlap(&pool, "/root/sub", "sub", Some(merged.id.clone()), &[])
+            .await
+            .unwrap();
+        assert_eq!(find_test_file(&pool, "a").await.unwrap().directory_id, nested.id);
+        assert_eq!(db.get_file_tombstones(&pool).await.unwrap()[0].directory_id, nested.id);
+        // 墓標の記録したパスが登録の外にあっても（移動前のパスなど）引き継ぐ
+        db.add_file(&pool, &test_file("old", "/root/sub/old.txt", &nested.id)).await.unwrap();
+        db.add_file_tag(&pool, "old", &tag.id).await.unwrap();
+        sqlx::query("UPDATE files SET path = '/elsewhere/old.txt' WHERE id = 'old'").execute(&pool).await.unwrap();
+        db.remove_file_by_path(&pool, "/elsewhere/old.txt").await.unwrap();
+        let deleted = db.remove_directory(&pool, &nested.id, Some(merged.id.clone())).await.unwrap();
+        assert!(deleted.is_empty());
+        assert_eq!(find_test_file(&pool, "a").await.unwrap().directory_id, merged.id);
+        let tombstones = db.get_file_tombstones(&pool).await.unwrap();
+        assert_eq!(tombstones.len(), 2);
+        assert!(tombstones.iter().all(|t| t.directory_id == merged.id));
+
+        // 外側がなければファイルと墓標ごと削除し、孤児になったタグも消す
+        let deleted = db.remove_directory(&pool, &merged.id, None).await.unwrap();
+        assert_eq!(deleted, vec![tag.id]);
+        assert!(find_test_file(&pool, "a").await.is_none());
+        assert!(db.get_file_tombstones(&pool).await.unwrap().is_empty());
     }
 
     #[tokio::test]
-    async fn test_get_all_tags() {
+    async fn test_ensure_tag_path_creates_and_adopts_parents() {
         let pool = setup_test_db().await;
         let db = Database;
 
-        db.create_tag(&pool, "tag1", "#ff0000").await.unwrap();
-        db.create_tag(&pool, "tag2", "#00ff00").await.unwrap();
+        // 既存の平坦なタグはそのまま階層に組み込まれる
+        let flat = db.create_tag(&pool, "client/acme", "#00ff00").await.unwrap();
+        let leaf = db.ensure_tag_path(&pool, "client/acme/invoices", "#ff0000").await.unwrap();
+        assert_eq!(leaf.name, "client/acme/invoices");
+        assert_eq!(leaf.label(), "invoices");
+        assert_eq!(leaf.parent_id.as_deref(), Some(flat.id.as_str()));
+
+        let client = db.get_tag_by_name(&pool, "client").await.unwrap();
+        assert!(client.parent_id.is_none());
+        let acme = db.get_tag_by_name(&pool, "client/acme").await.unwrap();
+        assert_eq!(acme.parent_id.as_deref(), Some(client.id.as_str()));
+
+        // 2回目は同じタグを返す
+        let again = db.ensure_tag_path(&pool, "client/acme/invoices", "#ff0000").await.unwrap();
+        assert_eq!(again.id, leaf.id);
+        assert_eq!(db.get_tag_subtree_ids(&pool, &client.id).await.unwrap().len(), 3);
+    }
 
-        let tags = db.get_all_tags(&pool).await.unwrap();
-        assert_eq!(tags.len(), 2);
+    #[tokio::test]
+    async fn test_move_tag_and_counts() {
+        let pool = setup_test_db().await;
+        let db = Database;
+
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        db.add_file(&pool, &test_file("a", "/test/a.txt", &dir.id)).await.unwrap();
+        db.add_file(&pool, &test_file("b", "/test/b.txt", &dir.id)).await.unwrap();
+
+        let q3 = db.ensure_tag_path(&pool, "project/2024/q3", "#ff0000").await.unwrap();
+        let year = db.get_tag_by_name(&pool, "project/2024").await.unwrap();
+        let archive = db.ensure_tag_path(&pool, "archive", "#000000").await.unwrap();
+        db.add_file_tag(&pool, "a", &q3.id).await.unwrap();
+        db.add_file_tag(&pool, "a", &year.id).await.unwrap();
+        db.add_file_tag(&pool, "b", &year.id).await.unwrap();
+        db.add_file_tag(&pool, "b", &archive.id).await.unwrap();
+
+        let counts = db.get_tag_file_counts(&pool).await.unwrap();
+        assert_eq!(counts.get(&q3.id), Some(&(1, 1)));
+        assert_eq!(counts.get(&year.id), Some(&(2, 2)));
+        let project = db.get_tag_by_name(&pool, "project").await.unwrap();
+        assert_eq!(counts.get(&project.id), Some(&(0, 2)));
+
+        // 子孫も含めて名前が書き換わる
+        let moved = db.move_tag(&pool, &year.id, Some(archive.id.clone())).await.unwrap();
+        assert_eq!(moved.name, "archive/2024");
+        assert_eq!(db.get_tag_by_name(&pool, "archive/2024/q3").await.unwrap().id, q3.id);
+        let counts = db.get_tag_file_counts(&pool).await.unwrap();
+        assert_eq!(counts.get(&archive.id), Some(&(1, 2)));
+
+        // 子タグを持つ親は未参照でも削除されず、子がなくなれば削除される
+        let deleted = db.delete_orphaned_tags(&pool).await.unwrap();
+        assert_eq!(deleted, vec![project.id.clone()]);
+        db.remove_file_tag(&pool, "a", &q3.id).await.unwrap();
+        db.remove_file_tag(&pool, "a", &year.id).await.unwrap();
+        db.remove_file_tag(&pool, "b", &year.id).await.unwrap();
+        let deleted = db.delete_orphaned_tags(&pool).await.unwrap();
+        assert_eq!(deleted.len(), 2);
+        assert!(db.get_tag_by_name(&pool, "archive").await.is_ok());
     }
 
     #[tokio::test]
-    async fn test_init_database() {
+    async fn test_rename_and_recolor_tag() {
         let pool = setup_test_db().await;
         let db = Database;
 
-        let result = db.init_database(&pool, &pool).await;
-        assert!(result.is_ok());
+        let leaf = db.ensure_tag_path(&pool, "client/acme/invoices", "#ff0000").await.unwrap();
+        let acme = db.get_tag_by_name(&pool, "client/acme").await.unwrap();
+
+        let renamed = db.rename_tag(&pool, &acme.id, "globex").await.unwrap();
+        assert_eq!(renamed.name, "client/globex");
+        assert_eq!(db.get_tag_by_name(&pool, "client/globex/invoices").await.unwrap().id, leaf.id);
+
+        let recolored = db.update_tag_color(&pool, &leaf.id, "#0000ff").await.unwrap();
+        assert_eq!(recolored.color, "#0000ff");
+        assert_eq!(recolored.name, "client/globex/invoices");
     }
 
     #[tokio::test]
-    async fn test_add_file_with_various_types() {
+    async fn test_merge_tags_deduplicates_and_keeps_alias() {
         let pool = setup_test_db().await;
         let db = Database;
 
         let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        db.add_file(&pool, &test_file("a", "/test/a.txt", &dir.id)).await.unwrap();
+        db.add_file(&pool, &test_file("b", "/test/b.txt", &dir.id)).await.unwrap();
+
+        let source_child = db.ensure_tag_path(&pool, "invoice/2024", "#ff0000").await.unwrap();
+        let source_other = db.ensure_tag_path(&pool, "invoice/draft", "#ff0000").await.unwrap();
+        let target_child = db.ensure_tag_path(&pool, "invoices/2024", "#00ff00").await.unwrap();
+        let source = db.get_tag_by_name(&pool, "invoice").await.unwrap();
+        let target = db.get_tag_by_name(&pool, "invoices").await.unwrap();
+
+        // a には両方のタグが付いている
+        db.add_file_tag(&pool, "a", &source.id).await.unwrap();
+        db.add_file_tag(&pool, "a", &target.id).await.unwrap();
+        db.add_file_tag(&pool, "b", &source.id).await.unwrap();
+        db.add_file_tag(&pool, "b", &source_child.id).await.unwrap();
+
+        let merged = db.merge_tags(&pool, &source.id, &target.id).await.unwrap();
+        assert_eq!(merged.id, target.id);
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM file_tags WHERE tag_id = ?")
+            .bind(&target.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        // 同名の子タグは統合され、それ以外の子タグは移動する
+        let b_tags: Vec<_> = db.get_file_tags(&pool, "b").await.unwrap().into_iter().map(|t| t.id).collect();
+        assert!(b_tags.contains(&target_child.id));
+        assert_eq!(db.get_tag_by_name(&pool, "invoices/draft").await.unwrap().id, source_other.id);
+
+        // 統合元の名前は別名として統合先に解決される
+        assert_eq!(db.get_tag_by_name(&pool, "invoice").await.unwrap().id, target.id);
+        assert_eq!(db.resolve_tag_alias(&pool, "Invoice/2024").await.unwrap().unwrap().id, target_child.id);
+        let found = db.search_tags_by_name(&pool, "invoice/2").await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, target_child.id);
+        let via_path = db.ensure_tag_path(&pool, "invoice/q1", "#ff0000").await.unwrap();
+        assert_eq!(via_path.name, "invoices/q1");
+        assert_eq!(via_path.parent_id.as_deref(), Some(target.id.as_str()));
+
+        let aliases = db.get_tag_aliases(&pool, &target.id).await.unwrap();
+        assert_eq!(aliases, vec!["invoice".to_string()]);
+        db.remove_tag_alias(&pool, "invoice").await.unwrap();
+        assert!(db.get_tag_by_name(&pool, "invoice").await.is_err());
+    }
 
-        // ディレクトリファイル
-        let dir_file = File {
-            id: "dir_file".to_string(),
-            path: "/test/subdir".to_string(),
-            name: "subdir".to_string(),
-            directory_id: dir.id.clone(),
-            size: 0,
-            file_type: None,
-            created_at: None,
-            modified_at: None,
-            birth_time: None,
-            inode: None,
-            is_directory: true,
-            created_at_db: Utc::now(),
-            updated_at_db: Utc::now(),
-            file_size: Some(1024),
-            mime_type: Some("text/plain".to_string()),
-            permissions: Some("644".to_string()),
-            owner_uid: Some(1000),
-            group_gid: Some(1000),
-            hard_links: Some(1),
-            device_id: Some(12345),
-            last_accessed: None,
-            metadata: None,
-        };
+    #[tokio::test]
+    async fn test_bulk_file_tag_operations() {
+        let pool = setup_test_db().await;
+        let db = Database;
 
-        let result = db.add_file(&pool, &dir_file).await;
-        assert!(result.is_ok());
+        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let file_ids: Vec<String> = ["a", "b", "c"].iter().map(|s| s.to_string()).collect();
+        for id in &file_ids {
+            let path = format!("/test/{id}.txt");
+            db.add_file(&pool, &test_file(id, &path, &dir.id)).await.unwrap();
+        }
+        let red = db.create_tag(&pool, "red", "#ff0000").await.unwrap();
+        let blue = db.create_tag(&pool, "blue", "#0000ff").await.unwrap();
+        db.add_file_tag(&pool, "a", &red.id).await.unwrap();
+
+        // 既に付いているものは数えない
+        let added = db.bulk_add_file_tags(&pool, &file_ids, &[red.id.clone(), blue.id.clone()]).await.unwrap();
+        assert_eq!(added, 5);
+
+        let removed = db.bulk_remove_file_tags(&pool, &file_ids[..2], std::slice::from_ref(&blue.id)).await.unwrap();
+        assert_eq!(removed, 2);
+
+        // 一部にしか付いていなければ全ファイルに付ける
+        assert_eq!(db.bulk_toggle_file_tag(&pool, &file_ids, &blue.id).await.unwrap(), (2, 0));
+        // 全ファイルに付いていれば外す
+        assert_eq!(db.bulk_toggle_file_tag(&pool, &file_ids, &blue.id).await.unwrap(), (0, 3));
+
+        // 存在しないファイルが含まれていれば何も変更しない
+        let mut with_missing = file_ids.clone();
+        with_missing.push("missing".to_string());
+        assert!(db.bulk_add_file_tags(&pool, &with_missing, std::slice::from_ref(&blue.id)).await.is_err());
+        assert!(db.get_file_tags(&pool, "a").await.unwrap().iter().all(|t| t.id != blue.id));
     }
 
     #[tokio::test]
-    async fn test_add_file_tag_duplicate() {
+    async fn test_pinned_tags_survive_orphan_cleanup() {
         let pool = setup_test_db().await;
         let db = Database;
 
         let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
-        let tag = db.create_tag(&pool, "test_tag", "#ff0000").await.unwrap();
+        db.add_file(&pool, &test_file("a", "/test/a.txt", &dir.id)).await.unwrap();
+
+        let curated = db.create_tag(&pool, "curated", "#ff0000").await.unwrap();
+        let curated = db.set_tag_pinned(&pool, &curated.id, true).await.unwrap();
+        assert!(curated.pinned);
+        let generated = db.create_tag(&pool, "generated", "#00ff00").await.unwrap();
+        let leaf = db.ensure_tag_path(&pool, "project/old", "#0000ff").await.unwrap();
+        let project = db.get_tag_by_name(&pool, "project").await.unwrap();
+        db.set_tag_pinned(&pool, &project.id, true).await.unwrap();
+        db.add_file_tag(&pool, "a", &curated.id).await.unwrap();
+        db.remove_file_tag(&pool, "a", &curated.id).await.unwrap();
+
+        // 固定されていないタグだけが自動削除される
+        let deleted = db.delete_orphaned_tags(&pool).await.unwrap();
+        assert_eq!(deleted.len(), 2);
+        assert!(deleted.contains(&generated.id) && deleted.contains(&leaf.id));
+        assert!(db.get_tag_by_name(&pool, "curated").await.is_ok());
+
+        // 色を変えたタグは固定される
+        let recolored = db.create_tag(&pool, "recolored", "#ff0000").await.unwrap();
+        assert!(db.update_tag_color(&pool, &recolored.id, "#123456").await.unwrap().pinned);
+
+        // 明示的な整理はプレビューでは削除しない
+        let preview = db.delete_unused_tags(&pool, true, true).await.unwrap();
+        let names: Vec<&str> = preview.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["curated", "project", "recolored"]);
+        assert_eq!(db.get_all_tags(&pool).await.unwrap().len(), 3);
+
+        assert!(db.delete_unused_tags(&pool, false, false).await.unwrap().is_empty());
+        assert_eq!(db.delete_unused_tags(&pool, true, false).await.unwrap().len(), 3);
+        assert!(db.get_all_tags(&pool).await.unwrap().is_empty());
+    }
 
-        let file = File {
-            id: "test_file".to_string(),
-            path: "/test/file.txt".to_string(),
-            name: "file.txt".to_string(),
-            directory_id: dir.id.clone(),
-            size: 1024,
-            file_type: Some("txt".to_string()),
-            created_at: Some(Utc::now()),
-            modified_at: Some(Utc::now()),
-            birth_time: Some(Utc::now()),
-            inode: Some(12345),
-            is_directory: false,
-            created_at_db: Utc::now(),
-            updated_at_db: Utc::now(),
-            file_size: Some(1024),
-            mime_type: Some("text/plain".to_string()),
-            permissions: Some("644".to_string()),
-            owner_uid: Some(1000),
-            group_gid: Some(1000),
-            hard_links: Some(1),
-            device_id: Some(12345),
-            last_accessed: None,
-            metadata: None,
-        };
+    #[tokio::test]
+    async fn test_suggest_tags_from_similar_files() {
+        let pool = setup_test_db().await;
+        let db = Database;
 
-        db.add_file(&pool, &file).await.unwrap();
+        let dir = db.add_directory(&pool, "/photos", "photos").await.unwrap();
+        for (id, path) in [
+            ("a", "/photos/trip/beach_01.jpg"),
+            ("b", "/photos/trip/beach_02.jpg"),
+            ("c", "/photos/trip/sunset.jpg"),
+            ("d", "/photos/other/notes.txt"),
+        ] {
+            db.add_file(&pool, &test_file(id, path, &dir.id)).await.unwrap();
+        }
+        let trip = db.create_tag(&pool, "trip", "#ff0000").await.unwrap();
+        let beach = db.create_tag(&pool, "beach", "#00ff00").await.unwrap();
+        let notes = db.create_tag(&pool, "notes", "#0000ff").await.unwrap();
+        for file_id in ["a", "b"] {
+            db.add_file_tag(&pool, file_id, &trip.id).await.unwrap();
+            db.add_file_tag(&pool, file_id, &beach.id).await.unwrap();
+        }
+        db.add_file_tag(&pool, "c", &trip.id).await.unwrap();
+        db.add_file_tag(&pool, "d", &notes.id).await.unwrap();
+
+        // 同じディレクトリ・似た名前のファイルのタグが上位に来る
+        let suggestions = db.suggest_tags(&pool, &["c".to_string()], 10).await.unwrap();
+        assert_eq!(suggestions[0].tag.name, "beach");
+        assert!(suggestions[0].reasons.contains(&TagSuggestionReason::SameDirectory));
+        assert!(suggestions[0].reasons.contains(&TagSuggestionReason::CoOccurrence));
+        assert!(suggestions.iter().all(|s| s.tag.id != trip.id));
+
+        // 似た名前のファイルのタグも根拠付きで挙がる
+        db.add_file(&pool, &test_file("e", "/photos/beach_03.jpg", &dir.id)).await.unwrap();
+        let suggestions = db.suggest_tags(&pool, &["e".to_string()], 1).await.unwrap();
+        assert_eq!(suggestions.len(), 1);
+        assert!(suggestions[0].reasons.contains(&TagSuggestionReason::SimilarName));
+        assert!(suggestions.iter().all(|s| s.tag.id != notes.id));
+
+        // 全ファイルに付いているタグは複数選択時も提案しない
+        let suggestions = db.suggest_tags(&pool, &["a".to_string(), "b".to_string()], 10).await.unwrap();
+        assert!(suggestions.iter().all(|s| s.tag.id != trip.id && s.tag.id != beach.id));
+    }
 
-        // 同じファイル・タグの組み合わせを複数回追加（IGNORE句でエラーにならない）
-        db.add_file_tag(&pool, &file.id, &tag.id).await.unwrap();
-        db.add_file_tag(&pool, &file.id, &tag.id).await.unwrap();
+    #[tokio::test]
+    async fn test_directory_tags_are_inherited() {
+        let pool = setup_test_db().await;
+        let db = Database;
 
-        let file_tags = db.get_file_tags(&pool, &file.id).await.unwrap();
-        assert_eq!(file_tags.len(), 1);
+        let dir = db.add_directory(&pool, "/work", "work").await.unwrap();
+        let mut folder = test_file("folder", "/work/code", &dir.id);
+        folder.is_directory = true;
+        db.add_file(&pool, &folder).await.unwrap();
+        for (id, path) in [
+            ("direct", "/work/code/main.rs"),
+            ("nested", "/work/code/src/lib.rs"),
+            ("sibling", "/work/code-notes.txt"),
+        ] {
+            db.add_file(&pool, &test_file(id, path, &dir.id)).await.unwrap();
+        }
+        let programming = db.create_tag(&pool, "Programming", "#ff0000").await.unwrap();
+        db.add_file_tag(&pool, "folder", &programming.id).await.unwrap();
+        db.add_file_tag(&pool, "direct", &programming.id).await.unwrap();
+
+        async fn tagged(pool: &SqlitePool, tag_id: &str) -> Vec<String> {
+            sqlx::query_scalar("SELECT DISTINCT file_id FROM effective_file_tags WHERE tag_id = ? ORDER BY file_id")
+                .bind(tag_id)
+                .fetch_all(pool)
+                .await
+                .unwrap()
+        }
+        assert_eq!(tagged(&pool, &programming.id).await, vec!["direct", "folder", "nested"]);
+
+        // 直接付いたタグが継承より優先して表示される
+        let direct = db.get_file_tag_assignments(&pool, "direct").await.unwrap();
+        assert_eq!(direct.len(), 1);
+        assert!(direct[0].inherited_from.is_none());
+        let nested = db.get_file_tag_assignments(&pool, "nested").await.unwrap();
+        assert_eq!(nested[0].inherited_from.as_ref().unwrap().path, "/work/code");
+        assert!(db.get_file_tags(&pool, "nested").await.unwrap().is_empty());
+
+        // サブフォルダへの継承を止める
+        db.set_tag_include_subfolders(&pool, "folder", &programming.id, false).await.unwrap();
+        assert_eq!(tagged(&pool, &programming.id).await, vec!["direct", "folder"]);
+        assert!(!db.get_file_tag_assignments(&pool, "folder").await.unwrap()[0].include_subfolders);
+        assert!(db.set_tag_include_subfolders(&pool, "nested", &programming.id, true).await.is_err());
     }
 
     #[tokio::test]
-    async fn test_remove_nonexistent_file_tag() {
+    async fn test_tag_usage_statistics() {
         let pool = setup_test_db().await;
         let db = Database;
 
-        // 存在しないファイル・タグの組み合わせを削除（エラーにならない）
-        let result = db
-            .remove_file_tag(&pool, "nonexistent_file", "nonexistent_tag")
-            .await;
-        assert!(result.is_ok());
+        let dir = db.add_directory(&pool, "/docs", "docs").await.unwrap();
+        for (id, path) in [("a", "/docs/a.txt"), ("b", "/docs/b.txt"), ("c", "/docs/2024/march/c.txt")] {
+            db.add_file(&pool, &test_file(id, path, &dir.id)).await.unwrap();
+        }
+        let invoice = db.create_tag(&pool, "invoice", "#ff0000").await.unwrap();
+        let invoices = db.create_tag(&pool, "invoices", "#00ff00").await.unwrap();
+        db.create_tag(&pool, "unused", "#0000ff").await.unwrap();
+        for file_id in ["a", "b"] {
+            db.add_file_tag(&pool, file_id, &invoice.id).await.unwrap();
+            db.add_file_tag(&pool, file_id, &invoices.id).await.unwrap();
+        }
+        db.add_file_tag(&pool, "c", &invoice.id).await.unwrap();
+
+        let usage = db.get_tag_usage(&pool).await.unwrap();
+        let counts: Vec<(&str, i64, i64)> =
+            usage.iter().map(|u| (u.tag.name.as_str(), u.file_count, u.total_bytes)).collect();
+        assert_eq!(counts, vec![("invoice", 3, 6144), ("invoices", 2, 4096), ("unused", 0, 0)]);
+
+        let pairs = db.get_tag_pair_counts(&pool, 10).await.unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].file_count, 2);
+
+        // 登録したディレクトリではなく実際の親フォルダごとに数える
+        let by_directory = db.get_tag_counts_by_directory(&pool).await.unwrap();
+        let invoice_groups: Vec<(Option<&str>, i64)> = by_directory
+            .iter()
+            .filter(|c| c.tag_id == invoice.id)
+            .map(|c| (c.group.as_deref(), c.file_count))
+            .collect();
+        assert_eq!(invoice_groups, vec![(Some("/docs"), 2), (Some("/docs/2024/march"), 1)]);
+
+        // 付与日時はトリガーで記録される
+        let timeline = db.get_tag_counts_by_period(&pool, "%Y").await.unwrap();
+        assert_eq!(timeline.iter().map(|c| c.file_count).sum::<i64>(), 5);
+        assert!(timeline.iter().all(|c| c.group.is_some()));
+    }
+
+    #[test]
+    fn test_custom_metadata_values_are_normalized_by_type() {
+        let number = metadata_key("number_key", "number");
+        assert_eq!(number.normalize_value(Some(" 42.0 ")).unwrap().as_deref(), Some("42"));
+        assert_eq!(number.normalize_value(Some("1.5")).unwrap().as_deref(), Some("1.5"));
+        assert!(number.normalize_value(Some("abc")).is_err());
+        assert_eq!(number.normalize_value(None).unwrap(), None);
+
+        let date = metadata_key("date_key", "date");
+        assert_eq!(date.normalize_value(Some("2024/3/5")).unwrap().as_deref(), Some("2024-03-05"));
+        assert_eq!(
+            date.normalize_value(Some("2024-03-05T10:00:00+09:00")).unwrap().as_deref(),
+            Some("2024-03-05T01:00:00Z")
+        );
+        assert!(date.normalize_value(Some("next tuesday")).is_err());
+
+        let boolean = metadata_key("boolean_key", "boolean");
+        assert_eq!(boolean.normalize_value(Some("Yes")).unwrap().as_deref(), Some("true"));
+        assert!(boolean.normalize_value(Some("maybe")).is_err());
+
+        let json = metadata_key("json_key", "json");
+        assert_eq!(json.normalize_value(Some("{ \"a\": [1, 2] }")).unwrap().as_deref(), Some("{\"a\":[1,2]}"));
+        assert!(json.normalize_value(Some("{a:1}")).is_err());
+
+        // 検証パターンは値全体に一致する必要がある
+        let mut code = metadata_key("text_key", "text");
+        code.validation_pattern = Some("[A-Z]{3}-\\d+".to_string());
+        assert!(code.normalize_value(Some("ABC-12")).is_ok());
+        assert!(code.normalize_value(Some("xABC-12")).is_err());
+
+        // 空の値には既定値、必須キーで既定値がなければエラー
+        code.is_required = true;
+        assert!(code.normalize_value(Some("")).is_err());
+        code.default_value = Some("NEW-0".to_string());
+        assert_eq!(code.normalize_value(None).unwrap().as_deref(), Some("NEW-0"));
     }
 
     #[tokio::test]
-    async fn test_get_file_tags_nonexistent_file() {
+    async fn test_audit_custom_metadata() {
         let pool = setup_test_db().await;
+        let settings_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./settings_migrations").run(&settings_pool).await.unwrap();
         let db = Database;
 
-        let file_tags = db.get_file_tags(&pool, "nonexistent_file").await.unwrap();
-        assert_eq!(file_tags.len(), 0);
+        let dir = db.add_directory(&pool, "/docs", "docs").await.unwrap();
+        for id in ["a", "b"] {
+            db.add_file(&pool, &test_file(id, &format!("/docs/{id}.txt"), &dir.id)).await.unwrap();
+        }
+        let mut pages = metadata_key("number_key", "number");
+        pages.is_required = true;
+        let pages = db.create_custom_metadata_key(&settings_pool, &pages).await.unwrap();
+
+        let value = db.set_custom_metadata_value(&pool, &settings_pool, "a", &pages.id, Some("12.0".to_string())).await.unwrap();
+        assert_eq!(value.value.as_deref(), Some("12"));
+        let err = db.set_custom_metadata_value(&pool, &settings_pool, "a", &pages.id, Some("many".to_string())).await;
+        assert!(matches!(err, Err(MetadataError::Invalid(_))));
+
+        // 検証前に保存された不正な値も監査で見つかる
+        sqlx::query("UPDATE custom_metadata_values SET value = 'many' WHERE file_id = 'a'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let violations = db.audit_custom_metadata(&pool, &settings_pool).await.unwrap();
+        let found: Vec<(&str, Option<&str>)> =
+            violations.iter().map(|v| (v.file_id.as_str(), v.value.as_deref())).collect();
+        assert_eq!(found, vec![("a", Some("many")), ("b", None)]);
+    }
+
+    #[test]
+    fn test_richer_custom_metadata_types() {
+        let mut status = metadata_key("select_key", "select");
+        status.options = Some(vec!["Draft".to_string(), "Final".to_string()]);
+        assert_eq!(status.normalize_value(Some("final")).unwrap().as_deref(), Some("Final"));
+        assert!(status.normalize_value(Some("Archived")).is_err());
+
+        let mut labels = metadata_key("multi_select_key", "multi_select");
+        labels.options = Some(vec!["red".to_string(), "green".to_string(), "blue".to_string()]);
+        assert_eq!(labels.normalize_value(Some("blue, red, blue")).unwrap().as_deref(), Some("[\"blue\",\"red\"]"));
+        assert_eq!(labels.normalize_value(Some("[\"green\"]")).unwrap().as_deref(), Some("[\"green\"]"));
+        assert_eq!(labels.normalize_value(Some("[]")).unwrap(), None);
+        assert!(labels.normalize_value(Some("red, pink")).is_err());
+
+        let rating = metadata_key("rating_key", "rating");
+        assert_eq!(rating.normalize_value(Some("★★★☆☆")).unwrap().as_deref(), Some("3"));
+        assert!(rating.normalize_value(Some("6")).is_err());
+        assert!(rating.normalize_value(Some("2.5")).is_err());
+
+        let url = metadata_key("url_key", "url");
+        assert_eq!(url.normalize_value(Some("https://example.com")).unwrap().as_deref(), Some("https://example.com/"));
+        assert!(url.normalize_value(Some("example dot com")).is_err());
+
+        let duration = metadata_key("duration_key", "duration");
+        assert_eq!(duration.normalize_value(Some("1:02:03")).unwrap().as_deref(), Some("3723"));
+        assert_eq!(duration.normalize_value(Some("1h 30m")).unwrap().as_deref(), Some("5400"));
+        assert_eq!(duration.normalize_value(Some("90.5")).unwrap().as_deref(), Some("90.5"));
+        assert!(duration.normalize_value(Some("1:75")).is_err());
+        assert!(duration.normalize_value(Some("soon")).is_err());
     }
 
     #[tokio::test]
-    async fn test_file_exists_by_path() {
+    async fn test_multi_value_and_file_reference_metadata() {
         let pool = setup_test_db().await;
+        let settings_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./settings_migrations").run(&settings_pool).await.unwrap();
         let db = Database;
 
-        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let dir = db.add_directory(&pool, "/docs", "docs").await.unwrap();
+        for id in ["a", "b"] {
+            db.add_file(&pool, &test_file(id, &format!("/docs/{id}.txt"), &dir.id)).await.unwrap();
+        }
+        let mut labels = metadata_key("multi_select_key", "multi_select");
+        labels.options = Some(vec!["red".to_string(), "green".to_string()]);
+        let labels = db.create_custom_metadata_key(&settings_pool, &labels).await.unwrap();
+        assert_eq!(db.get_all_custom_metadata_keys(&settings_pool).await.unwrap()[0].options, labels.options);
+        let source = db.create_custom_metadata_key(&settings_pool, &metadata_key("file_ref_key", "file_ref")).await.unwrap();
+
+        async fn items(pool: &SqlitePool, file_id: &str) -> Vec<String> {
+            sqlx::query_scalar(
+                "SELECT it.item FROM custom_metadata_value_items it
+                 INNER JOIN custom_metadata_values v ON v.id = it.value_id
+                 WHERE v.file_id = ? ORDER BY it.position",
+            )
+            .bind(file_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+        }
 
-        let file = File {
-            id: "test_file".to_string(),
-            path: "/test/file.txt".to_string(),
-            name: "file.txt".to_string(),
-            directory_id: dir.id.clone(),
-            size: 1024,
-            file_type: Some("txt".to_string()),
-            created_at: Some(Utc::now()),
-            modified_at: Some(Utc::now()),
-            birth_time: None,
-            inode: Some(12345),
-            is_directory: false,
-            created_at_db: Utc::now(),
-            updated_at_db: Utc::now(),
-            file_size: Some(1024),
-            mime_type: Some("text/plain".to_string()),
-            permissions: Some("644".to_string()),
-            owner_uid: Some(1000),
-            group_gid: Some(1000),
-            hard_links: Some(1),
-            device_id: Some(12345),
-            last_accessed: None,
-            metadata: None,
+        db.set_custom_metadata_value(&pool, &settings_pool, "a", &labels.id, Some("green,red".to_string())).await.unwrap();
+        assert_eq!(items(&pool, "a").await, vec!["green", "red"]);
+        db.set_custom_metadata_value(&pool, &settings_pool, "a", &labels.id, Some("red".to_string())).await.unwrap();
+        assert_eq!(items(&pool, "a").await, vec!["red"]);
+
+        // ファイル参照はパスでも指定でき、IDとして保存される
+        let reference = db.set_custom_metadata_value(&pool, &settings_pool, "a", &source.id, Some("/docs/b.txt".to_string())).await.unwrap();
+        assert_eq!(reference.value.as_deref(), Some("b"));
+        assert!(db.set_custom_metadata_value(&pool, &settings_pool, "a", &source.id, Some("/nowhere".to_string())).await.is_err());
+
+        // 墓標から復元しても項目の行が作り直される
+        let tombstone = {
+            let mut conn = pool.acquire().await.unwrap();
+            assert!(create_tombstone(&mut conn, "a").await.unwrap());
+            db.get_file_tombstones(&pool).await.unwrap().remove(0)
         };
+        sqlx::query("DELETE FROM files WHERE id = 'a'").execute(&pool).await.unwrap();
+        assert!(items(&pool, "a").await.is_empty());
+        db.add_file(&pool, &test_file("a2", "/docs/a2.txt", &dir.id)).await.unwrap();
+        db.restore_file_tombstone(&pool, &tombstone.id, "a2").await.unwrap();
+        assert_eq!(items(&pool, "a2").await, vec!["red"]);
+    }
 
-        // ファイルを追加
-        db.add_file(&pool, &file).await.unwrap();
+    #[tokio::test]
+    async fn test_bulk_custom_metadata_operations() {
+        let pool = setup_test_db().await;
+        let settings_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./settings_migrations").run(&settings_pool).await.unwrap();
+        let db = Database;
 
-        // ファイルが存在することを確認
-        let exists = db
-            .file_exists_by_path(&pool, "/test/file.txt")
+        let dir = db.add_directory(&pool, "/docs", "docs").await.unwrap();
+        for id in ["a", "b", "c"] {
+            db.add_file(&pool, &test_file(id, &format!("/docs/{id}.txt"), &dir.id)).await.unwrap();
+        }
+        let number = db.create_custom_metadata_key(&settings_pool, &metadata_key("number_key", "number")).await.unwrap();
+        let text = db.create_custom_metadata_key(&settings_pool, &metadata_key("text_key", "text")).await.unwrap();
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        async fn value(db: &Database, pool: &SqlitePool, file_id: &str, key_id: &str) -> Option<String> {
+            db.get_custom_metadata_value(pool, file_id, key_id).await.unwrap().and_then(|v| v.value)
+        }
+
+        // 存在しないファイルは失敗として返し、他のファイルには書き込む
+        let result = db
+            .bulk_set_custom_metadata_value(&pool, &settings_pool, &ids(&["a", "b", "missing"]), &number.id, Some("3.0".to_string()))
             .await
             .unwrap();
-        assert!(exists);
+        assert_eq!((result.file_count, result.updated), (3, 2));
+        assert_eq!(result.failures.len(), 1);
+        assert_eq!(result.failures[0].file_id, "missing");
+        assert_eq!(value(&db, &pool, "b", &number.id).await.as_deref(), Some("3"));
 
-        // 存在しないファイルを確認
-        let not_exists = db
-            .file_exists_by_path(&pool, "/test/nonexistent.txt")
+        // 型に合わない値はどのファイルにも書き込まない
+        let result = db
+            .bulk_set_custom_metadata_value(&pool, &settings_pool, &ids(&["a", "b"]), &number.id, Some("abc".to_string()))
             .await
             .unwrap();
-        assert!(!not_exists);
+        assert_eq!((result.updated, result.failures.len()), (0, 2));
+        assert_eq!(value(&db, &pool, "a", &number.id).await.as_deref(), Some("3"));
+
+        let result = db.bulk_clear_custom_metadata_value(&pool, &ids(&["b", "c"]), &number.id).await.unwrap();
+        assert_eq!(result.updated, 1);
+        assert_eq!(value(&db, &pool, "b", &number.id).await, None);
+
+        // 上書きしない場合は値のあるキーを残す
+        db.set_custom_metadata_value(&pool, &settings_pool, "a", &text.id, Some("from a".to_string())).await.unwrap();
+        db.set_custom_metadata_value(&pool, &settings_pool, "b", &text.id, Some("keep".to_string())).await.unwrap();
+        let result = db
+            .copy_custom_metadata(&pool, &settings_pool, "a", &ids(&["a", "b", "c"]), None, false)
+            .await
+            .unwrap();
+        // 件数は値の数ではなく、値を書き込んだファイルの数
+        assert_eq!((result.file_count, result.updated), (2, 2));
+        assert_eq!(value(&db, &pool, "b", &text.id).await.as_deref(), Some("keep"));
+        assert_eq!(value(&db, &pool, "b", &number.id).await.as_deref(), Some("3"));
+        assert_eq!(value(&db, &pool, "c", &text.id).await.as_deref(), Some("from a"));
+
+        db.copy_custom_metadata(&pool, &settings_pool, "a", &ids(&["b"]), Some(ids(&[&text.id])), true).await.unwrap();
+        assert_eq!(value(&db, &pool, "b", &text.id).await.as_deref(), Some("from a"));
+        assert_eq!(db.get_custom_metadata_values_by_files(&pool, &ids(&["a", "b", "c"])).await.unwrap().len(), 6);
     }
 
     #[tokio::test]
-    async fn test_find_file_by_inode() {
+    async fn test_import_file_metadata() {
         let pool = setup_test_db().await;
+        let settings_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./settings_migrations").run(&settings_pool).await.unwrap();
         let db = Database;
 
-        let dir = db.add_directory(&pool, "/test", "test").await.unwrap();
+        let dir = db.add_directory(&pool, "/docs", "docs").await.unwrap();
+        db.add_file(&pool, &file_with_hash("a", "/docs/a.pdf", &dir.id, "abc123")).await.unwrap();
+        db.add_file(&pool, &file_with_hash("b", "/docs/sub/b.pdf", &dir.id, "abc123")).await.unwrap();
+        assert_eq!(db.match_file_ids(&pool, FileMatchField::RelativePath, "sub/b.pdf").await.unwrap(), vec!["b"]);
+        assert_eq!(db.match_file_ids(&pool, FileMatchField::ContentHash, "ABC123").await.unwrap().len(), 2);
+        assert!(db.match_file_ids(&pool, FileMatchField::Path, "/docs/c.pdf").await.unwrap().is_empty());
+
+        let number = db.create_custom_metadata_key(&settings_pool, &metadata_key("number_key", "number")).await.unwrap();
+        let rows = vec![
+            MetadataImportRow {
+                row: 2,
+                file_id: "a".to_string(),
+                tags: vec!["clients/acme".to_string()],
+                values: vec![(number.id.clone(), "7".to_string())],
+            },
+            // 値が不正な行はタグも含めて書き込まない
+            MetadataImportRow {
+                row: 3,
+                file_id: "b".to_string(),
+                tags: vec!["draft".to_string()],
+                values: vec![(number.id.clone(), "seven".to_string())],
+            },
+        ];
+
+        let results = db.import_file_metadata(&pool, &settings_pool, &rows, "#000000", false, true).await.unwrap();
+        assert_eq!((results[0].tags_added, results[0].values_set, results[0].error.is_none()), (1, 1, true));
+        assert!(results[1].error.is_some());
+        assert!(db.get_file_tags(&pool, "a").await.unwrap().is_empty());
+        assert!(db.get_all_tags(&pool).await.unwrap().is_empty());
+
+        // skip_invalid がなければ、不正な行があると正しい行も書き込まない
+        let results = db.import_file_metadata(&pool, &settings_pool, &rows, "#000000", true, false).await.unwrap();
+        assert!(results[1].error.is_some());
+        assert!(db.get_file_tags(&pool, "a").await.unwrap().is_empty());
+        assert!(db.get_all_tags(&pool).await.unwrap().is_empty());
+
+        db.import_file_metadata(&pool, &settings_pool, &rows, "#000000", true, true).await.unwrap();
+        assert_eq!(db.get_file_tags(&pool, "a").await.unwrap()[0].name, "clients/acme");
+        assert_eq!(db.get_custom_metadata_value(&pool, "a", &number.id).await.unwrap().unwrap().value.as_deref(), Some("7"));
+        assert!(db.get_file_tags(&pool, "b").await.unwrap().is_empty());
+        assert!(db.get_all_tags(&pool).await.unwrap().iter().all(|t| t.name != "draft"));
+
+        let tags = db.get_file_tags_by_files(&pool, &["a".to_string(), "b".to_string()]).await.unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].0, "a");
+        let files = db.get_files_by_ids(&pool, &["b".to_string(), "a".to_string()]).await.unwrap();
+        assert_eq!(files.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+    }
 
-        let file = File {
-            id: "test_file".to_string(),
-            path: "/test/file.txt".to_string(),
-            name: "file.txt".to_string(),
-            directory_id: dir.id.clone(),
-            size: 1024,
-            file_type: Some("txt".to_string()),
-            created_at: Some(Utc::now()),
-            modified_at: Some(Utc::now()),
-            birth_time: None,
-            inode: Some(12345),
-            is_directory: false,
-            created_at_db: Utc::now(),
-            updated_at_db: Utc::now(),
-            file_size: Some(1024),
-            mime_type: Some("text/plain".to_string()),
-            permissions: Some("644".to_string()),
-            owner_uid: Some(1000),
-            group_gid: Some(1000),
-            hard_links: Some(1),
-            device_id: Some(67890),
-            last_accessed: None,
-            metadata: None,
+    #[tokio::test]
+    async fn test_custom_metadata_key_lifecycle_across_shelves() {
+        let first = setup_test_db().await;
+        let second = setup_test_db().await;
+        let settings_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./settings_migrations").run(&settings_pool).await.unwrap();
+        let db = Database;
+
+        for pool in [&first, &second] {
+            let dir = db.add_directory(pool, "/docs", "docs").await.unwrap();
+            for id in ["a", "b"] {
+                db.add_file(pool, &test_file(id, &format!("/docs/{id}.txt"), &dir.id)).await.unwrap();
+            }
+        }
+        let text = db.create_custom_metadata_key(&settings_pool, &metadata_key("text_key", "text")).await.unwrap();
+        db.set_custom_metadata_value(&first, &settings_pool, "a", &text.id, Some("12.0".to_string())).await.unwrap();
+        db.set_custom_metadata_value(&first, &settings_pool, "b", &text.id, Some("twelve".to_string())).await.unwrap();
+
+        // テキストから数値に変える
+        let mut number = text.clone();
+        number.data_type = "number".to_string();
+        let failures = db.convert_custom_metadata_values(&first, &text, &number, false, false).await.unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].file_id, "b");
+        assert_eq!(db.get_custom_metadata_value(&first, "a", &text.id).await.unwrap().unwrap().value.as_deref(), Some("12.0"));
+
+        // 変換できない値があれば、どのシェルフにもキー定義にも書き込まない
+        let shelves = [first.clone(), second.clone()];
+        db.set_custom_metadata_value(&second, &settings_pool, "a", &text.id, Some("3.50".to_string())).await.unwrap();
+        assert!(db.update_custom_metadata_key_in_shelves(&settings_pool, &shelves, &text, &number, false).await.is_err());
+        assert_eq!(db.get_custom_metadata_value(&second, "a", &text.id).await.unwrap().unwrap().value.as_deref(), Some("3.50"));
+        assert_eq!(db.get_custom_metadata_key_by_name(&settings_pool, &text.name).await.unwrap().unwrap().data_type, "text");
+
+        // キー定義の書き込みに失敗した場合も、各シェルフの変換を取り消す
+        let broken_settings = SqlitePool::connect(":memory:").await.unwrap();
+        assert!(db.update_custom_metadata_key_in_shelves(&broken_settings, &shelves, &text, &number, true).await.is_err());
+        assert_eq!(db.get_custom_metadata_value(&first, "a", &text.id).await.unwrap().unwrap().value.as_deref(), Some("12.0"));
+        assert_eq!(db.get_custom_metadata_value(&second, "a", &text.id).await.unwrap().unwrap().value.as_deref(), Some("3.50"));
+
+        db.update_custom_metadata_key_in_shelves(&settings_pool, &shelves, &text, &number, true).await.unwrap();
+        assert_eq!(db.get_custom_metadata_value(&first, "a", &text.id).await.unwrap().unwrap().value.as_deref(), Some("12"));
+        assert!(db.get_custom_metadata_value(&first, "b", &text.id).await.unwrap().is_none());
+        assert_eq!(db.get_custom_metadata_value(&second, "a", &text.id).await.unwrap().unwrap().value.as_deref(), Some("3.5"));
+        assert_eq!(db.get_custom_metadata_key_by_name(&settings_pool, &text.name).await.unwrap().unwrap().data_type, "number");
+
+        // 削除はすべてのシェルフの値に及ぶ
+        db.set_custom_metadata_value(&second, &settings_pool, "a", &text.id, Some("7".to_string())).await.unwrap();
+        db.delete_custom_metadata_key(&settings_pool, &[first.clone(), second.clone()], &text.id).await.unwrap();
+        assert!(db.get_custom_metadata_values_by_file(&first, "a").await.unwrap().is_empty());
+        assert!(db.get_custom_metadata_values_by_file(&second, "a").await.unwrap().is_empty());
+
+        // キーのない値を見つけて削除する
+        sqlx::query("INSERT INTO custom_metadata_values (id, file_id, key_id, value, created_at, updated_at) VALUES ('v1', 'a', 'gone', 'x', ?, ?)")
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&second)
+            .await
+            .unwrap();
+        let known = vec!["other".to_string()];
+        let orphans = db.find_orphaned_custom_metadata_values(&second, &known, false).await.unwrap();
+        assert_eq!(orphans, vec![OrphanedMetadataValues { key_id: "gone".to_string(), value_count: 1 }]);
+        db.find_orphaned_custom_metadata_values(&second, &known, true).await.unwrap();
+        assert!(db.find_orphaned_custom_metadata_values(&second, &known, false).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_annotation_history() {
+        let pool = setup_test_db().await;
+        let settings_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./settings_migrations").run(&settings_pool).await.unwrap();
+        let db = Database;
+        crate::settings::set_shelf_setting(&pool, "annotation_actor", "alice").await.unwrap();
+
+        let dir = db.add_directory(&pool, "/docs", "docs").await.unwrap();
+        db.add_file(&pool, &test_file("a", "/docs/a.txt", &dir.id)).await.unwrap();
+        let key = db.create_custom_metadata_key(&settings_pool, &metadata_key("text_key", "text")).await.unwrap();
+        let tag = db.ensure_tag_path(&pool, "contract", "#000000").await.unwrap();
+
+        db.set_custom_metadata_value(&pool, &settings_pool, "a", &key.id, Some("draft".to_string())).await.unwrap();
+        db.set_custom_metadata_value(&pool, &settings_pool, "a", &key.id, Some("draft".to_string())).await.unwrap();
+        db.set_custom_metadata_value(&pool, &settings_pool, "a", &key.id, Some("signed".to_string())).await.unwrap();
+        db.add_file_tag(&pool, "a", &tag.id).await.unwrap();
+        db.remove_file_tag(&pool, "a", &tag.id).await.unwrap();
+
+        let history = db.get_annotation_history(&pool, "a", None, None).await.unwrap();
+        let summary: Vec<(&str, Option<&str>, Option<&str>)> = history
+            .iter()
+            .map(|h| (h.action.as_str(), h.old_value.as_deref(), h.new_value.as_deref()))
+            .collect();
+        // 値の変わらない更新は記録しない
+        assert_eq!(
+            summary,
+            vec![
+                ("tag_removed", Some("contract"), None),
+                ("tag_added", None, Some("contract")),
+                ("set", Some("draft"), Some("signed")),
+                ("set", None, Some("draft")),
+            ]
+        );
+        assert!(history.iter().all(|h| h.changed_by.as_deref() == Some("alice")));
+
+        // 変更を取り消すと変更前の値に戻り、その操作も履歴に残る
+        let signed = db.get_annotation_history(&pool, "a", Some(key.id.clone()), Some(1)).await.unwrap().remove(0);
+        let reverted = db.revert_custom_metadata_change(&pool, &settings_pool, signed.id).await.unwrap();
+        assert_eq!(reverted.unwrap().value.as_deref(), Some("draft"));
+        let first = db.get_annotation_history(&pool, "a", Some(key.id.clone()), None).await.unwrap().pop().unwrap();
+        assert_eq!(db.revert_custom_metadata_change(&pool, &settings_pool, first.id).await.unwrap(), None);
+        assert_eq!(db.get_annotation_history(&pool, "a", Some(key.id.clone()), Some(1)).await.unwrap()[0].action, "delete");
+        let tag_change = db.get_annotation_history(&pool, "a", None, None).await.unwrap()
+            .into_iter()
+            .find(|h| h.tag_id.is_some())
+            .unwrap();
+        assert!(db.revert_custom_metadata_change(&pool, &settings_pool, tag_change.id).await.is_err());
+
+        // タグを削除して外れた場合もタグ名を記録する
+        db.add_file_tag(&pool, "a", &tag.id).await.unwrap();
+        sqlx::query("DELETE FROM tags WHERE id = ?").bind(&tag.id).execute(&pool).await.unwrap();
+        let removed = db.get_annotation_history(&pool, "a", None, Some(1)).await.unwrap().remove(0);
+        assert_eq!((removed.action.as_str(), removed.old_value.as_deref()), ("tag_removed", Some("contract")));
+
+        // 墓標の間は履歴を残し、戻したファイルに引き継ぐ
+        db.set_custom_metadata_value(&pool, &settings_pool, "a", &key.id, Some("final".to_string())).await.unwrap();
+        let count = |pool: SqlitePool| async move {
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM annotation_history").fetch_one(&pool).await.unwrap()
         };
+        let before = count(pool.clone()).await;
+        db.remove_file_by_path(&pool, "/docs/a.txt").await.unwrap();
+        assert_eq!(count(pool.clone()).await, before);
+        db.add_file(&pool, &test_file("b", "/docs/b.txt", &dir.id)).await.unwrap();
+        let tombstone = db.get_file_tombstones(&pool).await.unwrap().remove(0);
+        db.restore_file_tombstone(&pool, &tombstone.id, "b").await.unwrap();
+        let history = db.get_annotation_history(&pool, "b", Some(key.id.clone()), None).await.unwrap();
+        assert!(history.iter().any(|h| h.new_value.as_deref() == Some("signed")));
+        assert!(db.get_annotation_history(&pool, "a", None, None).await.unwrap().is_empty());
+
+        // 墓標が期限切れで消えると履歴も消える
+        db.remove_file_by_path(&pool, "/docs/b.txt").await.unwrap();
+        assert!(count(pool.clone()).await > 0);
+        db.purge_expired_tombstones(&pool, Utc::now() + chrono::Duration::days(1)).await.unwrap();
+        assert_eq!(count(pool.clone()).await, 0);
+
+        // 墓標のないファイルを削除すると履歴も消える
+        db.add_file(&pool, &test_file("c", "/docs/c.txt", &dir.id)).await.unwrap();
+        db.set_custom_metadata_value(&pool, &settings_pool, "c", &key.id, Some("x".to_string())).await.unwrap();
+        sqlx::query("DELETE FROM files WHERE id = 'c'").execute(&pool).await.unwrap();
+        assert_eq!(count(pool.clone()).await, 0);
+    }
 
-        // ファイルを追加
-        db.add_file(&pool, &file).await.unwrap();
+    #[tokio::test]
+    async fn test_custom_metadata_schemas() {
+        let pool = setup_test_db().await;
+        let settings_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./settings_migrations").run(&settings_pool).await.unwrap();
+        let db = Database;
 
-        // inode番号とdevice_idでファイルを検索
-        let found_file = db
-            .find_file_by_inode(&pool, 12345, Some(67890))
+        let dir = db.add_directory(&pool, "/docs", "docs").await.unwrap();
+        // カテゴリは検索と同じく MIME タイプを優先して判定する（拡張子のない写真も画像）
+        for (id, path, mime_type) in [
+            ("photo", "/docs/IMG_0001", "image/jpeg"),
+            ("contract", "/docs/contracts/c.pdf", "application/pdf"),
+            ("note", "/docs/b.txt", "text/plain"),
+        ] {
+            let file = File { mime_type: Some(mime_type.to_string()), ..test_file(id, path, &dir.id) };
+            db.add_file(&pool, &file).await.unwrap();
+        }
+        let shoot = db.ensure_tag_path(&pool, "photos/shoot", "#000000").await.unwrap();
+        db.add_file_tag(&pool, "note", &shoot.id).await.unwrap();
+
+        let comment = db.create_custom_metadata_key(&settings_pool, &metadata_key("text_key", "text")).await.unwrap();
+        let license = db
+            .create_custom_metadata_key(&settings_pool, &CustomMetadataKey { is_required: true, ..metadata_key("select_key", "select") })
             .await
             .unwrap();
-        assert!(found_file.is_some());
-        assert_eq!(found_file.unwrap().id, "test_file");
-
-        // 存在しないinode番号で検索
-        let not_found = db
-            .find_file_by_inode(&pool, 99999, Some(67890))
+        let expiry = db
+            .create_custom_metadata_key(&settings_pool, &CustomMetadataKey { is_required: true, ..metadata_key("date_key", "date") })
             .await
             .unwrap();
-        assert!(not_found.is_none());
+        let schema = |name: &str, key_id: &str, conditions: Vec<SchemaCondition>| CustomMetadataSchema {
+            id: String::new(),
+            name: name.to_string(),
+            description: None,
+            key_ids: vec![key_id.to_string()],
+            conditions,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let photos = db
+            .create_custom_metadata_schema(
+                &settings_pool,
+                &schema("photos", &license.id, vec![SchemaCondition::Category { category: "image".to_string() }]),
+            )
+            .await
+            .unwrap();
+        db.create_custom_metadata_schema(
+            &settings_pool,
+            &schema("contracts", &expiry.id, vec![SchemaCondition::Directory { path: "/docs/contracts/".to_string() }]),
+        )
+        .await
+        .unwrap();
 
-        // 存在しないdevice_idで検索
-        let not_found = db
-            .find_file_by_inode(&pool, 12345, Some(99999))
+        let applicable = |file_id: &'static str| {
+            let (pool, settings_pool) = (pool.clone(), settings_pool.clone());
+            async move {
+                let keys = Database.get_applicable_custom_metadata_keys(&pool, &settings_pool, file_id).await.unwrap();
+                keys.into_iter().map(|k| k.id).collect::<Vec<_>>()
+            }
+        };
+        // キーは名前順
+        assert_eq!(applicable("photo").await, vec![license.id.clone(), comment.id.clone()]);
+        assert_eq!(applicable("contract").await, vec![expiry.id.clone(), comment.id.clone()]);
+        assert_eq!(applicable("note").await, vec![comment.id.clone()]);
+
+        // 上位のタグを条件にすると下位のタグが付いたファイルにも適用する
+        let mut photos = photos;
+        photos.conditions.push(SchemaCondition::Tag { tag_id: shoot.parent_id.clone().unwrap() });
+        db.update_custom_metadata_schema(&settings_pool, &photos).await.unwrap();
+        assert_eq!(applicable("note").await, vec![license.id.clone(), comment.id.clone()]);
+
+        // 必須のキーは適用されるファイルでだけ確かめる
+        let violations = db.audit_custom_metadata(&pool, &settings_pool).await.unwrap();
+        let mut required: Vec<(&str, &str)> = violations.iter().map(|v| (v.file_id.as_str(), v.key_id.as_str())).collect();
+        required.sort();
+        assert_eq!(
+            required,
+            vec![("contract", expiry.id.as_str()), ("note", license.id.as_str()), ("photo", license.id.as_str())]
+        );
+        db.set_custom_metadata_value(&pool, &settings_pool, "photo", &comment.id, Some("signed".to_string())).await.unwrap();
+        assert!(db.set_custom_metadata_value(&pool, &settings_pool, "photo", &expiry.id, None).await.is_ok());
+        assert!(db.set_custom_metadata_value(&pool, &settings_pool, "contract", &expiry.id, None).await.is_err());
+
+        // コピーと取り込みでも、対象外のファイルでは必須のキーを空にできる
+        let copied = db
+            .copy_custom_metadata(&pool, &settings_pool, "photo", &["note".to_string(), "contract".to_string()], None, true)
             .await
             .unwrap();
-        assert!(not_found.is_none());
+        assert_eq!((copied.file_count, copied.updated), (2, 1));
+        assert_eq!(copied.failures.iter().map(|f| f.file_id.as_str()).collect::<Vec<_>>(), vec!["contract"]);
+        // 書き込めない値のあったファイルは、先に書き込んだ値も取り消す
+        let comment_of = |file_id: &'static str| {
+            let pool = pool.clone();
+            let comment_id = comment.id.clone();
+            async move { Database.get_custom_metadata_value(&pool, file_id, &comment_id).await.unwrap().and_then(|v| v.value) }
+        };
+        assert_eq!(comment_of("note").await.as_deref(), Some("signed"));
+        assert_eq!(comment_of("contract").await, None);
+        let import_row = |row: usize, file_id: &str| MetadataImportRow {
+            row,
+            file_id: file_id.to_string(),
+            tags: Vec::new(),
+            values: vec![(expiry.id.clone(), " ".to_string())],
+        };
+        let imported = db
+            .import_file_metadata(&pool, &settings_pool, &[import_row(1, "note"), import_row(2, "contract")], "#000000", true, true)
+            .await
+            .unwrap();
+        assert!(imported[0].error.is_none());
+        assert!(imported[1].error.is_some());
+
+        // キーを削除するとスキーマからも外れる
+        db.delete_custom_metadata_key(&settings_pool, std::slice::from_ref(&pool), &license.id).await.unwrap();
+        let schemas = db.get_all_custom_metadata_schemas(&settings_pool).await.unwrap();
+        assert!(schemas.iter().find(|s| s.name == "photos").unwrap().key_ids.is_empty());
+        db.delete_custom_metadata_schema(&settings_pool, &schemas[0].id).await.unwrap();
+        assert_eq!(db.get_all_custom_metadata_schemas(&settings_pool).await.unwrap().len(), 1);
     }
 }
@@ -4,16 +4,21 @@ use crate::watcher::FileWatcher;
 use crate::settings;
 use crate::ShelfManager;
 use crate::file_manager::FileCategory;
-use sqlx::SqlitePool;
+use crate::file_manager::tombstones::{
+    compute_content_hash, purge_expired_tombstones, reattach_tombstone_by_content, reattach_tombstone_by_identity,
+};
+use crate::file_manager::post_index::PostIndexHook;
+use sqlx::{Row, SqlitePool};
 use tauri::State;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use walkdir::WalkDir;
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::fs::PermissionsExt;
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, serde::Serialize)]
 pub struct DirectoryRemovalResult {
@@ -22,17 +27,154 @@ pub struct DirectoryRemovalResult {
     pub deleted_tag_ids: Vec<String>,
 }
 
+/// 登録済みディレクトリとの重なり
+#[derive(Debug, serde::Serialize)]
+pub struct DirectoryOverlap {
+    /// 正規化したパス
+    pub path: String,
+    /// 同じパスで登録済みのディレクトリ
+    pub duplicate: Option<Directory>,
+    /// このパスを含む登録済みディレクトリ（最も深いもの）
+    pub parent: Option<Directory>,
+    /// このパスの配下にある登録済みディレクトリ
+    pub children: Vec<Directory>,
+}
+
+impl DirectoryOverlap {
+    pub fn has_overlap(&self) -> bool {
+        self.duplicate.is_some() || self.parent.is_some() || !self.children.is_empty()
+    }
+}
+
+/// 重なりがある場合の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlapResolution {
+    /// 外側のディレクトリひとつにまとめる
+    Merge,
+    /// それぞれを別の登録として残し、ファイルは最も深い登録に属させる
+    Convert,
+}
+
+/// 登録ディレクトリのパスを正規化する（シンボリックリンクや末尾の区切りを解決）
+fn normalize_directory_path(path: &str) -> Result<String, String> {
+    let canonical = fs::canonicalize(path).map_err(|e| format!("ディレクトリにアクセスできません: {e}"))?;
+    if !canonical.is_dir() {
+        return Err("ディレクトリではありません".to_string());
+    }
+    Ok(canonical.to_string_lossy().to_string())
+}
+
+/// パスを含む登録ディレクトリのうち、最も深いものを返す
+pub(crate) fn most_specific_directory<'a>(directories: &'a [Directory], path: &Path) -> Option<&'a Directory> {
+    directories
+        .iter()
+        .filter(|d| path.starts_with(&d.path))
+        .max_by_key(|d| Path::new(&d.path).components().count())
+}
+
+/// 登録済みディレクトリとの重なりを調べる
+pub(crate) fn find_directory_overlap(directories: &[Directory], path: &str) -> DirectoryOverlap {
+    let target = Path::new(path);
+    // 登録時に正規化されていない古いパスも実体で比較する
+    let resolved = |d: &Directory| fs::canonicalize(&d.path).unwrap_or_else(|_| PathBuf::from(&d.path));
+    
+    let duplicate = directories.iter().find(|d| resolved(d) == target).cloned();
+    let parent = directories
+        .iter()
+        .filter(|d| {
+            let root = resolved(d);
+            root != target && target.starts_with(&root)
+        })
+        .max_by_key(|d| resolved(d).components().count())
+        .cloned();
+    let children = directories
+        .iter()
+        .filter(|d| {
+            let root = resolved(d);
+            root != target && root.starts_with(target)
+        })
+        .cloned()
+        .collect();
+    
+    DirectoryOverlap {
+        path: path.to_string(),
+        duplicate,
+        parent,
+        children,
+    }
+}
+
+/// 追加しようとしているディレクトリが登録済みのものと重なっていないか確認する
+#[tauri::command]
+pub async fn check_directory_overlap(
+    pools: State<'_, ShelfManager>,
+    path: String,
+) -> Result<DirectoryOverlap, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let path = normalize_directory_path(&path)?;
+    let directories = db.get_directories(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    Ok(find_directory_overlap(&directories, &path))
+}
+
 /// ディレクトリを追加し、ファイルスキャンと監視を開始する
+/// 登録済みのディレクトリと重なる場合は `resolution` で扱いを指定する
 #[tauri::command]
 pub async fn add_directory(
     pools: State<'_, ShelfManager>,
     watcher: State<'_, Arc<Mutex<FileWatcher>>>,
     path: String,
     name: String,
+    resolution: Option<OverlapResolution>,
 ) -> Result<Directory, String> {
     let db = Database;
     let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
-    let directory = db.add_directory(&data_pool, &path, &name)
+    let path = normalize_directory_path(&path)?;
+    
+    let directories = db.get_directories(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let overlap = find_directory_overlap(&directories, &path);
+    
+    if let Some(duplicate) = &overlap.duplicate {
+        return Err(format!("このディレクトリは既に登録されています: {}", duplicate.path));
+    }
+    let resolution = match resolution {
+        Some(resolution) => resolution,
+        None if overlap.has_overlap() => {
+            return Err("登録済みのディレクトリと重なっています。統合するか別の登録として追加するかを指定してください".to_string());
+        }
+        None => OverlapResolution::Convert,
+    };
+    
+    // 外側が登録済みなら既にインデックスされているので、そのまま外側を使う
+    if let (OverlapResolution::Merge, Some(parent)) = (resolution, &overlap.parent) {
+        return Ok(parent.clone());
+    }
+    
+    // 統合する場合は内側の登録をこのディレクトリに吸収する
+    // 吸収する登録の監視は、外側の監視を始める前に止めておく
+    let absorbed_ids: Vec<String> = if resolution == OverlapResolution::Merge {
+        overlap.children.iter().map(|child| child.id.clone()).collect()
+    } else {
+        Vec::new()
+    };
+    {
+        let mut watcher_guard = watcher.lock().map_err(|e| e.to_string())?;
+        for absorbed_id in &absorbed_ids {
+            if let Err(e) = watcher_guard.unwatch_directory(absorbed_id) {
+                eprintln!("ファイル監視停止エラー: {e}");
+            }
+        }
+    }
+    
+    // 外側の登録からこの配下のファイルを引き継ぐ（IDと注釈は保たれる）
+    let parent_id = overlap.parent.as_ref().map(|parent| parent.id.clone());
+    let directory = db.add_directory_with_overlap(&data_pool, &path, &name, parent_id, &absorbed_ids)
         .await
         .map_err(|e| e.to_string())?;
     
@@ -61,6 +203,70 @@ pub async fn add_directory(
     Ok(directory)
 }
 
+/// 登録ディレクトリのディスク上の移動・名前変更を反映する
+/// IDとインデックス済みのファイル・注釈はそのまま引き継ぐ
+#[tauri::command]
+pub async fn relocate_directory(
+    pools: State<'_, ShelfManager>,
+    watcher: State<'_, Arc<Mutex<FileWatcher>>>,
+    directory_id: String,
+    new_path: String,
+    name: Option<String>,
+) -> Result<Directory, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let new_path = normalize_directory_path(&new_path)?;
+    
+    let directories = db.get_directories(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let directory = directories.iter()
+        .find(|d| d.id == directory_id)
+        .ok_or("ディレクトリが見つかりません")?
+        .clone();
+    
+    // 一緒に移動する配下の登録を除いて、移動先が他の登録と重ならないか確認する
+    let (moving, others): (Vec<Directory>, Vec<Directory>) = directories
+        .into_iter()
+        .partition(|d| Path::new(&d.path).starts_with(&directory.path));
+    if find_directory_overlap(&others, &new_path).has_overlap() {
+        return Err("移動先が登録済みのディレクトリと重なっています".to_string());
+    }
+    
+    {
+        let mut watcher_guard = watcher.lock().map_err(|e| e.to_string())?;
+        for d in &moving {
+            if let Err(e) = watcher_guard.unwatch_directory(&d.id) {
+                eprintln!("ファイル監視停止エラー: {e}");
+            }
+        }
+    }
+    
+    let name = name.unwrap_or_else(|| directory.name.clone());
+    db.relocate_directory(&data_pool, &directory_id, &new_path, &name)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    let directories = db.get_directories(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    {
+        let mut watcher_guard = watcher.lock().map_err(|e| e.to_string())?;
+        for d in directories.iter().filter(|d| moving.iter().any(|m| m.id == d.id)) {
+            if let Err(e) = watcher_guard.watch_directory(&d.id, &d.path) {
+                eprintln!("ファイル監視開始エラー: {e}");
+            }
+        }
+    }
+    
+    // 移動中の変更を取り込む
+    reconcile_subtree(&pools, Path::new(&new_path)).await?;
+    
+    directories.into_iter()
+        .find(|d| d.id == directory_id)
+        .ok_or_else(|| "ディレクトリが見つかりません".to_string())
+}
+
 /// ディレクトリを削除し、関連ファイルと孤児タグを削除する
 #[tauri::command]
 pub async fn remove_directory(
@@ -68,7 +274,7 @@ pub async fn remove_directory(
     watcher: State<'_, Arc<Mutex<FileWatcher>>>,
     id: String,
 ) -> Result<DirectoryRemovalResult, String> {
-    let _db = Database;
+    let db = Database;
     
     // ファイル監視を停止
     {
@@ -78,48 +284,34 @@ pub async fn remove_directory(
         }
     }
     
-    // トランザクション開始
     let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
-    let mut tx = data_pool.begin().await.map_err(|e| e.to_string())?;
     
-    // ディレクトリを削除（ON DELETE CASCADEにより関連ファイルも自動削除）
-    sqlx::query("DELETE FROM directories WHERE id = ?")
-        .bind(&id)
-        .execute(&mut *tx)
+    // 外側のディレクトリが登録されていれば、ファイルと注釈はそちらに引き継ぐ
+    let directories = db.get_directories(&data_pool)
         .await
         .map_err(|e| e.to_string())?;
+    let parent_id = directories.iter().find(|d| d.id == id).and_then(|directory| {
+        let others: Vec<Directory> = directories.iter()
+            .filter(|d| d.id != id)
+            .cloned()
+            .collect();
+        most_specific_directory(&others, Path::new(&directory.path)).map(|parent| parent.id.clone())
+    });
     
-    // 孤児タグを削除
-    let orphaned_tag_ids: Vec<String> = sqlx::query_scalar(
-        "SELECT t.id FROM tags t 
-         LEFT JOIN file_tags ft ON t.id = ft.tag_id 
-         WHERE ft.tag_id IS NULL"
-    )
-    .fetch_all(&mut *tx)
-    .await
-    .map_err(|e| e.to_string())?;
-
-    // 孤児タグを削除
-    let deleted_tags_count = orphaned_tag_ids.len();
-    if !orphaned_tag_ids.is_empty() {
-        for tag_id in &orphaned_tag_ids {
-            sqlx::query("DELETE FROM tags WHERE id = ?")
-                .bind(tag_id)
-                .execute(&mut *tx)
-                .await
-                .map_err(|e| e.to_string())?;
-        }
-        #[cfg(debug_assertions)]
+    // ディレクトリを削除し（外側の登録がなければ関連ファイルと墓標は ON DELETE CASCADE で削除）、孤児タグも削除する
+    let deleted_tag_ids = db.remove_directory(&data_pool, &id, parent_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    let deleted_tags_count = deleted_tag_ids.len();
+    #[cfg(debug_assertions)]
+    if deleted_tags_count > 0 {
         println!("削除されたタグ数: {deleted_tags_count}");
     }
     
-    // トランザクションをコミット
-    tx.commit().await.map_err(|e| e.to_string())?;
-    
     Ok(DirectoryRemovalResult {
         success: true,
         deleted_tags_count,
-        deleted_tag_ids: orphaned_tag_ids,
+        deleted_tag_ids,
     })
 }
 
@@ -141,6 +333,10 @@ pub async fn rescan_directory(
     pools: State<'_, ShelfManager>,
     directory_id: String,
 ) -> Result<(), String> {
+    rescan_directory_internal(&pools, &directory_id).await
+}
+
+async fn rescan_directory_internal(pools: &ShelfManager, directory_id: &str) -> Result<(), String> {
     let db = Database;
     let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
     
@@ -153,15 +349,36 @@ pub async fn rescan_directory(
         .find(|d| d.id == directory_id)
         .ok_or("ディレクトリが見つかりません")?;
     
-    // 既存のファイル情報を削除
-    sqlx::query("DELETE FROM files WHERE directory_id = ?")
+    // 既存のファイル情報を削除（注釈付きのファイルは墓標として残り、再スキャンで復元される）
+    db.remove_files_by_directory(&data_pool, directory_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    // ディレクトリを再スキャン
+    scan_directory(pools, directory_id, &directory.path).await
+}
+
+/// シンボリックリンクを辿るかどうかを設定し、ディレクトリを再スキャンする
+#[tauri::command]
+pub async fn set_directory_follow_symlinks(
+    pools: State<'_, ShelfManager>,
+    directory_id: String,
+    follow_symlinks: bool,
+) -> Result<(), String> {
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let result = sqlx::query("UPDATE directories SET follow_symlinks = ?, updated_at = ? WHERE id = ?")
+        .bind(follow_symlinks)
+        .bind(Utc::now())
         .bind(&directory_id)
         .execute(&data_pool)
         .await
         .map_err(|e| e.to_string())?;
     
-    // ディレクトリを再スキャン
-    scan_directory(&pools, &directory_id, &directory.path).await
+    if result.rows_affected() == 0 {
+        return Err("ディレクトリが見つかりません".to_string());
+    }
+    
+    rescan_directory_internal(&pools, &directory_id).await
 }
 
 /// ディレクトリをスキャンしてファイル情報をデータベースに追加する
@@ -170,13 +387,32 @@ pub async fn scan_directory(pools: &ShelfManager, directory_id: &str, path: &str
     let exclusion_manager = ExclusionPatternManager::new();
     exclusion_manager.refresh_patterns(pools.get_settings_pool()).await?;
     
-    let walker = WalkDir::new(path)
-        .follow_links(false)
+    // 保持期間切れの墓標を整理し、残っていればスキャン中に照合する
+    purge_expired_tombstones(pools).await?;
+    let has_tombstones: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM file_tombstones)")
+        .fetch_one(&pools.get_active_data_pool().map_err(|e| e.to_string())?)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    let follow_symlinks = directory_follows_symlinks(pools, directory_id).await?;
+    
+    // 配下に別途登録されたディレクトリはそちらのスキャンに任せる
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let nested_roots: Vec<PathBuf> = db.get_directories(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?
         .into_iter()
-        .filter_map(|e| e.ok());
+        .filter(|d| d.id != directory_id && Path::new(&d.path).starts_with(path))
+        .map(|d| PathBuf::from(d.path))
+        .collect();
     
-    for entry in walker {
-        let path = entry.path();
+    let post_index = PostIndexHook::load(pools).await?;
+    // 内容ハッシュでの照合は、移動したファイルが inode で墓標を取り戻してから行う（同じ内容のコピーに付けないため）
+    let mut unmatched = Vec::new();
+    
+    for (path, metadata) in IndexWalker::new(Path::new(path), follow_symlinks).skip_subtrees(nested_roots) {
+        let path = path.as_path();
         let path_str = path.to_string_lossy();
         
         // 除外パターンチェック
@@ -184,58 +420,356 @@ pub async fn scan_directory(pools: &ShelfManager, directory_id: &str, path: &str
             continue;
         }
         
-        if let Ok(metadata) = fs::metadata(path) {
-            // MIMEタイプの推定
-            let mime_type = infer_mime_type(path);
+        let file = build_file_entry(path, &metadata, directory_id);
+        
+        let file_id = db.add_file(&data_pool, &file)
+            .await
+            .map_err(|e| e.to_string())?;
+        
+        // 既存エントリを更新した場合は注釈がそのまま残っている
+        if has_tombstones && file_id == file.id {
+            let reattached = reattach_tombstone_by_identity(&data_pool, &file)
+                .await
+                .map_err(|e| e.to_string())?;
+            if !reattached && file.content_hash.is_some() {
+                unmatched.push(file.clone());
+            }
+        }
+        
+        let file = File { id: file_id, ..file };
+        post_index.run(pools, &file).await;
+    }
+    
+    for file in unmatched {
+        reattach_tombstone_by_content(&data_pool, &file)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    
+    Ok(())
+}
+
+/// 登録ディレクトリがシンボリックリンクを辿る設定かどうか
+async fn directory_follows_symlinks(pools: &ShelfManager, directory_id: &str) -> Result<bool, String> {
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let follow: Option<bool> = sqlx::query_scalar("SELECT follow_symlinks FROM directories WHERE id = ?")
+        .bind(directory_id)
+        .fetch_optional(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    Ok(follow.unwrap_or(false))
+}
+
+/// インデックス対象のエントリを列挙するイテレータ
+/// リンクを辿らない場合はリンク自体のメタデータを、辿る場合はリンク先のメタデータを返す。
+/// ループや、別のリンク経由で同じディレクトリに到達した場合は一度しか走査しない
+pub(crate) struct IndexWalker {
+    walker: walkdir::IntoIter,
+    visited_dirs: HashSet<(u64, u64)>,
+    skipped_subtrees: Vec<PathBuf>,
+}
+
+impl IndexWalker {
+    pub(crate) fn new(root: &Path, follow_links: bool) -> Self {
+        Self {
+            walker: WalkDir::new(root).follow_links(follow_links).into_iter(),
+            visited_dirs: HashSet::new(),
+            skipped_subtrees: Vec::new(),
+        }
+    }
+    
+    /// 指定したパス（とその配下）を列挙から除外する
+    pub(crate) fn skip_subtrees(mut self, paths: Vec<PathBuf>) -> Self {
+        self.skipped_subtrees = paths;
+        self
+    }
+}
+
+impl Iterator for IndexWalker {
+    type Item = (PathBuf, fs::Metadata);
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.walker.next()? {
+                Ok(entry) => entry,
+                Err(e) => {
+                    if let Some(ancestor) = e.loop_ancestor() {
+                        eprintln!(
+                            "シンボリックリンクのループを検出したためスキップしました: {} -> {}",
+                            e.path().map(|p| p.display().to_string()).unwrap_or_default(),
+                            ancestor.display()
+                        );
+                        continue;
+                    }
+                    // リンクを辿る設定でもリンク切れはリンクとして記録する
+                    if let Some(path) = e.path() {
+                        if let Ok(metadata) = fs::symlink_metadata(path) {
+                            if metadata.file_type().is_symlink() {
+                                return Some((path.to_path_buf(), metadata));
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
             
-            // ファイルパーミッション（8進数）
-            let permissions = format!("{:o}", metadata.permissions().mode() & 0o777);
+            if entry.depth() > 0 && self.skipped_subtrees.iter().any(|p| p == entry.path()) {
+                if entry.file_type().is_dir() {
+                    self.walker.skip_current_dir();
+                }
+                continue;
+            }
             
-            let file = File {
-                id: Uuid::new_v4().to_string(),
-                path: path.to_string_lossy().to_string(),
-                name: path.file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("unknown")
-                    .to_string(),
-                directory_id: directory_id.to_string(),
-                size: metadata.len() as i64,
-                file_type: path.extension()
-                    .and_then(|ext| ext.to_str())
-                    .map(|s| s.to_string()),
-                created_at: metadata.created()
-                    .ok()
-                    .map(DateTime::from),
-                modified_at: metadata.modified()
-                    .ok()
-                    .map(DateTime::from),
-                birth_time: None, // macOS固有の実装が必要
-                inode: Some(metadata.ino() as i64),
-                is_directory: metadata.is_dir(),
-                created_at_db: Utc::now(),
-                updated_at_db: Utc::now(),
-                file_size: Some(metadata.len() as i64),
-                mime_type,
-                permissions: Some(permissions),
-                owner_uid: Some(metadata.uid() as i64),
-                group_gid: Some(metadata.gid() as i64),
-                hard_links: Some(metadata.nlink() as i64),
-                device_id: Some(metadata.dev() as i64),
-                last_accessed: metadata.accessed()
-                    .ok()
-                    .map(DateTime::from),
-                metadata: extract_metadata(path),
+            let Ok(metadata) = entry.metadata() else {
+                continue;
             };
             
-            let db = Database;
-            let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
-            db.add_file(&data_pool, &file)
-                .await
-                .map_err(|e| e.to_string())?;
+            if metadata.is_dir() && !self.visited_dirs.insert((metadata.dev(), metadata.ino())) {
+                // 既に走査済みのディレクトリ。リンク自体は記録するが中には入らない
+                self.walker.skip_current_dir();
+                if entry.path_is_symlink() {
+                    return Some((entry.into_path(), metadata));
+                }
+                continue;
+            }
+            
+            return Some((entry.into_path(), metadata));
         }
     }
+}
+
+/// シンボリックリンクの情報（リンクかどうか, リンク先, リンク切れかどうか）
+pub(crate) fn symlink_details(path: &Path) -> (bool, Option<String>, bool) {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            let target = fs::read_link(path)
+                .ok()
+                .map(|target| target.to_string_lossy().to_string());
+            let is_broken = fs::metadata(path).is_err();
+            (true, target, is_broken)
+        }
+        _ => (false, None, false),
+    }
+}
+
+/// ディスク上のエントリからファイル情報を組み立てる
+/// `metadata` はリンクを辿らない場合はリンク自体、辿る場合はリンク先のものを渡す
+pub(crate) fn build_file_entry(path: &Path, metadata: &fs::Metadata, directory_id: &str) -> File {
+    // ファイルパーミッション（8進数）
+    let permissions = format!("{:o}", metadata.permissions().mode() & 0o777);
+    let (is_symlink, symlink_target, is_broken_link) = symlink_details(path);
     
-    Ok(())
+    File {
+        id: Uuid::new_v4().to_string(),
+        path: path.to_string_lossy().to_string(),
+        name: path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string(),
+        directory_id: directory_id.to_string(),
+        size: metadata.len() as i64,
+        file_type: path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|s| s.to_string()),
+        created_at: metadata.created()
+            .ok()
+            .map(DateTime::from),
+        modified_at: metadata.modified()
+            .ok()
+            .map(DateTime::from),
+        // Linuxではstatx経由で取得され、未対応のファイルシステムではNoneになる
+        birth_time: metadata.created()
+            .ok()
+            .map(DateTime::from),
+        inode: Some(metadata.ino() as i64),
+        is_directory: metadata.is_dir(),
+        created_at_db: Utc::now(),
+        updated_at_db: Utc::now(),
+        file_size: Some(metadata.len() as i64),
+        // MIMEタイプの推定
+        mime_type: infer_mime_type(path),
+        permissions: Some(permissions),
+        owner_uid: Some(metadata.uid() as i64),
+        group_gid: Some(metadata.gid() as i64),
+        hard_links: Some(metadata.nlink() as i64),
+        device_id: Some(metadata.dev() as i64),
+        last_accessed: metadata.accessed()
+            .ok()
+            .map(DateTime::from),
+        // リンクを辿らない場合はリンク先の内容を読まない
+        metadata: if is_symlink && metadata.file_type().is_symlink() {
+            None
+        } else {
+            extract_metadata(path)
+        },
+        content_hash: compute_content_hash(path, metadata),
+        is_symlink,
+        symlink_target,
+        is_broken_link,
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ReconcileResult {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+impl ReconcileResult {
+    pub fn has_changes(&self) -> bool {
+        self.added + self.updated + self.removed > 0
+    }
+}
+
+/// 再照合で比べるインデックス済みのエントリ
+struct IndexedEntry {
+    size: i64,
+    modified_at: Option<DateTime<Utc>>,
+    birth_time: Option<DateTime<Utc>>,
+}
+
+/// 指定パス配下のディスク上の状態とインデックスを照合し、差分だけを反映する
+/// 監視イベントを取りこぼした場合の復旧に使う
+pub async fn reconcile_subtree(pools: &ShelfManager, root: &Path) -> Result<ReconcileResult, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let exclusion_manager = ExclusionPatternManager::new();
+    exclusion_manager.refresh_patterns(pools.get_settings_pool()).await?;
+    
+    let root_str = root.to_string_lossy().trim_end_matches('/').to_string();
+    let prefix = format!("{root_str}/");
+    
+    // インデックス済みのエントリ（パス -> サイズ・更新日時・作成日時）
+    let rows = sqlx::query(
+        "SELECT path, size, modified_at, birth_time FROM files WHERE path = ? OR substr(path, 1, ?) = ?"
+    )
+    .bind(&root_str)
+    .bind(prefix.chars().count() as i64)
+    .bind(&prefix)
+    .fetch_all(&data_pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    
+    let mut indexed: HashMap<String, IndexedEntry> = rows
+        .into_iter()
+        .map(|row| {
+            let entry = IndexedEntry {
+                size: row.get("size"),
+                modified_at: row.get("modified_at"),
+                birth_time: row.get("birth_time"),
+            };
+            (row.get("path"), entry)
+        })
+        .collect();
+    
+    let mut result = ReconcileResult::default();
+    // 内容ハッシュでの照合は、消えたエントリを墓標にしてから行う
+    let mut unmatched = Vec::new();
+    
+    if root.exists() {
+        let directories = db.get_directories(&data_pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        let follow_symlinks = most_specific_directory(&directories, root)
+            .map(|d| d.follow_symlinks)
+            .unwrap_or(false);
+        let post_index = PostIndexHook::load(pools).await?;
+        
+        for (path, metadata) in IndexWalker::new(root, follow_symlinks) {
+            let path = path.as_path();
+            let path_str = path.to_string_lossy().to_string();
+            
+            if exclusion_manager.should_exclude(&path_str) {
+                continue;
+            }
+            
+            match indexed.remove(&path_str) {
+                Some(IndexedEntry { size, modified_at, birth_time }) => {
+                    let disk_modified: Option<DateTime<Utc>> = metadata.modified().ok().map(DateTime::from);
+                    let modified_changed = disk_modified.map(|t| t.timestamp()) != modified_at.map(|t| t.timestamp());
+                    if size != metadata.len() as i64 || modified_changed {
+                        db.update_file_metadata(&data_pool, &path_str, &metadata)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        db.update_file_content_hash(&data_pool, &path_str, compute_content_hash(path, &metadata))
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        result.updated += 1;
+                    } else if birth_time.is_none() && metadata.created().is_ok() {
+                        // 作成日時を記録する前にインデックスした行は、ここで記録しておく
+                        db.update_file_metadata(&data_pool, &path_str, &metadata)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    }
+                }
+                None => {
+                    // 同一性（device_id + inode + 作成日時）が一致し、元のパスが消えていれば移動として扱う
+                    let birth_time = metadata.created().ok().map(DateTime::from);
+                    let moved = db.find_file_by_identity(&data_pool, Some(metadata.dev() as i64), metadata.ino() as i64, birth_time)
+                        .await
+                        .map_err(|e| e.to_string())?
+                        .filter(|existing| !Path::new(&existing.path).exists());
+                    if let Some(existing) = moved {
+                        let name = path.file_name()
+                            .and_then(|n| n.to_str())
+                            .unwrap_or("unknown");
+                        db.update_file_path(&data_pool, &existing.id, &path_str, name)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        if existing.birth_time.is_none() {
+                            db.update_file_metadata(&data_pool, &path_str, &metadata)
+                                .await
+                                .map_err(|e| e.to_string())?;
+                        }
+                        indexed.remove(&existing.path);
+                        result.updated += 1;
+                        continue;
+                    }
+                    
+                    // 入れ子の登録がある場合は最も深い登録ディレクトリに属させる
+                    let Some(directory) = most_specific_directory(&directories, path) else {
+                        continue;
+                    };
+                    let file = build_file_entry(path, &metadata, &directory.id);
+                    let file_id = db.add_file(&data_pool, &file)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    if file_id == file.id {
+                        let reattached = reattach_tombstone_by_identity(&data_pool, &file)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        if !reattached && file.content_hash.is_some() {
+                            unmatched.push(file.clone());
+                        }
+                    }
+                    let file = File { id: file_id, ..file };
+                    post_index.run(pools, &file).await;
+                    result.added += 1;
+                }
+            }
+        }
+    }
+    
+    // ディスク上に存在しなくなったエントリを削除（注釈付きは墓標として残る）
+    for path in indexed.into_keys() {
+        if exclusion_manager.should_exclude(&path) || fs::symlink_metadata(&path).is_ok() {
+            continue;
+        }
+        db.remove_file_by_path(&data_pool, &path)
+            .await
+            .map_err(|e| e.to_string())?;
+        result.removed += 1;
+    }
+    
+    for file in unmatched {
+        reattach_tombstone_by_content(&data_pool, &file)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    
+    Ok(result)
 }
 
 /// ディレクトリツリーを分析して自動タグ付けを行う
@@ -437,12 +971,13 @@ async fn get_or_create_directory_file_entry(
     let now = Utc::now();
     
     // ディレクトリメタデータを取得
-    let (size, created_at, modified_at, inode, permissions, owner_uid, group_gid, hard_links, device_id, last_accessed) = 
+    let (size, created_at, modified_at, birth_time, inode, permissions, owner_uid, group_gid, hard_links, device_id, last_accessed) = 
         if let Ok(metadata) = std::fs::metadata(directory_path) {
             (
                 metadata.len() as i64,
                 metadata.created().ok().map(DateTime::from),
                 metadata.modified().ok().map(DateTime::from),
+                metadata.created().ok().map(DateTime::from),
                 Some(metadata.ino() as i64),
                 Some(format!("{:o}", metadata.mode() & 0o777)),
                 Some(metadata.uid() as i64),
@@ -452,7 +987,7 @@ async fn get_or_create_directory_file_entry(
                 metadata.accessed().ok().map(DateTime::from),
             )
         } else {
-            (0, None::<DateTime<Utc>>, None::<DateTime<Utc>>, None, None, None, None, None, None, None::<DateTime<Utc>>)
+            (0, None::<DateTime<Utc>>, None::<DateTime<Utc>>, None::<DateTime<Utc>>, None, None, None, None, None, None, None::<DateTime<Utc>>)
         };
     
     // ディレクトリをfilesテーブルに挿入
@@ -467,7 +1002,7 @@ async fn get_or_create_directory_file_entry(
     .bind(None::<String>) // file_type (ディレクトリなのでnull)
     .bind(created_at)
     .bind(modified_at)
-    .bind(None::<DateTime<Utc>>) // birth_time
+    .bind(birth_time)
     .bind(inode)
     .bind(true) // is_directory
     .bind(now)
@@ -717,4 +1252,235 @@ fn extract_audio_metadata(file_path: &std::path::Path) -> Result<serde_json::Val
     }
     
     Ok(metadata)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_index_walker_records_symlinks_without_following() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("real")).unwrap();
+        fs::write(root.join("real/file.txt"), "hello").unwrap();
+        symlink(root.join("real"), root.join("link")).unwrap();
+        symlink(root.join("missing.txt"), root.join("broken")).unwrap();
+
+        let entries: Vec<_> = IndexWalker::new(root, false).collect();
+        let paths: Vec<_> = entries.iter().map(|(p, _)| p.clone()).collect();
+
+        // リンクを辿らないのでリンク先のファイルは一度だけ
+        assert!(paths.contains(&root.join("link")));
+        assert!(paths.contains(&root.join("broken")));
+        assert!(!paths.contains(&root.join("link/file.txt")));
+
+        let (is_symlink, target, is_broken) = symlink_details(&root.join("broken"));
+        assert!(is_symlink);
+        assert_eq!(target, Some(root.join("missing.txt").to_string_lossy().to_string()));
+        assert!(is_broken);
+
+        let (is_symlink, _, is_broken) = symlink_details(&root.join("link"));
+        assert!(is_symlink);
+        assert!(!is_broken);
+    }
+
+    #[test]
+    fn test_index_walker_follows_links_without_looping() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("real")).unwrap();
+        fs::write(root.join("real/file.txt"), "hello").unwrap();
+        // 自分自身の祖先を指すループ
+        symlink(root, root.join("real/loop")).unwrap();
+        symlink(root.join("missing.txt"), root.join("broken")).unwrap();
+
+        let paths: Vec<_> = IndexWalker::new(root, true).map(|(p, _)| p).collect();
+
+        assert!(paths.contains(&root.join("real/file.txt")));
+        assert!(paths.contains(&root.join("broken")));
+        assert!(!paths.iter().any(|p| p.starts_with(root.join("real/loop"))));
+    }
+
+    fn test_directory(id: &str, path: &Path) -> Directory {
+        Directory {
+            id: id.to_string(),
+            path: path.to_string_lossy().to_string(),
+            name: id.to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            follow_symlinks: false,
+        }
+    }
+
+    #[test]
+    fn test_find_directory_overlap() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::create_dir_all(root.join("ab")).unwrap();
+
+        let directories = vec![
+            test_directory("a", &root.join("a")),
+            test_directory("c", &root.join("a/b/c")),
+            test_directory("ab", &root.join("ab")),
+        ];
+
+        let overlap = find_directory_overlap(&directories, &root.join("a/b").to_string_lossy());
+        assert!(overlap.duplicate.is_none());
+        assert_eq!(overlap.parent.map(|d| d.id), Some("a".to_string()));
+        let children: Vec<_> = overlap.children.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(children, vec!["c"]);
+
+        let overlap = find_directory_overlap(&directories, &root.join("a").to_string_lossy());
+        assert_eq!(overlap.duplicate.map(|d| d.id), Some("a".to_string()));
+
+        // 名前の前方一致だけでは重なりとみなさない
+        fs::create_dir_all(root.join("abc")).unwrap();
+        let overlap = find_directory_overlap(&directories, &root.join("abc").to_string_lossy());
+        assert!(!overlap.has_overlap());
+    }
+
+    #[test]
+    fn test_most_specific_directory() {
+        let directories = vec![
+            test_directory("outer", Path::new("/data")),
+            test_directory("inner", Path::new("/data/photos")),
+        ];
+
+        let resolve = |path: &str| most_specific_directory(&directories, Path::new(path)).map(|d| d.id.as_str());
+        assert_eq!(resolve("/data/photos/2024/a.jpg"), Some("inner"));
+        assert_eq!(resolve("/data/photos"), Some("inner"));
+        assert_eq!(resolve("/data/photos-old/a.jpg"), Some("outer"));
+        assert_eq!(resolve("/other/a.jpg"), None);
+    }
+
+    #[test]
+    fn test_index_walker_skips_nested_roots() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("nested/deep")).unwrap();
+        fs::write(root.join("nested/deep/file.txt"), "hello").unwrap();
+        fs::write(root.join("top.txt"), "hello").unwrap();
+
+        let paths: Vec<_> = IndexWalker::new(root, false)
+            .skip_subtrees(vec![root.join("nested")])
+            .map(|(p, _)| p)
+            .collect();
+
+        assert!(paths.contains(&root.join("top.txt")));
+        assert!(!paths.iter().any(|p| p.starts_with(root.join("nested"))));
+    }
+
+    /// パスのインデックス済みエントリに付いているタグ名
+    async fn tag_names_at(pool: &sqlx::SqlitePool, path: &Path) -> Vec<String> {
+        let file_id: String = sqlx::query_scalar("SELECT id FROM files WHERE path = ?")
+            .bind(path.to_string_lossy())
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        Database.get_file_tags(pool, &file_id).await.unwrap().into_iter().map(|t| t.name).collect()
+    }
+
+    #[tokio::test]
+    async fn test_scan_reattaches_tombstone_by_identity_before_content() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("report.txt"), "quarterly numbers").unwrap();
+
+        let pools = crate::database::tests::test_shelf_manager().await;
+        let data_pool = pools.get_active_data_pool().unwrap();
+        let db = Database;
+        let directory = db.add_directory(&data_pool, &root.to_string_lossy(), "root").await.unwrap();
+        scan_directory(&pools, &directory.id, &directory.path).await.unwrap();
+
+        let tag = db.create_tag(&data_pool, "finance", "#00ff00").await.unwrap();
+        let file_id: String = sqlx::query_scalar("SELECT id FROM files WHERE path = ?")
+            .bind(root.join("report.txt").to_string_lossy())
+            .fetch_one(&data_pool)
+            .await
+            .unwrap();
+        db.add_file_tag(&data_pool, &file_id, &tag.id).await.unwrap();
+
+        // 監視していない間に移動され、同じ内容のコピーも作られた
+        fs::rename(root.join("report.txt"), root.join("moved.txt")).unwrap();
+        fs::copy(root.join("moved.txt"), root.join("copy.txt")).unwrap();
+        db.remove_file_by_path(&data_pool, &root.join("report.txt").to_string_lossy()).await.unwrap();
+
+        scan_directory(&pools, &directory.id, &directory.path).await.unwrap();
+        assert_eq!(tag_names_at(&data_pool, &root.join("moved.txt")).await, vec!["finance"]);
+        assert!(tag_names_at(&data_pool, &root.join("copy.txt")).await.is_empty());
+        assert!(db.get_file_tombstones(&data_pool).await.unwrap().is_empty());
+
+        // 消えた後に別の inode で同じ内容が書き戻された場合は内容ハッシュで戻す
+        fs::remove_file(root.join("moved.txt")).unwrap();
+        db.remove_file_by_path(&data_pool, &root.join("moved.txt").to_string_lossy()).await.unwrap();
+        fs::write(root.join("restored.txt"), "quarterly numbers").unwrap();
+        scan_directory(&pools, &directory.id, &directory.path).await.unwrap();
+        assert_eq!(tag_names_at(&data_pool, &root.join("restored.txt")).await, vec!["finance"]);
+        assert!(tag_names_at(&data_pool, &root.join("copy.txt")).await.is_empty());
+        assert!(db.get_file_tombstones(&data_pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_subtree_applies_missed_changes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let sub = root.join("sub");
+        fs::create_dir(&sub).unwrap();
+        fs::write(sub.join("a.txt"), "alpha").unwrap();
+        fs::write(sub.join("b.txt"), "beta").unwrap();
+        fs::write(sub.join("c.txt"), "gamma").unwrap();
+        fs::write(root.join("outside.txt"), "outside").unwrap();
+
+        let pools = crate::database::tests::test_shelf_manager().await;
+        let data_pool = pools.get_active_data_pool().unwrap();
+        let db = Database;
+        let directory = db.add_directory(&data_pool, &root.to_string_lossy(), "root").await.unwrap();
+        scan_directory(&pools, &directory.id, &directory.path).await.unwrap();
+
+        let tag = db.create_tag(&data_pool, "draft", "#0000ff").await.unwrap();
+        let file_id: String = sqlx::query_scalar("SELECT id FROM files WHERE path = ?")
+            .bind(sub.join("a.txt").to_string_lossy())
+            .fetch_one(&data_pool)
+            .await
+            .unwrap();
+        db.add_file_tag(&data_pool, &file_id, &tag.id).await.unwrap();
+
+        // 監視イベントを取りこぼしている間に、名前の変更・削除・追加・変更があった
+        fs::rename(sub.join("a.txt"), sub.join("renamed.txt")).unwrap();
+        fs::remove_file(sub.join("b.txt")).unwrap();
+        fs::write(sub.join("new.txt"), "new").unwrap();
+        fs::write(sub.join("c.txt"), "gamma, longer").unwrap();
+        // 照合の対象外のパスは変更があってもそのまま
+        fs::remove_file(root.join("outside.txt")).unwrap();
+
+        let result = reconcile_subtree(&pools, &sub).await.unwrap();
+        assert_eq!((result.added, result.updated, result.removed), (1, 2, 1));
+
+        let indexed: HashSet<String> = sqlx::query_scalar("SELECT path FROM files WHERE is_directory = 0")
+            .fetch_all(&data_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .collect();
+        let expected: HashSet<String> = [root.join("outside.txt"), sub.join("renamed.txt"), sub.join("c.txt"), sub.join("new.txt")]
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(indexed, expected);
+
+        // 名前を変えたファイルは同じエントリとしてタグを保つ
+        assert_eq!(tag_names_at(&data_pool, &sub.join("renamed.txt")).await, vec!["draft"]);
+        let size: i64 = sqlx::query_scalar("SELECT size FROM files WHERE path = ?")
+            .bind(sub.join("c.txt").to_string_lossy())
+            .fetch_one(&data_pool)
+            .await
+            .unwrap();
+        assert_eq!(size, "gamma, longer".len() as i64);
+
+        // 変更がなければ何も反映しない
+        assert!(!reconcile_subtree(&pools, &sub).await.unwrap().has_changes());
+    }
+
+}
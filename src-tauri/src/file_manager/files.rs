@@ -1,17 +1,19 @@
-use crate::database::{Database, DatabaseTrait, File, Tag};
+use crate::database::{file_from_row, CustomMetadataKey, CustomMetadataValue, Database, DatabaseTrait, Directory, File, Tag};
 use crate::settings;
 use crate::ShelfManager;
-use sqlx::{SqlitePool, Row};
+use sqlx::SqlitePool;
 use tauri::State;
 use chrono::Utc;
+use std::collections::HashMap;
 use std::process::Command;
 use regex::{Regex, RegexBuilder};
-use tera::{Context, Tera};
+use tera::Context;
 use thiserror::Error;
 use serde::Serialize;
 
 // Re-export structs and enums from parent module
 use super::FileWithTags;
+use super::template_filters::{parse_datetime, render_one_off};
 
 #[derive(Serialize)]
 pub struct DeleteResult {
@@ -983,39 +985,132 @@ pub fn get_tag_name(tag: exif::Tag) -> String {
 
 // ===== リネーム機能 =====
 
-// カスタムメタデータを取得してJSONに変換するヘルパー関数
-async fn get_custom_metadata_for_file(pools: &ShelfManager, file_id: &str) -> Result<serde_json::Value, RenameError> {
+// カスタムメタデータをファイルごとに、キー名で引けるJSONオブジェクトにする（値はキーの型に合わせる）
+fn custom_metadata_json(keys: &[CustomMetadataKey], values: Vec<CustomMetadataValue>) -> HashMap<String, serde_json::Value> {
+    let keys_by_id: HashMap<&str, &CustomMetadataKey> = keys.iter().map(|key| (key.id.as_str(), key)).collect();
+    let mut objects: HashMap<String, serde_json::Map<String, serde_json::Value>> = HashMap::new();
+    for value in values {
+        if let (Some(key), Some(val)) = (keys_by_id.get(value.key_id.as_str()), value.value) {
+            objects.entry(value.file_id).or_default().insert(key.name.clone(), key.typed_value(&val));
+        }
+    }
+    objects.into_iter().map(|(file_id, object)| (file_id, serde_json::Value::Object(object))).collect()
+}
+
+// 複数ファイルのカスタムメタデータをまとめて取得するヘルパー関数（値のないファイルは含まない）
+async fn get_custom_metadata_for_files(pools: &ShelfManager, file_ids: &[String]) -> Result<HashMap<String, serde_json::Value>, RenameError> {
     let db = Database;
     let pool = pools.get_active_data_pool()
         .map_err(|e| RenameError::Database(e.to_string()))?;
-    
-    let custom_values = db.get_custom_metadata_values_by_file(&pool, file_id).await
+
+    // 計算フィールドを最新にしてから読む
+    crate::computed_metadata::refresh_computed_metadata(&pool, &pools.settings_pool).await
+        .map_err(RenameError::Database)?;
+
+    let custom_values = db.get_custom_metadata_values_by_files(&pool, file_ids).await
         .map_err(|e| RenameError::Database(e.to_string()))?;
-    
+
     let custom_keys = db.get_all_custom_metadata_keys(&pools.settings_pool).await
         .map_err(|e| RenameError::Database(e.to_string()))?;
-    
-    // キーIDから名前へのマッピングを作成
-    let key_name_map: std::collections::HashMap<String, String> = custom_keys
-        .into_iter()
-        .map(|key| (key.id, key.name))
-        .collect();
-    
-    // カスタムメタデータをJSONオブジェクトに変換
-    let mut custom_metadata = serde_json::Map::new();
-    for value in custom_values {
-        if let Some(key_name) = key_name_map.get(&value.key_id) {
-            if let Some(val) = value.value {
-                custom_metadata.insert(key_name.clone(), serde_json::Value::String(val));
-            }
+
+    Ok(custom_metadata_json(&custom_keys, custom_values))
+}
+
+// カスタムメタデータを取得してJSONに変換するヘルパー関数
+async fn get_custom_metadata_for_file(pools: &ShelfManager, file_id: &str) -> Result<serde_json::Value, RenameError> {
+    let mut custom_metadata = get_custom_metadata_for_files(pools, &[file_id.to_string()]).await?;
+    Ok(custom_metadata.remove(file_id).unwrap_or_else(|| serde_json::json!({})))
+}
+
+// EXIF・音声タグの値を文字列にする（配列は先頭の要素、EXIF の文字列は末尾の空白やNULを除く）
+fn metadata_text(value: &serde_json::Value) -> Option<String> {
+    let text = match value {
+        serde_json::Value::String(s) => s.trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string(),
+        serde_json::Value::Number(n) => n.to_string(),
+        serde_json::Value::Array(items) => return items.first().and_then(metadata_text),
+        _ => return None,
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+// 登録したディレクトリからファイルまでのフォルダ名（登録したディレクトリの外なら親フォルダすべて）
+fn parent_folder_names(path: &str, root: Option<&Directory>) -> Vec<String> {
+    let parent = match std::path::Path::new(path).parent() {
+        Some(parent) => parent,
+        None => return Vec::new(),
+    };
+    let relative = root
+        .and_then(|root| parent.strip_prefix(&root.path).ok())
+        .unwrap_or(parent);
+    relative
+        .components()
+        .filter_map(|c| match c {
+            std::path::Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+// 撮影日時・カメラ・画像サイズ・音声タグを個別の変数にする（値がなければ変数を作らない）
+// 同じ値を media.* にも入れる。トップレベルの変数は同名のカスタムメタデータで上書きされるが、media.* は上書きされない
+fn insert_media_variables(context: &mut Context, metadata: &serde_json::Value) {
+    let mut media = serde_json::Map::new();
+    let exif = &metadata["exif"];
+    let capture = ["DateTimeOriginal", "DateTimeDigitized", "DateTime"]
+        .iter()
+        .find_map(|tag| metadata_text(&exif[*tag]).and_then(|text| parse_datetime(&text)));
+    if let Some(capture) = capture {
+        for (name, format) in [
+            ("capture_datetime", "%Y-%m-%dT%H:%M:%S"),
+            ("capture_date", "%Y-%m-%d"),
+            ("capture_time", "%H%M%S"),
+            ("capture_year", "%Y"),
+            ("capture_month", "%m"),
+            ("capture_day", "%d"),
+        ] {
+            media.insert(name.to_string(), capture.format(format).to_string().into());
         }
     }
-    
-    Ok(serde_json::Value::Object(custom_metadata))
+    if let Some(make) = metadata_text(&exif["Make"]) {
+        media.insert("camera_make".to_string(), make.into());
+    }
+    if let Some(model) = metadata_text(&exif["Model"]) {
+        media.insert("camera_model".to_string(), model.into());
+    }
+    for (name, tags) in [("width", ["PixelXDimension", "ImageWidth"]), ("height", ["PixelYDimension", "ImageLength"])] {
+        let size = tags.iter().find_map(|tag| metadata_text(&exif[*tag]).and_then(|text| text.parse::<u64>().ok()));
+        if let Some(size) = size {
+            media.insert(name.to_string(), size.into());
+        }
+    }
+
+    let audio_tags = &metadata["audio"]["tags"];
+    for name in ["artist", "album", "title"] {
+        if let Some(value) = metadata_text(&audio_tags[name]) {
+            media.insert(name.to_string(), value.into());
+        }
+    }
+    if let Some(track) = audio_tags["track"].as_u64() {
+        media.insert("track".to_string(), track.into());
+    }
+
+    for (name, value) in &media {
+        context.insert(name, value);
+    }
+    context.insert("media", &media);
 }
 
 // Teraテンプレートコンテキストを作成するヘルパー関数
-fn create_template_context(file: &File, tags: &[String], metadata: &serde_json::Value, custom_metadata: &serde_json::Value) -> Context {
+// root はファイルが属する登録したディレクトリ
+// カスタムメタデータはキー名のトップレベル変数にもなり、filename や artist など同名の変数より優先される
+// （created_year などの日時情報だけは後から入れるので上書きされない）。元の値は file.* や media.* で参照する
+pub(crate) fn create_template_context(
+    file: &File,
+    root: Option<&Directory>,
+    tags: &[String],
+    metadata: &serde_json::Value,
+    custom_metadata: &serde_json::Value,
+) -> Context {
     let mut context = Context::new();
     
     // ファイル情報
@@ -1024,7 +1119,25 @@ fn create_template_context(file: &File, tags: &[String], metadata: &serde_json::
     context.insert("name_without_ext", &file.name.rsplit('.').nth(1).map(|s| s.to_string()).unwrap_or_else(|| file.name.clone()));
     context.insert("extension", &file.name.split('.').next_back().unwrap_or(""));
     context.insert("path", &file.path);
-    context.insert("directory", &file.directory_id);
+    context.insert("directory_id", &file.directory_id);
+
+    // フォルダ情報（directory は親フォルダの名前）
+    let folders = parent_folder_names(&file.path, root);
+    let parent_folder = std::path::Path::new(&file.path)
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    context.insert("directory", &parent_folder);
+    context.insert("parent_folder", &parent_folder);
+    if folders.len() >= 2 {
+        context.insert("grandparent_folder", &folders[folders.len() - 2]);
+    }
+    context.insert("parent_folders", &folders);
+    if let Some(root) = root {
+        context.insert("root_name", &root.name);
+        context.insert("root_path", &root.path);
+    }
     
     // タグ情報
     context.insert("tags", tags);
@@ -1033,6 +1146,7 @@ fn create_template_context(file: &File, tags: &[String], metadata: &serde_json::
     
     // ファイルメタデータ（EXIF、オーディオタグなど）
     context.insert("metadata", metadata);
+    insert_media_variables(&mut context, metadata);
     
     // カスタムメタデータ（ユーザー設定）
     context.insert("custom_metadata", custom_metadata);
@@ -1087,6 +1201,16 @@ fn build_safe_regex(pattern: &str) -> RenameResult<Regex> {
         .map_err(|e| RenameError::InvalidRegex(e.to_string()))
 }
 
+// 登録したディレクトリをIDで引けるようにするヘルパー関数
+async fn get_root_directories(pools: &ShelfManager) -> RenameResult<HashMap<String, Directory>> {
+    let data_pool = pools.get_active_data_pool()
+        .map_err(|e| RenameError::Database(e.to_string()))?;
+    let directories = Database.get_directories(&data_pool)
+        .await
+        .map_err(|e| RenameError::Database(e.to_string()))?;
+    Ok(directories.into_iter().map(|directory| (directory.id.clone(), directory)).collect())
+}
+
 async fn get_file_with_context(
     pools: &ShelfManager,
     file_id: &str,
@@ -1096,41 +1220,12 @@ async fn get_file_with_context(
         .map_err(|e| RenameError::Database(e.to_string()))?;
 
     // ファイル情報を取得
-    let row = sqlx::query(
-        "SELECT id, path, name, directory_id, size, file_type, created_at, modified_at, 
-         birth_time, inode, is_directory, created_at_db, updated_at_db, file_size, 
-         mime_type, permissions, owner_uid, group_gid, hard_links, device_id, 
-         last_accessed, metadata FROM files WHERE id = ?"
-    )
-    .bind(file_id)
-    .fetch_one(&data_pool)
-    .await
-    .map_err(|e| RenameError::Database(e.to_string()))?;
-
-    let file = File {
-        id: row.get("id"),
-        path: row.get("path"),
-        name: row.get("name"),
-        directory_id: row.get("directory_id"),
-        size: row.get("size"),
-        file_type: row.get("file_type"),
-        created_at: row.get("created_at"),
-        modified_at: row.get("modified_at"),
-        birth_time: row.get("birth_time"),
-        inode: row.get("inode"),
-        is_directory: row.get("is_directory"),
-        created_at_db: row.get("created_at_db"),
-        updated_at_db: row.get("updated_at_db"),
-        file_size: row.get("file_size"),
-        mime_type: row.get("mime_type"),
-        permissions: row.get("permissions"),
-        owner_uid: row.get("owner_uid"),
-        group_gid: row.get("group_gid"),
-        hard_links: row.get("hard_links"),
-        device_id: row.get("device_id"),
-        last_accessed: row.get("last_accessed"),
-        metadata: row.get("metadata"),
-    };
+    let row = sqlx::query("SELECT * FROM files WHERE id = ?")
+        .bind(file_id)
+        .fetch_one(&data_pool)
+        .await
+        .map_err(|e| RenameError::Database(e.to_string()))?;
+    let file = file_from_row(&row);
 
     // タグ情報を取得
     let tags = db.get_file_tags(&data_pool, file_id)
@@ -1170,12 +1265,13 @@ pub async fn preview_rename(
     
     // カスタムメタデータを取得
     let custom_metadata = get_custom_metadata_for_file(&pools, &file_id).await?;
+    let roots = get_root_directories(&pools).await?;
     
     // Teraテンプレートコンテキストを作成
-    let context = create_template_context(&file, &tag_names, &metadata, &custom_metadata);
+    let context = create_template_context(&file, roots.get(&file.directory_id), &tag_names, &metadata, &custom_metadata);
     
     // Teraテンプレートをレンダリング
-    let intermediate_string = Tera::one_off(&format_template, &context, false)
+    let intermediate_string = render_one_off(&format_template, &context)
         .map_err(|e| RenameError::TemplateError(format!("Template error: {e}")))?;
 
     #[cfg(debug_assertions)]
@@ -1247,6 +1343,10 @@ pub async fn preview_advanced_batch_rename(
     operations: Vec<AdvancedBatchRenameOperation>,
 ) -> Result<Vec<AdvancedBatchRenamePreview>, String> {
     let mut results = Vec::new();
+    let file_ids: Vec<String> = operations.iter().map(|op| op.file_id.clone()).collect();
+    let custom_metadata = get_custom_metadata_for_files(&pools, &file_ids).await.map_err(|e| e.to_string())?;
+    let no_custom_metadata = serde_json::json!({});
+    let roots = get_root_directories(&pools).await.map_err(|e| e.to_string())?;
     
     for (index, op) in operations.iter().enumerate() {
         let (file, tags, metadata) = match get_file_with_context(&pools, &op.file_id).await {
@@ -1262,7 +1362,8 @@ pub async fn preview_advanced_batch_rename(
             }
         };
         
-        let new_name = match generate_advanced_rename(&file, &tags, &metadata, op, index).await {
+        let custom = custom_metadata.get(&op.file_id).unwrap_or(&no_custom_metadata);
+        let new_name = match generate_advanced_rename(&file, roots.get(&file.directory_id), &tags, &metadata, custom, op, index).await {
             Ok(name) => name,
             Err(e) => {
                 results.push(AdvancedBatchRenamePreview {
@@ -1295,6 +1396,12 @@ pub async fn execute_advanced_batch_rename(
     
     let mut successful_files = Vec::new();
     let mut failed_files = Vec::new();
+
+    // カスタムメタデータはリネーム前にまとめて取得
+    let file_ids: Vec<String> = operations.iter().map(|op| op.file_id.clone()).collect();
+    let custom_metadata = get_custom_metadata_for_files(&pools, &file_ids).await.map_err(|e| e.to_string())?;
+    let no_custom_metadata = serde_json::json!({});
+    let roots = get_root_directories(&pools).await.map_err(|e| e.to_string())?;
     
     // トランザクションを開始
     let mut tx = data_pool.begin()
@@ -1313,7 +1420,8 @@ pub async fn execute_advanced_batch_rename(
         let old_path = file.path.clone();
         
         // 新しいファイル名を生成
-        let new_name = match generate_advanced_rename(&file, &tags, &metadata, op, index).await {
+        let custom = custom_metadata.get(&op.file_id).unwrap_or(&no_custom_metadata);
+        let new_name = match generate_advanced_rename(&file, roots.get(&file.directory_id), &tags, &metadata, custom, op, index).await {
             Ok(name) => name,
             Err(e) => {
                 failed_files.push((old_path, format!("ファイル名生成エラー: {e}")));
@@ -1391,8 +1499,10 @@ pub async fn execute_advanced_batch_rename(
 
 async fn generate_advanced_rename(
     file: &File,
+    root: Option<&Directory>,
     tags: &[Tag],
     metadata: &serde_json::Value,
+    custom_metadata: &serde_json::Value,
     op: &AdvancedBatchRenameOperation,
     index: usize,
 ) -> RenameResult<String> {
@@ -1407,16 +1517,13 @@ async fn generate_advanced_rename(
         // タグ名のリストを作成
         let tag_names: Vec<String> = tags.iter().map(|t| t.name.clone()).collect();
         
-        // カスタムメタデータを取得（空のJSONオブジェクトで代替、後で改善）
-        let custom_metadata = serde_json::json!({});
-        
         // Teraテンプレートコンテキストを作成
-        let mut context = create_template_context(file, &tag_names, metadata, &custom_metadata);
+        let mut context = create_template_context(file, root, &tag_names, metadata, custom_metadata);
         // 連番を追加（1から開始）
         context.insert("n", &(index + 1));
         
         // Teraテンプレートをレンダリング
-        let intermediate_string = Tera::one_off(&op.replace_pattern, &context, false)
+        let intermediate_string = render_one_off(&op.replace_pattern, &context)
             .map_err(|e| RenameError::TemplateError(format!("Template error: {e}")))?;
         
         // 正規表現の後方参照を置換
@@ -1433,16 +1540,13 @@ async fn generate_advanced_rename(
             // タグ名のリストを作成
             let tag_names: Vec<String> = tags.iter().map(|t| t.name.clone()).collect();
             
-            // カスタムメタデータを取得（空のJSONオブジェクトで代替、後で改善）
-            let custom_metadata = serde_json::json!({});
-            
             // Teraテンプレートコンテキストを作成
-            let mut context = create_template_context(file, &tag_names, metadata, &custom_metadata);
+            let mut context = create_template_context(file, root, &tag_names, metadata, custom_metadata);
             // 連番を追加（1から開始）
             context.insert("n", &(index + 1));
             
             // Teraテンプレートをレンダリング
-            let rendered_replacement = Tera::one_off(&op.replace_pattern, &context, false)
+            let rendered_replacement = render_one_off(&op.replace_pattern, &context)
                 .map_err(|e| RenameError::TemplateError(format!("Template error: {e}")))?;
             
             let final_name = original_name.replace(&op.find_pattern, &rendered_replacement);
@@ -1535,4 +1639,148 @@ pub async fn batch_rename_files(
         successful_files,
         failed_files,
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{metadata_key, metadata_value, test_file};
+
+    fn template_operation(file_id: &str, find_pattern: &str, replace_pattern: &str, use_regex: bool) -> AdvancedBatchRenameOperation {
+        AdvancedBatchRenameOperation {
+            file_id: file_id.to_string(),
+            find_pattern: find_pattern.to_string(),
+            replace_pattern: replace_pattern.to_string(),
+            use_regex,
+            use_template: true,
+        }
+    }
+
+    #[test]
+    fn test_custom_metadata_json_is_keyed_by_name_and_typed() {
+        let keys = vec![
+            metadata_key("client", "text"),
+            metadata_key("pages", "number"),
+            metadata_key("signed", "boolean"),
+            metadata_key("labels", "multi_select"),
+        ];
+        let values = vec![
+            metadata_value("a", "client", "acme"),
+            metadata_value("a", "pages", "12"),
+            metadata_value("a", "signed", "true"),
+            metadata_value("a", "labels", r#"["red","green"]"#),
+            metadata_value("b", "client", "globex"),
+            // 削除されたキーの値は含めない
+            metadata_value("b", "removed", "x"),
+        ];
+
+        let objects = custom_metadata_json(&keys, values);
+        assert_eq!(
+            objects["a"],
+            serde_json::json!({"client": "acme", "pages": 12, "signed": true, "labels": ["red", "green"]})
+        );
+        assert_eq!(objects["b"], serde_json::json!({"client": "globex"}));
+    }
+
+    #[tokio::test]
+    async fn test_advanced_rename_templates_use_custom_metadata() {
+        let keys = vec![metadata_key("client", "text"), metadata_key("pages", "number"), metadata_key("labels", "multi_select")];
+        let objects = custom_metadata_json(&keys, vec![
+            metadata_value("a", "client", "acme"),
+            metadata_value("a", "pages", "12"),
+            metadata_value("a", "labels", r#"["red","green"]"#),
+        ]);
+        let file = test_file("a", "/docs/scan.pdf", "d1");
+        let metadata = serde_json::json!({});
+
+        // キー名で直接、または custom_metadata から型のある値として参照できる
+        let op = template_operation("a", "scan", "{{ client }}_{{ n }}", false);
+        let name = generate_advanced_rename(&file, None, &[], &metadata, &objects["a"], &op, 2).await.unwrap();
+        assert_eq!(name, "acme_3.pdf");
+
+        let op = template_operation("a", r"^(\w+)\.pdf$", "{{ custom_metadata.pages + 1 }}p_{{ labels }}_$1.pdf", true);
+        let name = generate_advanced_rename(&file, None, &[], &metadata, &objects["a"], &op, 0).await.unwrap();
+        assert_eq!(name, "13p_red, green_scan.pdf");
+
+        // 値のないキーを参照するとエラーになる
+        let op = template_operation("a", "scan", "{{ client }}", false);
+        assert!(generate_advanced_rename(&file, None, &[], &metadata, &serde_json::json!({}), &op, 0).await.is_err());
+    }
+
+    #[test]
+    fn test_template_context_media_and_folder_variables() {
+        let root = Directory {
+            id: "d1".to_string(),
+            path: "/photos".to_string(),
+            name: "Photos".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            follow_symlinks: false,
+        };
+        let file = test_file("a", "/photos/2021/trip/IMG_0001.JPG", "d1");
+        let metadata = serde_json::json!({
+            "exif": {
+                "DateTimeOriginal": "2021:07:04 10:05:30",
+                "Make": "Canon\u{0}",
+                "Model": " EOS R5 ",
+                "PixelXDimension": [8192],
+                "PixelYDimension": [5464]
+            }
+        });
+        let context = create_template_context(&file, Some(&root), &[], &metadata, &serde_json::json!({}));
+        let render = |template: &str| render_one_off(template, &context).unwrap();
+
+        assert_eq!(render("{{ capture_date }}_{{ capture_time }}"), "2021-07-04_100530");
+        assert_eq!(render("{{ capture_datetime | format_date(format='%Y/%m') }}"), "2021/07");
+        assert_eq!(render("{{ camera_make }}-{{ camera_model | slugify }}"), "Canon-eos-r5");
+        assert_eq!(render("{{ width }}x{{ height }}"), "8192x5464");
+        // directory は親フォルダの名前、フォルダは登録したディレクトリからの相対
+        assert_eq!(render("{{ root_name }}/{{ parent_folders | join(sep='/') }}"), "Photos/2021/trip");
+        assert_eq!(render("{{ directory }}|{{ grandparent_folder }}|{{ directory_id }}"), "trip|2021|d1");
+
+        let song = test_file("s", "/music/track.flac", "d2");
+        let metadata = serde_json::json!({
+            "audio": {"duration": 200, "tags": {"artist": "Björk", "album": "Debut", "title": "Human Behaviour", "track": 1}}
+        });
+        let context = create_template_context(&song, None, &[], &metadata, &serde_json::json!({}));
+        assert_eq!(
+            render_one_off("{{ artist | transliterate }} - {{ album }} - {{ track | pad }} {{ title | kebab_case }}", &context).unwrap(),
+            "Bjork - Debut - 01 human-behaviour"
+        );
+        // 撮影日時のないファイルでは既定値を使える
+        assert_eq!(render_one_off("{{ capture_date | default(value='undated') }}", &context).unwrap(), "undated");
+
+        // 同名のカスタムメタデータはトップレベルの変数を上書きするが、media.* には元の値が残る
+        let context = create_template_context(&song, None, &[], &metadata, &serde_json::json!({"title": "Custom"}));
+        assert_eq!(render_one_off("{{ title }}|{{ media.title }}", &context).unwrap(), "Custom|Human Behaviour");
+    }
+
+    #[tokio::test]
+    async fn test_file_with_context_reads_indexed_row() {
+        let pools = crate::database::tests::test_shelf_manager().await;
+        let data_pool = pools.get_active_data_pool().unwrap();
+        let db = Database;
+        let directory = db.add_directory(&data_pool, "/docs", "docs").await.unwrap();
+        let file = File {
+            metadata: Some(r#"{"pages": 3}"#.to_string()),
+            is_symlink: true,
+            symlink_target: Some("/archive/scan.pdf".to_string()),
+            ..test_file("a", "/docs/scan.pdf", &directory.id)
+        };
+        db.add_file(&data_pool, &file).await.unwrap();
+        let tag = db.create_tag(&data_pool, "invoice", "#ff0000").await.unwrap();
+        db.add_file_tag(&data_pool, "a", &tag.id).await.unwrap();
+
+        // リネームのプレビュー・実行はこの行の読み込みを通る
+        let (loaded, tags, metadata) = get_file_with_context(&pools, "a").await.unwrap();
+        assert_eq!(loaded.path, "/docs/scan.pdf");
+        assert!(loaded.is_symlink);
+        assert_eq!(loaded.symlink_target.as_deref(), Some("/archive/scan.pdf"));
+        assert!(!loaded.is_broken_link);
+        assert_eq!(tags.into_iter().map(|t| t.name).collect::<Vec<_>>(), vec!["invoice"]);
+        assert_eq!(metadata, serde_json::json!({"pages": 3}));
+
+        assert!(get_file_with_context(&pools, "missing").await.is_err());
+    }
+
+}
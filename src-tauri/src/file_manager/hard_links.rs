@@ -0,0 +1,28 @@
+use crate::database::{Database, DatabaseTrait, File};
+use crate::ShelfManager;
+use tauri::State;
+
+/// 同じ実体（device_id + inode）を共有する他のファイルを取得する
+#[tauri::command]
+pub async fn get_hard_link_siblings(
+    pools: State<'_, ShelfManager>,
+    file_id: String,
+) -> Result<Vec<File>, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    db.get_hard_link_siblings(&data_pool, &file_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ハードリンクでつながったファイルのグループを取得する
+#[tauri::command]
+pub async fn get_hard_link_groups(
+    pools: State<'_, ShelfManager>,
+) -> Result<Vec<Vec<File>>, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    db.get_hard_link_groups(&data_pool)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,539 @@
+use crate::database::{
+    multi_select_items, CustomMetadataKey, Database, DatabaseTrait, File, FileMatchField, MetadataImportRow,
+    MetadataImportRowResult,
+};
+use crate::file_manager::tags::normalize_tag_path;
+use crate::search::{resolve_bulk_targets, SearchFilter};
+use crate::ShelfManager;
+use chrono::SecondsFormat;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use tauri::State;
+
+/// タグを入れる列の名前
+const TAGS_COLUMN: &str = "tags";
+
+/// CSVのタグ列の区切り
+const TAG_SEPARATOR: char = ';';
+
+/// 取り込みで作ったタグの色
+const DEFAULT_IMPORT_TAG_COLOR: &str = "#6B7280";
+
+/// 書き出す組み込みの項目を指定しなかった場合の項目
+const DEFAULT_EXPORT_FIELDS: &[ExportField] =
+    &[ExportField::Path, ExportField::Name, ExportField::Size, ExportField::ModifiedAt];
+
+/// 書き出し・取り込みのファイル形式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataFileFormat {
+    Csv,
+    /// 1行に1ファイル分のJSONオブジェクト
+    JsonLines,
+}
+
+/// 書き出せるファイルの組み込みの項目（列名は snake_case の名前）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportField {
+    Id,
+    Path,
+    /// 登録ディレクトリからの相対パス
+    RelativePath,
+    Name,
+    Size,
+    FileType,
+    MimeType,
+    CreatedAt,
+    ModifiedAt,
+    ContentHash,
+}
+
+impl ExportField {
+    fn column(self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+
+    fn value(self, file: &File, directory_path: Option<&str>) -> Value {
+        let date = |d: Option<chrono::DateTime<chrono::Utc>>| {
+            d.map(|d| Value::String(d.to_rfc3339_opts(SecondsFormat::Secs, true))).unwrap_or(Value::Null)
+        };
+        match self {
+            ExportField::Id => Value::String(file.id.clone()),
+            ExportField::Path => Value::String(file.path.clone()),
+            ExportField::RelativePath => directory_path
+                .and_then(|dir| file.path.strip_prefix(&format!("{dir}/")))
+                .map(|p| Value::String(p.to_string()))
+                .unwrap_or(Value::Null),
+            ExportField::Name => Value::String(file.name.clone()),
+            ExportField::Size => Value::from(file.file_size.unwrap_or(file.size)),
+            ExportField::FileType => file.file_type.clone().map(Value::String).unwrap_or(Value::Null),
+            ExportField::MimeType => file.mime_type.clone().map(Value::String).unwrap_or(Value::Null),
+            ExportField::CreatedAt => date(file.birth_time.or(file.created_at)),
+            ExportField::ModifiedAt => date(file.modified_at),
+            ExportField::ContentHash => file.content_hash.clone().map(Value::String).unwrap_or(Value::Null),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataExportResult {
+    pub path: String,
+    pub file_count: usize,
+    pub columns: Vec<String>,
+}
+
+/// 取り込む列の書き込み先
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImportColumnTarget {
+    /// タグのパス（CSVでは `;` 区切り）を追加する
+    Tags,
+    Metadata { key_id: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImportColumnMapping {
+    pub column: String,
+    pub target: ImportColumnTarget,
+}
+
+/// 取り込めなかった行
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImportRowIssue {
+    pub row: usize,
+    pub message: String,
+}
+
+/// 取り込みの結果（dry_run の場合は書き込まずに同じ内容を返す）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataImportReport {
+    pub dry_run: bool,
+    /// 書き込んだかどうか（不正な行があって取り消した場合は false）
+    pub applied: bool,
+    pub columns: Vec<String>,
+    pub total_rows: usize,
+    pub matched: Vec<MetadataImportRowResult>,
+    /// 対応するファイルがない、または複数のファイルに一致した行
+    pub unmatched: Vec<ImportRowIssue>,
+    /// 読み取れない、または値がキーの型に合わない行
+    pub invalid: Vec<ImportRowIssue>,
+}
+
+/// 取り込み元の1行（行番号と、列名ごとの値または読み取りエラー）
+type SourceRecord = (usize, Result<HashMap<String, Value>, String>);
+
+/// CSV・JSON Lines を読み、列名と行を返す（空の値は含めない）
+fn read_records(content: &str, format: MetadataFileFormat) -> Result<(Vec<String>, Vec<SourceRecord>), String> {
+    let content = content.trim_start_matches('\u{feff}');
+    match format {
+        MetadataFileFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(content.as_bytes());
+            let columns: Vec<String> = reader
+                .headers()
+                .map_err(|e| format!("CSVのヘッダーを読み取れません: {e}"))?
+                .iter()
+                .map(|c| c.trim().to_string())
+                .collect();
+            let mut records = Vec::new();
+            for record in reader.records() {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) => {
+                        let row = e.position().map(|p| p.line() as usize).unwrap_or_default();
+                        records.push((row, Err(e.to_string())));
+                        continue;
+                    }
+                };
+                let row = record.position().map(|p| p.line() as usize).unwrap_or_default();
+                let values = columns
+                    .iter()
+                    .zip(record.iter())
+                    .filter(|(_, v)| !v.trim().is_empty())
+                    .map(|(c, v)| (c.clone(), Value::String(v.to_string())))
+                    .collect();
+                records.push((row, Ok(values)));
+            }
+            Ok((columns, records))
+        }
+        MetadataFileFormat::JsonLines => {
+            let mut columns: Vec<String> = Vec::new();
+            let mut records = Vec::new();
+            for (index, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let object = match serde_json::from_str::<Value>(line) {
+                    Ok(Value::Object(object)) => object,
+                    Ok(_) => {
+                        records.push((index + 1, Err("JSONオブジェクトではありません".to_string())));
+                        continue;
+                    }
+                    Err(e) => {
+                        records.push((index + 1, Err(format!("JSONが不正です: {e}"))));
+                        continue;
+                    }
+                };
+                for column in object.keys() {
+                    if !columns.contains(column) {
+                        columns.push(column.clone());
+                    }
+                }
+                let values = object
+                    .into_iter()
+                    .filter(|(_, v)| !v.is_null() && !matches!(v, Value::String(s) if s.trim().is_empty()))
+                    .collect();
+                records.push((index + 1, Ok(values)));
+            }
+            Ok((columns, records))
+        }
+    }
+}
+
+/// 取り込んだ値をカスタムメタデータの入力値にする（配列はJSON配列として渡す）
+fn value_to_input(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// タグ列の値をタグのパスに分ける
+fn value_to_tag_paths(value: &Value) -> Vec<String> {
+    let items: Vec<String> = match value {
+        Value::Array(items) => items.iter().map(value_to_input).collect(),
+        other => value_to_input(other).split(TAG_SEPARATOR).map(str::to_string).collect(),
+    };
+    items.iter().filter_map(|item| normalize_tag_path(item).ok()).collect()
+}
+
+/// カスタムメタデータの値を書き出し用に型に合わせて変換する
+fn metadata_export_value(key: &CustomMetadataKey, value: &str) -> Value {
+    match key.data_type.as_str() {
+        "number" | "rating" | "duration" => serde_json::from_str::<serde_json::Number>(value)
+            .map(Value::Number)
+            .unwrap_or_else(|_| Value::String(value.to_string())),
+        "boolean" => Value::Bool(value == "true"),
+        "json" => serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())),
+        "multi_select" => Value::from(multi_select_items(value)),
+        _ => Value::String(value.to_string()),
+    }
+}
+
+/// CSVのセルの文字列にする（タグは `;`、複数選択の項目は `,` で区切る）
+fn csv_cell(column: &str, value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(items)) => {
+            let separator = if column == TAGS_COLUMN { "; " } else { ", " };
+            items.iter().map(value_to_input).collect::<Vec<_>>().join(separator)
+        }
+        Some(other) => other.to_string(),
+    }
+}
+
+fn write_records(
+    destination: &str,
+    format: MetadataFileFormat,
+    columns: &[String],
+    records: &[Map<String, Value>],
+) -> Result<(), String> {
+    match format {
+        MetadataFileFormat::Csv => {
+            let mut writer = csv::Writer::from_path(destination).map_err(|e| e.to_string())?;
+            writer.write_record(columns).map_err(|e| e.to_string())?;
+            for record in records {
+                writer
+                    .write_record(columns.iter().map(|c| csv_cell(c, record.get(c))))
+                    .map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())
+        }
+        MetadataFileFormat::JsonLines => {
+            let mut content = String::new();
+            for record in records {
+                content.push_str(&serde_json::to_string(record).map_err(|e| e.to_string())?);
+                content.push('\n');
+            }
+            fs::write(destination, content).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// 書き出す列の名前（カスタムメタデータキーの名前が組み込みの列と重なる場合はエラー）
+fn export_columns(fields: &[ExportField], include_tags: bool, keys: &[CustomMetadataKey]) -> Result<Vec<String>, String> {
+    let mut columns: Vec<String> = fields.iter().map(|f| f.column()).collect();
+    if include_tags {
+        columns.push(TAGS_COLUMN.to_string());
+    }
+    for key in keys {
+        if columns.contains(&key.name) {
+            return Err(format!(
+                "カスタムメタデータキー「{}」の名前が書き出す列と重なっています。キーか項目を書き出しの対象から外してください",
+                key.name
+            ));
+        }
+        columns.push(key.name.clone());
+    }
+    Ok(columns)
+}
+
+/// ファイルのタグ・カスタムメタデータ・組み込みの項目を CSV / JSON Lines に書き出す
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn export_file_metadata(
+    pools: State<'_, ShelfManager>,
+    destination: String,
+    format: MetadataFileFormat,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+    fields: Option<Vec<ExportField>>,
+    key_ids: Option<Vec<String>>,
+    include_tags: Option<bool>,
+) -> Result<MetadataExportResult, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    crate::computed_metadata::refresh_computed_metadata(&data_pool, pools.get_settings_pool()).await?;
+    let file_ids = resolve_bulk_targets(&pools, file_ids, filter).await?;
+    let fields = fields.unwrap_or_else(|| DEFAULT_EXPORT_FIELDS.to_vec());
+    let keys: Vec<CustomMetadataKey> = db
+        .get_all_custom_metadata_keys(pools.get_settings_pool())
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|key| match &key_ids {
+            Some(ids) => ids.contains(&key.id),
+            None => true,
+        })
+        .collect();
+    let include_tags = include_tags.unwrap_or(true);
+
+    let files = db.get_files_by_ids(&data_pool, &file_ids).await.map_err(|e| e.to_string())?;
+    let directories: HashMap<String, String> = db
+        .get_directories(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|d| (d.id, d.path))
+        .collect();
+    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+    if include_tags {
+        for (file_id, tag) in db.get_file_tags_by_files(&data_pool, &file_ids).await.map_err(|e| e.to_string())? {
+            tags.entry(file_id).or_default().push(tag.name);
+        }
+    }
+    let mut values: HashMap<(String, String), String> = HashMap::new();
+    for value in db.get_custom_metadata_values_by_files(&data_pool, &file_ids).await.map_err(|e| e.to_string())? {
+        if let Some(v) = value.value {
+            values.insert((value.file_id, value.key_id), v);
+        }
+    }
+
+    let columns = export_columns(&fields, include_tags, &keys)?;
+
+    let records: Vec<Map<String, Value>> = files
+        .iter()
+        .map(|file| {
+            let mut record = Map::new();
+            let directory_path = directories.get(&file.directory_id).map(String::as_str);
+            for field in &fields {
+                record.insert(field.column(), field.value(file, directory_path));
+            }
+            if include_tags {
+                record.insert(TAGS_COLUMN.to_string(), Value::from(tags.remove(&file.id).unwrap_or_default()));
+            }
+            for key in &keys {
+                let value = values
+                    .get(&(file.id.clone(), key.id.clone()))
+                    .map(|v| metadata_export_value(key, v))
+                    .unwrap_or(Value::Null);
+                record.insert(key.name.clone(), value);
+            }
+            record
+        })
+        .collect();
+
+    write_records(&destination, format, &columns, &records)
+        .map_err(|e| format!("書き出しに失敗しました: {e}"))?;
+    Ok(MetadataExportResult {
+        path: destination,
+        file_count: records.len(),
+        columns,
+    })
+}
+
+/// CSV / JSON Lines のタグ・カスタムメタデータを取り込む
+/// 行は match_column の値を match_by の項目と照合してファイルに対応付け、すべての行を1つのトランザクションで書き込む
+/// 不正な行が1行でもあれば何も書き込まない（skip_invalid が true の場合は不正な行だけを飛ばす）
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn import_file_metadata(
+    pools: State<'_, ShelfManager>,
+    source: String,
+    format: MetadataFileFormat,
+    match_by: FileMatchField,
+    match_column: String,
+    mappings: Vec<ImportColumnMapping>,
+    dry_run: bool,
+    skip_invalid: Option<bool>,
+) -> Result<MetadataImportReport, String> {
+    let skip_invalid = skip_invalid.unwrap_or(false);
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let content = fs::read_to_string(&source).map_err(|e| format!("ファイルを読み込めません: {e}"))?;
+    let (columns, records) = read_records(&content, format)?;
+
+    if !columns.contains(&match_column) {
+        return Err(format!("照合に使う列がありません: {match_column}"));
+    }
+    let keys = db.get_all_custom_metadata_keys(pools.get_settings_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+    for mapping in &mappings {
+        if !columns.contains(&mapping.column) {
+            return Err(format!("列がありません: {}", mapping.column));
+        }
+        if let ImportColumnTarget::Metadata { key_id } = &mapping.target {
+            if !keys.iter().any(|key| &key.id == key_id) {
+                return Err(format!("カスタムメタデータキーが見つかりません: {key_id}"));
+            }
+        }
+    }
+
+    let mut report = MetadataImportReport {
+        dry_run,
+        applied: false,
+        columns,
+        total_rows: records.len(),
+        matched: Vec::new(),
+        unmatched: Vec::new(),
+        invalid: Vec::new(),
+    };
+    let mut rows = Vec::new();
+    for (row, values) in records {
+        let values = match values {
+            Ok(values) => values,
+            Err(message) => {
+                report.invalid.push(ImportRowIssue { row, message });
+                continue;
+            }
+        };
+        let Some(match_value) = values.get(&match_column).map(value_to_input) else {
+            report.invalid.push(ImportRowIssue { row, message: format!("{match_column} が空です") });
+            continue;
+        };
+        let match_value = match match_by {
+            FileMatchField::RelativePath => match_value.trim().trim_start_matches("./").trim_start_matches('/').to_string(),
+            _ => match_value.trim().to_string(),
+        };
+        let file_ids = db.match_file_ids(&data_pool, match_by, &match_value)
+            .await
+            .map_err(|e| e.to_string())?;
+        let file_id = match file_ids.as_slice() {
+            [file_id] => file_id.clone(),
+            [] => {
+                report.unmatched.push(ImportRowIssue { row, message: format!("一致するファイルがありません: {match_value}") });
+                continue;
+            }
+            _ => {
+                report.unmatched.push(ImportRowIssue {
+                    row,
+                    message: format!("{}件のファイルに一致しました: {match_value}", file_ids.len()),
+                });
+                continue;
+            }
+        };
+
+        let mut import_row = MetadataImportRow { row, file_id, tags: Vec::new(), values: Vec::new() };
+        for mapping in &mappings {
+            let Some(value) = values.get(&mapping.column) else {
+                continue;
+            };
+            match &mapping.target {
+                ImportColumnTarget::Tags => import_row.tags.extend(value_to_tag_paths(value)),
+                ImportColumnTarget::Metadata { key_id } => import_row.values.push((key_id.clone(), value_to_input(value))),
+            }
+        }
+        rows.push(import_row);
+    }
+
+    // 読み取りの段階で不正な行があれば、skip_invalid がない限り書き込まない
+    let apply = !dry_run && (skip_invalid || report.invalid.is_empty());
+    let results = db
+        .import_file_metadata(&data_pool, pools.get_settings_pool(), &rows, DEFAULT_IMPORT_TAG_COLOR, apply, skip_invalid)
+        .await
+        .map_err(|e| format!("取り込みに失敗しました: {e}"))?;
+    for result in results {
+        match result.error {
+            Some(message) => report.invalid.push(ImportRowIssue { row: result.row, message }),
+            None => report.matched.push(result),
+        }
+    }
+    report.invalid.sort_by_key(|issue| issue.row);
+    report.applied = apply && (skip_invalid || report.invalid.is_empty());
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_csv_records() {
+        let content = "\u{feff}path,tags,client\n/docs/a.pdf,\"work/acme; draft\",Acme\n/docs/b.pdf,,\n";
+        let (columns, records) = read_records(content, MetadataFileFormat::Csv).unwrap();
+        assert_eq!(columns, vec!["path", "tags", "client"]);
+        assert_eq!(records.len(), 2);
+
+        let (row, values) = &records[0];
+        let values = values.as_ref().unwrap();
+        assert_eq!(*row, 2);
+        assert_eq!(value_to_tag_paths(&values["tags"]), vec!["work/acme", "draft"]);
+        assert_eq!(value_to_input(&values["client"]), "Acme");
+        // 空のセルは値として扱わない
+        assert_eq!(records[1].1.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_read_json_lines_records() {
+        let content = "{\"id\":\"a\",\"tags\":[\"x\",\"y/z\"],\"labels\":[\"red\"],\"pages\":12}\n\nnot json\n{\"id\":\"b\",\"note\":null}\n";
+        let (columns, records) = read_records(content, MetadataFileFormat::JsonLines).unwrap();
+        assert_eq!(columns, vec!["id", "labels", "pages", "tags", "note"]);
+        assert_eq!(records.iter().map(|(row, _)| *row).collect::<Vec<_>>(), vec![1, 3, 4]);
+
+        let values = records[0].1.as_ref().unwrap();
+        assert_eq!(value_to_tag_paths(&values["tags"]), vec!["x", "y/z"]);
+        assert_eq!(value_to_input(&values["labels"]), "[\"red\"]");
+        assert_eq!(value_to_input(&values["pages"]), "12");
+        assert!(records[1].1.is_err());
+        assert!(!records[2].1.as_ref().unwrap().contains_key("note"));
+    }
+
+    #[test]
+    fn test_csv_cell_separators() {
+        let tags = Value::from(vec!["a", "b/c"]);
+        assert_eq!(csv_cell(TAGS_COLUMN, Some(&tags)), "a; b/c");
+        assert_eq!(csv_cell("labels", Some(&tags)), "a, b/c");
+        assert_eq!(csv_cell("size", Some(&Value::from(42))), "42");
+        assert_eq!(csv_cell("note", None), "");
+    }
+
+    #[test]
+    fn test_export_columns_reject_collisions() {
+        let mut key = crate::test_support::metadata_key("text_key", "text");
+        let columns = export_columns(DEFAULT_EXPORT_FIELDS, true, std::slice::from_ref(&key)).unwrap();
+        assert_eq!(columns, vec!["path", "name", "size", "modified_at", "tags", "text_key"]);
+
+        key.name = "tags".to_string();
+        assert!(export_columns(DEFAULT_EXPORT_FIELDS, true, std::slice::from_ref(&key)).is_err());
+        assert!(export_columns(DEFAULT_EXPORT_FIELDS, false, std::slice::from_ref(&key)).is_ok());
+        key.name = "path".to_string();
+        assert!(export_columns(DEFAULT_EXPORT_FIELDS, false, std::slice::from_ref(&key)).is_err());
+    }
+}
@@ -5,7 +5,15 @@
 pub mod types;
 pub mod directories;
 pub mod files;
+pub mod hard_links;
+pub mod metadata_io;
+pub mod post_index;
 pub mod tags;
+pub mod tag_stats;
+pub mod template_filters;
+pub mod tombstones;
+pub mod xattr_tags;
+pub mod xmp_sidecar;
 
 // Re-export commonly used types
 pub use types::{
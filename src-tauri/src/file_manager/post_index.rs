@@ -0,0 +1,68 @@
+use crate::auto_tag_rules::AutoTagRuleEngine;
+use crate::database::{file_from_row, File};
+use crate::file_manager::xattr_tags::{get_sync_direction, sync_file_xattr_tags, XattrSyncDirection};
+use crate::file_manager::xmp_sidecar::{import_changed_sidecar, import_sidecar};
+use crate::ShelfManager;
+use std::sync::Arc;
+
+/// インデックスに追加・更新したファイルに続けて行う処理
+/// 拡張属性タグの同期、XMPサイドカーの取り込み、自動タグルールの適用の順に行う
+/// スキャンではファイルごとに設定を読み直さないよう、読み込んだものを使い回す
+pub struct PostIndexHook {
+    rule_engine: Arc<AutoTagRuleEngine>,
+    xattr_direction: XattrSyncDirection,
+}
+
+impl PostIndexHook {
+    /// アクティブなシェルフの設定とルールを読み込む
+    pub async fn load(pools: &ShelfManager) -> Result<Self, String> {
+        let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+        Ok(Self {
+            rule_engine: AutoTagRuleEngine::cached(pools).await?,
+            xattr_direction: get_sync_direction(&data_pool).await?,
+        })
+    }
+
+    /// 1ファイル分の処理を行う（失敗はログに出し、インデックスの更新は止めない）
+    pub async fn run(&self, pools: &ShelfManager, file: &File) {
+        if self.xattr_direction != XattrSyncDirection::Off {
+            let result = match pools.get_active_data_pool() {
+                Ok(data_pool) => sync_file_xattr_tags(&data_pool, file, self.xattr_direction).await.map(|_| ()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = result {
+                eprintln!("拡張属性タグの同期エラー: {e} (パス: {})", file.path);
+            }
+        }
+        if let Err(e) = import_sidecar(pools, file).await {
+            eprintln!("XMPサイドカーの取り込みエラー: {e} (パス: {})", file.path);
+        }
+        if !self.rule_engine.is_empty() {
+            if let Err(e) = self.rule_engine.apply(pools, file, false).await {
+                eprintln!("自動タグルールの適用エラー: {e} (パス: {})", file.path);
+            }
+        }
+    }
+}
+
+/// インデックスに追加・更新されたパスに処理を行う（監視から呼ばれる）
+/// パスがサイドカーの場合は、対応する写真にも取り込む
+pub async fn run_post_index_hook_for_path(pools: &ShelfManager, path: &str) {
+    let result = async {
+        let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+        let row = sqlx::query("SELECT * FROM files WHERE path = ?")
+            .bind(path)
+            .fetch_optional(&data_pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(row) = row {
+            PostIndexHook::load(pools).await?.run(pools, &file_from_row(&row)).await;
+        }
+        import_changed_sidecar(pools, path).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        eprintln!("インデックス後の処理エラー: {e} (パス: {path})");
+    }
+}
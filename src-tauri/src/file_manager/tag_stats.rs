@@ -0,0 +1,160 @@
+use crate::database::{category_from_extension, Database, DatabaseTrait, TagGroupCount, TagUsage};
+use crate::ShelfManager;
+use std::collections::HashMap;
+use tauri::State;
+
+/// 共起ペアの既定の取得件数
+const DEFAULT_PAIR_LIMIT: u32 = 50;
+
+/// タグの付与数を集計する期間の単位
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TagTimelinePeriod {
+    Day,
+    Week,
+    #[default]
+    Month,
+}
+
+impl TagTimelinePeriod {
+    fn strftime_format(self) -> &'static str {
+        match self {
+            TagTimelinePeriod::Day => "%Y-%m-%d",
+            TagTimelinePeriod::Week => "%Y-W%W",
+            TagTimelinePeriod::Month => "%Y-%m",
+        }
+    }
+}
+
+/// 一緒に付けられることの多いタグの組
+#[derive(Debug, Clone, serde::Serialize, PartialEq)]
+pub struct TagPairStats {
+    pub tag_id: String,
+    pub other_tag_id: String,
+    /// 両方のタグが付いたファイル数
+    pub file_count: i64,
+    /// どちらかのタグが付いたファイルのうち両方が付いている割合（1に近いほど重複の疑いが強い）
+    pub similarity: f64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TagAnalytics {
+    pub tags: Vec<TagUsage>,
+    /// group はファイルが置かれているフォルダのパス（登録したディレクトリではない）
+    pub by_directory: Vec<TagGroupCount>,
+    /// group はファイルカテゴリ名
+    pub by_category: Vec<TagGroupCount>,
+    pub pairs: Vec<TagPairStats>,
+    /// group は期間（付与日時が記録される前に付いたタグは含まない）
+    pub timeline: Vec<TagGroupCount>,
+}
+
+/// タグの利用状況・共起・推移をまとめて取得する
+#[tauri::command]
+pub async fn get_tag_analytics(
+    pools: State<'_, ShelfManager>,
+    pair_limit: Option<u32>,
+    period: Option<TagTimelinePeriod>,
+) -> Result<TagAnalytics, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+
+    let tags = db.get_tag_usage(&data_pool).await.map_err(|e| e.to_string())?;
+    let by_directory = db.get_tag_counts_by_directory(&data_pool).await.map_err(|e| e.to_string())?;
+    let by_extension = db.get_tag_counts_by_extension(&data_pool).await.map_err(|e| e.to_string())?;
+    let pair_counts = db
+        .get_tag_pair_counts(&data_pool, pair_limit.unwrap_or(DEFAULT_PAIR_LIMIT))
+        .await
+        .map_err(|e| e.to_string())?;
+    let timeline = db
+        .get_tag_counts_by_period(&data_pool, period.unwrap_or_default().strftime_format())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let file_counts: HashMap<&str, i64> = tags.iter().map(|u| (u.tag.id.as_str(), u.file_count)).collect();
+    let pairs = pair_counts
+        .into_iter()
+        .map(|pair| {
+            let union = file_counts.get(pair.tag_id.as_str()).copied().unwrap_or(0)
+                + file_counts.get(pair.other_tag_id.as_str()).copied().unwrap_or(0)
+                - pair.file_count;
+            TagPairStats {
+                similarity: if union > 0 { pair.file_count as f64 / union as f64 } else { 0.0 },
+                tag_id: pair.tag_id,
+                other_tag_id: pair.other_tag_id,
+                file_count: pair.file_count,
+            }
+        })
+        .collect();
+
+    Ok(TagAnalytics {
+        by_category: group_by_category(by_extension),
+        tags,
+        by_directory,
+        pairs,
+        timeline,
+    })
+}
+
+/// 拡張子ごとの集計を検索と同じファイルカテゴリごとにまとめる（拡張子のないものは other）
+fn group_by_category(by_extension: Vec<TagGroupCount>) -> Vec<TagGroupCount> {
+    let mut totals: HashMap<(String, String), (i64, i64)> = HashMap::new();
+    for count in by_extension {
+        let category = category_from_extension(count.group.as_deref().unwrap_or_default()).to_string();
+        let total = totals.entry((count.tag_id, category)).or_default();
+        total.0 += count.file_count;
+        total.1 += count.total_bytes;
+    }
+
+    let mut by_category: Vec<TagGroupCount> = totals
+        .into_iter()
+        .map(|((tag_id, category), (file_count, total_bytes))| TagGroupCount {
+            tag_id,
+            group: Some(category),
+            file_count,
+            total_bytes,
+        })
+        .collect();
+    by_category.sort_by(|a, b| a.tag_id.cmp(&b.tag_id).then(b.file_count.cmp(&a.file_count)).then(a.group.cmp(&b.group)));
+    by_category
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(tag_id: &str, group: Option<&str>, file_count: i64, total_bytes: i64) -> TagGroupCount {
+        TagGroupCount {
+            tag_id: tag_id.to_string(),
+            group: group.map(str::to_string),
+            file_count,
+            total_bytes,
+        }
+    }
+
+    #[test]
+    fn test_group_by_category() {
+        let by_extension = vec![
+            count("t1", Some("jpg"), 2, 200),
+            count("t1", Some("png"), 1, 50),
+            count("t1", Some("pdf"), 1, 10),
+            count("t1", None, 3, 30),
+            count("t2", Some("mp3"), 4, 400),
+        ];
+        let by_category = group_by_category(by_extension);
+        let groups: Vec<(&str, Option<&str>, i64, i64)> = by_category
+            .iter()
+            .map(|c| (c.tag_id.as_str(), c.group.as_deref(), c.file_count, c.total_bytes))
+            .collect();
+        // 拡張子のないものは other にまとめ、タグごとにファイル数の多い順に並べる
+        assert_eq!(
+            groups,
+            vec![
+                ("t1", Some("image"), 3, 250),
+                ("t1", Some("other"), 3, 30),
+                ("t1", Some("document"), 1, 10),
+                ("t2", Some("audio"), 4, 400),
+            ]
+        );
+    }
+}
@@ -1,5 +1,8 @@
-use crate::database::{Database, DatabaseTrait, Tag};
+use crate::database::{Database, DatabaseTrait, FileTagAssignment, Tag, TagSuggestion};
+use crate::file_manager::xattr_tags::export_xattr_tags;
+use crate::search::{resolve_bulk_targets, SearchFilter};
 use crate::ShelfManager;
+use std::collections::{HashMap, HashSet};
 use tauri::State;
 
 #[tauri::command]
@@ -11,24 +14,27 @@ pub async fn update_file_tags(
     let db = Database;
     let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
     
-    // 既存のタグを削除
+    // 外されたタグだけを削除
     let current_tags = db.get_file_tags(&data_pool, &file_id)
         .await
         .map_err(|e| e.to_string())?;
     
-    for tag in current_tags {
+    for tag in current_tags.iter().filter(|t| !tag_ids.contains(&t.id)) {
         db.remove_file_tag(&data_pool, &file_id, &tag.id)
             .await
             .map_err(|e| e.to_string())?;
     }
     
-    // 新しいタグを追加
-    for tag_id in tag_ids {
-        db.add_file_tag(&data_pool, &file_id, &tag_id)
+    // 新しく付けられたタグだけを追加
+    for tag_id in tag_ids.iter().filter(|id| !current_tags.iter().any(|t| &t.id == *id)) {
+        db.add_file_tag(&data_pool, &file_id, tag_id)
             .await
             .map_err(|e| e.to_string())?;
     }
     
+    // 拡張属性にも書き出す（設定が有効な場合）
+    export_xattr_tags(&pools, std::slice::from_ref(&file_id)).await?;
+    
     // 未参照タグを削除
     db.delete_orphaned_tags(&data_pool)
         .await
@@ -47,4 +53,335 @@ pub async fn get_file_tags(
     db.get_file_tags(&data_pool, &file_id)
         .await
         .map_err(|e| e.to_string())
-}
\ No newline at end of file
+}
+
+/// ファイルに効いているタグを、ディレクトリから継承したものも含めて取得する
+#[tauri::command]
+pub async fn get_file_tag_assignments(
+    pools: State<'_, ShelfManager>,
+    file_id: String,
+) -> Result<Vec<FileTagAssignment>, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    db.get_file_tag_assignments(&data_pool, &file_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ディレクトリに付けたタグをサブフォルダまで継承するか、直下のファイルだけに留めるかを切り替える
+#[tauri::command]
+pub async fn set_tag_include_subfolders(
+    pools: State<'_, ShelfManager>,
+    file_id: String,
+    tag_id: String,
+    include_subfolders: bool,
+) -> Result<(), String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    db.set_tag_include_subfolders(&data_pool, &file_id, &tag_id, include_subfolders)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => "このタグは指定したディレクトリに直接付いていません".to_string(),
+            e => e.to_string(),
+        })
+}
+/// 一括タグ操作の結果
+#[derive(Debug, serde::Serialize)]
+pub struct BulkTagResult {
+    /// 対象になったファイル数
+    pub file_count: usize,
+    /// 追加されたファイルとタグの組の数
+    pub added: u64,
+    /// 削除されたファイルとタグの組の数
+    pub removed: u64,
+    /// 未参照になり削除されたタグ
+    pub deleted_tag_ids: Vec<String>,
+}
+
+/// 一括操作の後始末（拡張属性への反映と未参照タグの削除）
+async fn finish_bulk_tag_update(
+    pools: &ShelfManager,
+    file_ids: &[String],
+    added: u64,
+    removed: u64,
+) -> Result<BulkTagResult, String> {
+    export_xattr_tags(pools, file_ids).await?;
+    
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let deleted_tag_ids = if removed > 0 {
+        db.delete_orphaned_tags(&data_pool)
+            .await
+            .map_err(|e| e.to_string())?
+    } else {
+        Vec::new()
+    };
+    
+    Ok(BulkTagResult {
+        file_count: file_ids.len(),
+        added,
+        removed,
+        deleted_tag_ids,
+    })
+}
+
+/// 複数のファイルにタグを追加する
+#[tauri::command]
+pub async fn bulk_add_tags(
+    pools: State<'_, ShelfManager>,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+    tag_ids: Vec<String>,
+) -> Result<BulkTagResult, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let file_ids = resolve_bulk_targets(&pools, file_ids, filter).await?;
+    
+    let added = db.bulk_add_file_tags(&data_pool, &file_ids, &tag_ids)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    finish_bulk_tag_update(&pools, &file_ids, added, 0).await
+}
+
+/// 複数のファイルからタグを外す
+#[tauri::command]
+pub async fn bulk_remove_tags(
+    pools: State<'_, ShelfManager>,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+    tag_ids: Vec<String>,
+) -> Result<BulkTagResult, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let file_ids = resolve_bulk_targets(&pools, file_ids, filter).await?;
+    
+    let removed = db.bulk_remove_file_tags(&data_pool, &file_ids, &tag_ids)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    finish_bulk_tag_update(&pools, &file_ids, 0, removed).await
+}
+
+/// 複数のファイルのタグを切り替える
+/// すべてのファイルに付いていれば外し、そうでなければ全ファイルに付ける
+#[tauri::command]
+pub async fn bulk_toggle_tag(
+    pools: State<'_, ShelfManager>,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+    tag_id: String,
+) -> Result<BulkTagResult, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let file_ids = resolve_bulk_targets(&pools, file_ids, filter).await?;
+    
+    let (added, removed) = db.bulk_toggle_file_tag(&data_pool, &file_ids, &tag_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    finish_bulk_tag_update(&pools, &file_ids, added, removed).await
+}
+
+/// タグ階層の1ノード
+#[derive(Debug, serde::Serialize)]
+pub struct TagTreeNode {
+    #[serde(flatten)]
+    pub tag: Tag,
+    pub label: String,
+    /// このタグが直接付いているファイル数
+    pub file_count: i64,
+    /// 子孫タグを含めたファイル数（重複は除く）
+    pub total_file_count: i64,
+    pub children: Vec<TagTreeNode>,
+}
+
+/// `a / b/ c` のようなタグパスを `a/b/c` に正規化する
+pub(crate) fn normalize_tag_path(path: &str) -> Result<String, String> {
+    let segments: Vec<&str> = path
+        .split('/')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect();
+    if segments.is_empty() {
+        return Err("タグ名が空です".to_string());
+    }
+    Ok(segments.join("/"))
+}
+
+/// 階層タグを作成する（途中の階層がなければ合わせて作成する）
+#[tauri::command]
+pub async fn create_tag_path(
+    pools: State<'_, ShelfManager>,
+    path: String,
+    color: String,
+) -> Result<Tag, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let path = normalize_tag_path(&path)?;
+    let tag = db.ensure_tag_path(&data_pool, &path, &color)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    // 明示的に作成したタグは未使用になっても残す（途中の階層は子タグがある限り残る）
+    db.set_tag_pinned(&data_pool, &tag.id, true)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// タグを固定・固定解除する（固定したタグは未使用になっても自動削除されない）
+#[tauri::command]
+pub async fn set_tag_pinned(
+    pools: State<'_, ShelfManager>,
+    tag_id: String,
+    pinned: bool,
+) -> Result<Tag, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let tag = db.set_tag_pinned(&data_pool, &tag_id, pinned)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    if !pinned {
+        db.delete_orphaned_tags(&data_pool)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    
+    Ok(tag)
+}
+
+/// 未使用のタグを整理する
+/// `include_pinned` の場合は固定したタグも対象にし、`dry_run` の場合は削除せずに対象だけを返す
+#[tauri::command]
+pub async fn cleanup_unused_tags(
+    pools: State<'_, ShelfManager>,
+    include_pinned: bool,
+    dry_run: bool,
+) -> Result<Vec<Tag>, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    db.delete_unused_tags(&data_pool, include_pinned, dry_run)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// `a/b/c` のようなパスでファイルにタグを付ける
+#[tauri::command]
+pub async fn add_file_tag_path(
+    pools: State<'_, ShelfManager>,
+    file_id: String,
+    path: String,
+    color: String,
+) -> Result<Tag, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let path = normalize_tag_path(&path)?;
+    let tag = db.ensure_tag_path(&data_pool, &path, &color)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    db.add_file_tag(&data_pool, &file_id, &tag.id)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    export_xattr_tags(&pools, std::slice::from_ref(&file_id)).await?;
+    
+    Ok(tag)
+}
+
+/// タグを子孫ごと別の親の下へ移動する（`new_parent_id` が None なら最上位へ）
+#[tauri::command]
+pub async fn move_tag(
+    pools: State<'_, ShelfManager>,
+    tag_id: String,
+    new_parent_id: Option<String>,
+) -> Result<Tag, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    
+    if let Some(parent_id) = &new_parent_id {
+        let subtree = db.get_tag_subtree_ids(&data_pool, &tag_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        if subtree.contains(parent_id) {
+            return Err("タグを自身または子孫の下へ移動することはできません".to_string());
+        }
+    }
+    
+    db.move_tag(&data_pool, &tag_id, new_parent_id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(db_error) if db_error.is_unique_violation() => {
+                "移動先に同じ名前のタグが既に存在します".to_string()
+            }
+            e => e.to_string(),
+        })
+}
+
+/// タグを階層構造で取得する
+#[tauri::command]
+pub async fn get_tag_tree(
+    pools: State<'_, ShelfManager>,
+) -> Result<Vec<TagTreeNode>, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let tags = db.get_all_tags(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let counts = db.get_tag_file_counts(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    Ok(build_tag_tree(tags, &counts))
+}
+
+/// 提案するタグの数の既定値
+const DEFAULT_SUGGESTION_LIMIT: usize = 10;
+
+/// 似たファイルや一緒に使われるタグから、付けると良さそうなタグを提案する
+#[tauri::command]
+pub async fn suggest_tags(
+    pools: State<'_, ShelfManager>,
+    file_ids: Vec<String>,
+    limit: Option<usize>,
+) -> Result<Vec<TagSuggestion>, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    db.suggest_tags(&data_pool, &file_ids, limit.unwrap_or(DEFAULT_SUGGESTION_LIMIT))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn build_tag_tree(tags: Vec<Tag>, counts: &HashMap<String, (i64, i64)>) -> Vec<TagTreeNode> {
+    let ids: HashSet<String> = tags.iter().map(|t| t.id.clone()).collect();
+    let mut children_of: HashMap<Option<String>, Vec<Tag>> = HashMap::new();
+    for tag in tags {
+        // 親が見つからないタグは最上位として扱う
+        let parent = tag.parent_id.clone().filter(|id| ids.contains(id));
+        children_of.entry(parent).or_default().push(tag);
+    }
+    
+    build_tag_nodes(None, &mut children_of, counts)
+}
+
+fn build_tag_nodes(
+    parent: Option<String>,
+    children_of: &mut HashMap<Option<String>, Vec<Tag>>,
+    counts: &HashMap<String, (i64, i64)>,
+) -> Vec<TagTreeNode> {
+    let tags = children_of.remove(&parent).unwrap_or_default();
+    tags.into_iter()
+        .map(|tag| {
+            let (file_count, total_file_count) = counts.get(&tag.id).copied().unwrap_or((0, 0));
+            let children = build_tag_nodes(Some(tag.id.clone()), children_of, counts);
+            TagTreeNode {
+                label: tag.label().to_string(),
+                tag,
+                file_count,
+                total_file_count,
+                children,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,211 @@
+// Teraテンプレート（リネーム・計算フィールド）で使う独自フィルタ
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime};
+use heck::{ToKebabCase, ToLowerCamelCase, ToSnakeCase, ToUpperCamelCase};
+use std::collections::HashMap;
+use std::fmt::Write;
+use tera::{Context, Tera, Value};
+
+use crate::database::normalize_date;
+
+/// 独自フィルタを登録したTeraを作る
+pub fn template_engine() -> Tera {
+    let mut tera = Tera::default();
+    tera.register_filter("pad", pad_filter);
+    tera.register_filter("transliterate", transliterate_filter);
+    tera.register_filter("snake_case", |value: &Value, _: &HashMap<String, Value>| {
+        convert_case(value, "snake_case", |s| s.to_snake_case())
+    });
+    tera.register_filter("kebab_case", |value: &Value, _: &HashMap<String, Value>| {
+        convert_case(value, "kebab_case", |s| s.to_kebab_case())
+    });
+    tera.register_filter("camel_case", |value: &Value, _: &HashMap<String, Value>| {
+        convert_case(value, "camel_case", |s| s.to_lower_camel_case())
+    });
+    tera.register_filter("pascal_case", |value: &Value, _: &HashMap<String, Value>| {
+        convert_case(value, "pascal_case", |s| s.to_upper_camel_case())
+    });
+    tera.register_filter("format_date", format_date_filter);
+    tera.register_filter("to_date", to_date_filter);
+    tera.register_filter("year", year_filter);
+    tera.register_filter("days_until", days_until_filter);
+    tera
+}
+
+/// Tera::one_off と同じく1回だけレンダリングする（独自フィルタを使える）
+pub fn render_one_off(template: &str, context: &Context) -> tera::Result<String> {
+    let mut tera = template_engine();
+    tera.add_raw_template("__one_off", template)?;
+    tera.render("__one_off", context)
+}
+
+/// 日付・日時の文字列（EXIF の 2024:05:01 10:00:00 を含む）を日時にする。日付だけなら0時
+/// タイムゾーン付きの日時はUTCに変換せず、書かれている現地時刻のまま扱う
+pub fn parse_datetime(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim();
+    // normalize_date はUTCにそろえるので、タイムゾーン付きは先にそのまま読む
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+        return Some(datetime.naive_local());
+    }
+    let normalized = normalize_date(text)?;
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(&normalized) {
+        return Some(datetime.naive_local());
+    }
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(&normalized, "%Y-%m-%dT%H:%M:%S") {
+        return Some(datetime);
+    }
+    NaiveDate::parse_from_str(&normalized, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0)
+}
+
+/// フィルタに渡された値を文字列にする（EXIF の値は配列で保存されていることがあるので先頭を使う）
+fn value_to_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Array(items) => items.first().and_then(value_to_text),
+        _ => None,
+    }
+}
+
+fn text_argument(value: &Value, filter: &str) -> tera::Result<String> {
+    value_to_text(value).ok_or_else(|| tera::Error::msg(format!("{filter}: 文字列に変換できません: {value}")))
+}
+
+fn date_argument(value: &Value, filter: &str) -> tera::Result<NaiveDateTime> {
+    value_to_text(value)
+        .and_then(|text| parse_datetime(&text))
+        .ok_or_else(|| tera::Error::msg(format!("{filter}: 日付として解釈できません: {value}")))
+}
+
+/// pad で指定できる幅の上限（ファイル名の長さの上限に合わせる）
+const MAX_PAD_WIDTH: u64 = 255;
+
+/// `{{ track | pad(width=3) }}` → 007（fill で埋める文字を変えられる）
+fn pad_filter(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let text = text_argument(value, "pad")?;
+    let width = match args.get("width") {
+        Some(width) => width
+            .as_u64()
+            .filter(|width| *width <= MAX_PAD_WIDTH)
+            .ok_or_else(|| tera::Error::msg(format!("pad: width には0から{MAX_PAD_WIDTH}までの整数を指定してください")))?,
+        None => 2,
+    } as usize;
+    let fill = match args.get("fill") {
+        Some(fill) => {
+            let mut chars = fill.as_str().unwrap_or_default().chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(tera::Error::msg("pad: fill には1文字を指定してください")),
+            }
+        }
+        None => '0',
+    };
+    let length = text.chars().count();
+    if length >= width {
+        return Ok(Value::String(text));
+    }
+    // 負の数は符号の後ろを埋める
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) if fill == '0' && value.is_number() => ("-", digits),
+        _ => ("", text.as_str()),
+    };
+    let padding = fill.to_string().repeat(width - length);
+    Ok(Value::String(format!("{sign}{padding}{digits}")))
+}
+
+/// 非ASCII文字をASCIIの近い表記にする（Café → Cafe, 東京 → Dong Jing）
+fn transliterate_filter(value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
+    Ok(Value::String(deunicode::deunicode(&text_argument(value, "transliterate")?)))
+}
+
+fn convert_case(value: &Value, filter: &str, convert: impl Fn(&str) -> String) -> tera::Result<Value> {
+    Ok(Value::String(convert(&text_argument(value, filter)?)))
+}
+
+/// `{{ capture_datetime | format_date(format="%Y%m%d_%H%M%S") }}`（format の既定は %Y-%m-%d）
+fn format_date_filter(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let datetime = date_argument(value, "format_date")?;
+    let format = match args.get("format") {
+        Some(format) => format.as_str().ok_or_else(|| tera::Error::msg("format_date: format には文字列を指定してください"))?,
+        None => "%Y-%m-%d",
+    };
+    // 不正な書式やタイムゾーンの指定（%z など）は書き出しの失敗になり、to_string では panic するため write! で受ける
+    let mut formatted = String::new();
+    write!(formatted, "{}", datetime.format(format))
+        .map_err(|_| tera::Error::msg(format!("format_date: 日付の書式が不正です: {format}")))?;
+    Ok(Value::String(formatted))
+}
+
+/// `{{ exif.DateTimeOriginal | to_date }}` → YYYY-MM-DD
+fn to_date_filter(value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
+    Ok(Value::String(date_argument(value, "to_date")?.format("%Y-%m-%d").to_string()))
+}
+
+/// 日付の年
+fn year_filter(value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
+    Ok(Value::from(date_argument(value, "year")?.year()))
+}
+
+/// 今日から日付までの日数（過ぎていれば負の数）
+fn days_until_filter(value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let date = date_argument(value, "days_until")?.date();
+    Ok(Value::from((date - Local::now().date_naive()).num_days()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, context: &Context) -> String {
+        render_one_off(template, context).unwrap()
+    }
+
+    #[test]
+    fn test_pad_and_case_filters() {
+        let mut context = Context::new();
+        context.insert("track", &7);
+        context.insert("offset", &-3);
+        context.insert("title", &"Hello World song");
+
+        assert_eq!(render("{{ track | pad }}", &context), "07");
+        assert_eq!(render("{{ track | pad(width=3) }}-{{ offset | pad(width=3) }}", &context), "007--03");
+        assert_eq!(render("{{ '12345' | pad(width=3) }}|{{ 'a' | pad(width=3, fill='_') }}", &context), "12345|__a");
+        assert!(render_one_off("{{ track | pad(fill='ab') }}", &context).is_err());
+        assert!(render_one_off("{{ track | pad(width=18446744073709551615) }}", &context).is_err());
+
+        assert_eq!(render("{{ title | snake_case }}", &context), "hello_world_song");
+        assert_eq!(render("{{ title | kebab_case }}", &context), "hello-world-song");
+        assert_eq!(render("{{ title | camel_case }}", &context), "helloWorldSong");
+        assert_eq!(render("{{ title | pascal_case }}", &context), "HelloWorldSong");
+        // Teraの組み込みフィルタもそのまま使える
+        assert_eq!(render("{{ title | upper }}|{{ title | slugify }}", &context), "HELLO WORLD SONG|hello-world-song");
+    }
+
+    #[test]
+    fn test_transliterate_filter() {
+        let mut context = Context::new();
+        context.insert("artist", &"Beyoncé & Björk");
+        assert_eq!(render("{{ artist | transliterate }}", &context), "Beyonce & Bjork");
+        assert_eq!(render("{{ artist | slugify }}", &context), "beyonce-bjork");
+    }
+
+    #[test]
+    fn test_date_filters() {
+        let mut context = Context::new();
+        context.insert("exif_date", &"2021:07:04 10:05:30");
+        context.insert("iso", &"2024-03-05T03:00:00+09:00");
+        context.insert("array", &vec!["2020:01:02 03:04:05"]);
+
+        assert_eq!(render("{{ exif_date | format_date(format='%Y%m%d_%H%M%S') }}", &context), "20210704_100530");
+        assert_eq!(render("{{ exif_date | format_date }}", &context), "2021-07-04");
+        // タイムゾーン付きはUTCに変換せず現地の日付のまま
+        assert_eq!(render("{{ iso | to_date }}", &context), "2024-03-05");
+        assert_eq!(render("{{ iso | format_date(format='%H:%M') }}", &context), "03:00");
+        assert_eq!(render("{{ array | year }}", &context), "2020");
+        assert!(render_one_off("{{ exif_date | format_date(format='%Q') }}", &context).is_err());
+        // 日時にタイムゾーンはないため、オフセットやタイムゾーン名は書式にできない
+        assert!(render_one_off("{{ exif_date | format_date(format='%Y %z') }}", &context).is_err());
+        assert!(render_one_off("{{ iso | format_date(format='%Z') }}", &context).is_err());
+        assert!(render_one_off("{{ 'someday' | format_date }}", &context).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::database::{file_from_row, Database, DatabaseTrait, File, FileTombstone};
+use crate::settings;
+use crate::watcher;
+use crate::ShelfManager;
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use tauri::State;
+
+/// ファイルの内容ハッシュ（サイズ + 内容全体のMD5）を計算する
+/// 大きなファイルでもメモリに載せきらないよう、少しずつ読み込んで求める
+pub fn compute_content_hash(path: &Path, metadata: &fs::Metadata) -> Option<String> {
+    // ディレクトリや空ファイルは同一性の判定に使えないため対象外
+    if !metadata.is_file() || metadata.len() == 0 {
+        return None;
+    }
+
+    let mut file = fs::File::open(path).ok()?;
+    let mut context = md5::Context::new();
+    context.consume(metadata.len().to_le_bytes());
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => context.consume(&buffer[..read]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        }
+    }
+
+    Some(format!("{:x}", context.compute()))
+}
+
+/// inode + 作成日時が一致する墓標があれば注釈を復元する。復元した場合は true を返す
+/// 内容ハッシュでの照合は、同じ内容のコピーが先に墓標を取らないよう、スキャンやイベントのまとまりの後に行う
+pub async fn reattach_tombstone_by_identity(pool: &SqlitePool, file: &File) -> Result<bool, sqlx::Error> {
+    let tombstone = Database.find_tombstone_by_identity(pool, file).await?;
+    restore_tombstone(pool, tombstone, file).await
+}
+
+/// 内容ハッシュ + サイズが一致する墓標があれば注釈を復元する
+pub async fn reattach_tombstone_by_content(pool: &SqlitePool, file: &File) -> Result<bool, sqlx::Error> {
+    let tombstone = Database.find_tombstone_by_content(pool, file).await?;
+    restore_tombstone(pool, tombstone, file).await
+}
+
+async fn restore_tombstone(pool: &SqlitePool, tombstone: Option<FileTombstone>, file: &File) -> Result<bool, sqlx::Error> {
+    let Some(tombstone) = tombstone else {
+        return Ok(false);
+    };
+    Database.restore_file_tombstone(pool, &tombstone.id, &file.id).await?;
+    #[cfg(debug_assertions)]
+    println!("墓標から注釈を復元しました: {} -> {}", tombstone.path, file.path);
+    Ok(true)
+}
+
+/// 保持期間を過ぎた墓標を削除する
+pub async fn purge_expired_tombstones(pools: &ShelfManager) -> Result<u64, String> {
+    let settings = settings::get_all_settings(pools.get_settings_pool())
+        .await
+        .unwrap_or_default();
+    let cutoff = Utc::now() - Duration::days(settings.tombstone_retention_days.max(0) as i64);
+
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    db.purge_expired_tombstones(&data_pool, cutoff)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 見つからなくなったファイルと、関連付けの候補
+#[derive(Debug, Serialize)]
+pub struct MissingFile {
+    #[serde(flatten)]
+    pub tombstone: FileTombstone,
+    /// 内容ハッシュとサイズが一致するファイル（手動で関連付ける候補）
+    pub candidates: Vec<File>,
+}
+
+/// 見つからなくなったファイル（墓標）の一覧を取得する
+#[tauri::command]
+pub async fn get_missing_files(
+    pools: State<'_, ShelfManager>,
+) -> Result<Vec<MissingFile>, String> {
+    purge_expired_tombstones(&pools).await?;
+
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let tombstones = db.get_file_tombstones(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut missing = Vec::new();
+    for tombstone in tombstones {
+        let candidates = db.find_tombstone_candidates(&data_pool, &tombstone.id)
+            .await
+            .map_err(|e| e.to_string())?;
+        missing.push(MissingFile { tombstone, candidates });
+    }
+    Ok(missing)
+}
+
+/// 見つからなくなったファイルを手動で新しいパスに関連付け、注釈を復元する
+#[tauri::command]
+pub async fn relink_missing_file(
+    pools: State<'_, ShelfManager>,
+    tombstone_id: String,
+    new_path: String,
+) -> Result<File, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+
+    db.get_file_tombstone(&data_pool, &tombstone_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("見つからないファイルの記録が存在しません")?;
+
+    let path = Path::new(&new_path);
+    let metadata = fs::symlink_metadata(path).map_err(|e| format!("ファイルにアクセスできません: {e}"))?;
+
+    // 未登録のファイルであればインデックスに追加する
+    let file_id: Option<String> = sqlx::query_scalar("SELECT id FROM files WHERE path = ?")
+        .bind(&new_path)
+        .fetch_optional(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let file_id = match file_id {
+        Some(file_id) => file_id,
+        None => {
+            let directory_id = watcher::find_directory_id_for_path(&data_pool, path).await?;
+            let file = watcher::create_file_from_metadata(path, &metadata, &directory_id);
+            db.add_file(&data_pool, &file)
+                .await
+                .map_err(|e| e.to_string())?
+        }
+    };
+
+    db.restore_file_tombstone(&data_pool, &tombstone_id, &file_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let row = sqlx::query("SELECT * FROM files WHERE id = ?")
+        .bind(&file_id)
+        .fetch_one(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(file_from_row(&row))
+}
+
+/// 見つからなくなったファイルの記録を破棄する
+#[tauri::command]
+pub async fn delete_missing_file(
+    pools: State<'_, ShelfManager>,
+    tombstone_id: String,
+) -> Result<Vec<String>, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    db.delete_file_tombstone(&data_pool, &tombstone_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // 墓標だけが参照していたタグを削除
+    db.delete_orphaned_tags(&data_pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_hash_covers_whole_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let hash = |name: &str, content: &[u8]| {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            compute_content_hash(&path, &fs::metadata(&path).unwrap())
+        };
+
+        // 先頭・末尾が同じでも途中が違えば別の内容
+        let mut content = vec![b'a'; 512 * 1024];
+        let original = hash("original.bin", &content).unwrap();
+        assert_eq!(hash("copy.bin", &content).unwrap(), original);
+        content[256 * 1024] = b'b';
+        assert_ne!(hash("edited.bin", &content).unwrap(), original);
+
+        assert!(hash("empty.bin", b"").is_none());
+    }
+}
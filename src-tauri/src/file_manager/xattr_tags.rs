@@ -0,0 +1,512 @@
+use crate::database::{file_from_row, Database, DatabaseTrait, File};
+use crate::file_manager::tags::normalize_tag_path;
+use crate::settings;
+use crate::ShelfManager;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::path::Path;
+use tauri::State;
+
+/// Linux（freedesktop）のタグ属性。カンマ区切りのUTF-8文字列
+pub const XDG_TAGS_ATTR: &str = "user.xdg.tags";
+/// macOS Finderのタグ属性。"名前\n色番号" の文字列配列をバイナリplistで格納する
+pub const FINDER_TAGS_ATTR: &str = "com.apple.metadata:_kMDItemUserTags";
+
+/// 拡張属性から取り込んだタグの色（Finderの色指定がない場合）
+const DEFAULT_XATTR_TAG_COLOR: &str = "#6B7280";
+
+/// Finderの色番号（1〜7）に対応する色
+const FINDER_COLORS: [&str; 7] = [
+    "#8E8E93", // グレー
+    "#34C759", // グリーン
+    "#AF52DE", // パープル
+    "#007AFF", // ブルー
+    "#FFCC00", // イエロー
+    "#FF3B30", // レッド
+    "#FF9500", // オレンジ
+];
+
+const SYNC_DIRECTION_KEY: &str = "xattr_tag_sync_direction";
+
+/// 拡張属性タグの同期方向（シェルフごとの設定。既定では同期しない）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum XattrSyncDirection {
+    /// 同期しない
+    #[default]
+    Off,
+    /// 拡張属性のタグを取り込むだけ
+    Import,
+    /// Clericaのタグを拡張属性に書き出すだけ
+    Export,
+    /// 取り込みと書き出しの両方
+    Both,
+}
+
+impl XattrSyncDirection {
+    fn imports(self) -> bool {
+        matches!(self, XattrSyncDirection::Import | XattrSyncDirection::Both)
+    }
+
+    fn exports(self) -> bool {
+        matches!(self, XattrSyncDirection::Export | XattrSyncDirection::Both)
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            XattrSyncDirection::Off => "off",
+            XattrSyncDirection::Import => "import",
+            XattrSyncDirection::Export => "export",
+            XattrSyncDirection::Both => "both",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "import" => XattrSyncDirection::Import,
+            "export" => XattrSyncDirection::Export,
+            "both" => XattrSyncDirection::Both,
+            _ => XattrSyncDirection::Off,
+        }
+    }
+}
+
+/// 拡張属性に記録されたタグ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XattrTag {
+    pub name: String,
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct XattrSyncResult {
+    pub files_checked: usize,
+    /// 拡張属性から取り込んだファイルとタグの組の数
+    pub imported: usize,
+    /// 拡張属性から消えたためClericaで外したファイルとタグの組の数
+    pub removed: usize,
+    /// 拡張属性を書き換えたファイル数
+    pub exported: usize,
+}
+
+/// `user.xdg.tags` の値を解析する
+pub fn parse_xdg_tags(value: &[u8]) -> Vec<XattrTag> {
+    String::from_utf8_lossy(value)
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| XattrTag { name: name.to_string(), color: None })
+        .collect()
+}
+
+/// `user.xdg.tags` の値を作成する（カンマを含む名前は表現できないため除外する）
+pub fn encode_xdg_tags(tags: &[XattrTag]) -> Vec<u8> {
+    tags.iter()
+        .filter(|tag| !tag.name.contains(','))
+        .map(|tag| tag.name.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+        .into_bytes()
+}
+
+/// Finderタグ（バイナリplist）を解析する
+pub fn parse_finder_tags(value: &[u8]) -> Result<Vec<XattrTag>, String> {
+    let entries: Vec<String> = plist::from_bytes(value).map_err(|e| e.to_string())?;
+    Ok(entries
+        .iter()
+        .filter_map(|entry| {
+            let (name, color) = match entry.split_once('\n') {
+                Some((name, index)) => (name, index.trim().parse::<usize>().ok()),
+                None => (entry.as_str(), None),
+            };
+            let name = name.trim();
+            (!name.is_empty()).then(|| XattrTag {
+                name: name.to_string(),
+                color: color
+                    .and_then(|index| index.checked_sub(1))
+                    .and_then(|index| FINDER_COLORS.get(index))
+                    .map(|color| color.to_string()),
+            })
+        })
+        .collect())
+}
+
+/// Finderタグ（バイナリplist）を作成する。Finderの色と一致する色だけを色番号として残す
+pub fn encode_finder_tags(tags: &[XattrTag]) -> Result<Vec<u8>, String> {
+    let entries: Vec<String> = tags
+        .iter()
+        .map(|tag| {
+            let index = tag.color.as_deref().and_then(|color| {
+                FINDER_COLORS
+                    .iter()
+                    .position(|c| c.eq_ignore_ascii_case(color))
+                    .map(|i| i + 1)
+            });
+            match index {
+                Some(index) => format!("{}\n{index}", tag.name),
+                None => tag.name.clone(),
+            }
+        })
+        .collect();
+
+    let mut buffer = Vec::new();
+    plist::to_writer_binary(&mut buffer, &entries).map_err(|e| e.to_string())?;
+    Ok(buffer)
+}
+
+/// ファイルの拡張属性からタグを読み取る（属性がない・未対応のファイルシステムでは空）
+pub fn read_xattr_tags(path: &Path) -> Vec<XattrTag> {
+    if cfg!(target_os = "macos") {
+        match xattr::get(path, FINDER_TAGS_ATTR) {
+            Ok(Some(value)) => parse_finder_tags(&value).unwrap_or_else(|e| {
+                eprintln!("Finderタグの解析エラー: {e} (パス: {})", path.display());
+                Vec::new()
+            }),
+            _ => Vec::new(),
+        }
+    } else {
+        match xattr::get(path, XDG_TAGS_ATTR) {
+            Ok(Some(value)) => parse_xdg_tags(&value),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// タグを拡張属性に書き込む。内容が変わらない場合は書き込まず false を返す
+pub fn write_xattr_tags(path: &Path, tags: &[XattrTag]) -> Result<bool, String> {
+    // 順序の違いだけで書き換えないよう名前順にそろえる
+    let mut tags = tags.to_vec();
+    tags.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let (attr, value) = if cfg!(target_os = "macos") {
+        (FINDER_TAGS_ATTR, encode_finder_tags(&tags)?)
+    } else {
+        (XDG_TAGS_ATTR, encode_xdg_tags(&tags))
+    };
+
+    let current = xattr::get(path, attr).map_err(|e| e.to_string())?;
+    if tags.is_empty() {
+        if current.is_none() {
+            return Ok(false);
+        }
+        xattr::remove(path, attr).map_err(|e| e.to_string())?;
+        return Ok(true);
+    }
+    if current.as_deref() == Some(value.as_slice()) {
+        return Ok(false);
+    }
+
+    xattr::set(path, attr, &value).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// 現在のシェルフの同期方向を取得する
+pub async fn get_sync_direction(data_pool: &SqlitePool) -> Result<XattrSyncDirection, String> {
+    let value = settings::get_shelf_setting(data_pool, SYNC_DIRECTION_KEY).await?;
+    Ok(value.map(|v| XattrSyncDirection::parse(&v)).unwrap_or_default())
+}
+
+/// 現在のシェルフの同期方向を保存する
+pub async fn set_sync_direction(data_pool: &SqlitePool, direction: XattrSyncDirection) -> Result<(), String> {
+    settings::set_shelf_setting(data_pool, SYNC_DIRECTION_KEY, direction.as_str()).await
+}
+
+/// 拡張属性のタグ名（タグとして使えない名前は除く）
+fn xattr_tag_names(tags: &[XattrTag]) -> HashSet<String> {
+    tags.iter().filter_map(|tag| normalize_tag_path(&tag.name).ok()).collect()
+}
+
+/// 拡張属性のタグをClericaに取り込む。前回の同期以降に拡張属性へ追加されたタグを付け、
+/// 拡張属性から消えたタグを外す。（追加数, 削除数）を返す
+async fn import_file_tags(
+    data_pool: &SqlitePool,
+    file: &File,
+    synced: &HashSet<String>,
+) -> Result<(usize, usize), String> {
+    let db = Database;
+    let xattr_tags = read_xattr_tags(Path::new(&file.path));
+    let xattr_names = xattr_tag_names(&xattr_tags);
+
+    let current_tags = db.get_file_tags(data_pool, &file.id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut imported = 0;
+    for xattr_tag in &xattr_tags {
+        let Ok(name) = normalize_tag_path(&xattr_tag.name) else {
+            continue;
+        };
+        // 前回の同期で一致していたタグはClericaで外されていても付け直さない
+        if synced.contains(&name) || current_tags.iter().any(|t| t.name == name) {
+            continue;
+        }
+        let color = xattr_tag.color.as_deref().unwrap_or(DEFAULT_XATTR_TAG_COLOR);
+        let tag = db.ensure_tag_path(data_pool, &name, color)
+            .await
+            .map_err(|e| e.to_string())?;
+        if current_tags.iter().any(|t| t.id == tag.id) {
+            continue;
+        }
+        db.add_file_tag(data_pool, &file.id, &tag.id)
+            .await
+            .map_err(|e| e.to_string())?;
+        imported += 1;
+    }
+
+    let mut removed = 0;
+    for tag in &current_tags {
+        if synced.contains(&tag.name) && !xattr_names.contains(&tag.name) {
+            db.remove_file_tag(data_pool, &file.id, &tag.id)
+                .await
+                .map_err(|e| e.to_string())?;
+            removed += 1;
+        }
+    }
+
+    Ok((imported, removed))
+}
+
+/// Clericaのタグを拡張属性に書き出す。書き換えた場合は true
+async fn export_file_tags(data_pool: &SqlitePool, file: &File) -> Result<bool, String> {
+    // シンボリックリンクはリンク先の属性を書き換えてしまうため対象外
+    if file.is_symlink {
+        return Ok(false);
+    }
+
+    let db = Database;
+    let tags: Vec<XattrTag> = db.get_file_tags(data_pool, &file.id)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|tag| XattrTag { name: tag.name, color: Some(tag.color) })
+        .collect();
+
+    write_xattr_tags(Path::new(&file.path), &tags)
+}
+
+/// 同期後に拡張属性にあるタグ名を、次回の同期で比べる基準として記録する
+async fn record_synced_tags(data_pool: &SqlitePool, file: &File) -> Result<(), String> {
+    let mut names: Vec<String> = xattr_tag_names(&read_xattr_tags(Path::new(&file.path)))
+        .into_iter()
+        .collect();
+    names.sort();
+    Database.set_xattr_synced_tags(data_pool, &file.id, &names)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 同期方向に従って1ファイルの拡張属性タグを同期する。
+/// 前回一致していたタグと比べ、片側で追加・削除されたタグだけを反対側に反映する
+pub async fn sync_file_xattr_tags(
+    data_pool: &SqlitePool,
+    file: &File,
+    direction: XattrSyncDirection,
+) -> Result<XattrSyncResult, String> {
+    let mut result = XattrSyncResult { files_checked: 1, ..Default::default() };
+    // シンボリックリンクの属性はリンク先のものになるため対象外
+    if direction == XattrSyncDirection::Off || file.is_symlink {
+        return Ok(result);
+    }
+
+    if direction.imports() {
+        let synced: HashSet<String> = Database.get_xattr_synced_tags(data_pool, &file.id)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .collect();
+        (result.imported, result.removed) = import_file_tags(data_pool, file, &synced).await?;
+    }
+    if direction.exports() && export_file_tags(data_pool, file).await? {
+        result.exported = 1;
+    }
+    record_synced_tags(data_pool, file).await?;
+    Ok(result)
+}
+
+/// Clericaでタグを変更したファイルの拡張属性を更新する（書き出しが有効な場合）
+pub async fn export_xattr_tags(pools: &ShelfManager, file_ids: &[String]) -> Result<(), String> {
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    if !get_sync_direction(&data_pool).await?.exports() {
+        return Ok(());
+    }
+
+    for file_id in file_ids {
+        let row = sqlx::query("SELECT * FROM files WHERE id = ?")
+            .bind(file_id)
+            .fetch_optional(&data_pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        let Some(row) = row else {
+            continue;
+        };
+        let file = file_from_row(&row);
+        if file.is_symlink {
+            continue;
+        }
+        // 書き込めないファイルがあっても他のファイルは続ける
+        let exported = async {
+            export_file_tags(&data_pool, &file).await?;
+            record_synced_tags(&data_pool, &file).await
+        }
+        .await;
+        if let Err(e) = exported {
+            eprintln!("拡張属性タグの書き出しエラー: {e} (パス: {})", file.path);
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_xattr_sync_direction(
+    pools: State<'_, ShelfManager>,
+) -> Result<XattrSyncDirection, String> {
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    get_sync_direction(&data_pool).await
+}
+
+#[tauri::command]
+pub async fn set_xattr_sync_direction(
+    pools: State<'_, ShelfManager>,
+    direction: XattrSyncDirection,
+) -> Result<(), String> {
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    set_sync_direction(&data_pool, direction).await
+}
+
+/// 現在のシェルフの全ファイルについて拡張属性タグを同期する
+#[tauri::command]
+pub async fn sync_xattr_tags(
+    pools: State<'_, ShelfManager>,
+) -> Result<XattrSyncResult, String> {
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let direction = get_sync_direction(&data_pool).await?;
+    let mut result = XattrSyncResult::default();
+    if direction == XattrSyncDirection::Off {
+        return Ok(result);
+    }
+
+    let rows = sqlx::query("SELECT * FROM files ORDER BY path")
+        .fetch_all(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for row in rows {
+        let file = file_from_row(&row);
+        match sync_file_xattr_tags(&data_pool, &file, direction).await {
+            Ok(file_result) => {
+                result.imported += file_result.imported;
+                result.removed += file_result.removed;
+                result.exported += file_result.exported;
+            }
+            Err(e) => eprintln!("拡張属性タグの同期エラー: {e} (パス: {})", file.path),
+        }
+        result.files_checked += 1;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::TestDatabase;
+    use crate::test_support::test_file;
+
+    fn tag(name: &str, color: Option<&str>) -> XattrTag {
+        XattrTag { name: name.to_string(), color: color.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_xdg_tags_round_trip() {
+        assert_eq!(parse_xdg_tags(b" work , ,client/acme"), vec![tag("work", None), tag("client/acme", None)]);
+        assert_eq!(encode_xdg_tags(&[tag("a", None), tag("b,c", None), tag("d", Some("#fff"))]), b"a,d".to_vec());
+    }
+
+    #[test]
+    fn test_finder_tags_round_trip() {
+        let encoded = encode_finder_tags(&[tag("Red", Some("#ff3b30")), tag("Plain", Some("#123456"))]).unwrap();
+        assert_eq!(
+            parse_finder_tags(&encoded).unwrap(),
+            vec![tag("Red", Some("#FF3B30")), tag("Plain", None)]
+        );
+    }
+
+    #[test]
+    fn test_read_and_write_xattr_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("note.txt");
+        std::fs::write(&path, "x").unwrap();
+        if xattr::set(&path, "user.clerica.probe", b"1").is_err() {
+            // ユーザー拡張属性に対応していないファイルシステム
+            return;
+        }
+
+        assert!(read_xattr_tags(&path).is_empty());
+        assert!(write_xattr_tags(&path, &[tag("work", None), tag("todo", None)]).unwrap());
+        assert_eq!(read_xattr_tags(&path), vec![tag("todo", None), tag("work", None)]);
+        // 同じ内容なら書き込まない
+        assert!(!write_xattr_tags(&path, &[tag("todo", None), tag("work", None)]).unwrap());
+
+        assert!(write_xattr_tags(&path, &[]).unwrap());
+        assert!(read_xattr_tags(&path).is_empty());
+        assert!(!write_xattr_tags(&path, &[]).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sync_file_xattr_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpg");
+        std::fs::write(&path, "x").unwrap();
+        if xattr::set(&path, XDG_TAGS_ATTR, b"travel,client/acme").is_err() {
+            return;
+        }
+
+        let pool = TestDatabase::new_in_memory().await.into_pool();
+        let db = Database;
+        let path_str = path.to_string_lossy().to_string();
+        let directory = db.add_directory(&pool, &dir.path().to_string_lossy(), "test").await.unwrap();
+        let file = test_file("f1", &path_str, &directory.id);
+        db.add_file(&pool, &file).await.unwrap();
+
+        // 既定では同期しない
+        assert_eq!(get_sync_direction(&pool).await.unwrap(), XattrSyncDirection::Off);
+        let result = sync_file_xattr_tags(&pool, &file, XattrSyncDirection::Off).await.unwrap();
+        assert_eq!((result.imported, result.exported), (0, 0));
+        assert!(db.get_file_tags(&pool, "f1").await.unwrap().is_empty());
+
+        set_sync_direction(&pool, XattrSyncDirection::Import).await.unwrap();
+        let direction = get_sync_direction(&pool).await.unwrap();
+        assert_eq!(direction, XattrSyncDirection::Import);
+        let result = sync_file_xattr_tags(&pool, &file, direction).await.unwrap();
+        assert_eq!((result.imported, result.exported), (2, 0));
+        let names = |tags: Vec<crate::database::Tag>| tags.into_iter().map(|t| t.name).collect::<Vec<_>>();
+        assert_eq!(names(db.get_file_tags(&pool, "f1").await.unwrap()), vec!["client/acme", "travel"]);
+        assert!(db.get_tag_by_name(&pool, "client").await.is_ok());
+
+        // 拡張属性から消えたタグは外し、Clericaで外したタグは付け直さない
+        xattr::set(&path, XDG_TAGS_ATTR, b"client/acme").unwrap();
+        let acme = db.get_tag_by_name(&pool, "client/acme").await.unwrap();
+        db.remove_file_tag(&pool, "f1", &acme.id).await.unwrap();
+        let result = sync_file_xattr_tags(&pool, &file, direction).await.unwrap();
+        assert_eq!((result.imported, result.removed), (0, 1));
+        assert!(db.get_file_tags(&pool, "f1").await.unwrap().is_empty());
+
+        // 双方向ではClericaで付けたタグも書き出し、Clericaで外したタグは拡張属性からも消す
+        set_sync_direction(&pool, XattrSyncDirection::Both).await.unwrap();
+        let extra = db.create_tag(&pool, "favorite", "#ff0000").await.unwrap();
+        db.add_file_tag(&pool, "f1", &extra.id).await.unwrap();
+        let result = sync_file_xattr_tags(&pool, &file, XattrSyncDirection::Both).await.unwrap();
+        assert_eq!((result.imported, result.removed, result.exported), (0, 0, 1));
+        assert_eq!(xattr::get(&path, XDG_TAGS_ATTR).unwrap().unwrap(), b"favorite".to_vec());
+        let result = sync_file_xattr_tags(&pool, &file, XattrSyncDirection::Both).await.unwrap();
+        assert_eq!((result.imported, result.removed, result.exported), (0, 0, 0));
+
+        db.remove_file_tag(&pool, "f1", &extra.id).await.unwrap();
+        xattr::set(&path, XDG_TAGS_ATTR, b"favorite,work").unwrap();
+        let result = sync_file_xattr_tags(&pool, &file, XattrSyncDirection::Both).await.unwrap();
+        assert_eq!((result.imported, result.removed, result.exported), (1, 0, 1));
+        assert_eq!(names(db.get_file_tags(&pool, "f1").await.unwrap()), vec!["work"]);
+        assert_eq!(xattr::get(&path, XDG_TAGS_ATTR).unwrap().unwrap(), b"work".to_vec());
+    }
+}
@@ -0,0 +1,964 @@
+use crate::database::{file_from_row, multi_select_items, Database, DatabaseTrait, File, MetadataError};
+use crate::file_manager::tags::normalize_tag_path;
+use crate::search::{find_matching_file_ids, SearchFilter};
+use crate::settings;
+use crate::ShelfManager;
+use chrono::{DateTime, Utc};
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+/// サイドカーを読み書きする拡張子（写真・RAW）
+const SIDECAR_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "tif", "tiff", "webp", "heic", "heif", "dng", "cr2", "cr3", "nef",
+    "nrw", "arw", "srf", "sr2", "orf", "rw2", "raf", "pef", "srw", "x3f", "3fr", "iiq",
+];
+
+/// サイドカーから取り込んだタグの色
+const DEFAULT_XMP_TAG_COLOR: &str = "#6B7280";
+
+const FIELD_MAPPINGS_KEY: &str = "xmp_field_mappings";
+
+const KEYWORDS_FIELD: &str = "dc:subject";
+const HIERARCHICAL_KEYWORDS_FIELD: &str = "lr:hierarchicalSubject";
+
+/// 書き出しに使う名前空間（接頭辞 -> URI）
+const XMP_NAMESPACES: &[(&str, &str)] = &[
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+    ("xmp", "http://ns.adobe.com/xap/1.0/"),
+    ("xmpRights", "http://ns.adobe.com/xap/1.0/rights/"),
+    ("lr", "http://ns.adobe.com/lightroom/1.0/"),
+    ("photoshop", "http://ns.adobe.com/photoshop/1.0/"),
+    ("Iptc4xmpCore", "http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/"),
+];
+
+const EMPTY_XMP_PACKET: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\"/>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>
+";
+
+/// XMPのフィールド（`xmp:Rating` など）とカスタムメタデータキーの対応
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct XmpFieldMapping {
+    pub field: String,
+    pub key_id: String,
+}
+
+/// XMPプロパティの値の形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum XmpValueKind {
+    Simple,
+    Bag,
+    Seq,
+    Alt,
+}
+
+impl XmpValueKind {
+    fn of(field: &str) -> Self {
+        match field {
+            "dc:subject" | "lr:hierarchicalSubject" | "photoshop:SupplementalCategories" => XmpValueKind::Bag,
+            "dc:creator" => XmpValueKind::Seq,
+            "dc:title" | "dc:description" | "dc:rights" | "xmpRights:UsageTerms" => XmpValueKind::Alt,
+            _ => XmpValueKind::Simple,
+        }
+    }
+
+    fn container(self) -> Option<&'static str> {
+        match self {
+            XmpValueKind::Simple => None,
+            XmpValueKind::Bag => Some("rdf:Bag"),
+            XmpValueKind::Seq => Some("rdf:Seq"),
+            XmpValueKind::Alt => Some("rdf:Alt"),
+        }
+    }
+}
+
+/// サイドカーに書き込むプロパティ
+#[derive(Debug, Clone, PartialEq)]
+pub struct XmpProperty {
+    pub field: String,
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct XmpImportResult {
+    pub file_id: String,
+    pub sidecar_path: String,
+    pub tags_added: usize,
+    pub tags_removed: usize,
+    pub values_set: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct XmpExportResult {
+    pub files_checked: usize,
+    /// 作成・更新したサイドカーのパス
+    pub written: Vec<String>,
+}
+
+/// サイドカーを扱う種類のファイルか
+pub fn supports_sidecar(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SIDECAR_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// 既存のサイドカーを探す（`photo.xmp` と `photo.jpg.xmp` の両方の命名に対応）
+pub fn find_sidecar(path: &Path) -> Option<PathBuf> {
+    let mut appended = path.as_os_str().to_owned();
+    appended.push(".xmp");
+    [path.with_extension("xmp"), PathBuf::from(appended)]
+        .into_iter()
+        .find(|candidate| candidate.is_file())
+}
+
+/// 接頭辞が書き出しに対応しているフィールドか
+fn is_supported_field(field: &str) -> bool {
+    field
+        .split_once(':')
+        .is_some_and(|(prefix, name)| !name.is_empty() && XMP_NAMESPACES.iter().any(|(p, _)| *p == prefix))
+}
+
+/// 定義済み実体参照を解決する
+fn resolve_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => None,
+    }
+}
+
+/// XMPパケットから最上位の `rdf:Description` のプロパティを読み取る
+/// 配列（Bag/Seq/Alt）は要素ごと、単純な値は1要素の配列として返す。入れ子の構造体は無視する
+pub fn parse_xmp(content: &str) -> Result<HashMap<String, Vec<String>>, String> {
+    let mut reader = Reader::from_str(content);
+    let mut properties: HashMap<String, Vec<String>> = HashMap::new();
+    let mut stack: Vec<String> = Vec::new();
+    // 読み取り中のプロパティと、その要素を積んだ後のスタックの深さ
+    let mut current: Option<(String, usize)> = None;
+    let mut text = String::new();
+
+    loop {
+        match reader.read_event().map_err(|e| format!("XMPの解析に失敗しました: {e}"))? {
+            Event::Eof => break,
+            Event::Start(e) => {
+                let name = e.name().as_ref().to_string();
+                if is_top_description(&name, &stack) {
+                    read_attribute_properties(&e, &mut properties)?;
+                } else if current.is_none() && is_top_property(&stack) {
+                    properties.entry(name.clone()).or_default();
+                    current = Some((name.clone(), stack.len() + 1));
+                }
+                stack.push(name);
+                text.clear();
+            }
+            Event::Empty(e) if is_top_description(e.name().as_ref(), &stack) => {
+                read_attribute_properties(&e, &mut properties)?;
+            }
+            Event::Text(e) if collects_text(&current, &stack) => {
+                text.push_str(&e.xml10_content());
+            }
+            Event::CData(e) if collects_text(&current, &stack) => {
+                text.push_str(&e.xml10_content());
+            }
+            Event::GeneralRef(e) if collects_text(&current, &stack) => {
+                let resolved = e
+                    .resolve_char_ref()
+                    .ok()
+                    .flatten()
+                    .or_else(|| resolve_entity(e.as_ref()));
+                if let Some(c) = resolved {
+                    text.push(c);
+                }
+            }
+            Event::End(_) => {
+                if let Some((field, depth)) = &current {
+                    let is_item = stack.len() == depth + 2 && stack.last().is_some_and(|n| n == "rdf:li");
+                    let closes_property = stack.len() == *depth;
+                    let value = text.trim();
+                    if (is_item || closes_property) && !value.is_empty() {
+                        properties.entry(field.clone()).or_default().push(value.to_string());
+                    }
+                    if is_item || closes_property {
+                        text.clear();
+                    }
+                    if closes_property {
+                        current = None;
+                    }
+                }
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    properties.retain(|_, values| !values.is_empty());
+    Ok(properties)
+}
+
+fn is_top_description(name: &str, stack: &[String]) -> bool {
+    name == "rdf:Description" && stack.last().is_some_and(|parent| parent == "rdf:RDF")
+}
+
+fn is_top_property(stack: &[String]) -> bool {
+    let len = stack.len();
+    len >= 2 && stack[len - 1] == "rdf:Description" && stack[len - 2] == "rdf:RDF"
+}
+
+/// プロパティの値そのもの、または配列の要素の直下のテキストだけを集める
+fn collects_text(current: &Option<(String, usize)>, stack: &[String]) -> bool {
+    current.as_ref().is_some_and(|(_, depth)| {
+        stack.len() == *depth || (stack.len() == depth + 2 && stack.last().is_some_and(|n| n == "rdf:li"))
+    })
+}
+
+/// `rdf:Description` の属性として書かれた単純なプロパティを読み取る
+fn read_attribute_properties(element: &BytesStart, properties: &mut HashMap<String, Vec<String>>) -> Result<(), String> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| format!("XMPの解析に失敗しました: {e}"))?;
+        let key = attribute.key.as_ref();
+        if key.starts_with("xmlns") || key.starts_with("rdf:") || key.starts_with("xml:") {
+            continue;
+        }
+        let value = attribute
+            .normalized_value(quick_xml::XmlVersion::Implicit1_0)
+            .map_err(|e| format!("XMPの解析に失敗しました: {e}"))?;
+        if !value.trim().is_empty() {
+            properties.entry(key.to_string()).or_default().push(value.trim().to_string());
+        }
+    }
+    Ok(())
+}
+
+/// XMPパケットの指定プロパティを置き換える（`existing` がなければ新規に作る）
+/// 対象外のプロパティ・構造はそのまま残す。値が空のプロパティは削除する
+pub fn update_xmp(existing: Option<&str>, updates: &[XmpProperty]) -> Result<String, String> {
+    let content = existing.unwrap_or(EMPTY_XMP_PACKET);
+    let managed: HashSet<&str> = updates.iter().map(|u| u.field.as_str()).collect();
+
+    let mut reader = Reader::from_str(content);
+    let mut writer = Writer::new(Vec::new());
+    let mut stack: Vec<String> = Vec::new();
+    let mut declared_prefixes: HashSet<String> = HashSet::new();
+    // 置き換え中のプロパティを閉じる深さ
+    let mut skip_until: Option<usize> = None;
+    // 書き換え対象の `rdf:Description` の内側の深さ
+    let mut target_depth: Option<usize> = None;
+    let mut done = false;
+
+    // 書き換え対象の直下の空白は、次の要素を残すかどうかが決まるまで保留する
+    let mut pending_whitespace: Option<String> = None;
+
+    let write_error = |e: std::io::Error| format!("XMPの作成に失敗しました: {e}");
+
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XMPの解析に失敗しました: {e}"))?;
+        let in_target = target_depth == Some(stack.len()) && skip_until.is_none();
+        match event {
+            Event::Eof => break,
+            Event::Text(e) if in_target && e.bytes().all(|b| b.is_ascii_whitespace()) => {
+                pending_whitespace = Some(e.xml10_content().into_owned());
+            }
+            Event::Start(e) => {
+                let name = e.name().as_ref().to_string();
+                if skip_until.is_some() {
+                    stack.push(name);
+                    continue;
+                }
+                if in_target && managed.contains(name.as_str()) {
+                    pending_whitespace = None;
+                    stack.push(name);
+                    skip_until = Some(stack.len());
+                    continue;
+                }
+                write_pending_whitespace(&mut writer, &mut pending_whitespace).map_err(write_error)?;
+                collect_declared_prefixes(&e, &mut declared_prefixes);
+                if !done && target_depth.is_none() && is_top_description(&name, &stack) {
+                    let start = description_start(&e, &managed, updates, &declared_prefixes)?;
+                    writer.write_event(Event::Start(start)).map_err(write_error)?;
+                    stack.push(name);
+                    target_depth = Some(stack.len());
+                    continue;
+                }
+                writer.write_event(Event::Start(e)).map_err(write_error)?;
+                stack.push(name);
+            }
+            Event::Empty(e) => {
+                if skip_until.is_some() {
+                    continue;
+                }
+                let name = e.name().as_ref().to_string();
+                if in_target && managed.contains(name.as_str()) {
+                    pending_whitespace = None;
+                    continue;
+                }
+                write_pending_whitespace(&mut writer, &mut pending_whitespace).map_err(write_error)?;
+                if !done && target_depth.is_none() && is_top_description(&name, &stack) {
+                    collect_declared_prefixes(&e, &mut declared_prefixes);
+                    let start = description_start(&e, &managed, updates, &declared_prefixes)?;
+                    let end = start.to_end().into_owned();
+                    writer.write_event(Event::Start(start)).map_err(write_error)?;
+                    write_properties(&mut writer, updates, stack.len() + 1).map_err(write_error)?;
+                    writer.write_event(Event::End(end)).map_err(write_error)?;
+                    done = true;
+                    continue;
+                }
+                writer.write_event(Event::Empty(e)).map_err(write_error)?;
+            }
+            Event::End(e) => {
+                if let Some(depth) = skip_until {
+                    if stack.len() == depth {
+                        skip_until = None;
+                    }
+                    stack.pop();
+                    continue;
+                }
+                if in_target {
+                    // 閉じタグの前の改行は追加するプロパティの後に書く
+                    pending_whitespace = None;
+                    write_properties(&mut writer, updates, stack.len()).map_err(write_error)?;
+                    target_depth = None;
+                    done = true;
+                }
+                stack.pop();
+                writer.write_event(Event::End(e)).map_err(write_error)?;
+            }
+            other => {
+                if skip_until.is_none() {
+                    write_pending_whitespace(&mut writer, &mut pending_whitespace).map_err(write_error)?;
+                    writer.write_event(other).map_err(write_error)?;
+                }
+            }
+        }
+    }
+
+    if !done {
+        return Err("XMPに rdf:Description が見つかりません".to_string());
+    }
+    String::from_utf8(writer.into_inner()).map_err(|e| e.to_string())
+}
+
+fn write_pending_whitespace(writer: &mut Writer<Vec<u8>>, pending: &mut Option<String>) -> std::io::Result<()> {
+    if let Some(whitespace) = pending.take() {
+        writer.write_event(Event::Text(BytesText::new(&whitespace)))?;
+    }
+    Ok(())
+}
+
+fn collect_declared_prefixes(element: &BytesStart, prefixes: &mut HashSet<String>) {
+    for attribute in element.attributes().flatten() {
+        if let Some(prefix) = attribute.key.as_ref().strip_prefix("xmlns:") {
+            prefixes.insert(prefix.to_string());
+        }
+    }
+}
+
+/// 置き換えるプロパティの属性を除き、必要な名前空間宣言を加えた `rdf:Description` の開始タグを作る
+fn description_start<'a>(
+    element: &'a BytesStart,
+    managed: &HashSet<&str>,
+    updates: &[XmpProperty],
+    declared_prefixes: &HashSet<String>,
+) -> Result<BytesStart<'a>, String> {
+    let mut start = BytesStart::new("rdf:Description");
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| format!("XMPの解析に失敗しました: {e}"))?;
+        if managed.contains(attribute.key.as_ref()) {
+            continue;
+        }
+        start.push_attribute(attribute);
+    }
+
+    let mut added = HashSet::new();
+    for update in updates.iter().filter(|u| !u.values.is_empty()) {
+        let Some((prefix, _)) = update.field.split_once(':') else {
+            continue;
+        };
+        if declared_prefixes.contains(prefix) || !added.insert(prefix) {
+            continue;
+        }
+        if let Some((_, uri)) = XMP_NAMESPACES.iter().find(|(p, _)| *p == prefix) {
+            let key = format!("xmlns:{prefix}");
+            start.push_attribute(Attribute::from((key.as_str(), *uri)));
+        }
+    }
+    Ok(start)
+}
+
+/// プロパティを要素として書き出す（`depth` はプロパティの字下げの深さ）
+fn write_properties(writer: &mut Writer<Vec<u8>>, updates: &[XmpProperty], depth: usize) -> std::io::Result<()> {
+    let indent = |level: usize| format!("\n{}", " ".repeat(level));
+
+    for update in updates.iter().filter(|u| !u.values.is_empty()) {
+        let kind = XmpValueKind::of(&update.field);
+        writer.write_event(Event::Text(BytesText::new(&indent(depth))))?;
+        writer.write_event(Event::Start(BytesStart::new(update.field.as_str())))?;
+        match kind.container() {
+            None => {
+                writer.write_event(Event::Text(BytesText::new(&update.values.join(", "))))?;
+            }
+            Some(container) => {
+                writer.write_event(Event::Text(BytesText::new(&indent(depth + 1))))?;
+                writer.write_event(Event::Start(BytesStart::new(container)))?;
+                for value in &update.values {
+                    let mut item = BytesStart::new("rdf:li");
+                    if kind == XmpValueKind::Alt {
+                        item.push_attribute(("xml:lang", "x-default"));
+                    }
+                    writer.write_event(Event::Text(BytesText::new(&indent(depth + 2))))?;
+                    writer.write_event(Event::Start(item))?;
+                    writer.write_event(Event::Text(BytesText::new(value)))?;
+                    writer.write_event(Event::End(BytesEnd::new("rdf:li")))?;
+                }
+                writer.write_event(Event::Text(BytesText::new(&indent(depth + 1))))?;
+                writer.write_event(Event::End(BytesEnd::new(container)))?;
+                writer.write_event(Event::Text(BytesText::new(&indent(depth))))?;
+            }
+        }
+        writer.write_event(Event::End(BytesEnd::new(update.field.as_str())))?;
+    }
+    writer.write_event(Event::Text(BytesText::new(&indent(depth.saturating_sub(1)))))?;
+    Ok(())
+}
+
+/// サイドカーのキーワードをタグのパスにする
+/// 階層キーワード（`a|b|c`）を優先し、その階層に含まれないキーワードだけを平坦なタグとして扱う
+fn keywords_to_tag_paths(properties: &HashMap<String, Vec<String>>) -> Vec<String> {
+    let hierarchical: Vec<String> = properties
+        .get(HIERARCHICAL_KEYWORDS_FIELD)
+        .into_iter()
+        .flatten()
+        .filter_map(|keyword| normalize_tag_path(&keyword.replace('|', "/")).ok())
+        .collect();
+    let segments: HashSet<&str> = hierarchical.iter().flat_map(|path| path.split('/')).collect();
+
+    let mut paths = hierarchical.clone();
+    for keyword in properties.get(KEYWORDS_FIELD).into_iter().flatten() {
+        if segments.contains(keyword.as_str()) {
+            continue;
+        }
+        if let Ok(path) = normalize_tag_path(keyword) {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+}
+
+/// タグをサイドカーのキーワードにする（Lightroomと同じく、各階層の名前も `dc:subject` に含める）
+fn tag_paths_to_keywords(tag_names: &[String]) -> Vec<XmpProperty> {
+    let mut keywords: Vec<String> = Vec::new();
+    for segment in tag_names.iter().flat_map(|name| name.split('/')) {
+        if !keywords.iter().any(|k| k == segment) {
+            keywords.push(segment.to_string());
+        }
+    }
+    let hierarchical: Vec<String> = tag_names
+        .iter()
+        .filter(|name| name.contains('/'))
+        .map(|name| name.replace('/', "|"))
+        .collect();
+
+    vec![
+        XmpProperty { field: KEYWORDS_FIELD.to_string(), values: keywords },
+        XmpProperty { field: HIERARCHICAL_KEYWORDS_FIELD.to_string(), values: hierarchical },
+    ]
+}
+
+/// 現在のシェルフのフィールド対応を取得する
+pub async fn get_field_mappings(data_pool: &SqlitePool) -> Result<Vec<XmpFieldMapping>, String> {
+    match settings::get_shelf_setting(data_pool, FIELD_MAPPINGS_KEY).await? {
+        Some(value) => serde_json::from_str(&value).map_err(|e| e.to_string()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// 現在のシェルフのフィールド対応を保存する
+pub async fn set_field_mappings(data_pool: &SqlitePool, mappings: &[XmpFieldMapping]) -> Result<(), String> {
+    let value = serde_json::to_string(mappings).map_err(|e| e.to_string())?;
+    settings::set_shelf_setting(data_pool, FIELD_MAPPINGS_KEY, &value).await
+}
+
+/// サイドカーのキーワードとフィールドを取り込む。サイドカーがなければ None
+/// タグは前回の同期以降にサイドカーで追加・削除されたキーワードを反映し、カスタムメタデータは
+/// 値がないか、サイドカーの方が新しい場合だけ更新する
+pub async fn import_sidecar(pools: &ShelfManager, file: &File) -> Result<Option<XmpImportResult>, String> {
+    let path = Path::new(&file.path);
+    if !supports_sidecar(path) {
+        return Ok(None);
+    }
+    let Some(sidecar_path) = find_sidecar(path) else {
+        return Ok(None);
+    };
+
+    let content = fs::read_to_string(&sidecar_path).map_err(|e| e.to_string())?;
+    let properties = parse_xmp(&content)?;
+    let sidecar_modified: Option<DateTime<Utc>> = fs::metadata(&sidecar_path)
+        .and_then(|m| m.modified())
+        .ok()
+        .map(DateTime::from);
+
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let mut result = XmpImportResult {
+        file_id: file.id.clone(),
+        sidecar_path: sidecar_path.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+
+    let keyword_paths = keywords_to_tag_paths(&properties);
+    let synced: HashSet<String> = db.get_xmp_synced_tags(&data_pool, &file.id)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .collect();
+    let current_tags = db.get_file_tags(&data_pool, &file.id)
+        .await
+        .map_err(|e| e.to_string())?;
+    for tag_path in &keyword_paths {
+        // 前回の同期で一致していたタグはClericaで外されていても付け直さない
+        if synced.contains(tag_path) || current_tags.iter().any(|t| &t.name == tag_path) {
+            continue;
+        }
+        let tag = db.ensure_tag_path(&data_pool, tag_path, DEFAULT_XMP_TAG_COLOR)
+            .await
+            .map_err(|e| e.to_string())?;
+        if current_tags.iter().any(|t| t.id == tag.id) {
+            continue;
+        }
+        db.add_file_tag(&data_pool, &file.id, &tag.id)
+            .await
+            .map_err(|e| e.to_string())?;
+        result.tags_added += 1;
+    }
+    // サイドカーから消えたキーワードのタグを外す
+    for tag in &current_tags {
+        if synced.contains(&tag.name) && !keyword_paths.contains(&tag.name) {
+            db.remove_file_tag(&data_pool, &file.id, &tag.id)
+                .await
+                .map_err(|e| e.to_string())?;
+            result.tags_removed += 1;
+        }
+    }
+    db.set_xmp_synced_tags(&data_pool, &file.id, &keyword_paths)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let keys = db.get_all_custom_metadata_keys(pools.get_settings_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+    for mapping in get_field_mappings(&data_pool).await? {
+        let (Some(values), Some(key)) = (properties.get(&mapping.field), keys.iter().find(|k| k.id == mapping.key_id)) else {
+            continue;
+        };
+        let value = match XmpValueKind::of(&mapping.field) {
+            // 配列の要素は複数選択の項目にする
+            XmpValueKind::Bag | XmpValueKind::Seq => serde_json::to_string(values).map_err(|e| e.to_string())?,
+            _ => values[0].clone(),
+        };
+        let existing = db.get_custom_metadata_value(&data_pool, &file.id, &mapping.key_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let should_set = match &existing {
+            Some(existing) => match existing.value.as_deref() {
+                None | Some("") => true,
+                Some(current) if key.normalize_value(Some(&value)).ok().flatten().as_deref() == Some(current) => false,
+                Some(_) => sidecar_modified.is_some_and(|modified| modified > existing.updated_at),
+            },
+            None => true,
+        };
+        if should_set {
+            match db.set_custom_metadata_value(&data_pool, pools.get_settings_pool(), &file.id, &mapping.key_id, Some(value)).await {
+                Ok(_) => result.values_set += 1,
+                // キーの型に合わない値は取り込まない
+                Err(MetadataError::Invalid(message)) => {
+                    eprintln!("XMPの値を取り込めませんでした ({}): {message}", file.path);
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    Ok(Some(result))
+}
+
+/// Clericaのタグと対応付けたカスタムメタデータをサイドカーに書き出す。書き込んだ場合はそのパス
+pub async fn write_sidecar(pools: &ShelfManager, file: &File) -> Result<Option<PathBuf>, String> {
+    let path = Path::new(&file.path);
+    if !supports_sidecar(path) {
+        return Ok(None);
+    }
+
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let tag_names: Vec<String> = db.get_file_tags(&data_pool, &file.id)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|tag| tag.name)
+        .collect();
+    let mut updates = tag_paths_to_keywords(&tag_names);
+
+    // 値のあるフィールドだけを書き出す（Clericaで未設定のフィールドはサイドカーの値を残す）
+    for mapping in get_field_mappings(&data_pool).await? {
+        let value = db.get_custom_metadata_value(&data_pool, &file.id, &mapping.key_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .and_then(|v| v.value)
+            .filter(|v| !v.trim().is_empty());
+        let Some(value) = value else {
+            continue;
+        };
+        let values = match XmpValueKind::of(&mapping.field) {
+            XmpValueKind::Bag | XmpValueKind::Seq => multi_select_items(&value),
+            _ => vec![value],
+        };
+        updates.push(XmpProperty { field: mapping.field, values });
+    }
+
+    let sidecar_path = find_sidecar(path).unwrap_or_else(|| path.with_extension("xmp"));
+    let existing = match fs::read_to_string(&sidecar_path) {
+        Ok(content) => Some(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.to_string()),
+    };
+    if existing.is_none() && updates.iter().all(|u| u.values.is_empty()) {
+        return Ok(None);
+    }
+
+    let content = update_xmp(existing.as_deref(), &updates)?;
+    if existing.as_deref() == Some(content.as_str()) {
+        record_synced_keywords(&data_pool, file, &updates).await?;
+        return Ok(None);
+    }
+    fs::write(&sidecar_path, content).map_err(|e| e.to_string())?;
+    record_synced_keywords(&data_pool, file, &updates).await?;
+    Ok(Some(sidecar_path))
+}
+
+/// サイドカーに書き出したキーワードを、次の取り込みで比べる同期済みのタグとして記録する
+/// 取り込みと同じ読み方をするため、階層に含まれる平坦なタグは記録しない
+async fn record_synced_keywords(data_pool: &SqlitePool, file: &File, updates: &[XmpProperty]) -> Result<(), String> {
+    let properties: HashMap<String, Vec<String>> = updates
+        .iter()
+        .map(|update| (update.field.clone(), update.values.clone()))
+        .collect();
+    Database.set_xmp_synced_tags(data_pool, &file.id, &keywords_to_tag_paths(&properties))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 変更されたパスがサイドカーの場合に、対応する写真に取り込む（写真自体の取り込みはインデックス後の処理で行う）
+pub async fn import_changed_sidecar(pools: &ShelfManager, path: &str) -> Result<(), String> {
+    let path = Path::new(path);
+    let is_sidecar = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("xmp"));
+    if !is_sidecar {
+        return Ok(());
+    }
+
+    // `photo.xmp` は `photo.*`、`photo.jpg.xmp` は `photo.jpg` に対応する
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let stem = path.with_extension("");
+    let prefix = format!("{}.", stem.to_string_lossy());
+    let files: Vec<File> = sqlx::query("SELECT * FROM files WHERE path = ? OR substr(path, 1, ?) = ?")
+        .bind(stem.to_string_lossy().as_ref())
+        .bind(prefix.chars().count() as i64)
+        .bind(&prefix)
+        .fetch_all(&data_pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(file_from_row)
+        .filter(|file| {
+            let file_path = Path::new(&file.path);
+            supports_sidecar(file_path) && find_sidecar(file_path).as_deref() == Some(path)
+        })
+        .collect();
+
+    for file in files {
+        import_sidecar(pools, &file).await?;
+    }
+    Ok(())
+}
+
+/// 対象のファイルを取得する（ファイルID指定、検索条件、指定がなければ全ファイル）
+async fn resolve_sidecar_targets(
+    pools: &ShelfManager,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+) -> Result<Vec<File>, String> {
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let file_ids: Vec<String> = match (file_ids, filter) {
+        (Some(file_ids), _) => file_ids,
+        (None, Some(filter)) => find_matching_file_ids(pools, filter).await?,
+        (None, None) => sqlx::query_scalar("SELECT id FROM files ORDER BY path")
+            .fetch_all(&data_pool)
+            .await
+            .map_err(|e| e.to_string())?,
+    };
+
+    let mut files = Vec::new();
+    for file_id in file_ids {
+        let row = sqlx::query("SELECT * FROM files WHERE id = ?")
+            .bind(&file_id)
+            .fetch_optional(&data_pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(row) = row {
+            files.push(file_from_row(&row));
+        }
+    }
+    Ok(files)
+}
+
+#[tauri::command]
+pub async fn get_xmp_field_mappings(
+    pools: State<'_, ShelfManager>,
+) -> Result<Vec<XmpFieldMapping>, String> {
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    get_field_mappings(&data_pool).await
+}
+
+#[tauri::command]
+pub async fn set_xmp_field_mappings(
+    pools: State<'_, ShelfManager>,
+    mappings: Vec<XmpFieldMapping>,
+) -> Result<(), String> {
+    let db = Database;
+    let keys = db.get_all_custom_metadata_keys(pools.get_settings_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+    for mapping in &mappings {
+        if !is_supported_field(&mapping.field) {
+            return Err(format!("対応していないXMPフィールドです: {}", mapping.field));
+        }
+        if mapping.field == KEYWORDS_FIELD || mapping.field == HIERARCHICAL_KEYWORDS_FIELD {
+            return Err(format!("{} はタグとして扱われます", mapping.field));
+        }
+        let Some(key) = keys.iter().find(|key| key.id == mapping.key_id) else {
+            return Err(format!("カスタムメタデータキーが見つかりません: {}", mapping.key_id));
+        };
+        // 配列のフィールドは複数選択のキーとだけ対応付ける
+        let is_array = matches!(XmpValueKind::of(&mapping.field), XmpValueKind::Bag | XmpValueKind::Seq);
+        if is_array != (key.data_type == "multi_select") {
+            return Err(if is_array {
+                format!("{} は複数の値を持つため、複数選択のキーと対応付けてください", mapping.field)
+            } else {
+                format!("{} は1つの値のフィールドのため、複数選択のキーとは対応付けられません", mapping.field)
+            });
+        }
+    }
+
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    set_field_mappings(&data_pool, &mappings).await
+}
+
+/// サイドカーを取り込む
+#[tauri::command]
+pub async fn import_xmp_sidecars(
+    pools: State<'_, ShelfManager>,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+) -> Result<Vec<XmpImportResult>, String> {
+    let mut results = Vec::new();
+    for file in resolve_sidecar_targets(&pools, file_ids, filter).await? {
+        if let Some(result) = import_sidecar(&pools, &file).await? {
+            results.push(result);
+        }
+    }
+    Ok(results)
+}
+
+/// Clericaのタグとカスタムメタデータからサイドカーを作成・更新する
+#[tauri::command]
+pub async fn write_xmp_sidecars(
+    pools: State<'_, ShelfManager>,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+) -> Result<XmpExportResult, String> {
+    let mut result = XmpExportResult::default();
+    for file in resolve_sidecar_targets(&pools, file_ids, filter).await? {
+        result.files_checked += 1;
+        if let Some(path) = write_sidecar(&pools, &file).await? {
+            result.written.push(path.to_string_lossy().to_string());
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIGHTROOM_SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="Adobe XMP Core 7.0">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:lr="http://ns.adobe.com/lightroom/1.0/"
+    xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+   xmp:Rating="4"
+   xmp:Label="Red"
+   crs:Exposure2012="+0.35">
+   <dc:title>
+    <rdf:Alt>
+     <rdf:li xml:lang="x-default">Sunset &amp; sea</rdf:li>
+    </rdf:Alt>
+   </dc:title>
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>travel</rdf:li>
+     <rdf:li>places</rdf:li>
+     <rdf:li>japan</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+   <lr:hierarchicalSubject>
+    <rdf:Bag>
+     <rdf:li>places|japan</rdf:li>
+    </rdf:Bag>
+   </lr:hierarchicalSubject>
+   <crs:ToneCurvePV2012>
+    <rdf:Seq>
+     <rdf:li>0, 0</rdf:li>
+    </rdf:Seq>
+   </crs:ToneCurvePV2012>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    #[test]
+    fn test_parse_xmp() {
+        let properties = parse_xmp(LIGHTROOM_SIDECAR).unwrap();
+        assert_eq!(properties["xmp:Rating"], vec!["4"]);
+        assert_eq!(properties["xmp:Label"], vec!["Red"]);
+        assert_eq!(properties["dc:title"], vec!["Sunset & sea"]);
+        assert_eq!(properties["dc:subject"], vec!["travel", "places", "japan"]);
+        assert_eq!(keywords_to_tag_paths(&properties), vec!["places/japan", "travel"]);
+    }
+
+    #[test]
+    fn test_update_xmp_keeps_other_properties() {
+        let mut updates = tag_paths_to_keywords(&["places/japan".to_string(), "family".to_string()]);
+        updates.push(XmpProperty { field: "xmp:Rating".to_string(), values: vec!["5".to_string()] });
+        updates.push(XmpProperty { field: "photoshop:City".to_string(), values: vec!["Kyoto".to_string()] });
+
+        let updated = update_xmp(Some(LIGHTROOM_SIDECAR), &updates).unwrap();
+        let properties = parse_xmp(&updated).unwrap();
+        assert_eq!(properties["xmp:Rating"], vec!["5"]);
+        assert_eq!(properties["xmp:Label"], vec!["Red"]);
+        assert_eq!(properties["crs:Exposure2012"], vec!["+0.35"]);
+        assert_eq!(properties["crs:ToneCurvePV2012"], vec!["0, 0"]);
+        assert_eq!(properties["dc:title"], vec!["Sunset & sea"]);
+        assert_eq!(properties["photoshop:City"], vec!["Kyoto"]);
+        assert_eq!(properties["dc:subject"], vec!["places", "japan", "family"]);
+        assert_eq!(properties["lr:hierarchicalSubject"], vec!["places|japan"]);
+        assert!(updated.contains("xmlns:photoshop=\"http://ns.adobe.com/photoshop/1.0/\""));
+
+        // 同じ内容で更新しても変わらない
+        assert_eq!(update_xmp(Some(&updated), &updates).unwrap(), updated);
+    }
+
+    #[test]
+    fn test_update_xmp_creates_new_packet() {
+        let created = update_xmp(None, &tag_paths_to_keywords(&["work".to_string()])).unwrap();
+        let properties = parse_xmp(&created).unwrap();
+        assert_eq!(properties["dc:subject"], vec!["work"]);
+        assert!(!properties.contains_key("lr:hierarchicalSubject"));
+    }
+
+    #[test]
+    fn test_find_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw = dir.path().join("IMG_0001.CR2");
+        let jpg = dir.path().join("IMG_0002.jpg");
+        fs::write(&raw, "").unwrap();
+        fs::write(&jpg, "").unwrap();
+        fs::write(dir.path().join("IMG_0001.xmp"), "").unwrap();
+        fs::write(dir.path().join("IMG_0002.jpg.xmp"), "").unwrap();
+
+        assert!(supports_sidecar(&raw));
+        assert!(!supports_sidecar(Path::new("notes.txt")));
+        assert_eq!(find_sidecar(&raw), Some(dir.path().join("IMG_0001.xmp")));
+        assert_eq!(find_sidecar(&jpg), Some(dir.path().join("IMG_0002.jpg.xmp")));
+    }
+
+    #[tokio::test]
+    async fn test_import_sidecar_syncs_keywords_and_arrays() {
+        use crate::database::tests::test_shelf_manager;
+        use crate::test_support::{metadata_key, test_file};
+
+        let dir = tempfile::tempdir().unwrap();
+        let photo = dir.path().join("IMG_0001.jpg");
+        let sidecar = dir.path().join("IMG_0001.xmp");
+        fs::write(&photo, "").unwrap();
+        let keywords = |names: &[&str]| {
+            let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+            let mut updates = tag_paths_to_keywords(&names);
+            updates.push(XmpProperty {
+                field: "dc:creator".to_string(),
+                values: vec!["Alice".to_string(), "Bob".to_string()],
+            });
+            update_xmp(None, &updates).unwrap()
+        };
+        fs::write(&sidecar, keywords(&["travel", "places/japan"])).unwrap();
+
+        let pools = test_shelf_manager().await;
+        let pool = pools.get_active_data_pool().unwrap();
+        let db = Database;
+        let directory = db.add_directory(&pool, &dir.path().to_string_lossy(), "photos").await.unwrap();
+        let file = test_file("p1", &photo.to_string_lossy(), &directory.id);
+        db.add_file(&pool, &file).await.unwrap();
+
+        let mut creators = metadata_key("multi_select_key", "multi_select");
+        creators.options = Some(vec!["Alice".to_string(), "Bob".to_string()]);
+        let creators = db.create_custom_metadata_key(pools.get_settings_pool(), &creators).await.unwrap();
+        let mapping = XmpFieldMapping { field: "dc:creator".to_string(), key_id: creators.id.clone() };
+        set_field_mappings(&pool, std::slice::from_ref(&mapping)).await.unwrap();
+
+        let names = || async {
+            db.get_file_tags(&pool, "p1").await.unwrap().into_iter().map(|t| t.name).collect::<Vec<_>>()
+        };
+        let result = import_sidecar(&pools, &file).await.unwrap().unwrap();
+        assert_eq!((result.tags_added, result.tags_removed, result.values_set), (2, 0, 1));
+        assert_eq!(names().await, vec!["places/japan", "travel"]);
+        let value = db.get_custom_metadata_value(&pool, "p1", &creators.id).await.unwrap().unwrap().value.unwrap();
+        assert_eq!(multi_select_items(&value), vec!["Alice", "Bob"]);
+
+        // サイドカーから消えたキーワードのタグは外し、Clericaで外したタグは付け直さない
+        let japan = db.get_tag_by_name(&pool, "places/japan").await.unwrap();
+        db.remove_file_tag(&pool, "p1", &japan.id).await.unwrap();
+        fs::write(&sidecar, keywords(&["places/japan"])).unwrap();
+        let result = import_sidecar(&pools, &file).await.unwrap().unwrap();
+        assert_eq!((result.tags_added, result.tags_removed, result.values_set), (0, 1, 0));
+        assert!(names().await.is_empty());
+
+        // 書き出したタグは同期済みとして扱う
+        let favorite = db.create_tag(&pool, "favorite", "#ff0000").await.unwrap();
+        db.add_file_tag(&pool, "p1", &favorite.id).await.unwrap();
+        write_sidecar(&pools, &file).await.unwrap().unwrap();
+        let properties = parse_xmp(&fs::read_to_string(&sidecar).unwrap()).unwrap();
+        assert_eq!(properties["dc:creator"], vec!["Alice", "Bob"]);
+        fs::write(&sidecar, keywords(&[])).unwrap();
+        let result = import_sidecar(&pools, &file).await.unwrap().unwrap();
+        assert_eq!(result.tags_removed, 1);
+        assert!(names().await.is_empty());
+    }
+}
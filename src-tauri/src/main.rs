@@ -8,6 +8,8 @@ use std::sync::{Arc, Mutex};
 use tauri::Manager;
 use watcher::FileWatcher;
 
+mod auto_tag_rules;
+mod computed_metadata;
 mod custom_metadata;
 mod database;
 mod database_manager;
@@ -19,6 +21,8 @@ mod shelf_commands;
 mod shelf_manager;
 mod search;
 mod settings;
+#[cfg(test)]
+mod test_support;
 mod thumbnail;
 mod watcher;
 
@@ -126,6 +130,8 @@ async fn main() {
             file_manager::files::get_files_paginated,
             file_manager::files::get_files_with_tags,
             file_manager::directories::get_directories,
+            file_manager::directories::check_directory_overlap,
+            file_manager::directories::relocate_directory,
             file_manager::files::get_files_by_directory,
             file_manager::files::get_files_by_directory_paginated,
             file_manager::files::get_files_by_directory_with_tags,
@@ -139,17 +145,45 @@ async fn main() {
             file_manager::files::get_file_info,
             file_manager::tags::get_file_tags,
             file_manager::tags::update_file_tags,
+            file_manager::tags::bulk_add_tags,
+            file_manager::tags::bulk_remove_tags,
+            file_manager::tags::bulk_toggle_tag,
+            file_manager::tags::create_tag_path,
+            file_manager::tags::set_tag_pinned,
+            file_manager::tags::cleanup_unused_tags,
+            file_manager::tags::add_file_tag_path,
+            file_manager::tags::move_tag,
+            file_manager::tags::get_tag_tree,
+            file_manager::tags::suggest_tags,
+            file_manager::tags::get_file_tag_assignments,
+            file_manager::tags::set_tag_include_subfolders,
+            file_manager::tag_stats::get_tag_analytics,
+            file_manager::xattr_tags::get_xattr_sync_direction,
+            file_manager::xattr_tags::set_xattr_sync_direction,
+            file_manager::xattr_tags::sync_xattr_tags,
+            file_manager::xmp_sidecar::get_xmp_field_mappings,
+            file_manager::xmp_sidecar::set_xmp_field_mappings,
+            file_manager::xmp_sidecar::import_xmp_sidecars,
+            file_manager::xmp_sidecar::write_xmp_sidecars,
+            file_manager::metadata_io::export_file_metadata,
+            file_manager::metadata_io::import_file_metadata,
             file_manager::files::delete_file,
             file_manager::files::delete_files,
             file_manager::files::batch_rename_files,
             file_manager::files::move_file,
             file_manager::directories::rescan_directory,
+            file_manager::directories::set_directory_follow_symlinks,
+            file_manager::hard_links::get_hard_link_siblings,
+            file_manager::hard_links::get_hard_link_groups,
             file_manager::files::open_file,
             file_manager::files::reveal_in_finder,
             file_manager::files::preview_rename,
             file_manager::files::execute_rename,
             file_manager::files::preview_advanced_batch_rename,
             file_manager::files::execute_advanced_batch_rename,
+            file_manager::tombstones::get_missing_files,
+            file_manager::tombstones::relink_missing_file,
+            file_manager::tombstones::delete_missing_file,
             search::search_files,
             search::search_files_paginated,
             search::get_tags,
@@ -157,17 +191,38 @@ async fn main() {
             search::search_tags_by_name,
             search::create_tag,
             search::delete_tag,
+            search::rename_tag,
+            search::update_tag_color,
+            search::merge_tags,
+            search::add_tag_alias,
+            search::remove_tag_alias,
+            search::get_tag_aliases,
             watcher::start_watching,
             watcher::stop_watching,
+            watcher::get_watcher_health,
             custom_metadata::create_custom_metadata_key,
             custom_metadata::get_custom_metadata_keys,
+            custom_metadata::preview_custom_metadata_key_update,
             custom_metadata::update_custom_metadata_key,
             custom_metadata::delete_custom_metadata_key,
             custom_metadata::get_custom_metadata_key_by_name,
             custom_metadata::set_custom_metadata_value,
+            custom_metadata::bulk_set_custom_metadata_value,
+            custom_metadata::bulk_clear_custom_metadata_value,
+            custom_metadata::copy_custom_metadata,
             custom_metadata::get_custom_metadata_values_by_file,
+            custom_metadata::get_custom_metadata_values_by_files,
             custom_metadata::get_custom_metadata_value,
             custom_metadata::delete_custom_metadata_value,
+            custom_metadata::audit_custom_metadata,
+            custom_metadata::check_custom_metadata_consistency,
+            custom_metadata::create_custom_metadata_schema,
+            custom_metadata::get_custom_metadata_schemas,
+            custom_metadata::update_custom_metadata_schema,
+            custom_metadata::delete_custom_metadata_schema,
+            custom_metadata::get_applicable_custom_metadata_keys,
+            custom_metadata::get_annotation_history,
+            custom_metadata::revert_custom_metadata_change,
             exif_config::get_exif_config_data,
             thumbnail::generate_video_thumbnail,
             thumbnail::cleanup_thumbnail_cache,
@@ -191,6 +246,11 @@ async fn main() {
             exclusion_patterns::delete_exclusion_pattern,
             exclusion_patterns::test_exclusion_pattern,
             exclusion_patterns::validate_exclusion_pattern,
+            auto_tag_rules::get_auto_tag_rules,
+            auto_tag_rules::create_auto_tag_rule,
+            auto_tag_rules::update_auto_tag_rule,
+            auto_tag_rules::delete_auto_tag_rule,
+            auto_tag_rules::run_auto_tag_rules,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
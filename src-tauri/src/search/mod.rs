@@ -1,4 +1,6 @@
-use crate::database::{Database, DatabaseTrait, File, Tag};
+use crate::database::{
+    classify_file_category, normalize_date, parse_duration, CustomMetadataKey, Database, DatabaseTrait, File, Tag, TAG_SUBTREE_QUERY,
+};
 use crate::settings;
 use crate::ShelfManager;
 use chrono::Utc;
@@ -37,6 +39,18 @@ pub struct PaginatedSearchParams {
 }
 
 
+/// 一括操作の対象を検索条件で指定する場合の条件
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SearchFilter {
+    pub query: String,
+    pub tag_ids: Option<Vec<String>>,
+    pub metadata_filters: Vec<MetadataSearchFilter>,
+    pub metadata_logic: Option<String>,
+    pub directory_id: Option<String>,
+    pub category: Option<String>,
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct SearchResult {
     pub file: File,
@@ -112,7 +126,7 @@ async fn search_files_internal(
         category: params.category,
     };
     
-    let paginated_result = search_files_paginated_internal(pools, paginated_params).await?;
+    let paginated_result = search_files_paginated_internal(&pools, paginated_params).await?;
 
     // 実際にはSearchResult構造体でカテゴリ別件数も返すべきだが、
     // 後方互換性のためとりあえずresultsのみ返す
@@ -149,80 +163,272 @@ pub async fn search_files_paginated(
         category,
     };
     
-    search_files_paginated_internal(pools, params).await
+    search_files_paginated_internal(&pools, params).await
 }
 
 async fn search_files_paginated_internal(
-    pools: State<'_, ShelfManager>,
+    pools: &ShelfManager,
     params: PaginatedSearchParams,
 ) -> Result<PaginatedSearchResult, String> {
-    // メタデータフィルタの有効性をチェック
-    let valid_metadata_filters: Vec<&MetadataSearchFilter> = params.metadata_filters
-        .iter()
-        .filter(|f| !f.key_id.is_empty() && !f.value.is_empty())
-        .collect();
+    // 計算フィールドで絞り込み・並べ替えできるよう、最初のページを読むときに計算し直す
+    // （続きのページでは計算し直さず、ページ送りの途中で並びが変わらないようにする）
+    if params.offset.unwrap_or(0) == 0 {
+        crate::computed_metadata::refresh_active_shelf(pools).await?;
+    }
 
-    let has_metadata_filters = !valid_metadata_filters.is_empty();
+    let filter = SearchFilter {
+        query: params.query.clone(),
+        tag_ids: params.tag_ids.clone(),
+        metadata_filters: params.metadata_filters.clone(),
+        metadata_logic: params.metadata_logic.clone(),
+        directory_id: params.directory_id.clone(),
+        category: params.category.clone(),
+    };
+    let search = build_search_conditions(pools.get_settings_pool(), &filter).await?;
+    let sql_params = search.params;
 
-    let mut sql = String::from(
+    let base_sql = format!(
         "SELECT DISTINCT f.*, GROUP_CONCAT(DISTINCT t.name) as tag_names 
          FROM files f 
-         LEFT JOIN file_tags ft ON f.id = ft.file_id 
-         LEFT JOIN tags t ON ft.tag_id = t.id",
+         LEFT JOIN effective_file_tags ft ON f.id = ft.file_id 
+         LEFT JOIN tags t ON ft.tag_id = t.id{}",
+        search.joins
     );
+    let mut sql = format!("{base_sql}{} GROUP BY f.id", where_clause(&search.conditions));
 
-    // カスタムメタデータフィルタがある場合、JOINを追加
-    if has_metadata_filters {
-        for (i, _) in valid_metadata_filters.iter().enumerate() {
-            sql.push_str(&format!(
-                " LEFT JOIN custom_metadata_values cmv{i} ON f.id = cmv{i}.file_id"
-            ));
-        }
+    // ソート
+    let sort_field = params.sort_field.as_deref().unwrap_or("modified_at");
+    let sort_order = params.sort_order.as_deref().unwrap_or("desc");
+
+    // `metadata:<キーID>` を指定するとカスタムメタデータの値で並べる
+    let mut sort_params: Vec<String> = Vec::new();
+    let sort_column = match sort_field {
+        "name" => "f.name".to_string(),
+        "size" => "f.size".to_string(),
+        "created_at" => "f.created_at".to_string(),
+        "modified_at" => "f.modified_at".to_string(),
+        "last_accessed" => "f.last_accessed".to_string(),
+        "file_type" => "f.file_type".to_string(),
+        field => match field.strip_prefix("metadata:") {
+            Some(key_id) => {
+                let db = Database;
+                let keys = db.get_all_custom_metadata_keys(pools.get_settings_pool())
+                    .await
+                    .map_err(|e| e.to_string())?;
+                match keys.iter().find(|k| k.id == key_id) {
+                    Some(key) => metadata_sort_expression(key, &mut sort_params),
+                    None => "f.modified_at".to_string(),
+                }
+            }
+            None => "f.modified_at".to_string(),
+        },
+    };
+
+    let order_direction = match sort_order {
+        "asc" => "ASC",
+        _ => "DESC",
+    };
+
+    sql.push_str(&format!(
+        " ORDER BY {sort_column} {order_direction} NULLS LAST"
+    ));
+
+    // 総件数取得用のクエリ
+    let count_sql = format!(
+        "SELECT COUNT(DISTINCT f.id) as total_count FROM files f{}{}",
+        search.joins,
+        where_clause(&search.conditions)
+    );
+
+    // デバッグ情報（開発時のみ）
+    #[cfg(debug_assertions)]
+    {
+        println!("=== SEARCH SQL DEBUG ===");
+        println!("Main SQL: {sql}");
+        println!("Count SQL: {count_sql}");
+        println!("Conditions: {:?}", search.conditions);
+        println!("Parameters: {sql_params:?}");
+        println!("Tag IDs: {:?}", params.tag_ids);
+        println!("Category: {:?}", params.category);
+        println!("========================");
+    }
+
+    // 総件数を取得
+    let mut count_query = sqlx::query(&count_sql);
+    for param in &sql_params {
+        count_query = count_query.bind(param);
+    }
+
+    let total_count: i64 = count_query
+        .fetch_one(&pools.get_active_data_pool().map_err(|e| e.to_string())?)
+        .await
+        .map_err(|e| e.to_string())?
+        .get("total_count");
+
+    // ページネーション追加
+    if let (Some(limit), Some(offset)) = (params.limit, params.offset) {
+        sql.push_str(&format!(" LIMIT {limit} OFFSET {offset}"));
+    }
+
+    // クエリ実行（ソート用のパラメータは ORDER BY の位置に続く）
+    let main_params: Vec<String> = sql_params.iter().chain(&sort_params).cloned().collect();
+    let mut query_builder = sqlx::query(&sql);
+    for param in &main_params {
+        query_builder = query_builder.bind(param);
+    }
+
+    #[cfg(debug_assertions)]
+    {
+        println!("=== EXECUTING MAIN QUERY ===");
+        println!("Final SQL: {sql}");
+        println!("Final Parameters: {main_params:?}");
+        println!("============================");
+    }
+
+    let rows = query_builder
+        .fetch_all(&pools.get_active_data_pool().map_err(|e| e.to_string())?)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut results = Vec::new();
+    for row in rows {
+        let file = File {
+            id: row.get("id"),
+            path: row.get("path"),
+            name: row.get("name"),
+            directory_id: row.get("directory_id"),
+            size: row.get("size"),
+            file_type: row.get("file_type"),
+            created_at: row.get("created_at"),
+            modified_at: row.get("modified_at"),
+            birth_time: row.get("birth_time"),
+            inode: row.get("inode"),
+            is_directory: row.get("is_directory"),
+            created_at_db: row.get("created_at_db"),
+            updated_at_db: row.get("updated_at_db"),
+            file_size: row.get("file_size"),
+            mime_type: row.get("mime_type"),
+            permissions: row.get("permissions"),
+            owner_uid: row.get("owner_uid"),
+            group_gid: row.get("group_gid"),
+            hard_links: row.get("hard_links"),
+            device_id: row.get("device_id"),
+            last_accessed: row.get("last_accessed"),
+            metadata: row.get("metadata"),
+            content_hash: row.get("content_hash"),
+            is_symlink: row.get("is_symlink"),
+            symlink_target: row.get("symlink_target"),
+            is_broken_link: row.get("is_broken_link"),
+        };
+
+        let tag_names: Option<String> = row.get("tag_names");
+        let tags = if let Some(names) = tag_names {
+            names
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|name| Tag {
+                    id: Uuid::new_v4().to_string(), // 仮のID
+                    name: name.to_string(),
+                    color: "#3B82F6".to_string(),
+                    created_at: Utc::now(),
+                    parent_id: None,
+                    pinned: false,
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        // 簡易的なスコア計算（実装予定）
+        let score = 1.0;
+
+        results.push(SearchResult { file, tags, score });
+    }
+
+    // フィルタリングはSQLクエリレベルで処理されるため、ここでは不要
+
+    // カテゴリフィルタ適用前の総件数を計算
+    let total_sql = format!("{base_sql}{} GROUP BY f.id", where_clause(&search.pre_category_conditions));
+    let total_category_counts =
+        calculate_category_counts(&pools.get_active_data_pool().map_err(|e| e.to_string())?, &total_sql, &search.pre_category_params).await?;
+
+    // カテゴリフィルタ適用後の件数を計算
+    let category_counts = calculate_category_counts(&pools.get_active_data_pool().map_err(|e| e.to_string())?, &sql, &main_params).await?;
+
+    Ok(PaginatedSearchResult {
+        results,
+        total_count,
+        category_counts,
+        total_category_counts,
+    })
+}
+
+/// 検索条件から組み立てたカスタムメタデータのJOIN句とWHERE条件
+struct SearchConditions {
+    joins: String,
+    conditions: Vec<String>,
+    params: Vec<String>,
+    /// カテゴリ・カスタムメタデータ・表示設定の条件を適用する前の条件（カテゴリ別の総件数に使う）
+    pre_category_conditions: Vec<String>,
+    pre_category_params: Vec<String>,
+}
+
+fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
     }
+}
+
+/// 検索条件からJOIN句とWHERE条件を組み立てる（ページ単位の検索とIDだけの取得で共有する）
+async fn build_search_conditions(settings_pool: &SqlitePool, filter: &SearchFilter) -> Result<SearchConditions, String> {
+    // メタデータフィルタの有効性をチェック
+    let valid_metadata_filters: Vec<&MetadataSearchFilter> = filter.metadata_filters
+        .iter()
+        .filter(|f| !f.key_id.is_empty() && !f.value.is_empty())
+        .collect();
+
+    // カスタムメタデータフィルタがある場合、JOINを追加
+    let joins: String = (0..valid_metadata_filters.len())
+        .map(|i| format!(" LEFT JOIN custom_metadata_values cmv{i} ON f.id = cmv{i}.file_id"))
+        .collect();
 
     let mut conditions = Vec::new();
     let mut sql_params: Vec<String> = Vec::new();
 
     // ファイル名検索
-    if !params.query.is_empty() {
+    if !filter.query.is_empty() {
         conditions.push("f.name LIKE ?".to_string());
-        sql_params.push(format!("%{}%", params.query));
+        sql_params.push(format!("%{}%", filter.query));
     }
 
     // タグフィルタ - 複数のタグがAND条件で絞り込まれる
-    if let Some(ref tag_ids) = params.tag_ids {
-        if !tag_ids.is_empty() {
-            let tag_count = tag_ids.len();
-            let placeholders = tag_ids
-                .iter()
-                .map(|_| "?".to_string())
-                .collect::<Vec<_>>()
-                .join(",");
-            // 指定されたすべてのタグを持つファイルのみを取得
+    // 親タグを指定した場合は子孫タグが付いたファイルも含める
+    // ディレクトリに付いたタグは配下のファイルにも効く
+    if let Some(ref tag_ids) = filter.tag_ids {
+        for tag_id in tag_ids {
             conditions.push(format!(
-                "f.id IN (SELECT file_id FROM file_tags WHERE tag_id IN ({placeholders}) GROUP BY file_id HAVING COUNT(DISTINCT tag_id) = {tag_count})"
+                "f.id IN (SELECT file_id FROM effective_file_tags WHERE tag_id IN ({TAG_SUBTREE_QUERY}))"
             ));
-            for tag_id in tag_ids {
-                sql_params.push(tag_id.clone());
-            }
+            sql_params.push(tag_id.clone());
         }
     }
 
     // ディレクトリフィルタ
-    if let Some(ref dir_id) = params.directory_id {
+    if let Some(ref dir_id) = filter.directory_id {
         if dir_id != "all" {
             conditions.push("f.directory_id = ?".to_string());
             sql_params.push(dir_id.clone());
         }
     }
 
-    // カテゴリフィルタ適用前の総件数を計算（現在のSQL + 条件をコピー）
-    let pre_category_sql = sql.clone();
+    // カテゴリフィルタ適用前の総件数を計算（条件をコピー）
     let pre_category_conditions = conditions.clone();
     let pre_category_params = sql_params.clone();
 
     // カテゴリフィルタ
-    if let Some(ref cat) = params.category {
+    if let Some(ref cat) = filter.category {
         if cat != "all" {
             match cat.as_str() {
                 "image" => {
@@ -299,31 +505,14 @@ async fn search_files_paginated_internal(
 
     // カスタムメタデータフィルタ
     if !valid_metadata_filters.is_empty() {
-        let metadata_logic = params.metadata_logic.clone().unwrap_or_else(|| "AND".to_string());
+        let metadata_logic = filter.metadata_logic.clone().unwrap_or_else(|| "AND".to_string());
         let mut metadata_conditions = Vec::new();
 
-        for (i, filter) in valid_metadata_filters.iter().enumerate() {
-            let metadata_condition = match filter.operator.as_str() {
-                "equals" => format!("(cmv{i}.key_id = ? AND cmv{i}.value = ?)"),
-                "contains" => format!("(cmv{i}.key_id = ? AND cmv{i}.value LIKE ?)"),
-                "greater_than" => {
-                    format!("(cmv{i}.key_id = ? AND CAST(cmv{i}.value AS REAL) > CAST(? AS REAL))")
-                }
-                "less_than" => {
-                    format!("(cmv{i}.key_id = ? AND CAST(cmv{i}.value AS REAL) < CAST(? AS REAL))")
-                }
-                "not_equals" => format!("(cmv{i}.key_id = ? AND cmv{i}.value != ?)"),
-                _ => format!("(cmv{i}.key_id = ? AND cmv{i}.value = ?)"), // デフォルトは equals
-            };
-
-            metadata_conditions.push(metadata_condition);
-            sql_params.push(filter.key_id.clone());
-
-            if filter.operator == "contains" {
-                sql_params.push(format!("%{}%", filter.value));
-            } else {
-                sql_params.push(filter.value.clone());
-            }
+        for (i, metadata_filter) in valid_metadata_filters.iter().enumerate() {
+            let (metadata_condition, values) = metadata_filter_condition(i, metadata_filter);
+            metadata_conditions.push(format!("(cmv{i}.key_id = ? AND {metadata_condition})"));
+            sql_params.push(metadata_filter.key_id.clone());
+            sql_params.extend(values);
         }
 
         if !metadata_conditions.is_empty() {
@@ -336,7 +525,7 @@ async fn search_files_paginated_internal(
     }
 
     // 設定を取得してフィルタリング条件を追加
-    let settings = settings::get_all_settings(pools.get_settings_pool())
+    let settings = settings::get_all_settings(settings_pool)
         .await
         .map_err(|e| e.to_string())?;
 
@@ -348,177 +537,158 @@ async fn search_files_paginated_internal(
         conditions.push("f.is_directory = FALSE".to_string());
     }
 
-    if !conditions.is_empty() {
-        sql.push_str(" WHERE ");
-        sql.push_str(&conditions.join(" AND "));
-    }
-
-    sql.push_str(" GROUP BY f.id");
-
-    // ソート
-    let sort_field = params.sort_field.as_deref().unwrap_or("modified_at");
-    let sort_order = params.sort_order.as_deref().unwrap_or("desc");
-
-    let sort_column = match sort_field {
-        "name" => "f.name",
-        "size" => "f.size",
-        "created_at" => "f.created_at",
-        "modified_at" => "f.modified_at",
-        "last_accessed" => "f.last_accessed",
-        "file_type" => "f.file_type",
-        _ => "f.modified_at",
-    };
-
-    let order_direction = match sort_order {
-        "asc" => "ASC",
-        _ => "DESC",
-    };
-
-    sql.push_str(&format!(
-        " ORDER BY {sort_column} {order_direction} NULLS LAST"
-    ));
+    Ok(SearchConditions {
+        joins,
+        conditions,
+        params: sql_params,
+        pre_category_conditions,
+        pre_category_params,
+    })
+}
 
-    // 総件数取得用のクエリ
-    let count_sql = format!(
-        "SELECT COUNT(DISTINCT f.id) as total_count FROM files f {} {}",
-        if has_metadata_filters {
-            valid_metadata_filters
-                .iter()
-                .enumerate()
-                .map(|(i, _)| {
-                    format!(" LEFT JOIN custom_metadata_values cmv{i} ON f.id = cmv{i}.file_id")
-                })
-                .collect::<Vec<_>>()
-                .join("")
-        } else {
-            String::new()
+/// カスタムメタデータの絞り込み条件（キーの一致以外の部分）と、そのパラメータ
+/// データ型に合わせて比較方法を変え、フィルタの値も保存時と同じ正規形にしてから比較する
+fn metadata_filter_condition(i: usize, filter: &MetadataSearchFilter) -> (String, Vec<String>) {
+    let value = filter.value.trim();
+    let column = format!("cmv{i}.value");
+    // 複数指定できる演算子ではカンマ区切りで値を並べる
+    let list: Vec<String> = value.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect();
+    let placeholders = vec!["?"; list.len().max(1)].join(", ");
+    let list_or_value = if list.is_empty() { vec![value.to_string()] } else { list.clone() };
+
+    match filter.data_type.as_str() {
+        // 数値として比較する型
+        "number" | "rating" | "duration" => {
+            let number = if filter.data_type == "duration" {
+                parse_duration(value).map_or_else(|| value.to_string(), |seconds| seconds.to_string())
+            } else {
+                value.to_string()
+            };
+            let operator = comparison_operator(&filter.operator);
+            (format!("CAST({column} AS REAL) {operator} CAST(? AS REAL)"), vec![number])
+        }
+        // ISO 8601 で保存しているので文字列のまま大小を比較できる
+        "date" => {
+            let date = normalize_date(value).unwrap_or_else(|| value.to_string());
+            match filter.operator.as_str() {
+                "equals" => (format!("{column} LIKE ?"), vec![format!("{date}%")]),
+                "not_equals" => (format!("{column} NOT LIKE ?"), vec![format!("{date}%")]),
+                // 日付だけを指定した場合も時刻付きの値を含めるよう、「より後」「以前」は指定した日の終わりと比べる
+                "greater_than" => (format!("{column} > ? || '~'"), vec![date]),
+                "less_or_equal" => (format!("{column} <= ? || '~'"), vec![date]),
+                operator => (format!("{column} {} ?", comparison_operator(operator)), vec![date]),
+            }
+        }
+        "select" => match filter.operator.as_str() {
+            "in" => (format!("{column} IN ({placeholders})"), list_or_value),
+            "not_equals" => (format!("{column} != ?"), vec![value.to_string()]),
+            _ => (format!("{column} = ?"), vec![value.to_string()]),
         },
-        if !conditions.is_empty() {
-            format!(" WHERE {}", conditions.join(" AND "))
-        } else {
-            String::new()
+        // 複数選択は項目ごとの行で判定する
+        "multi_select" => {
+            let items = format!(
+                "SELECT it.item FROM custom_metadata_value_items it WHERE it.value_id = cmv{i}.id AND it.item IN ({placeholders})"
+            );
+            match filter.operator.as_str() {
+                "has_all" => {
+                    let count = list_or_value.len();
+                    (format!("(SELECT COUNT(DISTINCT item) FROM ({items})) = {count}"), list_or_value)
+                }
+                "not_contains" => (format!("NOT EXISTS ({items})"), list_or_value),
+                // contains / has_any
+                _ => (format!("EXISTS ({items})"), list_or_value),
+            }
         }
-    );
-
-    // デバッグ情報（開発時のみ）
-    #[cfg(debug_assertions)]
-    {
-        println!("=== SEARCH SQL DEBUG ===");
-        println!("Main SQL: {sql}");
-        println!("Count SQL: {count_sql}");
-        println!("Conditions: {conditions:?}");
-        println!("Parameters: {sql_params:?}");
-        println!("Tag IDs: {:?}", params.tag_ids);
-        println!("Category: {:?}", params.category);
-        println!("========================");
-    }
-
-    // 総件数を取得
-    let mut count_query = sqlx::query(&count_sql);
-    for param in &sql_params {
-        count_query = count_query.bind(param);
-    }
-
-    let total_count: i64 = count_query
-        .fetch_one(&pools.get_active_data_pool().map_err(|e| e.to_string())?)
-        .await
-        .map_err(|e| e.to_string())?
-        .get("total_count");
-
-    // ページネーション追加
-    if let (Some(limit), Some(offset)) = (params.limit, params.offset) {
-        sql.push_str(&format!(" LIMIT {limit} OFFSET {offset}"));
+        // ファイル参照はIDかパスで一致させ、contains は参照先のファイル名で探す
+        "file_ref" => match filter.operator.as_str() {
+            "contains" => (
+                format!("{column} IN (SELECT id FROM files WHERE name LIKE ?)"),
+                vec![format!("%{value}%")],
+            ),
+            "not_equals" => (
+                format!("{column} NOT IN (SELECT id FROM files WHERE id = ? OR path = ?)"),
+                vec![value.to_string(), value.to_string()],
+            ),
+            _ => (
+                format!("{column} IN (SELECT id FROM files WHERE id = ? OR path = ?)"),
+                vec![value.to_string(), value.to_string()],
+            ),
+        },
+        _ => match filter.operator.as_str() {
+            "contains" => (format!("{column} LIKE ?"), vec![format!("%{value}%")]),
+            "greater_than" => (format!("CAST({column} AS REAL) > CAST(? AS REAL)"), vec![filter.value.clone()]),
+            "less_than" => (format!("CAST({column} AS REAL) < CAST(? AS REAL)"), vec![filter.value.clone()]),
+            "not_equals" => (format!("{column} != ?"), vec![filter.value.clone()]),
+            _ => (format!("{column} = ?"), vec![filter.value.clone()]), // デフォルトは equals
+        },
     }
+}
 
-    // クエリ実行
-    let mut query_builder = sqlx::query(&sql);
-    for param in &sql_params {
-        query_builder = query_builder.bind(param);
+fn comparison_operator(operator: &str) -> &'static str {
+    match operator {
+        "greater_than" => ">",
+        "less_than" => "<",
+        "greater_or_equal" => ">=",
+        "less_or_equal" => "<=",
+        "not_equals" => "!=",
+        _ => "=",
     }
+}
 
-    #[cfg(debug_assertions)]
-    {
-        println!("=== EXECUTING MAIN QUERY ===");
-        println!("Final SQL: {sql}");
-        println!("Final Parameters: {sql_params:?}");
-        println!("============================");
+/// カスタムメタデータの値で並べるための式
+/// select は選択肢の順、file_ref は参照先のファイル名で並べる
+fn metadata_sort_expression(key: &CustomMetadataKey, sort_params: &mut Vec<String>) -> String {
+    let value = "(SELECT v.value FROM custom_metadata_values v WHERE v.file_id = f.id AND v.key_id = ?)";
+    sort_params.push(key.id.clone());
+    match key.data_type.as_str() {
+        "number" | "rating" | "duration" => format!("CAST({value} AS REAL)"),
+        "select" if key.options.as_ref().is_some_and(|options| !options.is_empty()) => {
+            let options = key.options.clone().unwrap_or_default();
+            let cases = vec!["WHEN ? THEN ?"; options.len()].join(" ");
+            for (position, option) in options.into_iter().enumerate() {
+                sort_params.push(option);
+                sort_params.push(position.to_string());
+            }
+            format!("CAST(CASE {value} {cases} END AS INTEGER)")
+        }
+        "file_ref" => format!("(SELECT rf.name FROM files rf WHERE rf.id = {value}) COLLATE NOCASE"),
+        _ => format!("{value} COLLATE NOCASE"),
     }
+}
 
-    let rows = query_builder
-        .fetch_all(&pools.get_active_data_pool().map_err(|e| e.to_string())?)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let mut results = Vec::new();
-    for row in rows {
-        let file = File {
-            id: row.get("id"),
-            path: row.get("path"),
-            name: row.get("name"),
-            directory_id: row.get("directory_id"),
-            size: row.get("size"),
-            file_type: row.get("file_type"),
-            created_at: row.get("created_at"),
-            modified_at: row.get("modified_at"),
-            birth_time: row.get("birth_time"),
-            inode: row.get("inode"),
-            is_directory: row.get("is_directory"),
-            created_at_db: row.get("created_at_db"),
-            updated_at_db: row.get("updated_at_db"),
-            file_size: row.get("file_size"),
-            mime_type: row.get("mime_type"),
-            permissions: row.get("permissions"),
-            owner_uid: row.get("owner_uid"),
-            group_gid: row.get("group_gid"),
-            hard_links: row.get("hard_links"),
-            device_id: row.get("device_id"),
-            last_accessed: row.get("last_accessed"),
-            metadata: row.get("metadata"),
-        };
-
-        let tag_names: Option<String> = row.get("tag_names");
-        let tags = if let Some(names) = tag_names {
-            names
-                .split(',')
-                .filter(|s| !s.is_empty())
-                .map(|name| Tag {
-                    id: Uuid::new_v4().to_string(), // 仮のID
-                    name: name.to_string(),
-                    color: "#3B82F6".to_string(),
-                    created_at: Utc::now(),
-                })
-                .collect()
-        } else {
-            Vec::new()
-        };
-
-        // 簡易的なスコア計算（実装予定）
-        let score = 1.0;
-
-        results.push(SearchResult { file, tags, score });
+/// 一括操作の対象ファイルを決める（ファイルID指定か検索条件のどちらか）
+pub async fn resolve_bulk_targets(
+    pools: &ShelfManager,
+    file_ids: Option<Vec<String>>,
+    filter: Option<SearchFilter>,
+) -> Result<Vec<String>, String> {
+    match (file_ids, filter) {
+        (Some(file_ids), _) => Ok(file_ids),
+        (None, Some(filter)) => find_matching_file_ids(pools, filter).await,
+        (None, None) => Err("対象のファイルが指定されていません".to_string()),
     }
+}
 
-    // フィルタリングはSQLクエリレベルで処理されるため、ここでは不要
+/// 検索条件に一致するすべてのファイルのIDを取得する
+pub async fn find_matching_file_ids(pools: &ShelfManager, filter: SearchFilter) -> Result<Vec<String>, String> {
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    matching_file_ids(&data_pool, pools.get_settings_pool(), &filter).await
+}
 
-    // カテゴリフィルタ適用前の総件数を計算
-    let mut total_sql = pre_category_sql;
-    if !pre_category_conditions.is_empty() {
-        total_sql.push_str(" WHERE ");
-        total_sql.push_str(&pre_category_conditions.join(" AND "));
+/// 検索と同じ条件で、一致するファイルのIDだけを更新日時の新しい順に取得する
+/// （ファイルの内容・タグ・カテゴリ別件数は読み込まない）
+async fn matching_file_ids(data_pool: &SqlitePool, settings_pool: &SqlitePool, filter: &SearchFilter) -> Result<Vec<String>, String> {
+    let search = build_search_conditions(settings_pool, filter).await?;
+    let sql = format!(
+        "SELECT f.id FROM files f{}{} GROUP BY f.id ORDER BY f.modified_at DESC NULLS LAST",
+        search.joins,
+        where_clause(&search.conditions)
+    );
+    let mut query = sqlx::query_scalar::<_, String>(&sql);
+    for param in &search.params {
+        query = query.bind(param);
     }
-    total_sql.push_str(" GROUP BY f.id");
-    let total_category_counts =
-        calculate_category_counts(&pools.get_active_data_pool().map_err(|e| e.to_string())?, &total_sql, &pre_category_params).await?;
-
-    // カテゴリフィルタ適用後の件数を計算
-    let category_counts = calculate_category_counts(&pools.get_active_data_pool().map_err(|e| e.to_string())?, &sql, &sql_params).await?;
-
-    Ok(PaginatedSearchResult {
-        results,
-        total_count,
-        category_counts,
-        total_category_counts,
-    })
+    query.fetch_all(data_pool).await.map_err(|e| e.to_string())
 }
 
 // カテゴリ別件数を計算する関数
@@ -556,109 +726,13 @@ async fn calculate_category_counts(
 
         let category = classify_file_category(&file_name, mime_type.as_deref());
 
-        *category_counts.get_mut(&category).unwrap() += 1;
+        *category_counts.get_mut(category).unwrap() += 1;
         *category_counts.get_mut("all").unwrap() += 1;
     }
 
     Ok(category_counts)
 }
 
-// ファイルのカテゴリを分類する関数
-fn classify_file_category(file_name: &str, mime_type: Option<&str>) -> String {
-    let lower_name = file_name.to_lowercase();
-
-    // MIMEタイプでの判定を優先
-    if let Some(mime) = mime_type {
-        if mime.starts_with("image/") {
-            return "image".to_string();
-        } else if mime.starts_with("audio/") {
-            return "audio".to_string();
-        } else if mime.starts_with("video/") {
-            return "video".to_string();
-        } else if mime.starts_with("application/pdf")
-            || mime.starts_with("application/msword")
-            || mime.starts_with("application/vnd.")
-            || mime.starts_with("text/")
-        {
-            return "document".to_string();
-        } else if mime.starts_with("application/zip")
-            || mime.starts_with("application/x-rar")
-            || mime.starts_with("application/x-7z")
-            || mime.starts_with("application/x-tar")
-            || mime.starts_with("application/gzip")
-        {
-            return "archive".to_string();
-        }
-    }
-
-    // 拡張子での判定
-    if lower_name.ends_with(".jpg")
-        || lower_name.ends_with(".jpeg")
-        || lower_name.ends_with(".png")
-        || lower_name.ends_with(".gif")
-        || lower_name.ends_with(".bmp")
-        || lower_name.ends_with(".webp")
-        || lower_name.ends_with(".svg")
-        || lower_name.ends_with(".ico")
-        || lower_name.ends_with(".tiff")
-        || lower_name.ends_with(".raw")
-    {
-        "image".to_string()
-    } else if lower_name.ends_with(".mp3")
-        || lower_name.ends_with(".wav")
-        || lower_name.ends_with(".ogg")
-        || lower_name.ends_with(".flac")
-        || lower_name.ends_with(".aac")
-        || lower_name.ends_with(".m4a")
-        || lower_name.ends_with(".wma")
-        || lower_name.ends_with(".opus")
-    {
-        "audio".to_string()
-    } else if lower_name.ends_with(".mp4")
-        || lower_name.ends_with(".avi")
-        || lower_name.ends_with(".mov")
-        || lower_name.ends_with(".wmv")
-        || lower_name.ends_with(".flv")
-        || lower_name.ends_with(".webm")
-        || lower_name.ends_with(".mkv")
-        || lower_name.ends_with(".m4v")
-        || lower_name.ends_with(".3gp")
-    {
-        "video".to_string()
-    } else if lower_name.ends_with(".pdf")
-        || lower_name.ends_with(".doc")
-        || lower_name.ends_with(".docx")
-        || lower_name.ends_with(".xls")
-        || lower_name.ends_with(".xlsx")
-        || lower_name.ends_with(".ppt")
-        || lower_name.ends_with(".pptx")
-        || lower_name.ends_with(".txt")
-        || lower_name.ends_with(".md")
-        || lower_name.ends_with(".html")
-        || lower_name.ends_with(".htm")
-        || lower_name.ends_with(".css")
-        || lower_name.ends_with(".js")
-        || lower_name.ends_with(".json")
-        || lower_name.ends_with(".xml")
-        || lower_name.ends_with(".csv")
-        || lower_name.ends_with(".rtf")
-    {
-        "document".to_string()
-    } else if lower_name.ends_with(".zip")
-        || lower_name.ends_with(".rar")
-        || lower_name.ends_with(".7z")
-        || lower_name.ends_with(".tar")
-        || lower_name.ends_with(".gz")
-        || lower_name.ends_with(".bz2")
-        || lower_name.ends_with(".xz")
-        || lower_name.ends_with(".lzma")
-    {
-        "archive".to_string()
-    } else {
-        "other".to_string()
-    }
-}
-
 #[tauri::command]
 pub async fn get_tags(pools: State<'_, ShelfManager>) -> Result<Vec<Tag>, String> {
     let db = Database;
@@ -691,7 +765,18 @@ pub async fn create_tag(
     color: String,
 ) -> Result<Tag, String> {
     let db = Database;
-    db.create_tag(&pools.get_active_data_pool().map_err(|e| e.to_string())?, &name, &color)
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    
+    // 別名が指定された場合は正式なタグを返す
+    if let Some(tag) = db.resolve_tag_alias(&data_pool, &name).await.map_err(|e| e.to_string())? {
+        return Ok(tag);
+    }
+    
+    // 明示的に作成したタグは未使用になっても残す
+    let tag = db.create_tag(&data_pool, &name, &color)
+        .await
+        .map_err(|e| e.to_string())?;
+    db.set_tag_pinned(&data_pool, &tag.id, true)
         .await
         .map_err(|e| e.to_string())
 }
@@ -706,3 +791,112 @@ pub async fn delete_tag(pools: State<'_, ShelfManager>, id: String) -> Result<()
 
     Ok(())
 }
+
+/// 同名タグとの衝突をわかりやすいエラーにする
+fn tag_name_error(e: sqlx::Error) -> String {
+    match e {
+        sqlx::Error::Database(db_error) if db_error.is_unique_violation() => {
+            "同じ名前のタグが既に存在します。まとめる場合は統合を使用してください".to_string()
+        }
+        e => e.to_string(),
+    }
+}
+
+/// タグの名前を変更する（子タグのパスも合わせて変わる）
+#[tauri::command]
+pub async fn rename_tag(
+    pools: State<'_, ShelfManager>,
+    tag_id: String,
+    name: String,
+) -> Result<Tag, String> {
+    let name = name.trim();
+    if name.is_empty() || name.contains('/') {
+        return Err("タグ名が正しくありません".to_string());
+    }
+    
+    let db = Database;
+    db.rename_tag(&pools.get_active_data_pool().map_err(|e| e.to_string())?, &tag_id, name)
+        .await
+        .map_err(tag_name_error)
+}
+
+#[tauri::command]
+pub async fn update_tag_color(
+    pools: State<'_, ShelfManager>,
+    tag_id: String,
+    color: String,
+) -> Result<Tag, String> {
+    let db = Database;
+    db.update_tag_color(&pools.get_active_data_pool().map_err(|e| e.to_string())?, &tag_id, &color)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// タグを別のタグに統合する
+/// 統合元が付いていたファイルには統合先が付き、統合元の名前は別名として残る
+#[tauri::command]
+pub async fn merge_tags(
+    pools: State<'_, ShelfManager>,
+    source_id: String,
+    target_id: String,
+) -> Result<Tag, String> {
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    
+    let source_subtree = db.get_tag_subtree_ids(&data_pool, &source_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    if source_subtree.contains(&target_id) {
+        return Err("タグを自身または子孫のタグに統合することはできません".to_string());
+    }
+    
+    db.merge_tags(&data_pool, &source_id, &target_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// タグに別名を追加する
+#[tauri::command]
+pub async fn add_tag_alias(
+    pools: State<'_, ShelfManager>,
+    tag_id: String,
+    alias: String,
+) -> Result<Vec<String>, String> {
+    let alias = alias.trim();
+    if alias.is_empty() {
+        return Err("別名が空です".to_string());
+    }
+    
+    let db = Database;
+    let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+    let tags = db.get_all_tags(&data_pool).await.map_err(|e| e.to_string())?;
+    if tags.iter().any(|t| t.name.eq_ignore_ascii_case(alias)) {
+        return Err("既存のタグと同じ名前は別名にできません".to_string());
+    }
+    
+    db.add_tag_alias(&data_pool, &tag_id, alias)
+        .await
+        .map_err(|e| e.to_string())?;
+    db.get_tag_aliases(&data_pool, &tag_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn remove_tag_alias(pools: State<'_, ShelfManager>, alias: String) -> Result<(), String> {
+    let db = Database;
+    db.remove_tag_alias(&pools.get_active_data_pool().map_err(|e| e.to_string())?, &alias)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_tag_aliases(
+    pools: State<'_, ShelfManager>,
+    tag_id: String,
+) -> Result<Vec<String>, String> {
+    let db = Database;
+    db.get_tag_aliases(&pools.get_active_data_pool().map_err(|e| e.to_string())?, &tag_id)
+        .await
+        .map_err(|e| e.to_string())
+}
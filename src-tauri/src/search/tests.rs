@@ -3,6 +3,7 @@ mod search_tests {
     use crate::database::{Database, DatabaseTrait, File, Tag};
     use crate::database::tests::TestDatabase;
     use crate::search::SearchResult;
+    use crate::settings;
     use chrono::Utc;
 
     #[tokio::test]
@@ -40,6 +41,10 @@ mod search_tests {
             hard_links: Some(1),
             device_id: Some(12345),
             metadata: None,
+            content_hash: None,
+            is_symlink: false,
+            symlink_target: None,
+            is_broken_link: false,
         };
         
         let tag = Tag {
@@ -47,6 +52,8 @@ mod search_tests {
             name: "test_tag".to_string(),
             color: "#ff0000".to_string(),
             created_at: Utc::now(),
+            parent_id: None,
+            pinned: false,
         };
         
         let search_result = SearchResult {
@@ -62,4 +69,161 @@ mod search_tests {
 
     // Note: Tauriコマンドのテストは実際のTauri環境でのみ可能
     // ここでは内部ロジックのテストにフォーカス
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_metadata_filter_conditions_by_type() {
+        use crate::test_support::test_file;
+        use crate::search::{metadata_filter_condition, MetadataSearchFilter};
+
+        let pool = TestDatabase::new_in_memory().await.into_pool();
+        let db = Database;
+        let dir = db.add_directory(&pool, "/music", "music").await.unwrap();
+        for (id, labels, length, released) in [
+            ("a", "[\"live\",\"rare\"]", "245", "2024-03-05T18:30:00Z"),
+            ("b", "[\"live\"]", "3600", "2024-03-06"),
+        ] {
+            db.add_file(&pool, &test_file(id, &format!("/music/{id}.mp3"), &dir.id)).await.unwrap();
+            for (key_id, value) in [("labels", labels), ("length", length), ("released", released)] {
+                let value_id = format!("{id}-{key_id}");
+                sqlx::query("INSERT INTO custom_metadata_values (id, file_id, key_id, value, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)")
+                    .bind(&value_id).bind(id).bind(key_id).bind(value).bind(Utc::now()).bind(Utc::now())
+                    .execute(&pool).await.unwrap();
+                for (position, item) in crate::database::multi_select_items(value).iter().enumerate() {
+                    sqlx::query("INSERT INTO custom_metadata_value_items (value_id, key_id, item, position) VALUES (?, ?, ?, ?)")
+                        .bind(&value_id).bind(key_id).bind(item).bind(position as i64)
+                        .execute(&pool).await.unwrap();
+                }
+            }
+        }
+
+        let matching = |key_id: &str, data_type: &str, operator: &str, value: &str| {
+            let filter = MetadataSearchFilter {
+                key_id: key_id.to_string(),
+                key_name: key_id.to_string(),
+                display_name: key_id.to_string(),
+                data_type: data_type.to_string(),
+                operator: operator.to_string(),
+                value: value.to_string(),
+            };
+            let (condition, params) = metadata_filter_condition(0, &filter);
+            let sql = format!(
+                "SELECT f.id FROM files f LEFT JOIN custom_metadata_values cmv0 ON f.id = cmv0.file_id
+                 WHERE cmv0.key_id = ? AND {condition} ORDER BY f.id"
+            );
+            let pool = pool.clone();
+            let key_id = key_id.to_string();
+            async move {
+                let mut query = sqlx::query_scalar::<_, String>(&sql).bind(key_id);
+                for param in params {
+                    query = query.bind(param);
+                }
+                query.fetch_all(&pool).await.unwrap()
+            }
+        };
+
+        assert_eq!(matching("labels", "multi_select", "contains", "rare").await, vec!["a"]);
+        assert_eq!(matching("labels", "multi_select", "has_all", "live, rare").await, vec!["a"]);
+        assert_eq!(matching("labels", "multi_select", "not_contains", "rare").await, vec!["b"]);
+        assert_eq!(matching("length", "duration", "greater_than", "30m").await, vec!["b"]);
+        assert_eq!(matching("length", "duration", "less_or_equal", "4:05").await, vec!["a"]);
+        // 日付だけの条件でも同じ日の時刻付きの値を含める
+        assert_eq!(matching("released", "date", "less_or_equal", "2024-03-05").await, vec!["a"]);
+        assert_eq!(matching("released", "date", "greater_than", "2024-03-05").await, vec!["b"]);
+        assert_eq!(matching("released", "date", "greater_or_equal", "2024-03-05").await, vec!["a", "b"]);
+        assert_eq!(matching("released", "date", "less_than", "2024-03-06").await, vec!["a"]);
+        assert_eq!(matching("released", "date", "equals", "2024-03-05").await, vec!["a"]);
+    }
+
+    #[tokio::test]
+    async fn test_matching_file_ids_combines_filters() {
+        use crate::test_support::test_file;
+        use crate::search::{matching_file_ids, MetadataSearchFilter, SearchFilter};
+        use chrono::Duration;
+
+        let pool = TestDatabase::new_in_memory().await.into_pool();
+        let settings_pool = sqlx::SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./settings_migrations").run(&settings_pool).await.unwrap();
+        let db = Database;
+        let docs = db.add_directory(&pool, "/docs", "docs").await.unwrap();
+        let photos = db.add_directory(&pool, "/photos", "photos").await.unwrap();
+        let work = db.ensure_tag_path(&pool, "work", "#000000").await.unwrap();
+        let trip = db.ensure_tag_path(&pool, "work/trip", "#000000").await.unwrap();
+
+        // 新しい順に a, b, c, d
+        let files = [
+            ("a", "/docs/report.pdf", &docs.id, Some(&work.id), Some("done")),
+            ("b", "/docs/notes.txt", &docs.id, Some(&work.id), Some("draft")),
+            ("c", "/photos/beach.jpg", &photos.id, Some(&trip.id), Some("done")),
+            ("d", "/docs/.hidden.txt", &docs.id, None, None),
+        ];
+        for (age, (id, path, directory_id, tag_id, status)) in files.into_iter().enumerate() {
+            let mut file = test_file(id, path, directory_id);
+            file.modified_at = Some(Utc::now() - Duration::hours(age as i64));
+            file.mime_type = None;
+            db.add_file(&pool, &file).await.unwrap();
+            if let Some(tag_id) = tag_id {
+                db.add_file_tag(&pool, id, tag_id).await.unwrap();
+            }
+            if let Some(status) = status {
+                sqlx::query("INSERT INTO custom_metadata_values (id, file_id, key_id, value, created_at, updated_at) VALUES (?, ?, 'status', ?, ?, ?)")
+                    .bind(format!("{id}-status")).bind(id).bind(status).bind(Utc::now()).bind(Utc::now())
+                    .execute(&pool).await.unwrap();
+            }
+        }
+
+        let status = |operator: &str, value: &str| MetadataSearchFilter {
+            key_id: "status".to_string(),
+            key_name: "status".to_string(),
+            display_name: "status".to_string(),
+            data_type: "text".to_string(),
+            operator: operator.to_string(),
+            value: value.to_string(),
+        };
+        let matching = |filter: SearchFilter| {
+            let pool = pool.clone();
+            let settings_pool = settings_pool.clone();
+            async move { matching_file_ids(&pool, &settings_pool, &filter).await.unwrap() }
+        };
+
+        // 隠しファイルは設定で表示しない限り含めない
+        assert_eq!(matching(SearchFilter::default()).await, vec!["a", "b", "c"]);
+        assert_eq!(matching(SearchFilter { query: "report".to_string(), ..Default::default() }).await, vec!["a"]);
+        // 親タグは子孫タグの付いたファイルも含める
+        let work_filter = SearchFilter { tag_ids: Some(vec![work.id.clone()]), ..Default::default() };
+        assert_eq!(matching(work_filter.clone()).await, vec!["a", "b", "c"]);
+        assert_eq!(
+            matching(SearchFilter { category: Some("image".to_string()), ..work_filter.clone() }).await,
+            vec!["c"]
+        );
+        assert_eq!(
+            matching(SearchFilter {
+                directory_id: Some(docs.id.clone()),
+                metadata_filters: vec![status("equals", "done")],
+                ..work_filter.clone()
+            })
+            .await,
+            vec!["a"]
+        );
+        assert_eq!(
+            matching(SearchFilter {
+                metadata_filters: vec![status("equals", "done"), status("equals", "draft")],
+                metadata_logic: Some("OR".to_string()),
+                ..Default::default()
+            })
+            .await,
+            vec!["a", "b", "c"]
+        );
+        assert!(matching(SearchFilter {
+            metadata_filters: vec![status("equals", "done"), status("equals", "draft")],
+            ..Default::default()
+        })
+        .await
+        .is_empty());
+
+        settings::set_setting(&settings_pool, "show_hidden_files", "true").await.unwrap();
+        assert_eq!(
+            matching(SearchFilter { directory_id: Some(docs.id.clone()), ..Default::default() }).await,
+            vec!["a", "b", "d"]
+        );
+    }
+}
@@ -20,6 +20,8 @@ pub struct AppSettings {
     pub files_per_page: i32,
     pub auto_tag_directories: bool,
     pub auto_tag_threshold: f64,
+    pub tombstone_retention_days: i32,
+    pub share_tags_across_hard_links: bool,
 }
 
 impl Default for AppSettings {
@@ -30,6 +32,8 @@ impl Default for AppSettings {
             files_per_page: 20,
             auto_tag_directories: true,
             auto_tag_threshold: 0.5,
+            tombstone_retention_days: 30,
+            share_tags_across_hard_links: false,
         }
     }
 }
@@ -57,6 +61,45 @@ pub async fn set_setting(pool: &SqlitePool, key: &str, value: &str) -> Result<()
     Ok(())
 }
 
+// シェルフごとの設定（各シェルフのデータベースの shelf_settings テーブル）
+// 拡張属性の同期方向・XMPフィールドの対応・計算フィールドの更新日などが使う
+
+/// シェルフごとの設定を取得する（シェルフのデータベースに保存される）
+pub async fn get_shelf_setting(data_pool: &SqlitePool, key: &str) -> Result<Option<String>, String> {
+    sqlx::query_scalar("SELECT value FROM shelf_settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(data_pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// シェルフごとの設定を保存する
+pub async fn set_shelf_setting(data_pool: &SqlitePool, key: &str, value: &str) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO shelf_settings (key, value) VALUES (?, ?)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP",
+    )
+    .bind(key)
+    .bind(value)
+    .execute(data_pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// ハードリンク間でタグを共有する設定（各シェルフにも写し、データベースのトリガーが参照する）
+pub const SHARE_TAGS_ACROSS_HARD_LINKS_KEY: &str = "share_tags_across_hard_links";
+
+/// ハードリンク間でタグを共有する設定をシェルフのデータベースに写す
+pub async fn mirror_hard_link_sharing(settings_pool: &SqlitePool, data_pool: &SqlitePool) -> Result<(), String> {
+    let value = get_setting(settings_pool, SHARE_TAGS_ACROSS_HARD_LINKS_KEY)
+        .await
+        .map_err(|e| e.to_string())?
+        .unwrap_or_else(|| "false".to_string());
+    set_shelf_setting(data_pool, SHARE_TAGS_ACROSS_HARD_LINKS_KEY, &value).await
+}
+
 pub async fn get_all_settings(pool: &SqlitePool) -> Result<AppSettings, Box<dyn Error>> {
     let show_hidden_files = get_setting(pool, "show_hidden_files")
         .await?
@@ -78,12 +121,22 @@ pub async fn get_all_settings(pool: &SqlitePool) -> Result<AppSettings, Box<dyn
         .await?
         .unwrap_or_else(|| "0.7".to_string());
 
+    let tombstone_retention_days = get_setting(pool, "tombstone_retention_days")
+        .await?
+        .unwrap_or_else(|| "30".to_string());
+
+    let share_tags_across_hard_links = get_setting(pool, SHARE_TAGS_ACROSS_HARD_LINKS_KEY)
+        .await?
+        .unwrap_or_else(|| "false".to_string());
+
     Ok(AppSettings {
         show_hidden_files: show_hidden_files == "true",
         show_directories: show_directories == "true",
         files_per_page: files_per_page.parse().unwrap_or(20),
         auto_tag_directories: auto_tag_directories == "true",
         auto_tag_threshold: auto_tag_threshold.parse().unwrap_or(0.7),
+        tombstone_retention_days: tombstone_retention_days.parse().unwrap_or(30),
+        share_tags_across_hard_links: share_tags_across_hard_links == "true",
     })
 }
 
@@ -135,7 +188,14 @@ pub async fn update_setting_bool_cmd(
 ) -> Result<(), String> {
     update_setting_bool(pools.get_settings_pool(), &key, value)
         .await
-        .map_err(|e| format!("Failed to update setting: {e}"))
+        .map_err(|e| format!("Failed to update setting: {e}"))?;
+
+    if key == SHARE_TAGS_ACROSS_HARD_LINKS_KEY {
+        for (_, data_pool) in pools.get_all_data_pools().await.map_err(|e| e.to_string())? {
+            mirror_hard_link_sharing(pools.get_settings_pool(), &data_pool).await?;
+        }
+    }
+    Ok(())
 }
 
 #[tauri::command]
@@ -7,6 +7,9 @@ use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use uuid::Uuid;
 
+/// 変更履歴の changed_by に使うシェルフ設定のキー
+const ANNOTATION_ACTOR_KEY: &str = "annotation_actor";
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Shelf {
     pub id: String,
@@ -143,6 +146,16 @@ impl ShelfManager {
         Ok(())
     }
 
+    /// すべてのシェルフとそのデータベース接続を取得する
+    pub async fn get_all_data_pools(&self) -> Result<Vec<(Shelf, SqlitePool)>, Box<dyn std::error::Error>> {
+        let mut pools = Vec::new();
+        for shelf in self.get_shelves().await? {
+            let pool = self.get_or_create_data_pool(&shelf.id).await?;
+            pools.push((shelf, pool));
+        }
+        Ok(pools)
+    }
+
     pub fn get_active_data_pool(&self) -> Result<SqlitePool, String> {
         let active_id = self.active_shelf_id.lock().unwrap().clone();
         let pools = self.data_pools.lock().unwrap();
@@ -214,6 +227,13 @@ impl ShelfManager {
         let migrator = sqlx::migrate::Migrator::new(Path::new("./data_migrations")).await?;
         migrator.run(&pool).await?;
 
+        // 変更履歴に記録する変更者（OSのユーザー名）
+        if let Ok(user) = env::var("USER").or_else(|_| env::var("USERNAME")) {
+            crate::settings::set_shelf_setting(&pool, ANNOTATION_ACTOR_KEY, &user).await?;
+        }
+        // ハードリンク間のタグ共有はシェルフのトリガーが設定を参照する
+        crate::settings::mirror_hard_link_sharing(&self.settings_pool, &pool).await?;
+
         // プールをキャッシュに追加
         {
             let mut pools = self.data_pools.lock().unwrap();
@@ -0,0 +1,74 @@
+//! テストで使うデータの組み立て
+
+use crate::database::{CustomMetadataKey, CustomMetadataValue, File};
+use chrono::Utc;
+
+/// インデックス済みの通常のファイル（必要な項目は構造体更新記法で上書きする）
+pub fn test_file(id: &str, path: &str, directory_id: &str) -> File {
+    File {
+        id: id.to_string(),
+        path: path.to_string(),
+        name: path.rsplit('/').next().unwrap().to_string(),
+        directory_id: directory_id.to_string(),
+        size: 2048,
+        file_type: Some("txt".to_string()),
+        created_at: Some(Utc::now()),
+        modified_at: Some(Utc::now()),
+        birth_time: None,
+        inode: None,
+        is_directory: false,
+        created_at_db: Utc::now(),
+        updated_at_db: Utc::now(),
+        file_size: Some(2048),
+        mime_type: Some("text/plain".to_string()),
+        permissions: Some("644".to_string()),
+        owner_uid: Some(1000),
+        group_gid: Some(1000),
+        hard_links: Some(1),
+        device_id: None,
+        last_accessed: None,
+        metadata: None,
+        content_hash: None,
+        is_symlink: false,
+        symlink_target: None,
+        is_broken_link: false,
+    }
+}
+
+/// カスタムメタデータキー（IDと表示名は name と同じ）
+pub fn metadata_key(name: &str, data_type: &str) -> CustomMetadataKey {
+    CustomMetadataKey {
+        id: name.to_string(),
+        name: name.to_string(),
+        display_name: name.to_string(),
+        data_type: data_type.to_string(),
+        description: None,
+        is_required: false,
+        default_value: None,
+        validation_pattern: None,
+        options: None,
+        expression: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+/// 計算フィールドのカスタムメタデータキー
+pub fn computed_metadata_key(name: &str, data_type: &str, expression: &str) -> CustomMetadataKey {
+    CustomMetadataKey {
+        expression: Some(expression.to_string()),
+        ..metadata_key(name, data_type)
+    }
+}
+
+/// ファイルに設定されたカスタムメタデータ値
+pub fn metadata_value(file_id: &str, key_id: &str, value: &str) -> CustomMetadataValue {
+    CustomMetadataValue {
+        id: format!("{file_id}_{key_id}"),
+        file_id: file_id.to_string(),
+        key_id: key_id.to_string(),
+        value: Some(value.to_string()),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
@@ -1,22 +1,207 @@
 use crate::database::{Database, DatabaseTrait, File};
 use crate::exclusion_patterns::ExclusionPatternManager;
+use crate::file_manager::directories::most_specific_directory;
+use crate::file_manager::tombstones::{compute_content_hash, reattach_tombstone_by_content, reattach_tombstone_by_identity};
+use crate::file_manager::post_index::run_post_index_hook_for_path;
 use crate::ShelfManager;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use notify::{Event, EventKind, RecursiveMode, Watcher};
 use sqlx::SqlitePool;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
-use std::sync::{mpsc, Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 use tauri::{AppHandle, Emitter, State};
+use tokio::sync::mpsc::{self, error::TrySendError};
 use uuid::Uuid;
 
+/// イベントチャネルの容量（溢れた分は取りこぼしとして記録し、後で再照合する）
+const EVENT_CHANNEL_CAPACITY: usize = 4096;
+/// 最後のイベントからこの時間だけ静かになったらまとめて処理する
+const DEBOUNCE_DURATION: Duration = Duration::from_millis(300);
+/// 一度にまとめるイベント数の上限
+const MAX_BATCH_EVENTS: usize = 1000;
+/// 再照合待ちのパスがこれを超えたら監視ルート全体を再照合する
+const MAX_PENDING_RECONCILE_PATHS: usize = 256;
+/// イベント流量を計測する区間
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// 監視スレッドへ送るメッセージ
+enum WatcherMessage {
+    Event(Event),
+    /// 再照合が必要になったことを知らせる
+    Wake,
+}
+
+/// ファイル監視の健全性
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WatcherHealth {
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub events_per_second: f64,
+    pub total_events: u64,
+    pub coalesced_events: u64,
+    pub dropped_events: u64,
+    pub reconciliations: u64,
+    pub pending_reconcile_paths: usize,
+    pub last_event_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct WatcherStats {
+    queue_depth: AtomicUsize,
+    total_events: AtomicU64,
+    coalesced_events: AtomicU64,
+    dropped_events: AtomicU64,
+    reconciliations: AtomicU64,
+    inner: Mutex<WatcherStatsInner>,
+}
+
+#[derive(Default)]
+struct WatcherStatsInner {
+    last_event_at: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    last_error_at: Option<DateTime<Utc>>,
+    rate_window_start: Option<Instant>,
+    rate_window_count: u64,
+    events_per_second: f64,
+    pending_reconcile: HashSet<PathBuf>,
+    full_reconcile: bool,
+}
+
+impl WatcherStats {
+    fn record_event(&self) {
+        self.total_events.fetch_add(1, Ordering::Relaxed);
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_event_at = Some(Utc::now());
+
+        let now = Instant::now();
+        let window_start = *inner.rate_window_start.get_or_insert(now);
+        inner.rate_window_count += 1;
+        let elapsed = now.duration_since(window_start);
+        if elapsed >= RATE_WINDOW {
+            inner.events_per_second = inner.rate_window_count as f64 / elapsed.as_secs_f64();
+            inner.rate_window_start = Some(now);
+            inner.rate_window_count = 0;
+        }
+    }
+
+    fn record_error(&self, message: String) {
+        eprintln!("ファイル監視エラー: {message}");
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_error = Some(message);
+        inner.last_error_at = Some(Utc::now());
+    }
+
+    /// 取りこぼした可能性のあるパスを再照合対象に加える
+    /// パスが空の場合は監視ルート全体を対象にする
+    fn mark_for_reconcile(&self, paths: &[PathBuf]) {
+        let mut inner = self.inner.lock().unwrap();
+        if paths.is_empty() {
+            inner.full_reconcile = true;
+        }
+        for path in paths {
+            // ファイル単位ではなく、それを含むディレクトリごと照合する
+            let target = if path.is_dir() {
+                path.clone()
+            } else {
+                path.parent().map(Path::to_path_buf).unwrap_or_else(|| path.clone())
+            };
+            inner.pending_reconcile.insert(target);
+        }
+        if inner.pending_reconcile.len() > MAX_PENDING_RECONCILE_PATHS {
+            inner.full_reconcile = true;
+        }
+        if inner.full_reconcile {
+            inner.pending_reconcile.clear();
+        }
+    }
+
+    /// 再照合待ちを取り出す（全体再照合が必要か, 対象パス）
+    fn take_pending_reconcile(&self) -> (bool, Vec<PathBuf>) {
+        let mut inner = self.inner.lock().unwrap();
+        let full = std::mem::take(&mut inner.full_reconcile);
+        let mut paths: Vec<PathBuf> = inner.pending_reconcile.drain().collect();
+        paths.sort();
+
+        // 親ディレクトリが含まれていれば子は不要
+        let mut targets: Vec<PathBuf> = Vec::new();
+        for path in paths {
+            if !targets.iter().any(|target| path.starts_with(target)) {
+                targets.push(path);
+            }
+        }
+        (full, targets)
+    }
+
+    fn snapshot(&self) -> WatcherHealth {
+        let inner = self.inner.lock().unwrap();
+        // 計測区間を過ぎてもイベントがなければ流量は0とみなす
+        let events_per_second = match inner.rate_window_start {
+            Some(start) if start.elapsed() < RATE_WINDOW * 2 => inner.events_per_second,
+            _ => 0.0,
+        };
+
+        WatcherHealth {
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            queue_capacity: EVENT_CHANNEL_CAPACITY,
+            events_per_second,
+            total_events: self.total_events.load(Ordering::Relaxed),
+            coalesced_events: self.coalesced_events.load(Ordering::Relaxed),
+            dropped_events: self.dropped_events.load(Ordering::Relaxed),
+            reconciliations: self.reconciliations.load(Ordering::Relaxed),
+            pending_reconcile_paths: inner.pending_reconcile.len(),
+            last_event_at: inner.last_event_at,
+            last_error: inner.last_error.clone(),
+            last_error_at: inner.last_error_at,
+        }
+    }
+}
+
+/// 同じパスに対するイベントをまとめ、最新のものだけを残す
+#[derive(Default)]
+struct EventCoalescer {
+    events: Vec<Option<Event>>,
+    index: HashMap<Vec<PathBuf>, usize>,
+    received: usize,
+}
+
+impl EventCoalescer {
+    /// イベントを追加する。既存のイベントを置き換えた場合は true を返す
+    fn push(&mut self, event: Event) -> bool {
+        self.received += 1;
+        let replaced = match self.index.get(&event.paths) {
+            Some(&i) => {
+                self.events[i] = None;
+                true
+            }
+            None => false,
+        };
+        self.index.insert(event.paths.clone(), self.events.len());
+        self.events.push(Some(event));
+        replaced
+    }
+
+    fn received(&self) -> usize {
+        self.received
+    }
+
+    /// 発生順を保ったまま、まとめたイベントを取り出す
+    fn into_events(self) -> Vec<Event> {
+        self.events.into_iter().flatten().collect()
+    }
+}
+
 pub struct FileWatcher {
     watcher: notify::FsEventWatcher,
     watched_directories: HashMap<String, String>, // directory_id -> path
     _exclusion_manager: Arc<ExclusionPatternManager>,
+    stats: Arc<WatcherStats>,
 }
 
 impl FileWatcher {
@@ -24,8 +209,9 @@ impl FileWatcher {
         pools: Arc<ShelfManager>,
         app_handle: Option<AppHandle>,
     ) -> Result<Self, notify::Error> {
-        let (tx, rx) = mpsc::channel();
+        let (tx, mut rx) = mpsc::channel::<WatcherMessage>(EVENT_CHANNEL_CAPACITY);
         let pools_clone = Arc::clone(&pools);
+        let stats = Arc::new(WatcherStats::default());
 
         // 除外パターンマネージャーを初期化
         let exclusion_manager = Arc::new(ExclusionPatternManager::new());
@@ -49,95 +235,87 @@ impl FileWatcher {
         });
 
         let exclusion_manager_for_thread = Arc::clone(&exclusion_manager);
+        let stats_for_thread = Arc::clone(&stats);
         thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
-            let mut last_event_time = Instant::now();
-            let mut event_queue: Vec<Event> = Vec::new();
-            let debounce_duration = Duration::from_millis(300); // デバウンス時間を少し長く
-            const MAX_QUEUE_SIZE: usize = 1000; // キューの最大サイズを制限
-
-            loop {
-                // イベントをキューにためる（最大サイズ制限）
-                while event_queue.len() < MAX_QUEUE_SIZE {
-                    if let Ok(event) = rx.try_recv() {
-                        event_queue.push(event);
-                        last_event_time = Instant::now();
-                    } else {
-                        break;
+            let stats = stats_for_thread;
+
+            rt.block_on(async move {
+                // イベントが来るまでブロックして待つ（アイドル時はポーリングしない）
+                while let Some(message) = rx.recv().await {
+                    let mut coalescer = EventCoalescer::default();
+                    accept_message(message, &mut coalescer, &stats);
+
+                    // 静かになるか上限に達するまでイベントをまとめる
+                    while coalescer.received() < MAX_BATCH_EVENTS {
+                        match tokio::time::timeout(DEBOUNCE_DURATION, rx.recv()).await {
+                            Ok(Some(message)) => accept_message(message, &mut coalescer, &stats),
+                            Ok(None) | Err(_) => break,
+                        }
                     }
-                }
 
-                // キューが満杯の場合は強制的に処理
-                let should_process = !event_queue.is_empty()
-                    && (last_event_time.elapsed() > debounce_duration
-                        || event_queue.len() >= MAX_QUEUE_SIZE);
-
-                if should_process {
-                    // イベントの重複を排除
-                    let mut processed_paths = HashSet::new();
-                    let mut unique_events = Vec::new();
-
-                    // 最新のイベントから処理するため、逆順で重複チェック
-                    for event in event_queue.drain(..).rev() {
-                        if let Some(path) = event.paths.first() {
-                            // 同じパスに対するイベントは最新のものだけを処理
-                            if processed_paths.insert(path.clone()) {
-                                unique_events.push(event);
-                            }
+                    let mut unmatched = Vec::new();
+                    for event in coalescer.into_events() {
+                        if let Err(e) = handle_batched_file_event(
+                            &pools_clone,
+                            event,
+                            &app_handle,
+                            &exclusion_manager_for_thread,
+                            &mut unmatched,
+                        )
+                        .await
+                        {
+                            stats.record_error(format!("ファイルイベント処理エラー: {e}"));
                         }
                     }
+                    restore_from_tombstones_by_content(&pools_clone, unmatched, &app_handle).await;
 
-                    // バッチサイズを制限（一度に処理するイベント数を制限）
-                    const BATCH_SIZE: usize = 50;
-                    let batches: Vec<_> = unique_events.chunks(BATCH_SIZE).collect();
-                    let batch_count = batches.len();
-
-                    for batch in batches {
-                        // 各バッチを元の順序に戻して処理
-                        for event in batch.iter().rev() {
-                            // データベースロック競合を避けるため、各イベント間に少し待機
-                            if let Err(e) = rt.block_on(async {
-                                tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-                                handle_file_event(
-                                    &pools_clone,
-                                    event.clone(),
-                                    app_handle.clone(),
-                                    &exclusion_manager_for_thread,
-                                )
-                                .await
-                            }) {
-                                eprintln!("ファイルイベント処理エラー: {e}");
-                            }
-                        }
+                    reconcile_pending(&pools_clone, &stats, &app_handle).await;
+                }
+            });
+        });
 
-                        // バッチ間にも少し待機
-                        if batch_count > 1 {
-                            thread::sleep(Duration::from_millis(100));
+        let stats_for_callback = Arc::clone(&stats);
+        let watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            let stats = &stats_for_callback;
+            match res {
+                Ok(event) => {
+                    stats.queue_depth.fetch_add(1, Ordering::Relaxed);
+                    match tx.try_send(WatcherMessage::Event(event)) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(WatcherMessage::Event(event))) => {
+                            // キューが溢れたイベントは後でサブツリーごと再照合する
+                            stats.queue_depth.fetch_sub(1, Ordering::Relaxed);
+                            stats.dropped_events.fetch_add(1, Ordering::Relaxed);
+                            stats.mark_for_reconcile(&event.paths);
+                        }
+                        Err(e) => {
+                            stats.queue_depth.fetch_sub(1, Ordering::Relaxed);
+                            stats.record_error(format!("ファイルイベント送信エラー: {e}"));
                         }
                     }
                 }
-
-                // CPU負荷を下げるために少し待つ
-                thread::sleep(Duration::from_millis(50));
-            }
-        });
-
-        let watcher = notify::recommended_watcher(move |res| match res {
-            Ok(event) => {
-                if let Err(e) = tx.send(event) {
-                    eprintln!("ファイルイベント送信エラー: {e}");
+                Err(e) => {
+                    stats.record_error(e.to_string());
+                    stats.mark_for_reconcile(&e.paths);
+                    let _ = tx.try_send(WatcherMessage::Wake);
                 }
             }
-            Err(e) => eprintln!("ファイル監視エラー: {e}"),
         })?;
 
         Ok(FileWatcher {
             watcher,
             watched_directories: HashMap::new(),
             _exclusion_manager: exclusion_manager,
+            stats,
         })
     }
 
+    /// 監視の健全性を取得する
+    pub fn health(&self) -> WatcherHealth {
+        self.stats.snapshot()
+    }
+
     pub fn watch_directory(&mut self, directory_id: &str, path: &str) -> Result<(), notify::Error> {
         self.watcher
             .watch(Path::new(path), RecursiveMode::Recursive)?;
@@ -154,6 +332,60 @@ impl FileWatcher {
     }
 }
 
+/// チャネルから受け取ったメッセージをまとめ処理に加える
+fn accept_message(message: WatcherMessage, coalescer: &mut EventCoalescer, stats: &WatcherStats) {
+    let WatcherMessage::Event(event) = message else {
+        return;
+    };
+    stats.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    stats.record_event();
+
+    // OS側でイベントが失われた通知（Flag::Rescan）は再照合で補う
+    if event.need_rescan() {
+        stats.mark_for_reconcile(&event.paths);
+        return;
+    }
+
+    if coalescer.push(event) {
+        stats.coalesced_events.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 取りこぼしが疑われるサブツリーをディスクと照合する
+async fn reconcile_pending(pools: &ShelfManager, stats: &WatcherStats, app_handle: &Option<AppHandle>) {
+    let (full, mut targets) = stats.take_pending_reconcile();
+    if full {
+        let db = Database;
+        let directories = match pools.get_active_data_pool() {
+            Ok(data_pool) => db.get_directories(&data_pool).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        match directories {
+            Ok(directories) => {
+                targets = directories.into_iter().map(|d| PathBuf::from(d.path)).collect();
+            }
+            Err(e) => {
+                stats.record_error(format!("監視ディレクトリ取得エラー: {e}"));
+                return;
+            }
+        }
+    }
+
+    for target in targets {
+        stats.reconciliations.fetch_add(1, Ordering::Relaxed);
+        match crate::file_manager::directories::reconcile_subtree(pools, &target).await {
+            Ok(result) => {
+                #[cfg(debug_assertions)]
+                println!("再照合が完了しました: {} ({result:?})", target.display());
+                if result.has_changes() {
+                    notify_ui(app_handle, "directory_reconciled", &target.to_string_lossy());
+                }
+            }
+            Err(e) => stats.record_error(format!("再照合エラー: {e} (パス: {})", target.display())),
+        }
+    }
+}
+
 #[tauri::command]
 pub async fn start_watching(
     pools: State<'_, ShelfManager>,
@@ -193,16 +425,32 @@ pub async fn handle_file_event(
     event: Event,
     app_handle: Option<AppHandle>,
     exclusion_manager: &Arc<ExclusionPatternManager>,
+) -> Result<(), String> {
+    let mut unmatched = Vec::new();
+    let result = handle_batched_file_event(pools, event, &app_handle, exclusion_manager, &mut unmatched).await;
+    restore_from_tombstones_by_content(pools, unmatched, &app_handle).await;
+    result
+}
+
+/// まとめて届いたイベントの1つを処理する
+/// inode で墓標に一致しなかった追加ファイルは unmatched に積み、まとめ終えてから内容ハッシュで照合する
+/// （移動したファイルより先に同じ内容のコピーが届いても、コピーに注釈を付けないため）
+async fn handle_batched_file_event(
+    pools: &ShelfManager,
+    event: Event,
+    app_handle: &Option<AppHandle>,
+    exclusion_manager: &Arc<ExclusionPatternManager>,
+    unmatched: &mut Vec<File>,
 ) -> Result<(), String> {
     match event.kind {
         EventKind::Create(_) => {
-            handle_create_event(pools, &event.paths, &app_handle, exclusion_manager).await
+            handle_create_event(pools, &event.paths, app_handle, exclusion_manager, unmatched).await
         }
         EventKind::Remove(_) => {
-            handle_remove_event(pools, &event.paths, &app_handle, exclusion_manager).await
+            handle_remove_event(pools, &event.paths, app_handle, exclusion_manager).await
         }
         EventKind::Modify(_) => {
-            handle_modify_event(pools, &event.paths, &app_handle, exclusion_manager).await
+            handle_modify_event(pools, &event.paths, app_handle, exclusion_manager, unmatched).await
         }
         _ => {
             // 他のイベントは無視
@@ -216,6 +464,7 @@ async fn handle_create_event(
     paths: &[std::path::PathBuf],
     app_handle: &Option<AppHandle>,
     exclusion_manager: &Arc<ExclusionPatternManager>,
+    unmatched: &mut Vec<File>,
 ) -> Result<(), String> {
     let db = Database;
 
@@ -229,16 +478,26 @@ async fn handle_create_event(
             continue;
         }
 
-        match fs::metadata(path) {
+        let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+        let Some(follow_symlinks) = symlink_policy_for_path(&data_pool, path).await? else {
+            continue;
+        };
+
+        // シンボリックリンクは登録ディレクトリの設定に合わせ、リンク先かリンク自体として記録する
+        match metadata_for_index(path, follow_symlinks) {
             Ok(metadata) => {
-                let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
                 match find_directory_id_for_path(&data_pool, path).await {
                     Ok(directory_id) => {
                         let file = create_file_from_metadata(path, &metadata, &directory_id);
 
                         match db.add_file(&data_pool, &file).await {
-                            Ok(()) => {
+                            Ok(file_id) => {
                                 notify_ui(app_handle, "file_created", &file.path);
+                                // 既存エントリの更新だった場合は注釈が残っているので復元不要
+                                if file_id == file.id {
+                                    restore_from_tombstone(&data_pool, &file, app_handle, unmatched).await;
+                                }
+                                run_post_index_hook_for_path(pools, &file.path).await;
                             }
                             Err(e) => eprintln!("ファイル追加エラー: {e}"),
                         }
@@ -290,6 +549,7 @@ async fn handle_modify_event(
     paths: &[std::path::PathBuf],
     app_handle: &Option<AppHandle>,
     exclusion_manager: &Arc<ExclusionPatternManager>,
+    unmatched: &mut Vec<File>,
 ) -> Result<(), String> {
     for path in paths {
         let path_str = path.to_string_lossy();
@@ -299,9 +559,14 @@ async fn handle_modify_event(
             continue;
         }
 
-        match fs::metadata(path) {
+        let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
+        let Some(follow_symlinks) = symlink_policy_for_path(&data_pool, path).await? else {
+            continue;
+        };
+
+        match metadata_for_index(path, follow_symlinks) {
             Ok(metadata) => {
-                handle_modify_with_metadata(pools, path, &metadata, app_handle).await?;
+                handle_modify_with_metadata(pools, path, &metadata, app_handle, unmatched).await?;
             }
             Err(e) => {
                 handle_modify_without_metadata(pools, path, e, app_handle).await?;
@@ -317,6 +582,7 @@ async fn handle_modify_with_metadata(
     path: &Path,
     metadata: &fs::Metadata,
     app_handle: &Option<AppHandle>,
+    unmatched: &mut Vec<File>,
 ) -> Result<(), String> {
     use std::os::unix::fs::MetadataExt;
 
@@ -333,7 +599,7 @@ async fn handle_modify_with_metadata(
                 handle_existing_file_update(pools, &path_str, metadata, app_handle).await
             } else {
                 handle_non_existing_file(
-                    pools, path, &path_str, inode, device_id, metadata, app_handle,
+                    pools, path, &path_str, inode, device_id, metadata, app_handle, unmatched,
                 )
                 .await
             }
@@ -364,9 +630,18 @@ async fn handle_existing_file_update(
         Err(e) => eprintln!("ファイル更新エラー: {e}"),
     }
 
+    // 内容が変わった可能性があるため内容ハッシュを更新
+    let content_hash = compute_content_hash(Path::new(path_str), metadata);
+    if let Err(e) = db.update_file_content_hash(&data_pool, path_str, content_hash).await {
+        eprintln!("内容ハッシュ更新エラー: {e}");
+    }
+
+    run_post_index_hook_for_path(pools, path_str).await;
+
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_non_existing_file(
     pools: &ShelfManager,
     path: &Path,
@@ -375,19 +650,23 @@ async fn handle_non_existing_file(
     device_id: Option<i64>,
     metadata: &fs::Metadata,
     app_handle: &Option<AppHandle>,
+    unmatched: &mut Vec<File>,
 ) -> Result<(), String> {
     let db = Database;
+    let birth_time = metadata.created().ok().map(chrono::DateTime::from);
 
     let data_pool = pools.get_active_data_pool().map_err(|e| e.to_string())?;
-    // inode番号で検索（ファイル名変更の可能性）
-    match db.find_file_by_inode(&data_pool, inode, device_id).await {
-        Ok(Some(existing_file)) => {
-            handle_file_rename(pools, path, path_str, &existing_file, app_handle).await
+    // inode + 作成日時で検索（ファイル名変更の可能性）
+    // inodeだけでは削除後に再利用された別ファイルと取り違えるため作成日時も照合する
+    match db.find_file_by_identity(&data_pool, device_id, inode, birth_time).await {
+        Ok(Some(existing_file)) if !Path::new(&existing_file.path).exists() => {
+            handle_file_rename(pools, path, path_str, &existing_file, metadata, app_handle).await
         }
-        Ok(None) => handle_new_file_from_move(pools, path, metadata, app_handle).await,
+        Ok(Some(_)) => handle_new_file_from_move(pools, path, metadata, app_handle, unmatched).await,
+        Ok(None) => handle_new_file_from_move(pools, path, metadata, app_handle, unmatched).await,
         Err(e) => {
-            eprintln!("inode検索エラー: {} (パス: {})", e, path.display());
-            handle_new_file_from_move(pools, path, metadata, app_handle).await
+            eprintln!("ファイル同一性検索エラー: {} (パス: {})", e, path.display());
+            handle_new_file_from_move(pools, path, metadata, app_handle, unmatched).await
         }
     }
 }
@@ -397,6 +676,7 @@ async fn handle_file_rename(
     path: &Path,
     path_str: &str,
     existing_file: &File,
+    metadata: &fs::Metadata,
     app_handle: &Option<AppHandle>,
 ) -> Result<(), String> {
     let db = Database;
@@ -413,7 +693,14 @@ async fn handle_file_rename(
         .await
     {
         Ok(()) => {
+            // 作成日時を記録する前にインデックスした行は、移動を機に記録する
+            if existing_file.birth_time.is_none() {
+                if let Err(e) = db.update_file_metadata(&data_pool, path_str, metadata).await {
+                    eprintln!("ファイルメタデータ更新エラー: {e}");
+                }
+            }
             notify_ui(app_handle, "file_renamed", path_str);
+            run_post_index_hook_for_path(pools, path_str).await;
         }
         Err(e) => {
             eprintln!("ファイル名変更更新エラー: {e}")
@@ -428,6 +715,7 @@ async fn handle_new_file_from_move(
     path: &Path,
     metadata: &fs::Metadata,
     app_handle: &Option<AppHandle>,
+    unmatched: &mut Vec<File>,
 ) -> Result<(), String> {
     let db = Database;
 
@@ -437,8 +725,12 @@ async fn handle_new_file_from_move(
             let file = create_file_from_metadata(path, metadata, &directory_id);
 
             match db.add_file(&data_pool, &file).await {
-                Ok(()) => {
+                Ok(file_id) => {
                     notify_ui(app_handle, "file_created", &file.path);
+                    if file_id == file.id {
+                        restore_from_tombstone(&data_pool, &file, app_handle, unmatched).await;
+                    }
+                    run_post_index_hook_for_path(pools, &file.path).await;
                 }
                 Err(e) => {
                     eprintln!("移動ファイル追加エラー: {e}")
@@ -503,9 +795,12 @@ async fn handle_modify_without_metadata(
     Ok(())
 }
 
-fn create_file_from_metadata(path: &Path, metadata: &fs::Metadata, directory_id: &str) -> File {
+pub(crate) fn create_file_from_metadata(path: &Path, metadata: &fs::Metadata, directory_id: &str) -> File {
     use std::os::unix::fs::MetadataExt;
 
+    let (is_symlink, symlink_target, is_broken_link) =
+        crate::file_manager::directories::symlink_details(path);
+
     File {
         id: Uuid::new_v4().to_string(),
         path: path.to_string_lossy().to_string(),
@@ -522,7 +817,7 @@ fn create_file_from_metadata(path: &Path, metadata: &fs::Metadata, directory_id:
             .map(|s| s.to_string()),
         created_at: metadata.created().ok().map(chrono::DateTime::from),
         modified_at: metadata.modified().ok().map(chrono::DateTime::from),
-        birth_time: None,
+        birth_time: metadata.created().ok().map(chrono::DateTime::from),
         inode: Some(metadata.ino() as i64),
         is_directory: metadata.is_dir(),
         created_at_db: Utc::now(),
@@ -536,6 +831,42 @@ fn create_file_from_metadata(path: &Path, metadata: &fs::Metadata, directory_id:
         device_id: Some(metadata.dev() as i64),
         last_accessed: metadata.accessed().ok().map(chrono::DateTime::from),
         metadata: None,
+        content_hash: compute_content_hash(path, metadata),
+        is_symlink,
+        symlink_target,
+        is_broken_link,
+    }
+}
+
+/// inode + 作成日時が墓標に一致するファイルであれば注釈を復元してUIに通知する
+/// 一致しなければ、内容ハッシュで照合するため unmatched に積む
+async fn restore_from_tombstone(pool: &SqlitePool, file: &File, app_handle: &Option<AppHandle>, unmatched: &mut Vec<File>) {
+    match reattach_tombstone_by_identity(pool, file).await {
+        Ok(true) => notify_ui(app_handle, "file_restored", &file.path),
+        Ok(false) if file.content_hash.is_some() => unmatched.push(file.clone()),
+        Ok(false) => {}
+        Err(e) => eprintln!("墓標の復元エラー: {e}"),
+    }
+}
+
+/// 内容ハッシュ + サイズが墓標に一致するファイルの注釈を復元してUIに通知する
+async fn restore_from_tombstones_by_content(pools: &ShelfManager, files: Vec<File>, app_handle: &Option<AppHandle>) {
+    if files.is_empty() {
+        return;
+    }
+    let data_pool = match pools.get_active_data_pool() {
+        Ok(data_pool) => data_pool,
+        Err(e) => {
+            eprintln!("墓標の復元エラー: {e}");
+            return;
+        }
+    };
+    for file in files {
+        match reattach_tombstone_by_content(&data_pool, &file).await {
+            Ok(true) => notify_ui(app_handle, "file_restored", &file.path),
+            Ok(false) => {}
+            Err(e) => eprintln!("墓標の復元エラー: {e} (パス: {})", file.path),
+        }
     }
 }
 
@@ -573,17 +904,43 @@ fn notify_ui(app_handle: &Option<AppHandle>, event_type: &str, file_path: &str)
     }
 }
 
-async fn find_directory_id_for_path(pool: &SqlitePool, path: &Path) -> Result<String, String> {
-    let db = Database;
-    let directories = db.get_directories(pool).await.map_err(|e| e.to_string())?;
+/// パスを含む登録ディレクトリのうち、最も深いもののIDを返す
+/// 登録ディレクトリがシンボリックリンクを辿るか（スキャンと同じ設定に従う）
+/// リンクを辿らない登録ディレクトリでは、配下のリンクしたディレクトリを経由するパスは対象外として None を返す
+async fn symlink_policy_for_path(pool: &SqlitePool, path: &Path) -> Result<Option<bool>, String> {
+    let directories = Database.get_directories(pool).await.map_err(|e| e.to_string())?;
+    let Some(directory) = most_specific_directory(&directories, path) else {
+        return Ok(Some(false));
+    };
+    if directory.follow_symlinks {
+        return Ok(Some(true));
+    }
 
-    for directory in directories {
-        if path.starts_with(&directory.path) {
-            return Ok(directory.id);
-        }
+    let root = Path::new(&directory.path);
+    let behind_symlink = path
+        .ancestors()
+        .skip(1)
+        .take_while(|ancestor| *ancestor != root && ancestor.starts_with(root))
+        .any(|ancestor| fs::symlink_metadata(ancestor).is_ok_and(|m| m.file_type().is_symlink()));
+    Ok((!behind_symlink).then_some(false))
+}
+
+/// リンクを辿る場合はリンク先（リンク切れはリンク自体）、辿らない場合はリンク自体のメタデータを返す
+fn metadata_for_index(path: &Path, follow_symlinks: bool) -> std::io::Result<fs::Metadata> {
+    if follow_symlinks {
+        fs::metadata(path).or_else(|_| fs::symlink_metadata(path))
+    } else {
+        fs::symlink_metadata(path)
     }
+}
 
-    Err("対応するディレクトリが見つかりません".to_string())
+pub(crate) async fn find_directory_id_for_path(pool: &SqlitePool, path: &Path) -> Result<String, String> {
+    let db = Database;
+    let directories = db.get_directories(pool).await.map_err(|e| e.to_string())?;
+
+    most_specific_directory(&directories, path)
+        .map(|directory| directory.id.clone())
+        .ok_or_else(|| "対応するディレクトリが見つかりません".to_string())
 }
 
 #[cfg(test)]
@@ -618,6 +975,19 @@ mod tests {
         .await
         .unwrap();
 
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS settings (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                key TEXT NOT NULL UNIQUE,
+                value TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
         pool
     }
 
@@ -726,4 +1096,196 @@ mod tests {
         let result = handle_file_event(&pools, event, None, &exclusion_manager).await;
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_event_coalescer_keeps_latest_event_per_path() {
+        let mut coalescer = EventCoalescer::default();
+        let path = PathBuf::from("/test/file.txt");
+
+        let create = Event::new(EventKind::Create(CreateKind::File)).add_path(path.clone());
+        let modify = Event::new(EventKind::Modify(notify::event::ModifyKind::Data(
+            notify::event::DataChange::Content,
+        )))
+        .add_path(path.clone());
+        let other = Event::new(EventKind::Create(CreateKind::File)).add_path(PathBuf::from("/test/other.txt"));
+        let remove = Event::new(EventKind::Remove(notify::event::RemoveKind::File)).add_path(path.clone());
+
+        assert!(!coalescer.push(create));
+        assert!(coalescer.push(modify));
+        assert!(!coalescer.push(other));
+        assert!(coalescer.push(remove));
+        assert_eq!(coalescer.received(), 4);
+
+        // 同じパスは最後の削除イベントだけが残り、順序は保たれる
+        let events = coalescer.into_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].paths, vec![PathBuf::from("/test/other.txt")]);
+        assert!(matches!(events[1].kind, EventKind::Remove(_)));
+    }
+
+    #[test]
+    fn test_rescan_flag_marks_subtree_for_reconcile() {
+        let stats = WatcherStats::default();
+        let mut coalescer = EventCoalescer::default();
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let rescan = Event::new(EventKind::Other)
+            .add_path(temp_dir.path().to_path_buf())
+            .set_flag(notify::event::Flag::Rescan);
+        stats.queue_depth.fetch_add(1, Ordering::Relaxed);
+        accept_message(WatcherMessage::Event(rescan), &mut coalescer, &stats);
+
+        // 再スキャン通知は通常のイベントとしては処理しない
+        assert!(coalescer.into_events().is_empty());
+        let (full, targets) = stats.take_pending_reconcile();
+        assert!(!full);
+        assert_eq!(targets, vec![temp_dir.path().to_path_buf()]);
+
+        let health = stats.snapshot();
+        assert_eq!(health.total_events, 1);
+        assert_eq!(health.queue_depth, 0);
+    }
+
+    #[test]
+    fn test_pending_reconcile_collapses_nested_paths() {
+        let stats = WatcherStats::default();
+        stats.mark_for_reconcile(&[
+            PathBuf::from("/watched/a/b/file.txt"),
+            PathBuf::from("/watched/a/file.txt"),
+            PathBuf::from("/watched/c/file.txt"),
+        ]);
+
+        let (full, targets) = stats.take_pending_reconcile();
+        assert!(!full);
+        assert_eq!(targets, vec![PathBuf::from("/watched/a"), PathBuf::from("/watched/c")]);
+
+        // パスのない通知や大量の取りこぼしは全体の再照合にまとめる
+        stats.mark_for_reconcile(&[]);
+        let (full, targets) = stats.take_pending_reconcile();
+        assert!(full);
+        assert!(targets.is_empty());
+
+        let many: Vec<PathBuf> = (0..=MAX_PENDING_RECONCILE_PATHS)
+            .map(|i| PathBuf::from(format!("/watched/dir{i}/file.txt")))
+            .collect();
+        stats.mark_for_reconcile(&many);
+        let (full, _) = stats.take_pending_reconcile();
+        assert!(full);
+    }
+
+    #[tokio::test]
+    async fn test_create_event_reattaches_tombstone_by_identity_before_content() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let original = root.join("photo.jpg");
+        fs::write(&original, "pixels").unwrap();
+
+        let pools = crate::database::tests::test_shelf_manager().await;
+        let data_pool = pools.get_active_data_pool().unwrap();
+        let db = Database;
+        let directory = db.add_directory(&data_pool, &root.to_string_lossy(), "root").await.unwrap();
+        let file = create_file_from_metadata(&original, &fs::metadata(&original).unwrap(), &directory.id);
+        db.add_file(&data_pool, &file).await.unwrap();
+        let tag = db.create_tag(&data_pool, "trip", "#0000ff").await.unwrap();
+        db.add_file_tag(&data_pool, &file.id, &tag.id).await.unwrap();
+
+        // 削除イベントの後に別の場所で作成イベントが届く（移動）。同じ内容の別ファイルも作られる
+        let moved = root.join("renamed.jpg");
+        let copy = root.join("copy.jpg");
+        fs::rename(&original, &moved).unwrap();
+        fs::copy(&moved, &copy).unwrap();
+        let exclusion_manager = Arc::new(ExclusionPatternManager::new());
+        let event = |kind, path: &PathBuf| Event { kind, paths: vec![path.clone()], attrs: Default::default() };
+        handle_file_event(&pools, event(EventKind::Remove(notify::event::RemoveKind::File), &original), None, &exclusion_manager)
+            .await
+            .unwrap();
+        assert_eq!(db.get_file_tombstones(&data_pool).await.unwrap().len(), 1);
+        // 同じまとまりで届いた場合、コピーが先でも inode の一致する移動先が墓標を取り戻す
+        let mut unmatched = Vec::new();
+        for path in [&copy, &moved] {
+            let event = event(EventKind::Create(CreateKind::File), path);
+            handle_batched_file_event(&pools, event, &None, &exclusion_manager, &mut unmatched).await.unwrap();
+        }
+        restore_from_tombstones_by_content(&pools, unmatched, &None).await;
+
+        let tags_at = |path: &PathBuf| {
+            let data_pool = data_pool.clone();
+            let path = path.to_string_lossy().to_string();
+            async move {
+                let file_id: String = sqlx::query_scalar("SELECT id FROM files WHERE path = ?")
+                    .bind(path)
+                    .fetch_one(&data_pool)
+                    .await
+                    .unwrap();
+                Database.get_file_tags(&data_pool, &file_id).await.unwrap().into_iter().map(|t| t.name).collect::<Vec<_>>()
+            }
+        };
+        assert!(tags_at(&copy).await.is_empty());
+        assert_eq!(tags_at(&moved).await, vec!["trip"]);
+
+        // 別の inode で同じ内容が書き戻された場合は内容ハッシュで戻す
+        fs::remove_file(&moved).unwrap();
+        handle_file_event(&pools, event(EventKind::Remove(notify::event::RemoveKind::File), &moved), None, &exclusion_manager)
+            .await
+            .unwrap();
+        let restored = root.join("restored.jpg");
+        fs::write(&restored, "pixels").unwrap();
+        handle_file_event(&pools, event(EventKind::Create(CreateKind::File), &restored), None, &exclusion_manager)
+            .await
+            .unwrap();
+        assert_eq!(tags_at(&restored).await, vec!["trip"]);
+        assert!(tags_at(&copy).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_create_event_follows_directory_symlink_setting() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().join("root");
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("report.txt"), "0123456789").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("linked")).unwrap();
+        std::os::unix::fs::symlink(outside.join("report.txt"), root.join("report-link.txt")).unwrap();
+
+        let pools = crate::database::tests::test_shelf_manager().await;
+        let data_pool = pools.get_active_data_pool().unwrap();
+        let db = Database;
+        let directory = db.add_directory(&data_pool, &root.to_string_lossy(), "root").await.unwrap();
+        let exclusion_manager = Arc::new(ExclusionPatternManager::new());
+        let create = |path: PathBuf| Event { kind: EventKind::Create(CreateKind::File), paths: vec![path], attrs: Default::default() };
+        let indexed = |path: PathBuf| {
+            let data_pool = data_pool.clone();
+            async move {
+                sqlx::query("SELECT * FROM files WHERE path = ?")
+                    .bind(path.to_string_lossy().as_ref())
+                    .fetch_optional(&data_pool)
+                    .await
+                    .unwrap()
+                    .as_ref()
+                    .map(crate::database::file_from_row)
+            }
+        };
+
+        // 辿らない設定では、リンクしたディレクトリの中は対象外で、リンクはリンク自体として記録する
+        for path in [root.join("linked/report.txt"), root.join("report-link.txt")] {
+            handle_file_event(&pools, create(path), None, &exclusion_manager).await.unwrap();
+        }
+        assert!(indexed(root.join("linked/report.txt")).await.is_none());
+        let link = indexed(root.join("report-link.txt")).await.unwrap();
+        assert!(link.is_symlink);
+        assert_ne!(link.size, 10);
+
+        // 辿る設定では、スキャンと同じくリンク先の内容で記録する
+        sqlx::query("UPDATE directories SET follow_symlinks = 1 WHERE id = ?")
+            .bind(&directory.id)
+            .execute(&data_pool)
+            .await
+            .unwrap();
+        for path in [root.join("linked/report.txt"), root.join("report-link.txt")] {
+            handle_file_event(&pools, create(path), None, &exclusion_manager).await.unwrap();
+        }
+        assert_eq!(indexed(root.join("linked/report.txt")).await.unwrap().size, 10);
+        assert_eq!(indexed(root.join("report-link.txt")).await.unwrap().size, 10);
+    }
 }